@@ -0,0 +1,55 @@
+/* C ABI of md-profiler, to convert captures from an emulator, link with the libmd_profiler library built in this
+   directory (cargo build --release). A capture is converted with the default options of md-profiler. */
+#ifndef MD_PROFILER_H
+#define MD_PROFILER_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct MdpTrace MdpTrace;
+
+/* an event of the trace, its strings belong to the trace and live as long as it */
+typedef struct MdpEvent {
+    const char *name;
+    /* "subroutine", "interrupt", "frame"... or NULL */
+    const char *category;
+    /* as in the trace event format: 'X' for a slice, 'i' for an instant, 'C' for a counter, 'M' for metadata... */
+    char phase;
+    /* microseconds from the start of the capture */
+    double ts_us;
+    double dur_us;
+    uint32_t pid;
+    uint32_t tid;
+} MdpEvent;
+
+/* Converts the bytes of an mdp capture, with the bytes of its symbol file and interval file, which can be NULL.
+   The buffers are only read during the call. Returns NULL on error, see mdp_last_error, the trace must be freed
+   with mdp_trace_free. */
+MdpTrace *mdp_convert(const uint8_t *capture, size_t capture_len, const uint8_t *symbol_file, size_t symbol_file_len, const uint8_t *interval_file, size_t interval_file_len);
+
+/* The message of the last call that failed on this thread, valid until the next call failing on this thread. */
+const char *mdp_last_error(void);
+
+/* The events, in the order they're written to the traces. */
+size_t mdp_trace_event_count(const MdpTrace *trace);
+/* Fills event with the event at this index, returns false if the index is out of range. */
+bool mdp_trace_event(const MdpTrace *trace, size_t index, MdpEvent *event);
+
+/* Write the trace to this path (UTF-8), as json or as a Perfetto protobuf trace, to open in https://ui.perfetto.dev.
+   Return false on error, see mdp_last_error. */
+bool mdp_trace_write_json(const MdpTrace *trace, const char *path);
+bool mdp_trace_write_perfetto(const MdpTrace *trace, const char *path);
+
+/* Frees a trace returned by mdp_convert, NULL is ignored. */
+void mdp_trace_free(MdpTrace *trace);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
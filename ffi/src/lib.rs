@@ -0,0 +1,160 @@
+// the C ABI of md_profiler.h: a capture converted in memory with the default options of md-profiler, its events read
+// one by one or written as a json or Perfetto trace, the contracts of each function are documented in the header
+#![allow(clippy::missing_safety_doc)]
+
+use std::{cell::RefCell, ffi::{CStr, CString}, fs::File, os::raw::c_char, panic::{catch_unwind, AssertUnwindSafe}, ptr, slice};
+
+use profiler::{intervals::read_intervals, perfetto::write_perfetto_trace, profiling::*, progress::{set_enabled, set_status_to_stderr}, schema::{TraceMetadata, SCHEMA_VERSION}, symbols::{read_symbols, Symbols}, tasks::TaskStacks};
+
+thread_local! {
+    // message of the last call that failed on this thread
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+// runs a call, its errors and panics (which can't unwind into C) becoming the last error
+fn guard<T>(call: impl FnOnce() -> Result<T, String>) -> Option<T> {
+    let result = catch_unwind(AssertUnwindSafe(call)).unwrap_or_else(|panic| {
+        let message = panic.downcast_ref::<&str>().map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "md-profiler panicked".to_string());
+        Err(message)
+    });
+    result.map_err(|err| LAST_ERROR.with(|last_error| *last_error.borrow_mut() = c_string(&err))).ok()
+}
+
+// the names never contain NUL bytes, but a corrupted symbol file could
+fn c_string(string: &str) -> CString {
+    CString::new(string.replace('\0', "")).unwrap()
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    (!data.is_null()).then(|| slice::from_raw_parts(data, len))
+}
+
+pub struct MdpTrace {
+    // borrowing the symbols, so declared before them to be dropped first
+    events: Vec<TraceEvent<'static>>,
+    // the strings handed out by mdp_trace_event, the category being null for the events without one
+    names: Vec<(CString, Option<CString>)>,
+    symbols: Box<Symbols>,
+}
+
+#[repr(C)]
+pub struct MdpEvent {
+    pub name: *const c_char,
+    pub category: *const c_char,
+    // as in the trace event format: 'X' for a slice, 'i' for an instant, 'C' for a counter, 'M' for metadata...
+    pub phase: c_char,
+    pub ts_us: f64,
+    pub dur_us: f64,
+    pub pid: u32,
+    pub tid: u32,
+}
+
+fn convert(capture: &[u8], symbol_file: Option<&[u8]>, interval_file: Option<&[u8]>) -> Result<MdpTrace, String> {
+    // the standard output belongs to the emulator, only the warnings go to stderr, with the status messages
+    set_enabled(false);
+    set_status_to_stderr(true);
+    let mut profiling = read_profiling_file(capture)?;
+    profiling.check_clocks()?;
+    profiling.resolve_clock_domains();
+    profiling.canonicalize_addresses(&AddressMap::default());
+    profiling.resolve_banked_addresses();
+    let symbols = Box::new(match symbol_file {
+        Some(symbol_file) => {
+            let mut symbols = read_symbols(symbol_file);
+            symbols.demangle();
+            symbols
+        },
+        None => Symbols::default(),
+    });
+    // the box doesn't move when the trace does, and outlives the events, see MdpTrace
+    let symbols_ref: &'static Symbols = unsafe { &*(symbols.as_ref() as *const Symbols) };
+    let (mut intervals, custom_threads) = match interval_file {
+        Some(interval_file) => read_intervals(interval_file, symbols_ref, "interval file")?,
+        None => Default::default(),
+    };
+    intervals.set_exit_matching(profiling.exit_matching);
+    let mut events = generate_trace_events(&profiling, symbols_ref, &mut intervals, custom_threads, &MarkerStyles::default(), false, &TaskStacks::default());
+    intervals.configure_threads(&mut events);
+    let names = events.iter().map(|event| (c_string(&event.name), event.cat.as_deref().map(c_string))).collect();
+    Ok(MdpTrace { events, names, symbols })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mdp_convert(capture: *const u8, capture_len: usize, symbol_file: *const u8, symbol_file_len: usize, interval_file: *const u8, interval_file_len: usize) -> *mut MdpTrace {
+    let capture = match bytes(capture, capture_len) {
+        Some(capture) => capture,
+        None => {
+            guard(|| Err::<(), _>("No capture given".to_string()));
+            return ptr::null_mut();
+        },
+    };
+    let (symbol_file, interval_file) = (bytes(symbol_file, symbol_file_len), bytes(interval_file, interval_file_len));
+    guard(|| convert(capture, symbol_file, interval_file)).map_or(ptr::null_mut(), |trace| Box::into_raw(Box::new(trace)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mdp_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| last_error.borrow().as_ptr())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mdp_trace_event_count(trace: *const MdpTrace) -> usize {
+    (*trace).events.len()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mdp_trace_event(trace: *const MdpTrace, index: usize, event: *mut MdpEvent) -> bool {
+    let trace = &*trace;
+    let (trace_event, (name, category)) = match (trace.events.get(index), trace.names.get(index)) {
+        (Some(trace_event), Some(names)) => (trace_event, names),
+        _ => return false,
+    };
+    *event = MdpEvent {
+        name: name.as_ptr(),
+        category: category.as_ref().map_or(ptr::null(), |category| category.as_ptr()),
+        phase: trace_event.ph as c_char,
+        ts_us: trace_event.ts,
+        dur_us: trace_event.dur,
+        pid: trace_event.pid,
+        tid: trace_event.tid,
+    };
+    true
+}
+
+unsafe fn write_trace(trace: *const MdpTrace, path: *const c_char, write: impl FnOnce(File, &[TraceEvent])) -> bool {
+    let trace = &*trace;
+    let path = CStr::from_ptr(path).to_string_lossy();
+    guard(|| {
+        let file = File::create(path.as_ref()).map_err(|err| format!("Couldn't create {}: {}", path, err))?;
+        write(file, &trace.events);
+        Ok(())
+    }).is_some()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mdp_trace_write_json(trace: *const MdpTrace, path: *const c_char) -> bool {
+    let symbol_file_hash = Some((*trace).symbols.hash).filter(|&hash| hash != 0).map(|hash| format!("{:016x}", hash));
+    let metadata = TraceMetadata {
+        schema_version: SCHEMA_VERSION,
+        symbol_file_hash,
+        converter_version: Some(format!("{} (embedded)", env!("CARGO_PKG_VERSION"))),
+        command_line: Vec::new(),
+        symbol_file_hashes: Vec::new(),
+        captures: Vec::new(),
+    };
+    write_trace(trace, path, |file, events| write_profiling_json(file, events, &metadata, Compression::None, JsonFormat::Object, false))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mdp_trace_write_perfetto(trace: *const MdpTrace, path: *const c_char) -> bool {
+    write_trace(trace, path, |file, events| write_perfetto_trace(file, events, Compression::None, false))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mdp_trace_free(trace: *mut MdpTrace) {
+    if !trace.is_null() {
+        drop(Box::from_raw(trace));
+    }
+}
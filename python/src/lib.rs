@@ -0,0 +1,202 @@
+// the md_profiler Python module: captures and symbol files, and the statistics md-profiler computes from them,
+// the tables being dicts of columns, ready for pandas.DataFrame
+use std::{collections::HashSet, fs};
+
+use profiler::{frames::{compute_frame_stats, FrameSource}, hotspots::{compute_function_stats, function_invocations}, latency::compute_interrupt_latencies, profiling::*, raster::{VideoStandard, VideoTiming}, symbols::{read_symbols, Symbols}};
+use pyo3::{exceptions::{PyOSError, PyValueError}, prelude::*, types::PyDict};
+
+fn read_file(path: &str) -> PyResult<Vec<u8>> {
+    fs::read(path).map_err(|err| PyOSError::new_err(format!("Couldn't read {}: {}", path, err)))
+}
+
+#[pyclass(name = "Symbols")]
+struct PySymbols {
+    symbols: Symbols,
+}
+
+#[pymethods]
+impl PySymbols {
+    // any symbol file md-profiler reads, the C++ and Rust names demangled unless raw_names is true
+    #[new]
+    #[pyo3(signature = (path, raw_names = false))]
+    fn new(path: &str, raw_names: bool) -> PyResult<PySymbols> {
+        let mut symbols = read_symbols(&read_file(path)?);
+        if !raw_names {
+            symbols.demangle();
+        }
+        Ok(PySymbols { symbols })
+    }
+
+    // the label of the address, or of the nearest one before it with the offset ("Update+0x12")
+    fn name(&self, address: u32) -> String {
+        self.symbols.name(address).into_owned()
+    }
+
+    // the address of a label, or of a hex address
+    fn address(&self, label: &str) -> Option<u32> {
+        self.symbols.resolve(label)
+    }
+
+    fn __len__(&self) -> usize {
+        self.symbols.len()
+    }
+
+    // FNV-1a hash of the symbol file, the one emulators record in the captures
+    #[getter]
+    fn hash(&self) -> u64 {
+        self.symbols.hash
+    }
+}
+
+#[pyclass(name = "Capture")]
+struct PyCapture {
+    profiling: ParsedProfilingFile,
+}
+
+#[pymethods]
+impl PyCapture {
+    // an mdp file, prepared the way the conversion prepares it with the default options
+    #[new]
+    fn new(path: &str) -> PyResult<PyCapture> {
+        let mut profiling = read_profiling_file(&read_file(path)?).map_err(|err| PyValueError::new_err(format!("{}: {}", path, err)))?;
+        profiling.check_clocks().map_err(PyValueError::new_err)?;
+        profiling.resolve_clock_domains();
+        profiling.canonicalize_addresses(&AddressMap::default());
+        profiling.resolve_banked_addresses();
+        Ok(PyCapture { profiling })
+    }
+
+    #[getter]
+    fn mclk(&self) -> f64 {
+        self.profiling.mclk
+    }
+
+    #[getter]
+    fn m68k_divider(&self) -> u64 {
+        self.profiling.m68k_divider
+    }
+
+    fn __len__(&self) -> usize {
+        self.profiling.packets.len()
+    }
+
+    // master clock cycles, which every cycle of the tables is counted in, to microseconds
+    fn cycles_to_us(&self, cycles: u64) -> f64 {
+        cycle_to_us(cycles, self.profiling.mclk)
+    }
+
+    // the packets of the 68000: cycle, stack_pointer, kind (named as in --overhead where it has a name there)
+    // and address (the subroutine, handler or PC it is about, if any)
+    fn packets<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let packets = &self.profiling.packets;
+        let columns = PyDict::new_bound(py);
+        columns.set_item("cycle", packets.iter().map(|packet| packet.cycle).collect::<Vec<_>>())?;
+        columns.set_item("stack_pointer", packets.iter().map(|packet| packet.stack_pointer).collect::<Vec<_>>())?;
+        columns.set_item("kind", packets.iter().map(|packet| packet_kind(&packet.inner)).collect::<Vec<_>>())?;
+        columns.set_item("address", packets.iter().map(|packet| packet_address(&packet.inner)).collect::<Vec<_>>())?;
+        Ok(columns)
+    }
+
+    // calls, total and self cycles of each function, as in the top subcommand, named if symbols are given,
+    // the time spent in the idle functions (addresses) is left out of their callers
+    #[pyo3(signature = (symbols = None, idle = Vec::new()))]
+    fn function_stats<'py>(&self, py: Python<'py>, symbols: Option<PyRef<'_, PySymbols>>, idle: Vec<u32>) -> PyResult<Bound<'py, PyDict>> {
+        let stats = compute_function_stats(&self.profiling, &idle.into_iter().collect());
+        let columns = PyDict::new_bound(py);
+        columns.set_item("address", stats.iter().map(|stats| stats.address).collect::<Vec<_>>())?;
+        if let Some(symbols) = symbols {
+            columns.set_item("name", stats.iter().map(|stats| symbols.symbols.name(stats.address).into_owned()).collect::<Vec<_>>())?;
+        }
+        columns.set_item("calls", stats.iter().map(|stats| stats.calls).collect::<Vec<_>>())?;
+        columns.set_item("total_cycles", stats.iter().map(|stats| stats.total_cycles).collect::<Vec<_>>())?;
+        columns.set_item("self_cycles", stats.iter().map(|stats| stats.self_cycles).collect::<Vec<_>>())?;
+        Ok(columns)
+    }
+
+    // (start cycle, cycles) of each call of the function at this address
+    fn function_invocations(&self, address: u32) -> Vec<(u64, u64)> {
+        function_invocations(&self.profiling, address)
+    }
+
+    // one row per frame, from one VInt to the next, busy time being what isn't spent in the idle functions (addresses),
+    // video_standard being "ntsc" or "pal" when the capture doesn't tell
+    #[pyo3(signature = (idle = Vec::new(), video_standard = None))]
+    fn frame_stats<'py>(&self, py: Python<'py>, idle: Vec<u32>, video_standard: Option<&str>) -> PyResult<Bound<'py, PyDict>> {
+        let standard = video_standard.map(VideoStandard::parse).transpose().map_err(PyValueError::new_err)?;
+        let timing = VideoTiming::from_capture(&self.profiling, standard);
+        let idle_functions: HashSet<u32> = idle.into_iter().collect();
+        let frames = compute_frame_stats(&self.profiling, FrameSource::VInt, &idle_functions, &timing);
+        let columns = PyDict::new_bound(py);
+        columns.set_item("start_cycle", frames.iter().map(|frame| frame.start_cycle).collect::<Vec<_>>())?;
+        columns.set_item("end_cycle", frames.iter().map(|frame| frame.end_cycle).collect::<Vec<_>>())?;
+        columns.set_item("busy_cycles", frames.iter().map(|frame| frame.busy_cycles).collect::<Vec<_>>())?;
+        columns.set_item("interrupt_cycles", frames.iter().map(|frame| frame.interrupt_cycles).collect::<Vec<_>>())?;
+        columns.set_item("dma_halt_cycles", frames.iter().map(|frame| frame.dma_halt_cycles).collect::<Vec<_>>())?;
+        columns.set_item("hints", frames.iter().map(|frame| frame.hints).collect::<Vec<_>>())?;
+        columns.set_item("vints", frames.iter().map(|frame| frame.vints).collect::<Vec<_>>())?;
+        // only meaningful with idle functions
+        columns.set_item("lag", frames.iter().map(|frame| (!idle_functions.is_empty()).then(|| frame.is_lag())).collect::<Vec<_>>())?;
+        Ok(columns)
+    }
+
+    // one row per VInt or HInt handler dispatched, as in --latency-report
+    fn interrupt_latencies<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let latencies = compute_interrupt_latencies(&self.profiling);
+        let columns = PyDict::new_bound(py);
+        columns.set_item("kind", latencies.iter().map(|latency| latency.kind).collect::<Vec<_>>())?;
+        columns.set_item("assert_cycle", latencies.iter().map(|latency| latency.assert_cycle).collect::<Vec<_>>())?;
+        columns.set_item("enter_cycle", latencies.iter().map(|latency| latency.enter_cycle).collect::<Vec<_>>())?;
+        columns.set_item("handler", latencies.iter().map(|latency| latency.handler).collect::<Vec<_>>())?;
+        columns.set_item("running", latencies.iter().map(|latency| latency.running).collect::<Vec<_>>())?;
+        Ok(columns)
+    }
+}
+
+fn packet_kind(inner: &ProfilingPacketInner) -> &'static str {
+    match inner {
+        ProfilingPacketInner::SubroutineEnter { .. } => "enter",
+        ProfilingPacketInner::SubroutineExit => "exit",
+        ProfilingPacketInner::InterruptEnter { .. } => "interrupt-enter",
+        ProfilingPacketInner::InterruptExit => "interrupt-exit",
+        ProfilingPacketInner::HInt => "hint",
+        ProfilingPacketInner::VInt => "vint",
+        ProfilingPacketInner::ManualBreakpoint { .. } => "breakpoint",
+        ProfilingPacketInner::Savestate { .. } => "savestate",
+        ProfilingPacketInner::Dma { .. } => "dma",
+        ProfilingPacketInner::TailCall { .. } => "tail-call",
+        ProfilingPacketInner::DmaStart { .. } => "dma-start",
+        ProfilingPacketInner::DmaEnd => "dma-end",
+        ProfilingPacketInner::VdpRegisterWrite { .. } => "vdp-register-write",
+        ProfilingPacketInner::Z80BusRequest => "z80-bus-request",
+        ProfilingPacketInner::Z80BusRelease => "z80-bus-release",
+        ProfilingPacketInner::BankSwitch { .. } => "bank-switch",
+        ProfilingPacketInner::WordRam { .. } => "word-ram",
+        ProfilingPacketInner::SoundCommand { .. } => "sound-command",
+        ProfilingPacketInner::Alloc { .. } => "alloc",
+        ProfilingPacketInner::Free { .. } => "free",
+        ProfilingPacketInner::UserEvent { .. } | ProfilingPacketInner::UserEventIndex { .. } => "user-event",
+        ProfilingPacketInner::PcSample { .. } => "pc-sample",
+        ProfilingPacketInner::TrapEnter { .. } => "trap-enter",
+        ProfilingPacketInner::TrapExit => "trap-exit",
+        ProfilingPacketInner::Exception { .. } => "exception",
+        ProfilingPacketInner::MemoryWatch { .. } => "memory-watch",
+    }
+}
+
+fn packet_address(inner: &ProfilingPacketInner) -> Option<u32> {
+    match *inner {
+        ProfilingPacketInner::SubroutineEnter { target_subroutine, .. } | ProfilingPacketInner::TailCall { target_subroutine } => Some(target_subroutine),
+        ProfilingPacketInner::InterruptEnter { target_interrupt } => Some(target_interrupt),
+        ProfilingPacketInner::TrapEnter { handler, .. } => Some(handler),
+        ProfilingPacketInner::ManualBreakpoint { pc } | ProfilingPacketInner::PcSample { pc } | ProfilingPacketInner::Exception { pc, .. } => Some(pc),
+        ProfilingPacketInner::Alloc { address, .. } | ProfilingPacketInner::Free { address } | ProfilingPacketInner::MemoryWatch { address, .. } => Some(address),
+        _ => None,
+    }
+}
+
+#[pymodule]
+fn md_profiler(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PySymbols>()?;
+    module.add_class::<PyCapture>()?;
+    Ok(())
+}
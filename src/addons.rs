@@ -0,0 +1,228 @@
+use crate::{profiling::*, symbols::Symbols};
+
+// interrupt handlers and what they call are drawn on the second thread of the process of their CPU
+const ADDON_MAIN_TID: u32 = 0;
+const ADDON_INTERRUPT_TID: u32 = 1;
+// when the word RAM of the Mega CD changes hands, on the process of the sub CPU
+const WORD_RAM_TID: u32 = 2;
+
+// the packets of a CPU of the 32X or the Mega CD, and how to place them on the timeline of the 68000
+struct AddonCpu<'p> {
+    packets: &'p [ProfilingPacket],
+    pid: u32,
+    name: String,
+    cycle_to_mclk: &'p dyn Fn(u64) -> u64,
+    // the SH2s don't push their return address on the stack, an exit returns from the innermost running subroutine,
+    // the sub CPU is a 68000 and matches its exits like the main CPU, see --exit-matching
+    exit_matching: ExitMatching,
+}
+
+// a subroutine or interrupt handler while it runs on an add-on CPU
+struct RunningSlice {
+    // its slice in the trace events, timed once it returns
+    index: usize,
+    start_cycle: u64,
+    depth: usize,
+}
+
+// what runs on an add-on CPU, the interrupts of the SH2s can nest
+struct RunningSlices {
+    main: CallStack<RunningSlice>,
+    // each interrupt handler running, with the subroutines it called
+    interrupts: Vec<(RunningSlice, CallStack<RunningSlice>)>,
+}
+
+impl RunningSlices {
+    // the subroutines the exits return from, an interrupt handler only returns with its interrupt exit
+    fn stack(&mut self) -> &mut CallStack<RunningSlice> {
+        self.interrupts.last_mut().map_or(&mut self.main, |(_handler, stack)| stack)
+    }
+
+    fn tid(&self) -> u32 {
+        if self.interrupts.is_empty() { ADDON_MAIN_TID } else { ADDON_INTERRUPT_TID }
+    }
+}
+
+fn metadata_event<'a>(name: &'static str, pid: u32, tid: u32, value: String) -> TraceEvent<'a> {
+    TraceEvent {
+        name: name.into(),
+        ph: 'M',
+        ts: 0.0,
+        dur: 0.0,
+        pid,
+        tid,
+        args: Some(TraceEventArgs {
+            name: Some(value),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+// the slice of a subroutine or interrupt handler entered at this cycle
+fn open_slice<'a>(trace_events: &mut Vec<TraceEvent<'a>>, running: &RunningSlices, symbols: &'a Symbols, pid: u32, address: u32, interrupt: bool, cycle: u64) -> RunningSlice {
+    let tid = if interrupt { ADDON_INTERRUPT_TID } else { running.tid() };
+    let depth = if tid == ADDON_INTERRUPT_TID { running.interrupts.iter().map(|(_handler, stack)| 1 + stack.len()).sum() } else { running.main.len() };
+    let slice = RunningSlice { index: trace_events.len(), start_cycle: cycle, depth };
+    trace_events.push(TraceEvent {
+        name: symbols.name(address),
+        ph: 'X',
+        ts: 0.0,
+        dur: 0.0,
+        pid,
+        tid,
+        args: function_args(symbols, address),
+        cname: symbols.region(address).map(|region| region.color.as_str().into()),
+        cat: Some(if interrupt { CATEGORY_INTERRUPT } else { CATEGORY_SUBROUTINE }.into()),
+        ..Default::default()
+    });
+    slice
+}
+
+fn close_slice(trace_events: &mut [TraceEvent], slice: RunningSlice, cycle: u64, mclk: f64) {
+    let (ts, dur) = nudged_slice(slice.start_cycle, cycle, slice.depth, mclk);
+    trace_events[slice.index].ts = ts;
+    trace_events[slice.index].dur = dur;
+}
+
+// an interrupt handler along with the subroutines it didn't return from
+fn close_interrupt(trace_events: &mut [TraceEvent], (handler, stack): (RunningSlice, CallStack<RunningSlice>), cycle: u64, mclk: f64) {
+    for (slice, _stack_pointer) in stack {
+        close_slice(trace_events, slice, cycle, mclk);
+    }
+    close_slice(trace_events, handler, cycle, mclk);
+}
+
+// the process of the CPU, with its code and its interrupt handlers on two threads
+fn code_flow_events<'a>(trace_events: &mut Vec<TraceEvent<'a>>, cpu: &AddonCpu, symbols: &'a Symbols, mclk: f64) {
+    trace_events.push(metadata_event("process_name", cpu.pid, ADDON_MAIN_TID, cpu.name.clone()));
+    trace_events.push(metadata_event("thread_name", cpu.pid, ADDON_MAIN_TID, "Main thread".into()));
+    trace_events.push(metadata_event("thread_name", cpu.pid, ADDON_INTERRUPT_TID, "Interrupts".into()));
+    let mut running = RunningSlices { main: CallStack::new(cpu.exit_matching), interrupts: Vec::new() };
+    for packet in cpu.packets {
+        let cycle = (cpu.cycle_to_mclk)(packet.cycle);
+        if let Some(change) = packet.call_stack_change() {
+            if let Some(exit_stack_pointer) = change.exit {
+                for (slice, _stack_pointer) in running.stack().exit(exit_stack_pointer) {
+                    close_slice(trace_events, slice, cycle, mclk);
+                }
+            }
+            if let Some((target_subroutine, stack_pointer)) = change.enter {
+                let slice = open_slice(trace_events, &running, symbols, cpu.pid, target_subroutine, false, cycle);
+                running.stack().enter(slice, stack_pointer);
+            }
+        }
+        match packet.inner {
+            ProfilingPacketInner::InterruptEnter { target_interrupt } => {
+                let handler = open_slice(trace_events, &running, symbols, cpu.pid, target_interrupt, true, cycle);
+                running.interrupts.push((handler, CallStack::new(cpu.exit_matching)));
+            },
+            ProfilingPacketInner::InterruptExit => {
+                if let Some(interrupt) = running.interrupts.pop() {
+                    close_interrupt(trace_events, interrupt, cycle, mclk);
+                }
+            },
+            ProfilingPacketInner::ManualBreakpoint { pc } => {
+                trace_events.push(TraceEvent {
+                    name: symbols.name(pc),
+                    ph: 'i',
+                    ts: cycle_to_us(cycle, mclk),
+                    dur: 0.0,
+                    pid: cpu.pid,
+                    tid: running.tid(),
+                    s: Some('t'),
+                    cat: Some(CATEGORY_MARKER.into()),
+                    ..Default::default()
+                });
+            },
+            _ => {},
+        }
+    }
+    // the ones still running end with the capture
+    let last_cycle = cpu.packets.last().map_or(0, |packet| (cpu.cycle_to_mclk)(packet.cycle) + 1);
+    while let Some(interrupt) = running.interrupts.pop() {
+        close_interrupt(trace_events, interrupt, last_cycle, mclk);
+    }
+    for (slice, _stack_pointer) in running.main {
+        close_slice(trace_events, slice, last_cycle, mclk);
+    }
+}
+
+// each SH2 of the 32X that has packets gets its own process
+pub fn generate_sh2_trace_events<'a>(input: &ParsedProfilingFile, symbols: &'a Symbols) -> Vec<TraceEvent<'a>> {
+    let mut trace_events = Vec::new();
+    let cycle_to_mclk = |cycle| input.sh2_cycle_to_mclk(cycle);
+    for cpu in Sh2Cpu::ALL {
+        let packets = &input.sh2_packets[cpu.index()];
+        if !packets.is_empty() {
+            let cpu = AddonCpu { packets, pid: cpu.pid(), name: cpu.name().into(), cycle_to_mclk: &cycle_to_mclk, exit_matching: ExitMatching::Innermost };
+            code_flow_events(&mut trace_events, &cpu, symbols, input.mclk);
+        }
+    }
+    trace_events
+}
+
+// the sub CPU of the Mega CD gets its own process, with a thread showing which CPU owns the word RAM:
+// a CPU waiting for the word RAM to be handed over is stalled for as long as the other one holds it
+pub fn generate_sub_cpu_trace_events<'a>(input: &ParsedProfilingFile, symbols: &'a Symbols) -> Vec<TraceEvent<'a>> {
+    let mut trace_events = Vec::new();
+    let word_ram_packets: Vec<(u64, WordRamMode)> = input.packets.iter().filter_map(|packet| match packet.inner {
+        ProfilingPacketInner::WordRam { mode } => Some((packet.cycle, mode)),
+        _ => None,
+    }).collect();
+    if input.sub_cpu_packets.is_empty() && word_ram_packets.is_empty() {
+        return trace_events;
+    }
+    let cycle_to_mclk = |cycle| input.sub_cpu_cycle_to_mclk(cycle);
+    let name = format!("Mega CD sub CPU ({} MHz)", input.sub_cpu_mclk() / input.sub_cpu_divider() as f64 / 1_000_000.0);
+    let cpu = AddonCpu { packets: &input.sub_cpu_packets, pid: SUB_CPU_PID, name, cycle_to_mclk: &cycle_to_mclk, exit_matching: input.exit_matching };
+    code_flow_events(&mut trace_events, &cpu, symbols, input.mclk);
+    if !word_ram_packets.is_empty() {
+        trace_events.push(metadata_event("thread_name", SUB_CPU_PID, WORD_RAM_TID, "Word RAM".into()));
+        let last_cycle = input.packets.last().map_or(0, |packet| packet.cycle + 1);
+        for (index, &(start_cycle, mode)) in word_ram_packets.iter().enumerate() {
+            let end_cycle = word_ram_packets.get(index + 1).map_or(last_cycle, |&(cycle, _mode)| cycle);
+            let (ts, dur) = nudged_slice(start_cycle, end_cycle, 0, input.mclk);
+            trace_events.push(TraceEvent {
+                name: mode.name().into(),
+                ph: 'X',
+                ts,
+                dur,
+                pid: SUB_CPU_PID,
+                tid: WORD_RAM_TID,
+                cat: Some(CATEGORY_WORD_RAM.into()),
+                ..Default::default()
+            });
+        }
+    }
+    trace_events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::MdpBuilder;
+
+    #[test]
+    fn sub_cpu_tail_call_outlives_the_subroutines_it_calls() {
+        // 0x200 jumps to 0x300, whose call to 0x400 must not end it when it returns
+        let mut builder = MdpBuilder::default();
+        builder.call(0x200).wait(100);
+        builder.tail_call(0x300).unwrap().wait(100).call(0x400).wait(100);
+        builder.ret().unwrap().wait(100).ret().unwrap();
+        let mut capture = builder.build();
+        capture.sub_cpu_packets = std::mem::take(&mut capture.packets);
+        let symbols = Symbols::default();
+        let trace_events = generate_sub_cpu_trace_events(&capture, &symbols);
+        let slice = |address: u32| {
+            let name = symbols.name(address);
+            let trace_event = trace_events.iter().find(|trace_event| trace_event.ph == 'X' && trace_event.name == name).unwrap();
+            (trace_event.ts, trace_event.ts + trace_event.dur)
+        };
+        let (target_start, target_end) = slice(0x300);
+        let (callee_start, callee_end) = slice(0x400);
+        assert!(target_start < callee_start && callee_end < target_end);
+        let last_cycle = capture.sub_cpu_cycle_to_mclk(capture.sub_cpu_packets.last().unwrap().cycle);
+        assert!((target_end - cycle_to_us(last_cycle, capture.mclk)).abs() < 0.01);
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use crate::{frames::{frame_cycle_range, FrameSource}, profiling::{cycle_to_us, parse_time, us_to_cycle, ParsedProfilingFile, TraceEvent, TraceEventArgs, CATEGORY_ANNOTATION, FIRST_CUSTOM_TID}};
+
+const ANNOTATIONS_THREAD: &str = "Annotations";
+
+// when an annotation happened, resolved once the capture is known
+#[derive(Debug, Clone, PartialEq)]
+enum AnnotationTime {
+    // "frame 1412"
+    Frame(usize),
+    // "2:05", "1:02:05.5", in seconds
+    Clock(f64),
+    // anything parse_time reads: "125s", "1500ms", "12000us"...
+    Time(String),
+}
+
+impl AnnotationTime {
+    fn parse(time: &str) -> Result<AnnotationTime, String> {
+        // notes taken while playing are approximate anyway
+        let time = time.trim().trim_start_matches('~').trim();
+        if let Some(frame) = time.strip_prefix("frame") {
+            return frame.trim().parse().map(AnnotationTime::Frame).map_err(|_| format!("Invalid frame: {}", time));
+        }
+        if time.contains(':') {
+            let parts: Option<Vec<f64>> = time.split(':').map(|part| part.trim().parse().ok().filter(|part: &f64| part.is_finite() && *part >= 0.0)).collect();
+            return match parts {
+                Some(parts) if parts.len() <= 3 => Ok(AnnotationTime::Clock(parts.iter().fold(0.0, |seconds, part| seconds * 60.0 + part))),
+                _ => Err(format!("Invalid time: {}, expected MINUTES:SECONDS or HOURS:MINUTES:SECONDS", time)),
+            };
+        }
+        Ok(AnnotationTime::Time(time.to_string()))
+    }
+
+    fn cycle(&self, input: &ParsedProfilingFile, frame_source: FrameSource) -> Result<u64, String> {
+        match self {
+            AnnotationTime::Frame(frame) => frame_cycle_range(input, frame_source, *frame, None).map(|(from, _to)| from),
+            AnnotationTime::Clock(seconds) => Ok(us_to_cycle(seconds * 1_000_000.0, input.mclk)),
+            AnnotationTime::Time(time) => parse_time(time, input.mclk),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Annotation {
+    time: AnnotationTime,
+    label: String,
+    duration: Option<String>,
+}
+
+// notes taken during a capture session, drawn on their own thread
+#[derive(Debug, Clone, Default)]
+pub struct Annotations {
+    annotations: Vec<Annotation>,
+    tid: u32,
+}
+
+// the label is quoted when it contains commas, a quote in it being doubled
+fn split_label(fields: &str) -> Result<(String, Option<&str>), String> {
+    let fields = fields.trim();
+    let quoted = match fields.strip_prefix('"') {
+        Some(quoted) => quoted,
+        None => return Ok(match fields.split_once(',') {
+            Some((label, duration)) => (label.trim().to_string(), Some(duration)),
+            None => (fields.to_string(), None),
+        }),
+    };
+    let mut label = String::new();
+    let mut chars = quoted.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        if c != '"' {
+            label.push(c);
+        } else if chars.peek().map(|&(_index, c)| c) == Some('"') {
+            label.push('"');
+            chars.next();
+        } else {
+            let rest = quoted[index + 1..].trim();
+            return match rest.strip_prefix(',') {
+                Some(duration) => Ok((label, Some(duration))),
+                None if rest.is_empty() => Ok((label, None)),
+                None => Err(format!("Unexpected {} after the quoted label", rest)),
+            };
+        }
+    }
+    Err("Unterminated quoted label".to_string())
+}
+
+impl Annotations {
+    // one WHEN,LABEL[,DURATION] line per annotation, WHEN being a time from the start of the capture or "frame N"
+    pub fn read(input: &[u8]) -> Result<Annotations, String> {
+        let input = String::from_utf8_lossy(input);
+        let mut annotations = Vec::new();
+        for line in input.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |err: String| format!("Invalid annotation: {}, {}", line, err);
+            let (time, fields) = line.split_once(',').ok_or_else(|| error("expected WHEN,LABEL[,DURATION]".to_string()))?;
+            let (label, duration) = split_label(fields).map_err(error)?;
+            if duration.is_some_and(|duration| duration.contains(',')) {
+                return Err(error("quote the label if it contains commas".to_string()));
+            }
+            annotations.push(Annotation {
+                time: AnnotationTime::parse(time).map_err(error)?,
+                label,
+                duration: duration.map(|duration| duration.trim().to_string()),
+            });
+        }
+        Ok(Annotations { annotations, tid: 0 })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.annotations.is_empty()
+    }
+
+    // the annotations get a thread after the custom threads of the interval file
+    pub fn assign_thread(&mut self, custom_threads: &mut HashMap<String, u32>) {
+        let new_tid = custom_threads.values().max().map_or(FIRST_CUSTOM_TID, |tid| tid + 1);
+        self.tid = *custom_threads.entry(ANNOTATIONS_THREAD.to_string()).or_insert(new_tid);
+    }
+
+    // an instant per annotation, or a slice for those with a duration, the ones outside of the capture are left out
+    pub fn trace_events<'a>(&self, input: &ParsedProfilingFile, frame_source: FrameSource) -> Result<Vec<TraceEvent<'a>>, String> {
+        let (first_cycle, last_cycle) = match (input.packets.first(), input.packets.last()) {
+            (Some(first), Some(last)) => (first.cycle, last.cycle),
+            _ => return Ok(Vec::new()),
+        };
+        let mut trace_events = Vec::new();
+        for annotation in &self.annotations {
+            let error = |err: String| format!("Invalid annotation {}: {}", annotation.label, err);
+            let start_cycle = annotation.time.cycle(input, frame_source).map_err(error)?;
+            if start_cycle < first_cycle || start_cycle > last_cycle {
+                eprintln!("Warning: annotation {} at {:.3} ms is outside of the capture", annotation.label, cycle_to_us(start_cycle, input.mclk) / 1000.0);
+                continue;
+            }
+            let duration_cycles = annotation.duration.as_deref().map(|duration| parse_time(duration, input.mclk)).transpose().map_err(error)?;
+            trace_events.push(TraceEvent {
+                name: annotation.label.clone().into(),
+                ph: if duration_cycles.is_some() { 'X' } else { 'i' },
+                ts: cycle_to_us(start_cycle, input.mclk),
+                dur: duration_cycles.map_or(0.0, |duration_cycles| cycle_to_us(duration_cycles, input.mclk)),
+                pid: 0,
+                tid: self.tid,
+                args: Some(TraceEventArgs {
+                    start_cycle: Some(start_cycle),
+                    ..Default::default()
+                }),
+                s: duration_cycles.is_none().then_some('t'),
+                cat: Some(CATEGORY_ANNOTATION.into()),
+                ..Default::default()
+            });
+        }
+        Ok(trace_events)
+    }
+}
@@ -0,0 +1,81 @@
+use std::io::Write;
+
+use crate::{frames::percentile, profiling::*, report::DurationFormat};
+
+// a pass from the start point of a benchmark to its end point
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Traversal {
+    pub start_cycle: u64,
+    pub end_cycle: u64,
+}
+
+impl Traversal {
+    pub fn cycles(&self) -> u64 {
+        self.end_cycle - self.start_cycle
+    }
+}
+
+// the address a packet shows the CPU reached: a breakpoint, or the start of a subroutine or handler
+fn reached_address(packet: &ProfilingPacketInner) -> Option<u32> {
+    match *packet {
+        ProfilingPacketInner::ManualBreakpoint { pc } => Some(pc),
+        ProfilingPacketInner::SubroutineEnter { target_subroutine, .. }
+        | ProfilingPacketInner::TailCall { target_subroutine } => Some(target_subroutine),
+        ProfilingPacketInner::InterruptEnter { target_interrupt } => Some(target_interrupt),
+        ProfilingPacketInner::TrapEnter { handler, .. } => Some(handler),
+        _ => None,
+    }
+}
+
+// every traversal from the start point to the next end point, and how many times the start point was reached again
+// before the end point, in which case the traversal starts over. The start and end points can be the same address,
+// to measure from one pass to the next
+pub fn traversals(input: &ParsedProfilingFile, start: u32, end: u32) -> (Vec<Traversal>, usize) {
+    let mut traversals = Vec::new();
+    let mut restarts = 0;
+    let mut start_cycle = None;
+    for packet in &input.packets {
+        let address = match reached_address(&packet.inner) {
+            Some(address) => address,
+            None => continue,
+        };
+        if address == end {
+            if let Some(start_cycle) = start_cycle.take() {
+                traversals.push(Traversal { start_cycle, end_cycle: packet.cycle });
+            }
+        }
+        if address == start && start_cycle.replace(packet.cycle).is_some() {
+            restarts += 1;
+        }
+    }
+    (traversals, restarts)
+}
+
+// the statistics of the traversals, with when the best and the worst ones happened
+pub fn write_bench_report(mut output: impl Write, start_name: &str, end_name: &str, traversals: &[Traversal], restarts: usize, duration_format: &DurationFormat) -> std::io::Result<()> {
+    if traversals.is_empty() {
+        return writeln!(output, "{} was never followed by {} in the capture", start_name, end_name);
+    }
+    let mut durations: Vec<u64> = traversals.iter().map(Traversal::cycles).collect();
+    durations.sort_unstable();
+    let count = durations.len() as f64;
+    let mean = durations.iter().sum::<u64>() as f64 / count;
+    let variance = durations.iter().map(|&cycles| (cycles as f64 - mean).powi(2)).sum::<f64>() / count;
+    let duration = |cycles: u64| duration_format.format(cycles as f64);
+    let at = |traversal: &Traversal| cycle_to_us(traversal.start_cycle, duration_format.mclk) / 1000.0;
+    // the first of the fastest and of the slowest traversals
+    let best = traversals.iter().min_by_key(|traversal| (traversal.cycles(), traversal.start_cycle)).unwrap();
+    let worst = traversals.iter().max_by_key(|traversal| (traversal.cycles(), std::cmp::Reverse(traversal.start_cycle))).unwrap();
+    writeln!(output, "{} to {}: {} iterations", start_name, end_name, durations.len())?;
+    writeln!(output, "  mean:   {}", duration_format.format(mean))?;
+    writeln!(output, "  stddev: {}", duration_format.format(variance.sqrt()))?;
+    writeln!(output, "  best:   {} at {:.3} ms", duration(best.cycles()), at(best))?;
+    writeln!(output, "  p50:    {}", duration(percentile(&durations, 50)))?;
+    writeln!(output, "  p90:    {}", duration(percentile(&durations, 90)))?;
+    writeln!(output, "  p99:    {}", duration(percentile(&durations, 99)))?;
+    writeln!(output, "  worst:  {} at {:.3} ms", duration(worst.cycles()), at(worst))?;
+    if restarts != 0 {
+        writeln!(output, "{} passes through {} didn't reach {} before the next one, they were left out", restarts, start_name, end_name)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use crate::{profiling::*, symbols::Symbols};
+
+// the stack pointer of the main code at the start of a synthetic capture, the top of the 68000 RAM
+const INITIAL_STACK_POINTER: u32 = 0x0100_0000;
+// the return address, and for interrupts the status register, pushed on the stack
+const CALL_FRAME_SIZE: u32 = 4;
+const INTERRUPT_FRAME_SIZE: u32 = 6;
+
+// builds a capture packet by packet, as the emulator would record it, to test interval files or the converter without
+// an emulator: the cycles and stack pointers are kept consistent, time only moves forward with wait
+pub struct MdpBuilder {
+    capture: ParsedProfilingFile,
+    // in master clock cycles
+    cycle: u64,
+    stack_pointer: u32,
+    // stack pointers to restore on return, for the subroutines and interrupts running
+    frames: Vec<(u32, bool)>,
+}
+
+impl Default for MdpBuilder {
+    fn default() -> MdpBuilder {
+        MdpBuilder::new(DEFAULT_MCLK, DEFAULT_M68K_DIVIDER)
+    }
+}
+
+impl MdpBuilder {
+    pub fn new(mclk: f64, m68k_divider: u64) -> MdpBuilder {
+        MdpBuilder {
+            capture: ParsedProfilingFile {
+                packets: Vec::new(),
+                mclk,
+                m68k_divider,
+                sh2_packets: Default::default(),
+                sh2_clock: None,
+                sub_cpu_packets: Vec::new(),
+                sub_cpu_mclk: None,
+                sub_cpu_divider: None,
+                domain_clocks: Default::default(),
+                domain_packets: Vec::new(),
+                symbol_file_hash: None,
+                capture_info: CaptureInfo::default(),
+                exit_matching: ExitMatching::default(),
+            },
+            cycle: 0,
+            stack_pointer: INITIAL_STACK_POINTER,
+            frames: Vec::new(),
+        }
+    }
+
+    // so the converter doesn't warn about the symbol file not matching the capture
+    pub fn symbol_file_hash(&mut self, hash: u64) -> &mut Self {
+        self.capture.symbol_file_hash = Some(hash);
+        self
+    }
+
+    // in 68000 cycles
+    pub fn wait(&mut self, cycles: u64) -> &mut Self {
+        self.cycle += cycles * self.capture.m68k_divider;
+        self
+    }
+
+    fn packet(&mut self, inner: ProfilingPacketInner) -> &mut Self {
+        self.capture.packets.push(ProfilingPacket {
+            cycle: self.cycle,
+            stack_pointer: self.stack_pointer,
+            inner,
+        });
+        self
+    }
+
+    // JSR/BSR to a subroutine
+    pub fn call(&mut self, target_subroutine: u32) -> &mut Self {
+        self.packet(ProfilingPacketInner::SubroutineEnter { target_subroutine, registers: Vec::new() });
+        self.frames.push((self.stack_pointer, false));
+        self.stack_pointer -= CALL_FRAME_SIZE;
+        self
+    }
+
+    // RTS from the running subroutine, the stack pointer is the one the subroutine was entered with
+    pub fn ret(&mut self) -> Result<&mut Self, String> {
+        match self.frames.pop() {
+            Some((stack_pointer, false)) => {
+                self.packet(ProfilingPacketInner::SubroutineExit);
+                self.stack_pointer = stack_pointer;
+                Ok(self)
+            },
+            Some((_stack_pointer, true)) => Err("Returning from a subroutine while the running code is an interrupt handler".to_string()),
+            None => Err("Returning from a subroutine while none is running".to_string()),
+        }
+    }
+
+    // JMP from the running subroutine to another one, which returns to the caller in its place
+    pub fn tail_call(&mut self, target_subroutine: u32) -> Result<&mut Self, String> {
+        if !matches!(self.frames.last(), Some((_stack_pointer, false))) {
+            return Err("Tail call while no subroutine is running".to_string());
+        }
+        Ok(self.packet(ProfilingPacketInner::TailCall { target_subroutine }))
+    }
+
+    // the 68000 takes an interrupt and starts running its handler
+    pub fn interrupt(&mut self, target_interrupt: u32) -> &mut Self {
+        self.packet(ProfilingPacketInner::InterruptEnter { target_interrupt });
+        self.frames.push((self.stack_pointer, true));
+        self.stack_pointer -= INTERRUPT_FRAME_SIZE;
+        self
+    }
+
+    // RTE from the running interrupt handler, the subroutines it called must have returned
+    pub fn interrupt_return(&mut self) -> Result<&mut Self, String> {
+        match self.frames.pop() {
+            Some((stack_pointer, true)) => {
+                self.packet(ProfilingPacketInner::InterruptExit);
+                self.stack_pointer = stack_pointer;
+                Ok(self)
+            },
+            Some((_stack_pointer, false)) => Err("Returning from an interrupt while a subroutine it called is still running".to_string()),
+            None => Err("Returning from an interrupt while none is running".to_string()),
+        }
+    }
+
+    // the VDP asserts a VInt, its handler only runs once interrupt is called
+    pub fn vint(&mut self) -> &mut Self {
+        self.packet(ProfilingPacketInner::VInt)
+    }
+
+    pub fn hint(&mut self) -> &mut Self {
+        self.packet(ProfilingPacketInner::HInt)
+    }
+
+    pub fn breakpoint(&mut self, pc: u32) -> &mut Self {
+        self.packet(ProfilingPacketInner::ManualBreakpoint { pc })
+    }
+
+    pub fn savestate(&mut self, path: &str) -> &mut Self {
+        self.packet(ProfilingPacketInner::Savestate { path: path.to_string() })
+    }
+
+    // the capture, whatever is still running when it ends is left running
+    pub fn build(self) -> ParsedProfilingFile {
+        self.capture
+    }
+
+    // the capture in the mdp format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_profiling_file(&mut bytes, &self.capture);
+        bytes
+    }
+
+    // runs a description of a capture, one command per line, "#" starting a comment:
+    // "wait CYCLES" (68000 cycles), "call TARGET", "return", "tail-call TARGET", "interrupt TARGET", "interrupt-return",
+    // "vint", "hint", "breakpoint TARGET", "savestate PATH", and "repeat COUNT" ... "end" blocks,
+    // targets being labels of the symbol file or hex addresses
+    pub fn run_description(&mut self, description: &str, symbols: &Symbols) -> Result<&mut Self, String> {
+        let lines: Vec<(usize, &str)> = description.lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.split('#').next().unwrap().trim()))
+            .filter(|(_line_number, line)| !line.is_empty())
+            .collect();
+        self.run_lines(&lines, symbols)?;
+        Ok(self)
+    }
+
+    fn run_lines(&mut self, lines: &[(usize, &str)], symbols: &Symbols) -> Result<(), String> {
+        let mut index = 0;
+        while index < lines.len() {
+            let (line_number, line) = lines[index];
+            let error = |err: String| format!("Line {}: {}", line_number, err);
+            let (command, argument) = line.split_once(char::is_whitespace).map_or((line, ""), |(command, argument)| (command, argument.trim()));
+            let target = || symbols.resolve(argument).ok_or_else(|| error(format!("{} not found in the symbol file", argument)));
+            match command {
+                "wait" => {
+                    let cycles = argument.parse().map_err(|_| error(format!("Invalid cycle count: {}", argument)))?;
+                    self.wait(cycles);
+                },
+                "call" => { self.call(target()?); },
+                "return" => { self.ret().map_err(error)?; },
+                "tail-call" => { self.tail_call(target()?).map_err(error)?; },
+                "interrupt" => { self.interrupt(target()?); },
+                "interrupt-return" => { self.interrupt_return().map_err(error)?; },
+                "vint" => { self.vint(); },
+                "hint" => { self.hint(); },
+                "breakpoint" => { self.breakpoint(target()?); },
+                "savestate" => { self.savestate(argument); },
+                "repeat" => {
+                    let count: usize = argument.parse().map_err(|_| error(format!("Invalid repeat count: {}", argument)))?;
+                    // the matching end, repeat blocks can be nested
+                    let mut depth = 0;
+                    let end = lines[index + 1..].iter().position(|&(_line_number, line)| {
+                        match line.split_whitespace().next() {
+                            Some("repeat") => depth += 1,
+                            Some("end") if depth == 0 => return true,
+                            Some("end") => depth -= 1,
+                            _ => {},
+                        }
+                        false
+                    }).ok_or_else(|| error("repeat without a matching end".to_string()))? + index + 1;
+                    for _ in 0..count {
+                        self.run_lines(&lines[index + 1..end], symbols)?;
+                    }
+                    index = end;
+                },
+                "end" => return Err(error("end without a matching repeat".to_string())),
+                _ => return Err(error(format!("Unknown command: {}", command))),
+            }
+            index += 1;
+        }
+        Ok(())
+    }
+}
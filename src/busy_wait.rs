@@ -0,0 +1,202 @@
+use std::{collections::HashMap, io::Write};
+
+use crate::{profiling::*, report::DurationFormat, symbols::Symbols};
+
+// polls in a row before a run counts as a busy wait
+const MIN_POLLS: u64 = 8;
+// 68000 cycles a polled function (reading the VDP status, a flag...) lasts at most, and between two of its calls
+const MAX_POLL_CYCLES: u64 = 500;
+// bytes of code a spinning loop spans at most
+const MAX_LOOP_BYTES: u32 = 32;
+
+// where the code seems to spin, waiting for something
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BusyWaitSite {
+    // a short function called again and again from the same place, caller being None at the top of a thread
+    PolledFunction { caller: Option<u32>, function: u32 },
+    // the PC samples or breakpoints kept landing in these few bytes, end included
+    Loop { start: u32, end: u32 },
+}
+
+impl BusyWaitSite {
+    pub fn description(self, symbols: &Symbols) -> String {
+        match self {
+            BusyWaitSite::PolledFunction { caller: Some(caller), function } => format!("{} polled by {}", symbols.name(function), symbols.name(caller)),
+            BusyWaitSite::PolledFunction { caller: None, function } => format!("{} polled", symbols.name(function)),
+            BusyWaitSite::Loop { start, end } => format!("loop at {} to {}", symbols.name(start), symbols.name(end)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BusyWait {
+    // how many times the code started spinning there
+    pub runs: u64,
+    // calls, samples or breakpoint hits of all the runs
+    pub polls: u64,
+    pub longest_run: u64,
+    // from the first poll of each run to its last one
+    pub cycles: u64,
+}
+
+// consecutive short calls of one function at one stack depth
+#[derive(Debug, Clone, Copy)]
+struct CallRun {
+    function: u32,
+    caller: Option<u32>,
+    stack_pointer: u32,
+    start_cycle: u64,
+    last_exit_cycle: u64,
+    calls: u64,
+}
+
+// consecutive PC samples or breakpoints without any enter or exit between them
+#[derive(Debug, Clone, Copy)]
+struct PcRun {
+    start: u32,
+    end: u32,
+    start_cycle: u64,
+    last_cycle: u64,
+    hits: u64,
+}
+
+// the main code or an interrupt handler
+#[derive(Debug)]
+struct Context {
+    // running subroutines, with the cycle they were entered at
+    stack: CallStack<(u32, u64)>,
+    call_run: Option<CallRun>,
+    pc_run: Option<PcRun>,
+}
+
+fn record(busy_waits: &mut HashMap<BusyWaitSite, BusyWait>, site: BusyWaitSite, polls: u64, cycles: u64) {
+    if polls < MIN_POLLS {
+        return;
+    }
+    let busy_wait = busy_waits.entry(site).or_default();
+    busy_wait.runs += 1;
+    busy_wait.polls += polls;
+    busy_wait.longest_run = busy_wait.longest_run.max(polls);
+    busy_wait.cycles += cycles;
+}
+
+impl Context {
+    fn new(exit_matching: ExitMatching) -> Context {
+        Context { stack: CallStack::new(exit_matching), call_run: None, pc_run: None }
+    }
+
+    fn end_call_run(&mut self, busy_waits: &mut HashMap<BusyWaitSite, BusyWait>) {
+        if let Some(run) = self.call_run.take() {
+            record(busy_waits, BusyWaitSite::PolledFunction { caller: run.caller, function: run.function }, run.calls, run.last_exit_cycle - run.start_cycle);
+        }
+    }
+
+    fn end_pc_run(&mut self, busy_waits: &mut HashMap<BusyWaitSite, BusyWait>) {
+        if let Some(run) = self.pc_run.take() {
+            record(busy_waits, BusyWaitSite::Loop { start: run.start, end: run.end }, run.hits, run.last_cycle - run.start_cycle);
+        }
+    }
+
+    // a call returned, it continues the run of calls if it's short and made right after the previous one from the same place
+    fn exited(&mut self, ((function, enter_cycle), stack_pointer): ((u32, u64), u32), cycle: u64, max_cycles: u64, busy_waits: &mut HashMap<BusyWaitSite, BusyWait>) {
+        if cycle - enter_cycle > max_cycles {
+            self.end_call_run(busy_waits);
+            return;
+        }
+        let caller = self.stack.last().map(|&((caller, _enter_cycle), _stack_pointer)| caller);
+        match &mut self.call_run {
+            Some(run) if run.function == function && run.stack_pointer == stack_pointer && run.caller == caller && enter_cycle - run.last_exit_cycle <= max_cycles => {
+                run.calls += 1;
+                run.last_exit_cycle = cycle;
+            },
+            _ => {
+                self.end_call_run(busy_waits);
+                self.call_run = Some(CallRun { function, caller, stack_pointer, start_cycle: enter_cycle, last_exit_cycle: cycle, calls: 1 });
+            },
+        }
+    }
+
+    fn reached(&mut self, pc: u32, cycle: u64, busy_waits: &mut HashMap<BusyWaitSite, BusyWait>) {
+        match &mut self.pc_run {
+            Some(run) if run.end.max(pc) - run.start.min(pc) < MAX_LOOP_BYTES => {
+                run.start = run.start.min(pc);
+                run.end = run.end.max(pc);
+                run.last_cycle = cycle;
+                run.hits += 1;
+            },
+            _ => {
+                self.end_pc_run(busy_waits);
+                self.pc_run = Some(PcRun { start: pc, end: pc, start_cycle: cycle, last_cycle: cycle, hits: 1 });
+            },
+        }
+    }
+}
+
+// the places the code of the capture spun at, waiting for the VDP, a flag set by an interrupt... found from runs of short calls
+// to the same function, and from PC samples or breakpoints staying within a few bytes, longest first
+pub fn find_busy_waits(input: &ParsedProfilingFile) -> Vec<(BusyWaitSite, BusyWait)> {
+    let max_cycles = MAX_POLL_CYCLES * input.m68k_divider;
+    let mut busy_waits = HashMap::new();
+    let mut main = Context::new(input.exit_matching);
+    let mut interrupt = Context::new(input.exit_matching);
+    let mut in_interrupt = false;
+    for packet in &input.packets {
+        let context = if in_interrupt { &mut interrupt } else { &mut main };
+        if let Some(change) = packet.call_stack_change() {
+            context.end_pc_run(&mut busy_waits);
+            if let Some(exit_stack_pointer) = change.exit {
+                for _ in 0..context.stack.returning(exit_stack_pointer) {
+                    let call = context.stack.pop().unwrap();
+                    context.exited(call, packet.cycle, max_cycles, &mut busy_waits);
+                }
+            }
+            if let Some((target_subroutine, stack_pointer)) = change.enter {
+                context.stack.enter((target_subroutine, packet.cycle), stack_pointer);
+            }
+        }
+        match packet.inner {
+            ProfilingPacketInner::InterruptEnter { target_interrupt } => {
+                in_interrupt = true;
+                interrupt = Context::new(input.exit_matching);
+                // the handler returns with its interrupt exit, never with a subroutine exit
+                interrupt.stack.enter((target_interrupt, packet.cycle), u32::MAX);
+            },
+            ProfilingPacketInner::InterruptExit => {
+                interrupt.end_call_run(&mut busy_waits);
+                interrupt.end_pc_run(&mut busy_waits);
+                in_interrupt = false;
+            },
+            ProfilingPacketInner::PcSample { pc } | ProfilingPacketInner::ManualBreakpoint { pc } => context.reached(pc, packet.cycle, &mut busy_waits),
+            _ => {},
+        }
+    }
+    for context in [&mut main, &mut interrupt] {
+        context.end_call_run(&mut busy_waits);
+        context.end_pc_run(&mut busy_waits);
+    }
+    let mut busy_waits: Vec<(BusyWaitSite, BusyWait)> = busy_waits.into_iter().collect();
+    busy_waits.sort_by_key(|(site, busy_wait)| (std::cmp::Reverse(busy_wait.cycles), match *site {
+        BusyWaitSite::PolledFunction { function, .. } => function,
+        BusyWaitSite::Loop { start, .. } => start,
+    }));
+    busy_waits
+}
+
+// the count longest busy waits, with their share of the capture
+pub fn write_busy_waits(mut output: impl Write, busy_waits: &[(BusyWaitSite, BusyWait)], symbols: &Symbols, count: usize, duration_format: &DurationFormat, capture_cycles: u64) -> std::io::Result<()> {
+    if busy_waits.is_empty() {
+        return writeln!(output, "No busy wait found: no function was called {} times in a row in less than {} cycles each, and no {} PC samples or breakpoints in a row stayed within {} bytes", MIN_POLLS, MAX_POLL_CYCLES, MIN_POLLS, MAX_LOOP_BYTES);
+    }
+    writeln!(output, "Busy wait candidates, longest first:")?;
+    for (site, busy_wait) in busy_waits.iter().take(count) {
+        writeln!(output, "  {}: {} ({:.1}% of the capture), {} times, {:.1} polls on average, {} at most",
+            site.description(symbols),
+            duration_format.format_total(busy_wait.cycles as f64),
+            busy_wait.cycles as f64 * 100.0 / capture_cycles.max(1) as f64,
+            busy_wait.runs,
+            busy_wait.polls as f64 / busy_wait.runs as f64,
+            busy_wait.longest_run,
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,350 @@
+use std::{borrow::Cow, collections::BTreeMap, convert::TryInto, io::{self, BufWriter, Write}};
+
+use crate::{builder::MdpBuilder, intervals::IntervalStats, profiling::{register_name, CaptureInfo, ParsedProfilingFile, TraceEvent, TraceEventArgs}, raster::{VideoStandard, VideoTiming}};
+
+// the trace events of a converted capture are saved next to it, game.mdpc for game.mdp,
+// so converting it again with other output options skips the parsing and the matching of the calls
+pub const CACHE_EXTENSION: &str = "mdpc";
+const CACHE_MAGIC: &[u8; 4] = b"MDPC";
+// bumped whenever the layout below changes, the caches of other versions are then converted again
+const CACHE_VERSION: u8 = 3;
+
+// what the conversion needs from a capture besides its trace events
+#[derive(Debug, Clone)]
+pub struct CacheHeader {
+    // of the capture, the symbols and the options it was converted with
+    pub key: u64,
+    pub mclk: f64,
+    pub m68k_divider: u64,
+    pub symbol_file_hash: Option<u64>,
+    pub capture_info: CaptureInfo,
+    pub timing: VideoTiming,
+    pub pid_count: u32,
+    pub interval_stats: Vec<CachedIntervalStats>,
+}
+
+impl CacheHeader {
+    // the capture as far as its header goes, without its packets
+    pub fn capture(&self) -> ParsedProfilingFile {
+        let mut capture = MdpBuilder::new(self.mclk, self.m68k_divider).build();
+        capture.symbol_file_hash = self.symbol_file_hash;
+        capture.capture_info = self.capture_info.clone();
+        capture
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CachedIntervalStats {
+    pub name: String,
+    pub completions: u64,
+    pub unfinished: u64,
+    pub total_cycles: u64,
+    pub min_cycles: Option<u64>,
+    pub max_cycles: Option<u64>,
+}
+
+impl CachedIntervalStats {
+    pub fn from_stats(stats: &IntervalStats) -> CachedIntervalStats {
+        CachedIntervalStats {
+            name: stats.name.to_string(),
+            completions: stats.completions,
+            unfinished: stats.unfinished,
+            total_cycles: stats.total_cycles,
+            min_cycles: stats.min_cycles,
+            max_cycles: stats.max_cycles,
+        }
+    }
+
+    pub fn stats(&self) -> IntervalStats<'_> {
+        IntervalStats {
+            name: &self.name,
+            completions: self.completions,
+            unfinished: self.unfinished,
+            total_cycles: self.total_cycles,
+            min_cycles: self.min_cycles,
+            max_cycles: self.max_cycles,
+        }
+    }
+}
+
+// little endian values, strings prefixed with their length, and optional values with whether they're present
+trait CacheWrite: Write {
+    fn write_u8(&mut self, value: u8) -> io::Result<()> {
+        self.write_all(&[value])
+    }
+
+    fn write_u16(&mut self, value: u16) -> io::Result<()> {
+        self.write_all(&value.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, value: u32) -> io::Result<()> {
+        self.write_all(&value.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, value: u64) -> io::Result<()> {
+        self.write_all(&value.to_le_bytes())
+    }
+
+    fn write_f64(&mut self, value: f64) -> io::Result<()> {
+        self.write_all(&value.to_le_bytes())
+    }
+
+    fn write_str(&mut self, value: &str) -> io::Result<()> {
+        self.write_u32(value.len() as u32)?;
+        self.write_all(value.as_bytes())
+    }
+
+    fn write_option<T>(&mut self, value: Option<T>, write: impl FnOnce(&mut Self, T) -> io::Result<()>) -> io::Result<()> {
+        match value {
+            Some(value) => {
+                self.write_u8(1)?;
+                write(self, value)
+            },
+            None => self.write_u8(0),
+        }
+    }
+}
+
+impl<W: Write> CacheWrite for W {}
+
+struct CacheReader<'d> {
+    input: &'d [u8],
+}
+
+impl<'d> CacheReader<'d> {
+    fn bytes(&mut self, count: usize) -> Result<&'d [u8], String> {
+        if self.input.len() < count {
+            return Err("truncated cache file".to_string());
+        }
+        let (bytes, rest) = self.input.split_at(count);
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.u32()? as usize;
+        String::from_utf8(self.bytes(length)?.to_vec()).map_err(|_| "invalid string in cache file".to_string())
+    }
+
+    fn char(&mut self) -> Result<char, String> {
+        char::from_u32(self.u32()?).ok_or_else(|| "invalid character in cache file".to_string())
+    }
+
+    fn option<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T, String>) -> Result<Option<T>, String> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => read(self).map(Some),
+        }
+    }
+}
+
+fn write_args(output: &mut impl Write, args: &TraceEventArgs) -> io::Result<()> {
+    output.write_option(args.name.as_deref(), CacheWrite::write_str)?;
+    output.write_option(args.sort_index, CacheWrite::write_u32)?;
+    output.write_option(args.source.as_deref(), CacheWrite::write_str)?;
+    output.write_option(args.region.as_deref(), CacheWrite::write_str)?;
+    output.write_option(args.savestate.as_deref(), CacheWrite::write_str)?;
+    output.write_option(args.value, CacheWrite::write_u64)?;
+    output.write_option(args.occurrence, CacheWrite::write_u64)?;
+    output.write_option(args.start_cycle, CacheWrite::write_u64)?;
+    output.write_option(args.end_cycle, CacheWrite::write_u64)?;
+    output.write_option(args.cycles, CacheWrite::write_u64)?;
+    output.write_option(args.interrupt_cycles, CacheWrite::write_u64)?;
+    output.write_option(args.start_pc.as_deref(), CacheWrite::write_str)?;
+    output.write_option(args.end_pc.as_deref(), CacheWrite::write_str)?;
+    output.write_option(args.dma_source.as_deref(), CacheWrite::write_str)?;
+    output.write_option(args.dma_destination.as_deref(), CacheWrite::write_str)?;
+    output.write_option(args.dma_length, CacheWrite::write_u32)?;
+    output.write_option(args.vdp_register, CacheWrite::write_u8)?;
+    output.write_option(args.vdp_value.as_deref(), CacheWrite::write_str)?;
+    output.write_option(args.recursion_depth, CacheWrite::write_u32)?;
+    output.write_option(args.calls, CacheWrite::write_u64)?;
+    output.write_option(args.sound_argument, CacheWrite::write_u16)?;
+    output.write_option(args.heap_address.as_deref(), CacheWrite::write_str)?;
+    output.write_option(args.heap_size, CacheWrite::write_u32)?;
+    output.write_option(args.heap_tag, CacheWrite::write_u16)?;
+    output.write_option(args.labels.as_deref(), CacheWrite::write_str)?;
+    output.write_option(args.frame, CacheWrite::write_u64)?;
+    output.write_option(args.scanline, CacheWrite::write_u64)?;
+    output.write_option(args.latency_cycles, CacheWrite::write_u64)?;
+    output.write_option(args.interrupts, CacheWrite::write_u64)?;
+    output.write_option(args.preempted_cycles, CacheWrite::write_u64)?;
+    // the values of the metadata can be anything json
+    output.write_option(args.metadata.as_ref(), |output, metadata| output.write_str(&serde_json::to_string(metadata).unwrap()))?;
+    output.write_option(args.registers.as_ref(), |output, registers| {
+        output.write_u32(registers.len() as u32)?;
+        for (&name, value) in registers {
+            output.write_u8((0..=u8::MAX).find(|&register| register_name(register) == name).unwrap())?;
+            output.write_str(value)?;
+        }
+        Ok(())
+    })
+}
+
+fn read_args(input: &mut CacheReader) -> Result<TraceEventArgs<'static>, String> {
+    Ok(TraceEventArgs {
+        name: input.option(CacheReader::string)?,
+        sort_index: input.option(CacheReader::u32)?,
+        source: input.option(CacheReader::string)?.map(Cow::Owned),
+        region: input.option(CacheReader::string)?.map(Cow::Owned),
+        savestate: input.option(CacheReader::string)?.map(Cow::Owned),
+        value: input.option(CacheReader::u64)?,
+        occurrence: input.option(CacheReader::u64)?,
+        start_cycle: input.option(CacheReader::u64)?,
+        end_cycle: input.option(CacheReader::u64)?,
+        cycles: input.option(CacheReader::u64)?,
+        interrupt_cycles: input.option(CacheReader::u64)?,
+        start_pc: input.option(CacheReader::string)?,
+        end_pc: input.option(CacheReader::string)?,
+        dma_source: input.option(CacheReader::string)?,
+        dma_destination: input.option(CacheReader::string)?,
+        dma_length: input.option(CacheReader::u32)?,
+        vdp_register: input.option(CacheReader::u8)?,
+        vdp_value: input.option(CacheReader::string)?,
+        recursion_depth: input.option(CacheReader::u32)?,
+        calls: input.option(CacheReader::u64)?,
+        sound_argument: input.option(CacheReader::u16)?,
+        heap_address: input.option(CacheReader::string)?,
+        heap_size: input.option(CacheReader::u32)?,
+        heap_tag: input.option(CacheReader::u16)?,
+        labels: input.option(CacheReader::string)?,
+        frame: input.option(CacheReader::u64)?,
+        scanline: input.option(CacheReader::u64)?,
+        latency_cycles: input.option(CacheReader::u64)?,
+        interrupts: input.option(CacheReader::u64)?,
+        preempted_cycles: input.option(CacheReader::u64)?,
+        metadata: input.option(|input| serde_json::from_str(&input.string()?).map_err(|err| format!("invalid metadata in cache file: {}", err)))?,
+        registers: input.option(|input| {
+            let count = input.u32()?;
+            (0..count).map(|_| Ok((register_name(input.u8()?), input.string()?))).collect::<Result<BTreeMap<_, _>, String>>()
+        })?,
+    })
+}
+
+fn write_event(output: &mut impl Write, trace_event: &TraceEvent) -> io::Result<()> {
+    output.write_str(&trace_event.name)?;
+    output.write_u32(trace_event.ph as u32)?;
+    output.write_f64(trace_event.ts)?;
+    output.write_f64(trace_event.dur)?;
+    output.write_u32(trace_event.pid)?;
+    output.write_u32(trace_event.tid)?;
+    output.write_option(trace_event.args.as_ref(), |output, args| write_args(output, args))?;
+    output.write_option(trace_event.s, |output, s| output.write_u32(s as u32))?;
+    output.write_option(trace_event.cname.as_deref(), CacheWrite::write_str)?;
+    output.write_option(trace_event.id, CacheWrite::write_u64)?;
+    output.write_option(trace_event.cat.as_deref(), CacheWrite::write_str)
+}
+
+fn read_event(input: &mut CacheReader) -> Result<TraceEvent<'static>, String> {
+    Ok(TraceEvent {
+        name: input.string()?.into(),
+        ph: input.char()?,
+        ts: input.f64()?,
+        dur: input.f64()?,
+        pid: input.u32()?,
+        tid: input.u32()?,
+        args: input.option(read_args)?,
+        s: input.option(CacheReader::char)?,
+        cname: input.option(CacheReader::string)?.map(Cow::Owned),
+        id: input.option(CacheReader::u64)?,
+        cat: input.option(CacheReader::string)?.map(Cow::Owned),
+    })
+}
+
+pub fn write_cache(output: impl Write, header: &CacheHeader, trace_events: &[TraceEvent]) -> io::Result<()> {
+    let mut output = BufWriter::new(output);
+    output.write_all(CACHE_MAGIC)?;
+    output.write_u8(CACHE_VERSION)?;
+    output.write_u64(header.key)?;
+    output.write_f64(header.mclk)?;
+    output.write_u64(header.m68k_divider)?;
+    output.write_option(header.symbol_file_hash, CacheWrite::write_u64)?;
+    let capture_info = &header.capture_info;
+    output.write_option(capture_info.rom_name.as_deref(), CacheWrite::write_str)?;
+    output.write_option(capture_info.rom_checksum, CacheWrite::write_u16)?;
+    output.write_option(capture_info.emulator.as_deref(), CacheWrite::write_str)?;
+    output.write_option(capture_info.captured_at, CacheWrite::write_u64)?;
+    output.write_option(capture_info.build_id.as_deref(), |output, build_id| {
+        output.write_u8(build_id.len() as u8)?;
+        output.write_all(build_id)
+    })?;
+    output.write_u8((header.timing.standard == VideoStandard::Pal) as u8)?;
+    output.write_u8(header.timing.v30 as u8)?;
+    output.write_u32(header.pid_count)?;
+    output.write_u32(header.interval_stats.len() as u32)?;
+    for stats in &header.interval_stats {
+        output.write_str(&stats.name)?;
+        output.write_u64(stats.completions)?;
+        output.write_u64(stats.unfinished)?;
+        output.write_u64(stats.total_cycles)?;
+        output.write_option(stats.min_cycles, CacheWrite::write_u64)?;
+        output.write_option(stats.max_cycles, CacheWrite::write_u64)?;
+    }
+    output.write_u64(trace_events.len() as u64)?;
+    for trace_event in trace_events {
+        write_event(&mut output, trace_event)?;
+    }
+    output.flush()
+}
+
+// None when the cache was made from other inputs or options, or by another version
+pub fn read_cache(input: &[u8], key: u64) -> Result<Option<(CacheHeader, Vec<TraceEvent<'static>>)>, String> {
+    let mut input = CacheReader { input };
+    if input.bytes(CACHE_MAGIC.len())? != CACHE_MAGIC {
+        return Err("not a cache file".to_string());
+    }
+    if input.u8()? != CACHE_VERSION || input.u64()? != key {
+        return Ok(None);
+    }
+    let mclk = input.f64()?;
+    let m68k_divider = input.u64()?;
+    let symbol_file_hash = input.option(CacheReader::u64)?;
+    let capture_info = CaptureInfo {
+        rom_name: input.option(CacheReader::string)?,
+        rom_checksum: input.option(CacheReader::u16)?,
+        emulator: input.option(CacheReader::string)?,
+        captured_at: input.option(CacheReader::u64)?,
+        build_id: input.option(|input| {
+            let len = input.u8()? as usize;
+            Ok(input.bytes(len)?.to_vec())
+        })?,
+    };
+    let timing = VideoTiming {
+        standard: if input.u8()? != 0 { VideoStandard::Pal } else { VideoStandard::Ntsc },
+        v30: input.u8()? != 0,
+    };
+    let pid_count = input.u32()?;
+    let interval_count = input.u32()?;
+    let interval_stats = (0..interval_count).map(|_| Ok(CachedIntervalStats {
+        name: input.string()?,
+        completions: input.u64()?,
+        unfinished: input.u64()?,
+        total_cycles: input.u64()?,
+        min_cycles: input.option(CacheReader::u64)?,
+        max_cycles: input.option(CacheReader::u64)?,
+    })).collect::<Result<_, String>>()?;
+    let event_count = input.u64()?;
+    let trace_events = (0..event_count).map(|_| read_event(&mut input)).collect::<Result<_, _>>()?;
+    let header = CacheHeader { key, mclk, m68k_divider, symbol_file_hash, capture_info, timing, pid_count, interval_stats };
+    Ok(Some((header, trace_events)))
+}
@@ -0,0 +1,111 @@
+use std::collections::HashSet;
+
+use crate::{profiling::{cycle_to_us, interrupt_tid, DmaType, ParsedProfilingFile, ProfilingPacketInner, TraceEvent, TraceEventArgs, CATEGORY_DMA, VDP_TID}, symbols::Symbols, tasks::TaskStacks};
+
+// SGDK queues the transfers to the VDP during the frame, and DMA_flushQueue starts them in the order they were queued,
+// usually from the VInt handler
+const QUEUE_FUNCTIONS: [&str; 2] = ["DMA_queueDma", "DMA_queueDmaFast"];
+const FLUSH_FUNCTION: &str = "DMA_flushQueue";
+
+// the functions of SGDK's DMA queue in the symbol file
+#[derive(Debug, Clone)]
+pub struct DmaQueue {
+    queue_functions: HashSet<u32>,
+    flush_function: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DmaQueueStats {
+    pub queued: usize,
+    pub flushes: usize,
+    // the queued calls the emulator recorded the transfer of, and their bytes
+    pub transfers: usize,
+    pub bytes: u64,
+    pub max_flush_bytes: u64,
+}
+
+impl DmaQueue {
+    // None unless the symbols have DMA_flushQueue and one of the functions queuing transfers
+    pub fn from_symbols(symbols: &Symbols) -> Option<DmaQueue> {
+        let labels = symbols.label_to_address();
+        let flush_function = *labels.get(FLUSH_FUNCTION)?;
+        let queue_functions: HashSet<u32> = QUEUE_FUNCTIONS.iter().filter_map(|label| labels.get(*label).copied()).collect();
+        (!queue_functions.is_empty()).then_some(DmaQueue { queue_functions, flush_function })
+    }
+
+    // a flow arrow from each call queuing a transfer to the transfer the next flush started for it, on the VDP thread with
+    // its length in its details, or to the flush itself if the emulator doesn't record the VDP transfers,
+    // and a counter of the bytes each flush transferred
+    pub fn trace_events<'a>(&self, input: &ParsedProfilingFile, split_interrupts: bool, task_stacks: &TaskStacks) -> (Vec<TraceEvent<'a>>, DmaQueueStats) {
+        let mut trace_events = Vec::new();
+        let mut stats = DmaQueueStats::default();
+        // None outside of interrupts, the main code then runs on the thread of its task
+        let mut running_interrupt = None;
+        // index of the packet and thread of the calls queued since the last flush
+        let mut pending: Vec<(usize, u32)> = Vec::new();
+        let flow = |ph: char, cycle: u64, tid: u32, id: usize| TraceEvent {
+            name: "DMA queue".into(),
+            ph,
+            ts: cycle_to_us(cycle, input.mclk),
+            dur: 0.0,
+            pid: 0,
+            tid,
+            id: Some(id as u64),
+            cat: Some(CATEGORY_DMA.into()),
+            ..Default::default()
+        };
+        for (i, packet) in input.packets.iter().enumerate() {
+            let tid = running_interrupt.unwrap_or_else(|| task_stacks.tid(packet.stack_pointer));
+            match packet.inner {
+                ProfilingPacketInner::InterruptEnter { .. } => running_interrupt = Some(interrupt_tid(input, i, split_interrupts)),
+                ProfilingPacketInner::InterruptExit => running_interrupt = None,
+                ProfilingPacketInner::SubroutineEnter { target_subroutine, .. } if self.queue_functions.contains(&target_subroutine) => {
+                    stats.queued += 1;
+                    pending.push((i, tid));
+                },
+                ProfilingPacketInner::SubroutineEnter { target_subroutine, .. } if target_subroutine == self.flush_function => {
+                    stats.flushes += 1;
+                    // the transfers from the 68000 bus the flush started, until the queue is used again
+                    let transfers: Vec<_> = input.packets[i + 1..].iter()
+                        .take_while(|packet| !matches!(packet.inner, ProfilingPacketInner::SubroutineEnter { target_subroutine, .. } if target_subroutine == self.flush_function || self.queue_functions.contains(&target_subroutine)))
+                        .filter_map(|packet| match packet.inner {
+                            ProfilingPacketInner::DmaStart { length, dma_type: DmaType::ToVram | DmaType::ToCram | DmaType::ToVsram, .. } => Some((packet.cycle, length)),
+                            _ => None,
+                        })
+                        .take(pending.len())
+                        .collect();
+                    let flush_bytes: u64 = transfers.iter().map(|&(_cycle, length)| length as u64).sum();
+                    for (transfer, (queued_index, queued_tid)) in pending.drain(..).enumerate() {
+                        let (end_cycle, end_tid) = match transfers.get(transfer) {
+                            Some(&(transfer_cycle, _length)) => (transfer_cycle, VDP_TID),
+                            None => (packet.cycle, tid),
+                        };
+                        trace_events.push(flow('s', input.packets[queued_index].cycle, queued_tid, queued_index));
+                        trace_events.push(flow('f', end_cycle, end_tid, queued_index));
+                    }
+                    if !transfers.is_empty() {
+                        stats.transfers += transfers.len();
+                        stats.bytes += flush_bytes;
+                        stats.max_flush_bytes = stats.max_flush_bytes.max(flush_bytes);
+                        trace_events.push(TraceEvent {
+                            name: "DMA queue flushed (bytes)".into(),
+                            ph: 'C',
+                            ts: cycle_to_us(packet.cycle, input.mclk),
+                            dur: 0.0,
+                            pid: 0,
+                            tid: 0,
+                            args: Some(TraceEventArgs {
+                                value: Some(flush_bytes),
+                                ..Default::default()
+                            }),
+                            cat: Some(CATEGORY_DMA.into()),
+                            ..Default::default()
+                        });
+                    }
+                },
+                _ => {},
+            }
+        }
+        (trace_events, stats)
+    }
+}
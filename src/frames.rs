@@ -0,0 +1,563 @@
+use std::{borrow::Cow, collections::{BTreeMap, HashMap, HashSet}, io::{BufWriter, Write}};
+
+use crate::{intervals::Intervals, profiling::{CallStack, ParsedProfilingFile, ProfilingPacketInner, TraceEvent, TraceEventArgs, CATEGORY_FRAME, CATEGORY_INTERRUPT, cycle_to_us}, raster::VideoTiming, report::DurationFormat, schema::{FrameJson, FunctionTimeJson, SCHEMA_VERSION}, symbols::Symbols};
+
+const WORST_FRAMES_COUNT: usize = 10;
+const TOP_FUNCTIONS_COUNT: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrameSource {
+    // VInt packets
+    #[default]
+    VInt,
+    // InterruptEnter packets targeting this handler
+    Interrupt(u32),
+    // SubroutineEnter or ManualBreakpoint packets at this address
+    Marker(u32),
+}
+
+impl FrameSource {
+    // "vint", "interrupt:<label or hex address>" or "<label or hex address>"
+    pub fn parse(source: &str, symbols: &Symbols) -> Result<FrameSource, String> {
+        if source == "vint" {
+            return Ok(FrameSource::VInt);
+        }
+        if let Some(handler) = source.strip_prefix("interrupt:") {
+            return symbols.resolve(handler)
+                .map(FrameSource::Interrupt)
+                .ok_or_else(|| format!("Frame source interrupt handler {} not found in the symbol file", handler));
+        }
+        symbols.resolve(source)
+            .map(FrameSource::Marker)
+            .ok_or_else(|| format!("Frame source {} not found in the symbol file", source))
+    }
+
+    pub fn is_frame_start(&self, packet: &ProfilingPacketInner) -> bool {
+        match (self, packet) {
+            (FrameSource::VInt, ProfilingPacketInner::VInt) => true,
+            (&FrameSource::Interrupt(handler), &ProfilingPacketInner::InterruptEnter { target_interrupt }) => handler == target_interrupt,
+            (&FrameSource::Marker(address), &ProfilingPacketInner::SubroutineEnter { target_subroutine, .. })
+            | (&FrameSource::Marker(address), &ProfilingPacketInner::TailCall { target_subroutine }) => address == target_subroutine,
+            (&FrameSource::Marker(address), &ProfilingPacketInner::ManualBreakpoint { pc }) => address == pc,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FrameStats {
+    pub index: usize,
+    pub start_cycle: u64,
+    pub end_cycle: u64,
+    pub busy_cycles: u64,
+    // time spent in interrupt handlers
+    pub interrupt_cycles: u64,
+    // time spent in each function, excluding the functions it called and DMA halts
+    pub self_cycles: HashMap<u32, u64>,
+    // time the 68000 was halted by DMA transfers
+    pub dma_halt_cycles: u64,
+    // always true without idle functions
+    pub reached_idle: bool,
+    // VBlanks that went by during this frame on top of the one ending it, given how long a frame lasts
+    pub missed_vblanks: u64,
+    // HInts and VInts asserted during this frame
+    pub hints: u64,
+    pub vints: u64,
+}
+
+impl FrameStats {
+    pub fn cycles(&self) -> u64 {
+        self.end_cycle - self.start_cycle
+    }
+
+    // the game never entered its idle loop during this frame, or it lasted for several frames, so it missed the VBlank
+    pub fn is_lag(&self) -> bool {
+        !self.reached_idle || self.missed_vblanks != 0
+    }
+}
+
+// Frames go from one frame start (see FrameSource) to the next, the partial frames before the first start and after the last one are discarded.
+// Time spent inside the idle functions (on the main thread, interrupts don't count) is idle time, everything else is busy time.
+// A frame lasting for one and a half frames of the video standard or more missed at least one VBlank.
+pub fn compute_frame_stats(input: &ParsedProfilingFile, frame_source: FrameSource, idle_functions: &HashSet<u32>, timing: &VideoTiming) -> Vec<FrameStats> {
+    let frame_cycles = timing.frame_cycles();
+    let mut frames = Vec::new();
+    let mut frame_start: Option<u64> = None;
+    let mut idle_cycles = 0;
+    let mut interrupt_cycles = 0;
+    let mut self_cycles: HashMap<u32, u64> = HashMap::new();
+    let mut dma_halt_cycles = 0;
+    let mut hints = 0;
+    let mut vints = 0;
+    // halted cycles of the last DMA transfer that have yet to elapse
+    let mut pending_dma_halt = 0;
+    let mut reached_idle = idle_functions.is_empty();
+    let mut idle_stack_pointer: Option<u32> = None;
+    let mut in_interrupt = false;
+    // subroutines and interrupt handler running
+    let mut main_stack: CallStack<u32> = CallStack::new(input.exit_matching);
+    let mut interrupt_stack: CallStack<u32> = CallStack::new(input.exit_matching);
+    let mut last_cycle = input.packets.first().map(|packet| packet.cycle).unwrap_or(0);
+    for packet in &input.packets {
+        let elapsed = packet.cycle - last_cycle;
+        if idle_stack_pointer.is_some() && !in_interrupt {
+            idle_cycles += elapsed;
+        }
+        if in_interrupt {
+            interrupt_cycles += elapsed;
+        }
+        let halted = pending_dma_halt.min(elapsed);
+        pending_dma_halt -= halted;
+        dma_halt_cycles += halted;
+        let stack = if in_interrupt { &interrupt_stack } else { &main_stack };
+        if let Some(&(function, _stack_pointer)) = stack.last() {
+            *self_cycles.entry(function).or_default() += elapsed - halted;
+        }
+        last_cycle = packet.cycle;
+        if frame_source.is_frame_start(&packet.inner) {
+            if let Some(start_cycle) = frame_start {
+                let cycles = packet.cycle - start_cycle;
+                frames.push(FrameStats {
+                    index: frames.len(),
+                    start_cycle,
+                    end_cycle: packet.cycle,
+                    busy_cycles: cycles - idle_cycles,
+                    interrupt_cycles,
+                    self_cycles: std::mem::take(&mut self_cycles),
+                    dma_halt_cycles,
+                    reached_idle,
+                    missed_vblanks: ((cycles + frame_cycles / 2) / frame_cycles).saturating_sub(1),
+                    hints,
+                    vints,
+                });
+            }
+            frame_start = Some(packet.cycle);
+            idle_cycles = 0;
+            interrupt_cycles = 0;
+            self_cycles.clear();
+            dma_halt_cycles = 0;
+            hints = 0;
+            vints = 0;
+            // a wait spanning the VInt is still idle
+            reached_idle = idle_functions.is_empty() || idle_stack_pointer.is_some();
+        }
+        let stack = if in_interrupt { &mut interrupt_stack } else { &mut main_stack };
+        if let Some(change) = packet.call_stack_change() {
+            if let Some(exit_stack_pointer) = change.exit {
+                // the idle function returned if it was one of them
+                for (_function, entered) in stack.exit(exit_stack_pointer) {
+                    if !in_interrupt && idle_stack_pointer == Some(entered) {
+                        idle_stack_pointer = None;
+                    }
+                }
+            }
+            if let Some((target_subroutine, stack_pointer)) = change.enter {
+                stack.enter(target_subroutine, stack_pointer);
+                if !in_interrupt && idle_stack_pointer.is_none() && idle_functions.contains(&target_subroutine) {
+                    idle_stack_pointer = Some(stack_pointer);
+                    reached_idle = true;
+                }
+            }
+        }
+        match packet.inner {
+            ProfilingPacketInner::InterruptEnter { target_interrupt } => {
+                in_interrupt = true;
+                interrupt_stack.clear();
+                interrupt_stack.enter(target_interrupt, packet.stack_pointer);
+            },
+            ProfilingPacketInner::InterruptExit => {
+                in_interrupt = false;
+                interrupt_stack.clear();
+            },
+            ProfilingPacketInner::Dma { halted_cycles, .. } => {
+                pending_dma_halt += halted_cycles as u64;
+            },
+            ProfilingPacketInner::HInt => hints += 1,
+            ProfilingPacketInner::VInt => vints += 1,
+            _ => {},
+        }
+    }
+    frames
+}
+
+// parses "first..end", "first..=last" or "first.." into a frame index range with an exclusive end
+pub fn parse_frame_range(range: &str) -> Result<(usize, Option<usize>), String> {
+    let error = || format!("Invalid frame range: {}, expected something like 1200..1260", range);
+    let (first, end) = range.split_once("..").ok_or_else(error)?;
+    let first = first.trim().parse().map_err(|_| error())?;
+    let end = if let Some(last) = end.strip_prefix('=') {
+        Some(last.trim().parse::<usize>().map_err(|_| error())? + 1)
+    } else if end.trim().is_empty() {
+        None
+    } else {
+        Some(end.trim().parse().map_err(|_| error())?)
+    };
+    if matches!(end, Some(end) if end <= first) {
+        return Err(error());
+    }
+    Ok((first, end))
+}
+
+// finds the cycle range (inclusive) covering these frames, frame 0 being the one that starts on the first frame start
+pub fn frame_cycle_range(input: &ParsedProfilingFile, frame_source: FrameSource, first: usize, end: Option<usize>) -> Result<(u64, Option<u64>), String> {
+    let frame_starts: Vec<_> = input.packets.iter()
+        .filter(|packet| frame_source.is_frame_start(&packet.inner))
+        .map(|packet| packet.cycle)
+        .collect();
+    let from = *frame_starts.get(first).ok_or_else(|| format!("Frame {} not found, the capture only has {} frame starts", first, frame_starts.len()))?;
+    // up to the last frame start is up to the end of the capture, past it is most likely a typo
+    if let Some(end) = end.filter(|&end| end > frame_starts.len()) {
+        return Err(format!("Frame {} not found, the capture only has {} frame starts", end - 1, frame_starts.len()));
+    }
+    // the start of the next frame is kept so the last frame is complete,
+    // the end of the capture is the end of the range if there is no next frame
+    let to = end.and_then(|end| frame_starts.get(end)).copied();
+    Ok((from, to))
+}
+
+// a counter of the busy time of each frame in percent of the frame, set at its start, to spot the heavy parts of a long capture at a glance
+pub fn utilization_counter_events<'a>(frames: &[FrameStats], mclk: f64) -> Vec<TraceEvent<'a>> {
+    frames.iter().filter(|frame| frame.cycles() != 0).map(|frame| TraceEvent {
+        name: "CPU utilization (%)".into(),
+        ph: 'C',
+        ts: cycle_to_us(frame.start_cycle, mclk),
+        dur: 0.0,
+        pid: 0,
+        tid: 0,
+        args: Some(TraceEventArgs {
+            value: Some((frame.busy_cycles as f64 * 100.0 / frame.cycles() as f64).round() as u64),
+            ..Default::default()
+        }),
+        cat: Some(CATEGORY_FRAME.into()),
+        ..Default::default()
+    }).collect()
+}
+
+// counters of the HInts and VInts asserted during each frame, set at its start, a frame missing some of its HInts
+// being the sign of a raster effect gone wrong
+pub fn interrupt_counter_events<'a>(frames: &[FrameStats], mclk: f64) -> Vec<TraceEvent<'a>> {
+    let counter = |name: &'static str, frame: &FrameStats, value: u64| TraceEvent {
+        name: name.into(),
+        ph: 'C',
+        ts: cycle_to_us(frame.start_cycle, mclk),
+        dur: 0.0,
+        pid: 0,
+        tid: 0,
+        args: Some(TraceEventArgs {
+            value: Some(value),
+            ..Default::default()
+        }),
+        cat: Some(CATEGORY_INTERRUPT.into()),
+        ..Default::default()
+    };
+    frames.iter()
+        .flat_map(|frame| [counter("HInts per frame", frame, frame.hints), counter("VInts per frame", frame, frame.vints)])
+        .collect()
+}
+
+// the frames with another number of HInts than most frames, and that number
+pub fn unusual_hint_frames(frames: &[FrameStats]) -> (Vec<&FrameStats>, u64) {
+    let mut frame_counts: BTreeMap<u64, usize> = BTreeMap::new();
+    for frame in frames {
+        *frame_counts.entry(frame.hints).or_default() += 1;
+    }
+    let usual_hints = frame_counts.into_iter().max_by_key(|&(hints, count)| (count, std::cmp::Reverse(hints))).map_or(0, |(hints, _count)| hints);
+    (frames.iter().filter(|frame| frame.hints != usual_hints).collect(), usual_hints)
+}
+
+// a red "LAG FRAME" marker at the start of each frame busy for longer than the budget, or which missed the VBlank,
+// on the frame track of Perfetto, to skim a long capture for them
+pub fn over_budget_events<'a>(frames: &[FrameStats], budget_cycles: u64, mclk: f64) -> Vec<TraceEvent<'a>> {
+    frames.iter().filter(|frame| frame.busy_cycles > budget_cycles || frame.is_lag()).map(|frame| TraceEvent {
+        name: "LAG FRAME".into(),
+        ph: 'i',
+        ts: cycle_to_us(frame.start_cycle, mclk),
+        dur: 0.0,
+        pid: 0,
+        tid: 0,
+        args: Some(TraceEventArgs {
+            occurrence: Some(frame.index as u64),
+            start_cycle: Some(frame.start_cycle),
+            end_cycle: Some(frame.end_cycle),
+            cycles: Some(frame.busy_cycles),
+            ..Default::default()
+        }),
+        s: Some('p'),
+        cname: Some("terrible".into()),
+        cat: Some(CATEGORY_FRAME.into()),
+        ..Default::default()
+    }).collect()
+}
+
+// the frame with the most busy time, the first one if several are as busy
+pub fn worst_frame(frames: &[FrameStats]) -> Option<&FrameStats> {
+    frames.iter().min_by_key(|frame| (std::cmp::Reverse(frame.busy_cycles), frame.index))
+}
+
+// a global instant at the start of the worst frame, a line across every track to jump to from the trace viewers
+pub fn worst_frame_event<'a>(frame: &FrameStats, mclk: f64) -> TraceEvent<'a> {
+    TraceEvent {
+        name: format!("Worst frame {}", frame.index).into(),
+        ph: 'i',
+        ts: cycle_to_us(frame.start_cycle, mclk),
+        dur: 0.0,
+        pid: 0,
+        tid: 0,
+        args: Some(TraceEventArgs {
+            start_cycle: Some(frame.start_cycle),
+            end_cycle: Some(frame.end_cycle),
+            cycles: Some(frame.busy_cycles),
+            ..Default::default()
+        }),
+        s: Some('g'),
+        cname: Some("terrible".into()),
+        cat: Some(CATEGORY_FRAME.into()),
+        ..Default::default()
+    }
+}
+
+pub fn percentile(sorted_values: &[u64], percent: usize) -> u64 {
+    let index = (sorted_values.len() - 1) * percent / 100;
+    sorted_values[index]
+}
+
+// the last savestate made before this cycle
+fn savestate_before<'a>(savestates: &[(u64, &'a str)], cycle: u64) -> Option<(u64, &'a str)> {
+    let index = savestates.partition_point(|&(savestate_cycle, _path)| savestate_cycle <= cycle);
+    index.checked_sub(1).map(|index| savestates[index])
+}
+
+// lag frames are only known with idle functions, or when frames don't start on VInts and can last longer than a frame of the video standard
+pub fn write_frame_report(output: &mut impl Write, frames: &[FrameStats], savestates: &[(u64, &str)], duration_format: &DurationFormat, timing: &VideoTiming, detects_lag: bool) {
+    let mut buf_writer = BufWriter::new(output);
+    if frames.is_empty() {
+        writeln!(buf_writer, "No complete frame in the capture").unwrap();
+        return;
+    }
+    let mut busy_cycles: Vec<_> = frames.iter().map(|frame| frame.busy_cycles).collect();
+    busy_cycles.sort_unstable();
+    let total_busy: u64 = busy_cycles.iter().sum();
+    let average_busy = total_busy as f64 / busy_cycles.len() as f64;
+    let ms = |cycles: u64| cycle_to_us(cycles, duration_format.mclk) / 1000.0;
+    let duration = |cycles: u64| duration_format.format(cycles as f64);
+    writeln!(buf_writer, "Video standard: {}, {} lines per frame ({:.3} ms) including {} lines of VBlank ({:.3} ms)",
+        timing.standard.name(),
+        timing.standard.lines_per_frame(),
+        ms(timing.frame_cycles()),
+        timing.vblank_lines(),
+        ms(timing.vblank_cycles()),
+    ).unwrap();
+    writeln!(buf_writer, "Frames: {}", frames.len()).unwrap();
+    writeln!(buf_writer, "Busy time per frame:").unwrap();
+    writeln!(buf_writer, "  min: {}", duration(busy_cycles[0])).unwrap();
+    writeln!(buf_writer, "  avg: {}", duration_format.format(average_busy)).unwrap();
+    writeln!(buf_writer, "  p50: {}", duration(percentile(&busy_cycles, 50))).unwrap();
+    writeln!(buf_writer, "  p90: {}", duration(percentile(&busy_cycles, 90))).unwrap();
+    writeln!(buf_writer, "  p99: {}", duration(percentile(&busy_cycles, 99))).unwrap();
+    writeln!(buf_writer, "  max: {}", duration(busy_cycles[busy_cycles.len() - 1])).unwrap();
+    let total_dma_halt: u64 = frames.iter().map(|frame| frame.dma_halt_cycles).sum();
+    if total_dma_halt != 0 {
+        let max_dma_halt = frames.iter().map(|frame| frame.dma_halt_cycles).max().unwrap();
+        writeln!(buf_writer, "CPU halted by DMA per frame:").unwrap();
+        writeln!(buf_writer, "  avg: {}", duration_format.format(total_dma_halt as f64 / frames.len() as f64)).unwrap();
+        writeln!(buf_writer, "  max: {}", duration(max_dma_halt)).unwrap();
+    }
+    if detects_lag {
+        let lag_frames: Vec<_> = frames.iter().filter(|frame| frame.is_lag()).collect();
+        writeln!(buf_writer, "Lag frames (missed VBlank): {}", lag_frames.len()).unwrap();
+        for frame in lag_frames {
+            write!(buf_writer, "  frame {} at {:.3} ms", frame.index, ms(frame.start_cycle)).unwrap();
+            if frame.missed_vblanks > 1 {
+                write!(buf_writer, ", missed {} VBlanks", frame.missed_vblanks).unwrap();
+            }
+            writeln!(buf_writer).unwrap();
+        }
+    } else {
+        writeln!(buf_writer, "Lag frames (missed VBlank): unknown, use --idle to specify the function waiting for VBlank").unwrap();
+    }
+    let mut worst_frames: Vec<_> = frames.iter().collect();
+    worst_frames.sort_by(|a, b| b.busy_cycles.cmp(&a.busy_cycles).then(a.index.cmp(&b.index)));
+    writeln!(buf_writer, "Worst frames:").unwrap();
+    for frame in worst_frames.iter().take(WORST_FRAMES_COUNT) {
+        writeln!(buf_writer, "  frame {} at {:.3} ms: busy {}{}",
+            frame.index,
+            ms(frame.start_cycle),
+            duration(frame.busy_cycles),
+            if detects_lag && frame.is_lag() { ", LAG" } else { "" },
+        ).unwrap();
+        if let Some((savestate_cycle, path)) = savestate_before(savestates, frame.start_cycle) {
+            writeln!(buf_writer, "    nearest savestate: {} ({:.3} ms before)", path, ms(frame.start_cycle - savestate_cycle)).unwrap();
+        }
+    }
+    if !savestates.is_empty() {
+        writeln!(buf_writer, "Savestates:").unwrap();
+        for (savestate_cycle, path) in savestates {
+            writeln!(buf_writer, "  {} at {:.3} ms", path, ms(*savestate_cycle)).unwrap();
+        }
+    }
+}
+
+// the cycles covered by the occurrences of each manual interval during each frame
+fn interval_occupancy<'a>(frames: &[FrameStats], intervals: &'a Intervals) -> Vec<BTreeMap<&'a str, u64>> {
+    let mut interval_occupancy = vec![BTreeMap::new(); frames.len()];
+    for occurrence in intervals.occurrences() {
+        let first_frame = frames.partition_point(|frame| frame.end_cycle <= occurrence.start_cycle);
+        for (frame, occupancy) in frames[first_frame..].iter().zip(&mut interval_occupancy[first_frame..]) {
+            if frame.start_cycle >= occurrence.end_cycle {
+                break;
+            }
+            let overlap = occurrence.end_cycle.min(frame.end_cycle) - occurrence.start_cycle.max(frame.start_cycle);
+            *occupancy.entry(intervals.interval_name(occurrence.interval)).or_default() += overlap;
+        }
+    }
+    interval_occupancy
+}
+
+// one json object per line and per frame
+pub fn write_frame_jsonl(output: &mut impl Write, frames: &[FrameStats], symbols: &Symbols, intervals: &Intervals, mclk: f64, detects_lag: bool) {
+    let mut buf_writer = BufWriter::new(output);
+    let us = |cycles: u64| cycle_to_us(cycles, mclk);
+    let interval_occupancy = interval_occupancy(frames, intervals).into_iter()
+        .map(|occupancy| occupancy.into_iter().map(|(name, cycles)| (Cow::from(name), us(cycles))).collect::<BTreeMap<_, _>>());
+    for (frame, interval_occupancy) in frames.iter().zip(interval_occupancy) {
+        let mut top_functions: Vec<_> = frame.self_cycles.iter().collect();
+        top_functions.sort_by(|(address_a, cycles_a), (address_b, cycles_b)| cycles_b.cmp(cycles_a).then(address_a.cmp(address_b)));
+        let top_functions = top_functions.into_iter()
+            .take(TOP_FUNCTIONS_COUNT)
+            .map(|(&address, &cycles)| FunctionTimeJson {
+                name: symbols.name(address),
+                self_us: us(cycles),
+            })
+            .collect();
+        let frame_json = FrameJson {
+            schema_version: SCHEMA_VERSION,
+            frame: frame.index,
+            start_ms: us(frame.start_cycle) / 1000.0,
+            duration_us: us(frame.cycles()),
+            busy_us: us(frame.busy_cycles),
+            utilization: frame.busy_cycles as f64 / frame.cycles() as f64,
+            lag: Some(frame.is_lag()).filter(|_| detects_lag),
+            interrupt_us: us(frame.interrupt_cycles),
+            dma_halt_us: us(frame.dma_halt_cycles),
+            top_functions,
+            intervals: interval_occupancy,
+        };
+        serde_json::to_writer(&mut buf_writer, &frame_json).expect("Error writing frame json");
+        writeln!(buf_writer).unwrap();
+    }
+}
+
+// the median over every frame of the cycles of each key (function or interval), the frames without it counting as 0
+fn typical_cycles<K: Copy + Eq + std::hash::Hash>(per_frame: impl Iterator<Item = impl Iterator<Item = (K, u64)>>, frame_count: usize) -> HashMap<K, u64> {
+    let mut values: HashMap<K, Vec<u64>> = HashMap::new();
+    for frame in per_frame {
+        for (key, cycles) in frame {
+            values.entry(key).or_default().push(cycles);
+        }
+    }
+    values.into_iter().map(|(key, mut values)| {
+        values.sort_unstable();
+        let zeros = frame_count - values.len();
+        let index = frame_count / 2;
+        (key, if index < zeros { 0 } else { values[index - zeros] })
+    }).collect()
+}
+
+// what the worst frames spent their time on compared to a typical frame: the functions (self time) and the manual
+// intervals that took the most time above their median over every frame
+pub fn write_frame_attribution(output: &mut impl Write, frames: &[FrameStats], symbols: &Symbols, intervals: &Intervals, duration_format: &DurationFormat, count: usize, detects_lag: bool) {
+    let mut buf_writer = BufWriter::new(output);
+    if frames.is_empty() {
+        writeln!(buf_writer, "No complete frame in the capture").unwrap();
+        return;
+    }
+    let duration = |cycles: u64| duration_format.format(cycles as f64);
+    let mut busy_cycles: Vec<_> = frames.iter().map(|frame| frame.busy_cycles).collect();
+    busy_cycles.sort_unstable();
+    let typical_busy = busy_cycles[busy_cycles.len() / 2];
+    let typical_functions = typical_cycles(frames.iter().map(|frame| frame.self_cycles.iter().map(|(&address, &cycles)| (address, cycles))), frames.len());
+    let interval_occupancy = interval_occupancy(frames, intervals);
+    let typical_intervals = typical_cycles(interval_occupancy.iter().map(|occupancy| occupancy.iter().map(|(&name, &cycles)| (name, cycles))), frames.len());
+    writeln!(buf_writer, "Typical frame (median): busy {}", duration(typical_busy)).unwrap();
+    let mut worst_frames: Vec<_> = frames.iter().enumerate().collect();
+    worst_frames.sort_by(|(_, a), (_, b)| b.busy_cycles.cmp(&a.busy_cycles).then(a.index.cmp(&b.index)));
+    for (position, frame) in worst_frames.into_iter().take(count) {
+        writeln!(buf_writer).unwrap();
+        writeln!(buf_writer, "Frame {} at {:.3} ms: busy {}, {} more than a typical frame{}",
+            frame.index,
+            cycle_to_us(frame.start_cycle, duration_format.mclk) / 1000.0,
+            duration(frame.busy_cycles),
+            duration(frame.busy_cycles.saturating_sub(typical_busy)),
+            if detects_lag && frame.is_lag() { ", LAG" } else { "" },
+        ).unwrap();
+        // the keys taking the most time above their typical time, as (name, cycles in this frame, typical cycles)
+        let mut write_excess = |title: &str, mut excess: Vec<(Cow<str>, u64, u64)>| {
+            excess.retain(|&(_, cycles, typical)| cycles > typical);
+            excess.sort_by(|a, b| (b.1 - b.2).cmp(&(a.1 - a.2)).then_with(|| a.0.cmp(&b.0)));
+            if excess.is_empty() {
+                return;
+            }
+            writeln!(buf_writer, "  {} above their typical time:", title).unwrap();
+            for (name, cycles, typical) in excess.into_iter().take(TOP_FUNCTIONS_COUNT) {
+                writeln!(buf_writer, "    {}: {} (typical {}, +{}, {:.1}% of the extra time)",
+                    name,
+                    duration(cycles),
+                    duration(typical),
+                    duration(cycles - typical),
+                    (cycles - typical) as f64 * 100.0 / frame.busy_cycles.saturating_sub(typical_busy).max(1) as f64,
+                ).unwrap();
+            }
+        };
+        write_excess("Functions", frame.self_cycles.iter()
+            .map(|(&address, &cycles)| (symbols.name(address), cycles, typical_functions.get(&address).copied().unwrap_or(0)))
+            .collect());
+        write_excess("Intervals", interval_occupancy[position].iter()
+            .map(|(&name, &cycles)| (Cow::from(name), cycles, typical_intervals.get(name).copied().unwrap_or(0)))
+            .collect());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::MdpBuilder;
+
+    const IDLE: u32 = 0x400;
+    // a frame of NTSC in 68000 cycles, give or take a few
+    const FRAME: u64 = 128_000;
+
+    #[test]
+    fn idle_wait_spanning_vints_is_not_lag() {
+        let mut builder = MdpBuilder::default();
+        builder.call(IDLE).wait(FRAME / 2).vint().wait(FRAME).vint().wait(FRAME).vint().wait(FRAME / 2);
+        builder.ret().unwrap();
+        let capture = builder.build();
+        let idle_functions = std::iter::once(IDLE).collect();
+        let frames = compute_frame_stats(&capture, FrameSource::VInt, &idle_functions, &VideoTiming::from_capture(&capture, None));
+        assert_eq!(frames.len(), 2);
+        for frame in &frames {
+            assert_eq!(frame.busy_cycles, 0);
+            assert!(frame.reached_idle);
+            assert!(!frame.is_lag());
+        }
+    }
+
+    #[test]
+    fn frame_without_idle_is_lag() {
+        let mut builder = MdpBuilder::default();
+        builder.vint().call(0x200).wait(FRAME / 2);
+        builder.ret().unwrap();
+        builder.call(IDLE).wait(FRAME / 2);
+        builder.ret().unwrap();
+        builder.vint().call(0x200).wait(FRAME);
+        builder.ret().unwrap();
+        builder.vint();
+        let capture = builder.build();
+        let idle_functions = std::iter::once(IDLE).collect();
+        let frames = compute_frame_stats(&capture, FrameSource::VInt, &idle_functions, &VideoTiming::from_capture(&capture, None));
+        assert_eq!(frames.len(), 2);
+        assert!(!frames[0].is_lag());
+        assert!(frames[1].is_lag());
+        assert_eq!(frames[1].busy_cycles, frames[1].cycles());
+    }
+}
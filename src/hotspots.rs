@@ -0,0 +1,810 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, io::Write};
+
+const HISTOGRAM_BUCKETS: u64 = 20;
+const HISTOGRAM_WIDTH: usize = 50;
+// resamples drawn for the confidence interval of the change of the median
+const BOOTSTRAP_RESAMPLES: usize = 1000;
+// below this p-value, the durations of a function are considered to have changed
+const SIGNIFICANCE_LEVEL: f64 = 0.05;
+
+use crate::{frames::percentile, profiling::*, report::DurationFormat, symbols::Symbols};
+
+#[derive(Debug, Default, Clone)]
+pub struct FunctionStats {
+    pub address: u32,
+    pub calls: u64,
+    // from enter to exit, recursive calls are only counted once
+    pub total_cycles: u64,
+    // excluding the functions it called and DMA halts
+    pub self_cycles: u64,
+}
+
+// what happens to the functions while walking the capture
+enum CallEvent<'s> {
+    Call(u32),
+    // recursive if the function was already running when it was called, idle cycles are the ones spent in idle functions meanwhile,
+    // the callers are the functions still running on its thread, the outermost first
+    Return { function: u32, enter_cycle: u64, exit_cycle: u64, idle_cycles: u64, recursive: bool, callers: &'s [(RunningFunction, u32)] },
+    // cycles spent in the innermost running function, DMA halts excluded, with the functions running on its thread, the outermost first
+    SelfTime(u32, u64, &'s [(RunningFunction, u32)]),
+    // cycles spent in an idle function or one it called, outside of interrupts
+    Idle(u64),
+}
+
+// a subroutine or interrupt handler while it runs
+struct RunningFunction {
+    function: u32,
+    // when it was entered
+    enter_cycle: u64,
+    idle_cycles_before: u64,
+    // it's an idle function, or it was called from one
+    idle: bool,
+}
+
+// pushes a function that was just entered with this stack pointer
+fn open(stack: &mut CallStack<RunningFunction>, running: RunningFunction, stack_pointer: u32, visit: &mut impl FnMut(CallEvent)) {
+    if !running.idle {
+        visit(CallEvent::Call(running.function));
+    }
+    stack.enter(running, stack_pointer);
+}
+
+// pops the innermost running function, the calls made by idle functions aren't reported
+fn close(stack: &mut CallStack<RunningFunction>, cycle: u64, idle_cycles: u64, visit: &mut impl FnMut(CallEvent)) {
+    let (running, _stack_pointer) = stack.pop().unwrap();
+    if !running.idle {
+        let recursive = stack.iter().any(|(caller, _stack_pointer)| caller.function == running.function);
+        visit(CallEvent::Return { function: running.function, enter_cycle: running.enter_cycle, exit_cycle: cycle, idle_cycles: idle_cycles - running.idle_cycles_before, recursive, callers: stack });
+    }
+}
+
+// follows the calls and returns of every subroutine and interrupt handler of the capture,
+// the ones still running at the end of the capture return there,
+// the time spent in the idle functions (and what they call) outside of interrupts is reported as idle instead
+fn walk_calls(input: &ParsedProfilingFile, idle_functions: &HashSet<u32>, mut visit: impl FnMut(CallEvent)) {
+    // halted cycles of the last DMA transfer that have yet to elapse
+    let mut pending_dma_halt = 0;
+    let mut in_interrupt = false;
+    let mut idle_cycles = 0;
+    let mut main_stack: CallStack<RunningFunction> = CallStack::new(input.exit_matching);
+    let mut interrupt_stack: CallStack<RunningFunction> = CallStack::new(input.exit_matching);
+    let mut last_cycle = input.packets.first().map(|packet| packet.cycle).unwrap_or(0);
+    for packet in &input.packets {
+        let elapsed = packet.cycle - last_cycle;
+        let halted = pending_dma_halt.min(elapsed);
+        pending_dma_halt -= halted;
+        let stack = if in_interrupt { &mut interrupt_stack } else { &mut main_stack };
+        match stack.last() {
+            Some((running, _stack_pointer)) if running.idle => {
+                idle_cycles += elapsed;
+                visit(CallEvent::Idle(elapsed));
+            },
+            Some((running, _stack_pointer)) => visit(CallEvent::SelfTime(running.function, elapsed - halted, stack)),
+            None => {},
+        }
+        last_cycle = packet.cycle;
+        let entered = |function: u32, idle: bool| RunningFunction { function, enter_cycle: packet.cycle, idle_cycles_before: idle_cycles, idle };
+        if let Some(change) = packet.call_stack_change() {
+            if let Some(exit_stack_pointer) = change.exit {
+                for _ in 0..stack.returning(exit_stack_pointer) {
+                    close(stack, packet.cycle, idle_cycles, &mut visit);
+                }
+            }
+            if let Some((target_subroutine, stack_pointer)) = change.enter {
+                let idle = !in_interrupt && (stack.last().is_some_and(|(caller, _stack_pointer)| caller.idle) || idle_functions.contains(&target_subroutine));
+                open(stack, entered(target_subroutine, idle), stack_pointer, &mut visit);
+            }
+        }
+        match packet.inner {
+            ProfilingPacketInner::InterruptEnter { target_interrupt } => {
+                in_interrupt = true;
+                while !interrupt_stack.is_empty() {
+                    close(&mut interrupt_stack, packet.cycle, idle_cycles, &mut visit);
+                }
+                open(&mut interrupt_stack, entered(target_interrupt, false), packet.stack_pointer, &mut visit);
+            },
+            ProfilingPacketInner::InterruptExit => {
+                in_interrupt = false;
+                while !interrupt_stack.is_empty() {
+                    close(&mut interrupt_stack, packet.cycle, idle_cycles, &mut visit);
+                }
+            },
+            ProfilingPacketInner::Dma { halted_cycles, .. } => {
+                pending_dma_halt += halted_cycles as u64;
+            },
+            _ => {},
+        }
+    }
+    while !interrupt_stack.is_empty() {
+        close(&mut interrupt_stack, last_cycle, idle_cycles, &mut visit);
+    }
+    while !main_stack.is_empty() {
+        close(&mut main_stack, last_cycle, idle_cycles, &mut visit);
+    }
+}
+
+// calls, total and self time of every subroutine and interrupt handler of the capture,
+// the idle functions and what they call are left out, and the time spent in them doesn't count in the total time of their callers
+pub fn compute_function_stats(input: &ParsedProfilingFile, idle_functions: &HashSet<u32>) -> Vec<FunctionStats> {
+    let mut stats: HashMap<u32, FunctionStats> = HashMap::new();
+    walk_calls(input, idle_functions, |event| match event {
+        CallEvent::Call(function) => stats.entry(function).or_default().calls += 1,
+        CallEvent::Return { function, enter_cycle, exit_cycle, idle_cycles, recursive: false, .. } => stats.entry(function).or_default().total_cycles += exit_cycle - enter_cycle - idle_cycles,
+        CallEvent::Return { recursive: true, .. } | CallEvent::Idle(_) => {},
+        CallEvent::SelfTime(function, cycles, _stack) => stats.entry(function).or_default().self_cycles += cycles,
+    });
+    let mut stats: Vec<_> = stats.into_iter()
+        .map(|(address, function_stats)| FunctionStats { address, ..function_stats })
+        .collect();
+    stats.sort_by_key(|function_stats| function_stats.address);
+    stats
+}
+
+// cycles spent in the idle functions and what they call, outside of interrupts
+pub fn idle_cycles(input: &ParsedProfilingFile, idle_functions: &HashSet<u32>) -> u64 {
+    let mut idle_cycles = 0;
+    walk_calls(input, idle_functions, |event| {
+        if let CallEvent::Idle(cycles) = event {
+            idle_cycles += cycles;
+        }
+    });
+    idle_cycles
+}
+
+// the self time spent with each stack of functions, outermost first, the interrupt handlers being at the bottom of the stacks they run,
+// the idle functions and what they call are left out
+pub fn collapse_calls(input: &ParsedProfilingFile, idle_functions: &HashSet<u32>) -> BTreeMap<Vec<u32>, u64> {
+    let mut stacks = BTreeMap::new();
+    walk_calls(input, idle_functions, |event| {
+        if let CallEvent::SelfTime(_function, cycles, stack) = event {
+            if cycles != 0 {
+                *stacks.entry(stack.iter().map(|(running, _stack_pointer)| running.function).collect()).or_insert(0) += cycles;
+            }
+        }
+    });
+    stacks
+}
+
+// one "outer;inner cycles" line per stack, the format of flamegraph.pl, inferno and speedscope, weighted by master clock cycles
+pub fn write_collapsed_calls(mut output: impl Write, stacks: &BTreeMap<Vec<u32>, u64>, symbols: &Symbols) -> std::io::Result<()> {
+    for (functions, cycles) in stacks {
+        let names: Vec<_> = functions.iter().map(|&function| symbols.name(function)).collect();
+        writeln!(output, "{} {}", names.join(";"), cycles)?;
+    }
+    Ok(())
+}
+
+// one row per function with its calls, total and self time in microseconds, by address
+pub fn write_function_stats_csv(mut output: impl Write, stats: &[FunctionStats], symbols: &Symbols, mclk: f64) -> std::io::Result<()> {
+    let us = |cycles: u64| format!("{:.3}", cycle_to_us(cycles, mclk));
+    writeln!(output, "name,address,calls,total_us,self_us")?;
+    for function_stats in stats {
+        // the name is quoted as it may contain commas
+        writeln!(output, "\"{}\",{:#x},{},{},{}",
+            symbols.name(function_stats.address).replace('"', "\"\""),
+            function_stats.address,
+            function_stats.calls,
+            us(function_stats.total_cycles),
+            us(function_stats.self_cycles),
+        )?;
+    }
+    Ok(())
+}
+
+// (start cycle, duration) of every invocation of a function, in the order they returned
+pub fn function_invocations(input: &ParsedProfilingFile, address: u32) -> Vec<(u64, u64)> {
+    let mut invocations = Vec::new();
+    walk_calls(input, &HashSet::new(), |event| {
+        if let CallEvent::Return { function, enter_cycle, exit_cycle, .. } = event {
+            if function == address {
+                invocations.push((enter_cycle, exit_cycle - enter_cycle));
+            }
+        }
+    });
+    invocations
+}
+
+// how much longer than the other invocations of its function an invocation has to be to be an outlier
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutlierThreshold {
+    // standard deviations above the mean
+    Sigma(f64),
+    // times the median
+    MedianFactor(f64),
+}
+
+// an invocation much longer than the others of its function
+#[derive(Debug, Clone)]
+pub struct Outlier {
+    pub function: u32,
+    pub enter_cycle: u64,
+    pub cycles: u64,
+    // spent in interrupt handlers while it ran
+    pub interrupt_cycles: u64,
+    pub median_cycles: u64,
+    pub threshold_cycles: f64,
+}
+
+impl Outlier {
+    // it's only an outlier because of the interrupts which preempted it, without them it would have been as long as usual
+    pub fn preempted(&self) -> bool {
+        ((self.cycles - self.interrupt_cycles) as f64) <= self.threshold_cycles
+    }
+}
+
+// start and end cycles of each interrupt, and the cycles spent in interrupts before each of them
+fn interrupt_spans(input: &ParsedProfilingFile) -> Vec<(u64, u64, u64)> {
+    let mut spans = Vec::new();
+    let mut entered_at = None;
+    let mut before = 0;
+    let mut close = |spans: &mut Vec<(u64, u64, u64)>, start: u64, end: u64| {
+        spans.push((start, end, before));
+        before += end - start;
+    };
+    for packet in &input.packets {
+        match packet.inner {
+            ProfilingPacketInner::InterruptEnter { .. } => {
+                if let Some(start) = entered_at.replace(packet.cycle) {
+                    close(&mut spans, start, packet.cycle);
+                }
+            },
+            ProfilingPacketInner::InterruptExit => {
+                if let Some(start) = entered_at.take() {
+                    close(&mut spans, start, packet.cycle);
+                }
+            },
+            _ => {},
+        }
+    }
+    if let (Some(start), Some(last)) = (entered_at, input.packets.last()) {
+        close(&mut spans, start, last.cycle);
+    }
+    spans
+}
+
+// cycles spent in interrupts up to this cycle
+fn interrupt_cycles_before(spans: &[(u64, u64, u64)], cycle: u64) -> u64 {
+    let index = spans.partition_point(|&(start, _end, _before)| start < cycle);
+    match index.checked_sub(1).map(|index| spans[index]) {
+        Some((start, end, before)) => before + end.min(cycle) - start,
+        None => 0,
+    }
+}
+
+// the invocations longer than the threshold computed from the other invocations of their function, the functions called
+// fewer than min_invocations times being left out, the longest outliers (relative to their threshold) first,
+// the time spent in the idle functions isn't counted, and the idle functions themselves are left out
+pub fn find_outliers(input: &ParsedProfilingFile, idle_functions: &HashSet<u32>, threshold: OutlierThreshold, min_invocations: usize) -> Vec<Outlier> {
+    let mut invocations: HashMap<u32, Vec<(u64, u64, u64)>> = HashMap::new();
+    let spans = interrupt_spans(input);
+    walk_calls(input, idle_functions, |event| {
+        if let CallEvent::Return { function, enter_cycle, exit_cycle, idle_cycles, .. } = event {
+            let interrupt_cycles = interrupt_cycles_before(&spans, exit_cycle) - interrupt_cycles_before(&spans, enter_cycle);
+            let cycles = exit_cycle - enter_cycle - idle_cycles;
+            // an interrupt handler doesn't preempt itself
+            let interrupt_cycles = if interrupt_cycles >= exit_cycle - enter_cycle { 0 } else { interrupt_cycles.min(cycles) };
+            invocations.entry(function).or_default().push((enter_cycle, cycles, interrupt_cycles));
+        }
+    });
+    let mut outliers = Vec::new();
+    for (function, invocations) in invocations {
+        if invocations.len() < min_invocations.max(2) {
+            continue;
+        }
+        let mut durations: Vec<u64> = invocations.iter().map(|&(_enter_cycle, cycles, _interrupt_cycles)| cycles).collect();
+        durations.sort_unstable();
+        let median_cycles = median(&durations);
+        let threshold_cycles = match threshold {
+            OutlierThreshold::Sigma(sigma) => {
+                let mean = durations.iter().sum::<u64>() as f64 / durations.len() as f64;
+                let variance = durations.iter().map(|&cycles| (cycles as f64 - mean).powi(2)).sum::<f64>() / (durations.len() - 1) as f64;
+                mean + sigma * variance.sqrt()
+            },
+            OutlierThreshold::MedianFactor(factor) => median_cycles as f64 * factor,
+        };
+        outliers.extend(invocations.into_iter()
+            .filter(|&(_enter_cycle, cycles, _interrupt_cycles)| cycles as f64 > threshold_cycles)
+            .map(|(enter_cycle, cycles, interrupt_cycles)| Outlier { function, enter_cycle, cycles, interrupt_cycles, median_cycles, threshold_cycles }));
+    }
+    outliers.sort_by(|a, b| (b.cycles as f64 / b.threshold_cycles.max(1.0)).total_cmp(&(a.cycles as f64 / a.threshold_cycles.max(1.0))).then(a.enter_cycle.cmp(&b.enter_cycle)));
+    outliers
+}
+
+// the count first outliers, with what made them long
+pub fn write_outliers(mut output: impl Write, outliers: &[Outlier], symbols: &Symbols, count: usize, duration_format: &DurationFormat) -> std::io::Result<()> {
+    if outliers.is_empty() {
+        return writeln!(output, "No outlier invocation");
+    }
+    let preempted = outliers.iter().filter(|outlier| outlier.preempted()).count();
+    writeln!(output, "{} outlier invocations, {} of them only because of the interrupts preempting them", outliers.len(), preempted)?;
+    for outlier in outliers.iter().take(count) {
+        writeln!(output, "  {} at {:.3} ms: {} ({:.1}x the median of {}), {} in interrupts: {}",
+            symbols.name(outlier.function),
+            cycle_to_us(outlier.enter_cycle, duration_format.mclk) / 1000.0,
+            duration_format.format(outlier.cycles as f64),
+            outlier.cycles as f64 / outlier.median_cycles.max(1) as f64,
+            duration_format.format(outlier.median_cycles as f64),
+            duration_format.format(outlier.interrupt_cycles as f64),
+            if outlier.preempted() { "preempted by interrupts" } else { "slow path" },
+        )?;
+    }
+    Ok(())
+}
+
+// a function running at some point of the capture
+#[derive(Debug, Clone)]
+pub struct StackEntry {
+    pub function: u32,
+    pub enter_cycle: u64,
+    // it runs on the stack of an interrupt handler
+    pub interrupt: bool,
+}
+
+// the functions running at this cycle, the innermost first, the interrupt handler and what it called (if the CPU was
+// in an interrupt then) coming before the main code they interrupted
+pub fn stack_at(input: &ParsedProfilingFile, cycle: u64) -> Vec<StackEntry> {
+    let mut in_interrupt = false;
+    let mut main_stack: CallStack<StackEntry> = CallStack::new(input.exit_matching);
+    let mut interrupt_stack: CallStack<StackEntry> = CallStack::new(input.exit_matching);
+    for packet in input.packets.iter().take_while(|packet| packet.cycle <= cycle) {
+        let stack = if in_interrupt { &mut interrupt_stack } else { &mut main_stack };
+        let entered = |function: u32| StackEntry { function, enter_cycle: packet.cycle, interrupt: in_interrupt };
+        stack.follow(packet, entered);
+        match packet.inner {
+            ProfilingPacketInner::InterruptEnter { target_interrupt } => {
+                in_interrupt = true;
+                interrupt_stack.clear();
+                interrupt_stack.enter(StackEntry { function: target_interrupt, enter_cycle: packet.cycle, interrupt: true }, packet.stack_pointer);
+            },
+            ProfilingPacketInner::InterruptExit => {
+                in_interrupt = false;
+                interrupt_stack.clear();
+            },
+            _ => {},
+        }
+    }
+    if !in_interrupt {
+        interrupt_stack.clear();
+    }
+    interrupt_stack.into_iter().rev().chain(main_stack.into_iter().rev()).map(|(entry, _stack_pointer)| entry).collect()
+}
+
+pub fn write_stack(mut output: impl Write, cycle: u64, frame: Option<usize>, stack: &[StackEntry], symbols: &Symbols, duration_format: &DurationFormat) -> std::io::Result<()> {
+    let ms = |cycle: u64| cycle_to_us(cycle, duration_format.mclk) / 1000.0;
+    write!(output, "Stack at {:.3} ms", ms(cycle))?;
+    if let Some(frame) = frame {
+        write!(output, " (frame {})", frame)?;
+    }
+    if stack.is_empty() {
+        return writeln!(output, ": no traced function running");
+    }
+    writeln!(output, "{}:", if stack[0].interrupt { ", in an interrupt" } else { "" })?;
+    let name_width = stack.iter().map(|entry| symbols.name(entry.function).len()).max().unwrap();
+    for (depth, entry) in stack.iter().enumerate() {
+        writeln!(output, "  #{:<2} {:<name_width$} entered at {:.3} ms, {} before{}",
+            depth,
+            symbols.name(entry.function),
+            ms(entry.enter_cycle),
+            duration_format.format((cycle - entry.enter_cycle) as f64),
+            if entry.interrupt { " (interrupt)" } else { "" },
+            name_width = name_width,
+        )?;
+    }
+    Ok(())
+}
+
+// an invocation of a function, with the functions it was called from
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    pub enter_cycle: u64,
+    pub cycles: u64,
+    // the functions running on its thread when it was called, the outermost first
+    pub callers: Vec<u32>,
+}
+
+// every invocation of a function, in the order they started
+pub fn invocations_with_callers(input: &ParsedProfilingFile, address: u32) -> Vec<Invocation> {
+    let mut invocations = Vec::new();
+    walk_calls(input, &HashSet::new(), |event| {
+        if let CallEvent::Return { function, enter_cycle, exit_cycle, callers, .. } = event {
+            if function == address {
+                invocations.push(Invocation {
+                    enter_cycle,
+                    cycles: exit_cycle - enter_cycle,
+                    callers: callers.iter().map(|(caller, _stack_pointer)| caller.function).collect(),
+                });
+            }
+        }
+    });
+    invocations.sort_by_key(|invocation| invocation.enter_cycle);
+    invocations
+}
+
+// the frame (index of the last frame start before it) of each invocation, None before the first frame start,
+// as a table or as csv, the durations of the csv being in microseconds and 68000 cycles
+pub fn write_invocations(mut output: impl Write, name: &str, invocations: &[Invocation], frame_starts: &[u64], symbols: &Symbols, duration_format: &DurationFormat, csv: bool) -> std::io::Result<()> {
+    let frame = |cycle: u64| frame_starts.partition_point(|&frame_start| frame_start <= cycle).checked_sub(1);
+    let context = |invocation: &Invocation, separator: &str| invocation.callers.iter().map(|&caller| symbols.name(caller)).collect::<Vec<_>>().join(separator);
+    let ms = |cycle: u64| cycle_to_us(cycle, duration_format.mclk) / 1000.0;
+    if csv {
+        writeln!(output, "start_us,duration_us,duration_cycles,frame,callers")?;
+        for invocation in invocations {
+            // the callers are quoted as the names may contain commas
+            writeln!(output, "{:.3},{:.3},{},{},\"{}\"",
+                cycle_to_us(invocation.enter_cycle, duration_format.mclk),
+                cycle_to_us(invocation.cycles, duration_format.mclk),
+                invocation.cycles / duration_format.m68k_divider.max(1),
+                frame(invocation.enter_cycle).map(|frame| frame.to_string()).unwrap_or_default(),
+                context(invocation, ";").replace('"', "\"\""),
+            )?;
+        }
+        return Ok(());
+    }
+    if invocations.is_empty() {
+        return writeln!(output, "{} was never called in the capture", name);
+    }
+    writeln!(output, "{}: {} invocations", name, invocations.len())?;
+    for invocation in invocations {
+        write!(output, "  at {:.3} ms", ms(invocation.enter_cycle))?;
+        if let Some(frame) = frame(invocation.enter_cycle) {
+            write!(output, " (frame {})", frame)?;
+        }
+        write!(output, ": {}", duration_format.format(invocation.cycles as f64))?;
+        if !invocation.callers.is_empty() {
+            write!(output, ", from {}", context(invocation, " > "))?;
+        }
+        writeln!(output)?;
+    }
+    Ok(())
+}
+
+// the invocations of a function and their time, attributed to its callers, then to their callers, up to a depth:
+// the inverted call tree of the function
+#[derive(Debug, Default)]
+pub struct CallerTree {
+    pub calls: u64,
+    pub cycles: u64,
+    pub callers: HashMap<u32, CallerTree>,
+}
+
+// recursive calls are only counted once, like in the total time of the hotspots
+pub fn caller_tree(input: &ParsedProfilingFile, address: u32, depth: usize) -> CallerTree {
+    let mut tree = CallerTree::default();
+    walk_calls(input, &HashSet::new(), |event| {
+        if let CallEvent::Return { function, enter_cycle, exit_cycle, recursive: false, callers, .. } = event {
+            if function == address {
+                let mut node = &mut tree;
+                node.calls += 1;
+                node.cycles += exit_cycle - enter_cycle;
+                for (caller, _stack_pointer) in callers.iter().rev().take(depth) {
+                    node = node.callers.entry(caller.function).or_default();
+                    node.calls += 1;
+                    node.cycles += exit_cycle - enter_cycle;
+                }
+            }
+        }
+    });
+    tree
+}
+
+// the callers of each level sorted by the time they account for, the calls made by no traced function (interrupt
+// handlers, functions running when the capture started) being left out
+pub fn write_caller_tree(mut output: impl Write, name: &str, tree: &CallerTree, symbols: &Symbols, duration_format: &DurationFormat) -> std::io::Result<()> {
+    if tree.calls == 0 {
+        return writeln!(output, "{} was never called in the capture", name);
+    }
+    writeln!(output, "{} ({} calls): {}", name, tree.calls, duration_format.format_total(tree.cycles as f64))?;
+    write_callers(&mut output, tree, tree.cycles, symbols, duration_format, 1)
+}
+
+fn write_callers(output: &mut impl Write, node: &CallerTree, total_cycles: u64, symbols: &Symbols, duration_format: &DurationFormat, level: usize) -> std::io::Result<()> {
+    let mut callers: Vec<_> = node.callers.iter().collect();
+    callers.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+    for (&caller, caller_node) in callers {
+        writeln!(output, "{:indent$}{:5.1}% {} ({} calls): {}",
+            "",
+            caller_node.cycles as f64 * 100.0 / total_cycles.max(1) as f64,
+            symbols.name(caller),
+            caller_node.calls,
+            duration_format.format_total(caller_node.cycles as f64),
+            indent = level * 2,
+        )?;
+        write_callers(output, caller_node, total_cycles, symbols, duration_format, level + 1)?;
+    }
+    Ok(())
+}
+
+// the count most expensive functions by total time, then by self time
+pub fn write_hotspots(mut output: impl Write, stats: &[FunctionStats], symbols: &Symbols, count: usize, duration_format: &DurationFormat, capture_cycles: u64) -> std::io::Result<()> {
+    let percent = |cycles: u64| if capture_cycles == 0 { 0.0 } else { cycles as f64 * 100.0 / capture_cycles as f64 };
+    let mut by_total: Vec<_> = stats.iter().collect();
+    by_total.sort_by(|a, b| b.total_cycles.cmp(&a.total_cycles).then(a.address.cmp(&b.address)));
+    writeln!(output, "Top {} functions by total time:", count)?;
+    for function_stats in by_total.iter().take(count) {
+        writeln!(output, "  {:5.1}% {} ({} calls): {}",
+            percent(function_stats.total_cycles),
+            symbols.name(function_stats.address),
+            function_stats.calls,
+            duration_format.format_total(function_stats.total_cycles as f64),
+        )?;
+    }
+    let mut by_self: Vec<_> = stats.iter().collect();
+    by_self.sort_by(|a, b| b.self_cycles.cmp(&a.self_cycles).then(a.address.cmp(&b.address)));
+    writeln!(output, "Top {} functions by self time:", count)?;
+    for function_stats in by_self.iter().take(count) {
+        writeln!(output, "  {:5.1}% {} ({} calls): {}",
+            percent(function_stats.self_cycles),
+            symbols.name(function_stats.address),
+            function_stats.calls,
+            duration_format.format_total(function_stats.self_cycles as f64),
+        )?;
+    }
+    Ok(())
+}
+
+// the count most called functions, with their average total and self time per call, the small functions called
+// thousands of times a frame being the ones worth inlining even when their total time is low
+pub fn write_call_counts(mut output: impl Write, stats: &[FunctionStats], symbols: &Symbols, count: usize, duration_format: &DurationFormat, frames: f64) -> std::io::Result<()> {
+    let mut by_calls: Vec<_> = stats.iter().filter(|function_stats| function_stats.calls != 0).collect();
+    by_calls.sort_by(|a, b| b.calls.cmp(&a.calls).then(a.address.cmp(&b.address)));
+    writeln!(output, "Top {} functions by calls:", count)?;
+    for function_stats in by_calls.iter().take(count) {
+        let calls = function_stats.calls as f64;
+        writeln!(output, "  {} calls ({:.1} per frame) {}: {} per call, {} self",
+            function_stats.calls,
+            if frames > 0.0 { calls / frames } else { calls },
+            symbols.name(function_stats.address),
+            duration_format.format(function_stats.total_cycles as f64 / calls),
+            duration_format.format(function_stats.self_cycles as f64 / calls),
+        )?;
+    }
+    Ok(())
+}
+
+// percentiles of the durations of the invocations of a function, and how they are distributed
+pub fn write_duration_histogram(mut output: impl Write, name: &str, invocations: &[(u64, u64)], duration_format: &DurationFormat) -> std::io::Result<()> {
+    if invocations.is_empty() {
+        return writeln!(output, "{} was never called in the capture", name);
+    }
+    let mut durations: Vec<u64> = invocations.iter().map(|&(_start_cycle, cycles)| cycles).collect();
+    durations.sort_unstable();
+    let min = durations[0];
+    let max = durations[durations.len() - 1];
+    let duration = |cycles: u64| duration_format.format(cycles as f64);
+    writeln!(output, "{}: {} invocations", name, durations.len())?;
+    writeln!(output, "  min: {}", duration(min))?;
+    writeln!(output, "  avg: {}", duration_format.format(durations.iter().sum::<u64>() as f64 / durations.len() as f64))?;
+    writeln!(output, "  p50: {}", duration(percentile(&durations, 50)))?;
+    writeln!(output, "  p90: {}", duration(percentile(&durations, 90)))?;
+    writeln!(output, "  p99: {}", duration(percentile(&durations, 99)))?;
+    writeln!(output, "  max: {}", duration(max))?;
+    // the slowest invocation is the one worth looking at in the trace
+    let &(slowest_start, _cycles) = invocations.iter().max_by_key(|&&(start_cycle, cycles)| (cycles, std::cmp::Reverse(start_cycle))).unwrap();
+    writeln!(output, "  slowest at {:.3} ms", cycle_to_us(slowest_start, duration_format.mclk) / 1000.0)?;
+    let bucket_width = ((max - min) / HISTOGRAM_BUCKETS).max(1);
+    let mut buckets = vec![0usize; ((max - min) / bucket_width + 1).min(HISTOGRAM_BUCKETS) as usize];
+    for &cycles in &durations {
+        let bucket = (((cycles - min) / bucket_width) as usize).min(buckets.len() - 1);
+        buckets[bucket] += 1;
+    }
+    let largest_bucket = *buckets.iter().max().unwrap();
+    let lower_bounds: Vec<_> = (0..buckets.len()).map(|index| duration(min + index as u64 * bucket_width)).collect();
+    let label_width = lower_bounds.iter().map(String::len).max().unwrap();
+    writeln!(output, "Histogram:")?;
+    for (lower_bound, &count) in lower_bounds.iter().zip(&buckets) {
+        // at least one character for non empty buckets, so outliers are visible
+        let bar_length = (count * HISTOGRAM_WIDTH).div_ceil(largest_bucket);
+        writeln!(output, "  >= {:>label_width$} |{:<bar_width$}| {}", lower_bound, "#".repeat(bar_length), count, label_width = label_width, bar_width = HISTOGRAM_WIDTH)?;
+    }
+    Ok(())
+}
+
+// the stats of a function in the captures before and after a change
+#[derive(Debug, Default, Clone)]
+pub struct FunctionComparison {
+    pub name: String,
+    pub before: FunctionStats,
+    pub after: FunctionStats,
+}
+
+impl FunctionComparison {
+    pub fn total_delta(&self) -> i64 {
+        self.after.total_cycles as i64 - self.before.total_cycles as i64
+    }
+
+    pub fn self_delta(&self) -> i64 {
+        self.after.self_cycles as i64 - self.before.self_cycles as i64
+    }
+}
+
+// matches the functions of two captures by name, as they may have moved between the builds,
+// the biggest regressions by total time come first and the biggest improvements last
+pub fn compare_function_stats(before: &[FunctionStats], before_symbols: &Symbols, after: &[FunctionStats], after_symbols: &Symbols) -> Vec<FunctionComparison> {
+    let mut comparisons: HashMap<String, FunctionComparison> = HashMap::new();
+    let mut add = |stats: &FunctionStats, symbols: &Symbols, is_after: bool| {
+        let name = symbols.name(stats.address).into_owned();
+        let comparison = comparisons.entry(name.clone()).or_insert_with(|| FunctionComparison { name, ..Default::default() });
+        let side = if is_after { &mut comparison.after } else { &mut comparison.before };
+        side.address = stats.address;
+        side.calls += stats.calls;
+        side.total_cycles += stats.total_cycles;
+        side.self_cycles += stats.self_cycles;
+    };
+    for stats in before {
+        add(stats, before_symbols, false);
+    }
+    for stats in after {
+        add(stats, after_symbols, true);
+    }
+    let mut comparisons: Vec<_> = comparisons.into_values().collect();
+    comparisons.sort_by(|a, b| b.total_delta().cmp(&a.total_delta()).then_with(|| a.name.cmp(&b.name)));
+    comparisons
+}
+
+// "+12.5%", or "new"/"gone" for functions only called in one of the captures
+fn percent_change(before: u64, after: u64) -> String {
+    match (before, after) {
+        (0, 0) => "0.0%".to_string(),
+        (0, _) => "new".to_string(),
+        (_, 0) => "gone".to_string(),
+        _ => format!("{:+.1}%", (after as f64 - before as f64) * 100.0 / before as f64),
+    }
+}
+
+// a markdown table of the count functions whose total time changed the most, regressions first
+pub fn write_comparison(mut output: impl Write, comparisons: &[FunctionComparison], count: usize, duration_format: &DurationFormat) -> std::io::Result<()> {
+    let duration = |cycles: u64| duration_format.format(cycles as f64);
+    let delta = |cycles: i64| format!("{}{}", if cycles < 0 { "-" } else { "+" }, duration_format.format(cycles.unsigned_abs() as f64));
+    let mut changed: Vec<_> = comparisons.iter().filter(|comparison| comparison.total_delta() != 0 || comparison.self_delta() != 0 || comparison.before.calls != comparison.after.calls).collect();
+    // the count largest changes either way, still listed regressions first
+    changed.sort_by_key(|comparison| std::cmp::Reverse(comparison.total_delta().unsigned_abs()));
+    changed.truncate(count);
+    changed.sort_by(|a, b| b.total_delta().cmp(&a.total_delta()).then_with(|| a.name.cmp(&b.name)));
+    if changed.is_empty() {
+        return writeln!(output, "No function changed between the captures");
+    }
+    writeln!(output, "| Function | Calls | Total before | Total after | Total change | Self before | Self after | Self change |")?;
+    writeln!(output, "|---|---:|---:|---:|---:|---:|---:|---:|")?;
+    for comparison in changed {
+        let (before, after) = (&comparison.before, &comparison.after);
+        writeln!(output, "| {} | {} -> {} | {} | {} | {} ({}) | {} | {} | {} ({}) |",
+            comparison.name,
+            before.calls,
+            after.calls,
+            duration(before.total_cycles),
+            duration(after.total_cycles),
+            delta(comparison.total_delta()),
+            percent_change(before.total_cycles, after.total_cycles),
+            duration(before.self_cycles),
+            duration(after.self_cycles),
+            delta(comparison.self_delta()),
+            percent_change(before.self_cycles, after.self_cycles),
+        )?;
+    }
+    Ok(())
+}
+
+// whether the durations of a function changed between two captures, beyond the noise from one invocation to the next
+#[derive(Debug, Clone)]
+pub struct DurationComparison {
+    pub before_median: u64,
+    pub after_median: u64,
+    pub before_mean: f64,
+    pub after_mean: f64,
+    // 95% confidence interval of the change of the median, after minus before
+    pub median_change_interval: (i64, i64),
+    // two-sided p-value of the Mann-Whitney U test
+    pub p_value: f64,
+}
+
+fn median(sorted_values: &[u64]) -> u64 {
+    sorted_values[sorted_values.len() / 2]
+}
+
+// complementary error function, with a fractional error below 1.2e-7 (Numerical Recipes' erfcc)
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let polynomial = -z * z - 1.26551223 + t * (1.00002368 + t * (0.37409196 + t * (0.09678418 + t * (-0.18628806
+        + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))));
+    let result = t * polynomial.exp();
+    if x >= 0.0 { result } else { 2.0 - result }
+}
+
+// normal approximation of the Mann-Whitney U test, with the correction for ties and for continuity,
+// which doesn't assume the durations are normally distributed
+fn mann_whitney_p_value(before: &[u64], after: &[u64]) -> f64 {
+    let mut values: Vec<(u64, bool)> = before.iter().map(|&cycles| (cycles, false)).chain(after.iter().map(|&cycles| (cycles, true))).collect();
+    values.sort_unstable();
+    let (n_before, n_after) = (before.len() as f64, after.len() as f64);
+    let n = n_before + n_after;
+    let mut before_rank_sum = 0.0;
+    let mut tie_correction = 0.0;
+    let mut start = 0;
+    while start < values.len() {
+        let end = start + values[start..].iter().take_while(|&&(cycles, _after)| cycles == values[start].0).count();
+        // tied values share the average of their ranks, which start at 1
+        let rank = (start + end + 1) as f64 / 2.0;
+        before_rank_sum += rank * values[start..end].iter().filter(|&&(_cycles, after)| !after).count() as f64;
+        let ties = (end - start) as f64;
+        tie_correction += ties * ties * ties - ties;
+        start = end;
+    }
+    let u = before_rank_sum - n_before * (n_before + 1.0) / 2.0;
+    let mean = n_before * n_after / 2.0;
+    let variance = n_before * n_after / 12.0 * ((n + 1.0) - tie_correction / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        return 1.0;
+    }
+    let z = ((u - mean).abs() - 0.5).max(0.0) / variance.sqrt();
+    erfc(z / std::f64::consts::SQRT_2).min(1.0)
+}
+
+// xorshift64*, seeded the same way every time so the same captures always give the same interval
+struct Xorshift(u64);
+
+impl Xorshift {
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) % bound as u64) as usize
+    }
+}
+
+fn resampled_median(values: &[u64], resample: &mut Vec<u64>, random: &mut Xorshift) -> u64 {
+    resample.clear();
+    resample.extend((0..values.len()).map(|_| values[random.below(values.len())]));
+    let middle = resample.len() / 2;
+    *resample.select_nth_unstable(middle).1
+}
+
+// the durations (in cycles) of the invocations of a function in each capture, both must have some
+pub fn compare_durations(before: &[u64], after: &[u64]) -> DurationComparison {
+    let mut sorted_before = before.to_vec();
+    sorted_before.sort_unstable();
+    let mut sorted_after = after.to_vec();
+    sorted_after.sort_unstable();
+    let mean = |values: &[u64]| values.iter().sum::<u64>() as f64 / values.len() as f64;
+    let mut random = Xorshift(0x9e37_79b9_7f4a_7c15);
+    let mut resample = Vec::new();
+    let mut median_changes: Vec<i64> = (0..BOOTSTRAP_RESAMPLES).map(|_| {
+        resampled_median(after, &mut resample, &mut random) as i64 - resampled_median(before, &mut resample, &mut random) as i64
+    }).collect();
+    median_changes.sort_unstable();
+    DurationComparison {
+        before_median: median(&sorted_before),
+        after_median: median(&sorted_after),
+        before_mean: mean(before),
+        after_mean: mean(after),
+        median_change_interval: (median_changes[BOOTSTRAP_RESAMPLES * 25 / 1000], median_changes[BOOTSTRAP_RESAMPLES * 975 / 1000 - 1]),
+        p_value: mann_whitney_p_value(before, after),
+    }
+}
+
+pub fn write_duration_comparison(mut output: impl Write, name: &str, before: &[u64], after: &[u64], duration_format: &DurationFormat) -> std::io::Result<()> {
+    if before.is_empty() || after.is_empty() {
+        let capture = if before.is_empty() { "before" } else { "after" };
+        return writeln!(output, "{} was never called in the capture made {} the change, there's nothing to compare", name, capture);
+    }
+    let comparison = compare_durations(before, after);
+    let duration = |cycles: f64| duration_format.format(cycles);
+    let delta = |cycles: i64| format!("{}{}", if cycles < 0 { "-" } else { "+" }, duration_format.format(cycles.unsigned_abs() as f64));
+    writeln!(output, "{}: {} invocations before, {} after", name, before.len(), after.len())?;
+    writeln!(output, "  median: {} -> {}, {} ({})",
+        duration(comparison.before_median as f64),
+        duration(comparison.after_median as f64),
+        delta(comparison.after_median as i64 - comparison.before_median as i64),
+        percent_change(comparison.before_median, comparison.after_median),
+    )?;
+    writeln!(output, "  mean: {} -> {}", duration(comparison.before_mean), duration(comparison.after_mean))?;
+    let (low, high) = comparison.median_change_interval;
+    writeln!(output, "  95% confidence interval of the change of the median (bootstrap): {} to {}", delta(low), delta(high))?;
+    writeln!(output, "  Mann-Whitney U test: p = {:.4}", comparison.p_value)?;
+    if comparison.p_value < SIGNIFICANCE_LEVEL {
+        let direction = if comparison.after_median > comparison.before_median || (comparison.after_median == comparison.before_median && comparison.after_mean > comparison.before_mean) { "slower" } else { "faster" };
+        writeln!(output, "The durations changed significantly, {} is {} after the change", name, direction)
+    } else {
+        writeln!(output, "No significant change, the difference is within the noise between invocations")
+    }
+}
@@ -0,0 +1,196 @@
+use std::{collections::HashSet, io::{BufWriter, Write}};
+
+use crate::{frames::{percentile, FrameStats}, hotspots::{compute_function_stats, idle_cycles}, intervals::Intervals, profiling::{cycle_to_us, ParsedProfilingFile}, raster::VideoTiming, report::DurationFormat, symbols::Symbols};
+
+const HOTSPOTS_COUNT: usize = 20;
+const WORST_FRAMES_COUNT: usize = 5;
+const TOP_FUNCTIONS_COUNT: usize = 5;
+// size of the per-frame chart, in the units of its viewBox
+const CHART_WIDTH: f64 = 1000.0;
+const CHART_HEIGHT: f64 = 200.0;
+
+const STYLE: &str = "body { font-family: sans-serif; max-width: 60em; margin: 2em auto; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { padding: 0.2em 0.8em; border-bottom: 1px solid #ddd; text-align: right; }
+th:first-child, td:first-child { text-align: left; }
+.chart { width: 100%; height: 15em; border: 1px solid #ccc; }
+.lag { color: #c00; font-weight: bold; }
+.note { color: #666; }";
+
+// what the report is made from, a single converted capture
+pub struct HtmlReport<'a> {
+    // the capture file, as the title of the page
+    pub title: &'a str,
+    pub profiling: &'a ParsedProfilingFile,
+    pub symbols: &'a Symbols,
+    pub idle_functions: &'a HashSet<u32>,
+    pub frames: &'a [FrameStats],
+    pub intervals: &'a Intervals,
+    pub duration_format: &'a DurationFormat,
+    pub timing: &'a VideoTiming,
+    pub detects_lag: bool,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// a standalone page, without scripts nor external resources, to read without a trace viewer
+pub fn write_html_report(output: impl Write, report: &HtmlReport) -> std::io::Result<()> {
+    let mut output = BufWriter::new(output);
+    writeln!(output, "<!DOCTYPE html>")?;
+    writeln!(output, "<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(output, "<title>{} - md-profiler report</title>", escape(report.title))?;
+    writeln!(output, "<style>\n{}\n</style>\n</head>\n<body>", STYLE)?;
+    writeln!(output, "<h1>{}</h1>", escape(report.title))?;
+    write_summary(&mut output, report)?;
+    write_frame_chart(&mut output, report)?;
+    write_hotspots(&mut output, report)?;
+    write_intervals(&mut output, report)?;
+    write_worst_frames(&mut output, report)?;
+    writeln!(output, "<p class=\"note\">Generated by md-profiler {}</p>", env!("CARGO_PKG_VERSION"))?;
+    writeln!(output, "</body>\n</html>")?;
+    output.flush()
+}
+
+fn write_summary(output: &mut impl Write, report: &HtmlReport) -> std::io::Result<()> {
+    let HtmlReport { profiling, frames, duration_format, timing, .. } = report;
+    let ms = |cycles: u64| cycle_to_us(cycles, profiling.mclk) / 1000.0;
+    writeln!(output, "<h2>Summary</h2>\n<table>")?;
+    if !profiling.capture_info.is_empty() {
+        writeln!(output, "<tr><td>Capture</td><td>{}</td></tr>", escape(&profiling.capture_info.description()))?;
+    }
+    let capture_cycles = match (profiling.packets.first(), profiling.packets.last()) {
+        (Some(first), Some(last)) => last.cycle - first.cycle,
+        _ => 0,
+    };
+    writeln!(output, "<tr><td>Duration</td><td>{:.3} ms</td></tr>", ms(capture_cycles))?;
+    writeln!(output, "<tr><td>Video standard</td><td>{}, {:.3} ms per frame</td></tr>", timing.standard.name(), ms(timing.frame_cycles()))?;
+    writeln!(output, "<tr><td>Frames</td><td>{}</td></tr>", frames.len())?;
+    if !frames.is_empty() {
+        let mut busy_cycles: Vec<_> = frames.iter().map(|frame| frame.busy_cycles).collect();
+        busy_cycles.sort_unstable();
+        let average_busy = busy_cycles.iter().sum::<u64>() as f64 / busy_cycles.len() as f64;
+        writeln!(output, "<tr><td>Average busy time per frame</td><td>{}</td></tr>", escape(&duration_format.format(average_busy)))?;
+        for percent in [50, 90, 99] {
+            writeln!(output, "<tr><td>p{} busy time per frame</td><td>{}</td></tr>", percent, escape(&duration_format.format(percentile(&busy_cycles, percent) as f64)))?;
+        }
+        writeln!(output, "<tr><td>Worst busy time per frame</td><td>{}</td></tr>", escape(&duration_format.format(busy_cycles[busy_cycles.len() - 1] as f64)))?;
+    }
+    if report.detects_lag {
+        let lag_frames = frames.iter().filter(|frame| frame.is_lag()).count();
+        writeln!(output, "<tr><td>Lag frames (missed VBlank)</td><td{}>{}</td></tr>", if lag_frames != 0 { " class=\"lag\"" } else { "" }, lag_frames)?;
+    }
+    writeln!(output, "</table>")?;
+    if report.idle_functions.is_empty() {
+        writeln!(output, "<p class=\"note\">Without --idle, the time spent waiting for VBlank counts as busy time and lag frames aren't detected.</p>")?;
+    }
+    Ok(())
+}
+
+// the busy time of every frame, in percent of a frame of the video standard, the lag frames in red
+fn write_frame_chart(output: &mut impl Write, report: &HtmlReport) -> std::io::Result<()> {
+    let HtmlReport { frames, timing, .. } = report;
+    if frames.is_empty() {
+        return Ok(());
+    }
+    let frame_cycles = timing.frame_cycles() as f64;
+    let max_percent = frames.iter().map(|frame| frame.busy_cycles as f64 * 100.0 / frame_cycles).fold(100.0, f64::max);
+    let x = |index: usize| (index as f64 + 0.5) * CHART_WIDTH / frames.len() as f64;
+    let y = |percent: f64| CHART_HEIGHT - percent * CHART_HEIGHT / max_percent;
+    writeln!(output, "<h2>Busy time per frame</h2>")?;
+    writeln!(output, "<svg class=\"chart\" viewBox=\"0 0 {} {}\" preserveAspectRatio=\"none\">", CHART_WIDTH, CHART_HEIGHT)?;
+    for frame in frames.iter().enumerate().filter(|(_index, frame)| report.detects_lag && frame.is_lag()).map(|(index, _frame)| index) {
+        writeln!(output, "<line x1=\"{0:.2}\" y1=\"0\" x2=\"{0:.2}\" y2=\"{1}\" stroke=\"#f88\" vector-effect=\"non-scaling-stroke\"/>", x(frame), CHART_HEIGHT)?;
+    }
+    writeln!(output, "<line x1=\"0\" y1=\"{0:.2}\" x2=\"{1}\" y2=\"{0:.2}\" stroke=\"#888\" stroke-dasharray=\"4\" vector-effect=\"non-scaling-stroke\"/>", y(100.0), CHART_WIDTH)?;
+    write!(output, "<polyline fill=\"none\" stroke=\"#36c\" vector-effect=\"non-scaling-stroke\" points=\"")?;
+    for (index, frame) in frames.iter().enumerate() {
+        write!(output, "{:.2},{:.2} ", x(index), y(frame.busy_cycles as f64 * 100.0 / frame_cycles))?;
+    }
+    writeln!(output, "\"/>\n</svg>")?;
+    writeln!(output, "<p class=\"note\">{} frames, from 0% to {:.0}% of a frame, the dashed line being a whole frame{}.</p>",
+        frames.len(), max_percent, if report.detects_lag { ", the red lines the lag frames" } else { "" })?;
+    Ok(())
+}
+
+fn write_hotspots(output: &mut impl Write, report: &HtmlReport) -> std::io::Result<()> {
+    let HtmlReport { profiling, symbols, idle_functions, duration_format, .. } = report;
+    let stats = compute_function_stats(profiling, idle_functions);
+    if stats.is_empty() {
+        return Ok(());
+    }
+    let capture_cycles = match (profiling.packets.first(), profiling.packets.last()) {
+        (Some(first), Some(last)) => last.cycle - first.cycle,
+        _ => 0,
+    };
+    let busy_cycles = capture_cycles - idle_cycles(profiling, idle_functions);
+    let percent = |cycles: u64| if busy_cycles == 0 { 0.0 } else { cycles as f64 * 100.0 / busy_cycles as f64 };
+    let mut by_self: Vec<_> = stats.iter().collect();
+    by_self.sort_by(|a, b| b.self_cycles.cmp(&a.self_cycles).then(a.address.cmp(&b.address)));
+    writeln!(output, "<h2>Hotspots</h2>")?;
+    writeln!(output, "<table>\n<tr><th>Function</th><th>Calls</th><th>Self time</th><th>Self %</th><th>Total time</th><th>Total %</th></tr>")?;
+    for function_stats in by_self.iter().take(HOTSPOTS_COUNT) {
+        writeln!(output, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{:.1}%</td><td>{}</td><td>{:.1}%</td></tr>",
+            escape(&symbols.name(function_stats.address)),
+            function_stats.calls,
+            escape(&duration_format.format_total(function_stats.self_cycles as f64)),
+            percent(function_stats.self_cycles),
+            escape(&duration_format.format_total(function_stats.total_cycles as f64)),
+            percent(function_stats.total_cycles),
+        )?;
+    }
+    writeln!(output, "</table>\n<p class=\"note\">The {} functions with the most self time, the percentages being of the busy time of the capture.</p>", HOTSPOTS_COUNT)?;
+    Ok(())
+}
+
+fn write_intervals(output: &mut impl Write, report: &HtmlReport) -> std::io::Result<()> {
+    let stats = report.intervals.stats();
+    if stats.is_empty() {
+        return Ok(());
+    }
+    let duration = |cycles: Option<f64>| cycles.map(|cycles| escape(&report.duration_format.format(cycles))).unwrap_or_default();
+    writeln!(output, "<h2>Intervals</h2>")?;
+    writeln!(output, "<table>\n<tr><th>Interval</th><th>Completed</th><th>Unfinished</th><th>Total</th><th>Average</th><th>Min</th><th>Max</th></tr>")?;
+    for stats in &stats {
+        writeln!(output, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(stats.name),
+            stats.completions,
+            stats.unfinished,
+            duration(Some(stats.total_cycles as f64)),
+            duration(stats.average_cycles()),
+            duration(stats.min_cycles.map(|cycles| cycles as f64)),
+            duration(stats.max_cycles.map(|cycles| cycles as f64)),
+        )?;
+    }
+    writeln!(output, "</table>")
+}
+
+// the busiest frames, with what their time went to
+fn write_worst_frames(output: &mut impl Write, report: &HtmlReport) -> std::io::Result<()> {
+    let HtmlReport { profiling, symbols, frames, duration_format, .. } = report;
+    if frames.is_empty() {
+        return Ok(());
+    }
+    let duration = |cycles: u64| escape(&duration_format.format(cycles as f64));
+    let mut worst_frames: Vec<_> = frames.iter().collect();
+    worst_frames.sort_by(|a, b| b.busy_cycles.cmp(&a.busy_cycles).then(a.index.cmp(&b.index)));
+    writeln!(output, "<h2>Worst frames</h2>")?;
+    for frame in worst_frames.into_iter().take(WORST_FRAMES_COUNT) {
+        writeln!(output, "<h3>Frame {} at {:.3} ms{}</h3>",
+            frame.index,
+            cycle_to_us(frame.start_cycle, profiling.mclk) / 1000.0,
+            if report.detects_lag && frame.is_lag() { " <span class=\"lag\">LAG</span>" } else { "" },
+        )?;
+        writeln!(output, "<p>Busy {}, in interrupts {}, CPU halted by DMA {}</p>", duration(frame.busy_cycles), duration(frame.interrupt_cycles), duration(frame.dma_halt_cycles))?;
+        let mut top_functions: Vec<_> = frame.self_cycles.iter().collect();
+        top_functions.sort_by(|(address_a, cycles_a), (address_b, cycles_b)| cycles_b.cmp(cycles_a).then(address_a.cmp(address_b)));
+        writeln!(output, "<table>\n<tr><th>Function</th><th>Self time</th><th>Of the busy time</th></tr>")?;
+        for (&address, &cycles) in top_functions.into_iter().take(TOP_FUNCTIONS_COUNT) {
+            let share = if frame.busy_cycles == 0 { 0.0 } else { cycles as f64 * 100.0 / frame.busy_cycles as f64 };
+            writeln!(output, "<tr><td>{}</td><td>{}</td><td>{:.1}%</td></tr>", escape(&symbols.name(address)), duration(cycles), share)?;
+        }
+        writeln!(output, "</table>")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::{io::{Cursor, Read}, path::Path};
+
+use crate::profiling::*;
+
+// a format of capture an emulator writes, read into the packets of the 68000 timeline the rest of the conversion works on
+pub trait InputFormat {
+    // shown in the messages
+    fn name(&self) -> &'static str;
+    // the extensions of its files, without the dot, to pick it when the content alone can't tell
+    fn extensions(&self) -> &'static [&'static str];
+    // whether the capture is in this format, from its magic bytes or the start of its content
+    fn detect(&self, input: &[u8]) -> bool;
+    fn read(&self, input: &[u8]) -> Result<ParsedProfilingFile, String>;
+    // the same from a pipe or stdin, size being 0 when it isn't known, formats that can't parse a chunk at a time read all of it first
+    fn read_stream(&self, input: &mut dyn Read, _size: u64) -> Result<ParsedProfilingFile, String> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data).map_err(|err| format!("Error reading the capture: {}", err))?;
+        self.read(&data)
+    }
+}
+
+// the mdp captures of BlastEm
+pub struct MdpFormat;
+
+impl InputFormat for MdpFormat {
+    fn name(&self) -> &'static str {
+        "mdp"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["mdp"]
+    }
+
+    fn detect(&self, input: &[u8]) -> bool {
+        input.starts_with(b"MDP")
+    }
+
+    fn read(&self, input: &[u8]) -> Result<ParsedProfilingFile, String> {
+        read_profiling_file(input)
+    }
+
+    fn read_stream(&self, input: &mut dyn Read, size: u64) -> Result<ParsedProfilingFile, String> {
+        read_profiling_stream(input, size)
+    }
+}
+
+// how much of the start of a stream is read to detect its format
+const DETECT_LEN: u64 = 64;
+
+// the formats tried in order, another emulator's format is added by implementing InputFormat and listing it here
+pub const INPUT_FORMATS: &[&dyn InputFormat] = &[&MdpFormat];
+
+// the format of a capture, detected from its content like read_symbols does, then from the extension of its path
+pub fn detect_input_format(input: &[u8], path: &str) -> Option<&'static dyn InputFormat> {
+    let extension = Path::new(path).extension().and_then(|extension| extension.to_str()).map(str::to_ascii_lowercase);
+    INPUT_FORMATS.iter().find(|format| format.detect(input))
+        .or_else(|| INPUT_FORMATS.iter().find(|format| extension.as_deref().is_some_and(|extension| format.extensions().contains(&extension))))
+        .copied()
+}
+
+// detect_input_format, failing with the list of the formats it knows
+fn known_input_format(input: &[u8], path: &str) -> Result<&'static dyn InputFormat, String> {
+    detect_input_format(input, path).ok_or_else(|| {
+        format!("Unknown capture format, the known formats are {}", INPUT_FORMATS.iter().map(|format| format.name()).collect::<Vec<_>>().join(", "))
+    })
+}
+
+// reads a capture in any of the known formats
+pub fn read_capture_file(input: &[u8], path: &str) -> Result<ParsedProfilingFile, String> {
+    known_input_format(input, path)?.read(input)
+}
+
+// reads a capture in any of the known formats from a pipe or stdin, its format is detected from its start
+pub fn read_capture_stream(mut input: impl Read, size: u64, path: &str) -> Result<ParsedProfilingFile, String> {
+    let mut start = Vec::new();
+    input.by_ref().take(DETECT_LEN).read_to_end(&mut start).map_err(|err| format!("Error reading the capture: {}", err))?;
+    known_input_format(&start, path)?.read_stream(&mut Cursor::new(start).chain(input), size)
+}
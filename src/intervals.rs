@@ -1,12 +1,134 @@
-use std::{collections::{BTreeMap, HashMap, HashSet}, fs::File, io::{BufWriter, Write}};
+use std::{borrow::Cow, convert::TryFrom, collections::{BTreeMap, BTreeSet, HashMap, VecDeque}, io::{BufWriter, Write}};
 
-use crate::profiling::{TraceEvent, cycle_to_us};
+#[cfg(feature = "toml-intervals")]
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::{frames::FrameSource, profiling::{CATEGORY_INTERVAL, CallStack, ExitMatching, FIRST_CUSTOM_PID, FIRST_CUSTOM_TID, ProfilingPacket, ProfilingPacketInner, TraceEvent, TraceEventArgs, cycle_to_us, nudged_slice}, report::DurationFormat, symbols::Symbols};
 
 #[derive(Debug)]
 struct IntervalInfo {
     name: String,
     tid: u32,
-    reached_at: Option<u64>,
+    color: Option<String>,
+    kind: IntervalKind,
+    // user defined key/values shown in the details of the slices
+    metadata: BTreeMap<String, serde_json::Value>,
+    // the interval its occurrences are nested in, on the thread of its parent, and how many ancestors it has
+    parent: Option<usize>,
+    depth: usize,
+    // (cycle, pc) the running occurrence started at
+    reached_at: Option<(u64, u32)>,
+    // for function spans, whether the running occurrence started in the interrupt, and the depth of the call starting it
+    entered_at: Option<(bool, usize)>,
+    // whether the time spent in the interrupt handlers is left out of its durations, and how much the running occurrence has
+    exclude_interrupts: bool,
+    interrupt_cycles: u64,
+    // (id, args) of the occurrences in flight, for async intervals
+    in_flight: VecDeque<(u64, OccurrenceArgs)>,
+    // number of occurrences so far, completed ones for regular intervals, started ones for async intervals
+    occurrence_count: u64,
+    // starts reached while the regular interval was already running, they never reach an end of their own
+    repeated_starts: u64,
+    // current value, for counters
+    count: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IntervalKind {
+    // from a start point to an end point
+    Interval,
+    // only marks when its start points are reached
+    Instant,
+    // incremented when its start points are reached, reset by its end points
+    Counter { reset_each_frame: bool },
+    // each start point starts a new occurrence, even if others are in flight, end points end the oldest one
+    Async,
+    // from the entry of a function until it returns, from the subroutine enters and exits rather than breakpoints
+    Function,
+}
+
+impl IntervalKind {
+    // whether its occurrences are drawn as regular slices, which can be nested
+    fn is_span(self) -> bool {
+        matches!(self, IntervalKind::Interval | IntervalKind::Function)
+    }
+}
+
+// display name, sort order and process of a thread of the trace, found by the name md-profiler gives it
+#[derive(Debug)]
+struct ThreadSettings {
+    name: String,
+    display_name: Option<String>,
+    sort_index: Option<u32>,
+    process: Option<String>,
+}
+
+// how the breakpoints subcommand writes the start and end points: the addresses BlastEm reads, a list to review them,
+// or the commands setting a breakpoint on each address in the debugger of an emulator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointFormat {
+    Binary,
+    Text,
+    Json,
+    BlastemCommands,
+    MameScript,
+}
+
+impl BreakpointFormat {
+    pub fn parse(format: &str) -> Result<BreakpointFormat, String> {
+        match format {
+            "binary" => Ok(BreakpointFormat::Binary),
+            "text" => Ok(BreakpointFormat::Text),
+            "json" => Ok(BreakpointFormat::Json),
+            "blastem" => Ok(BreakpointFormat::BlastemCommands),
+            "mame" => Ok(BreakpointFormat::MameScript),
+            _ => Err(format!("Unknown breakpoint format: {}, expected binary, text, json, blastem or mame", format)),
+        }
+    }
+}
+
+// a start or end point of an interval, as written by the text and json breakpoint formats
+#[derive(Debug, Serialize)]
+pub struct Breakpoint<'a> {
+    pub address: String,
+    // "start" or "end"
+    pub role: &'static str,
+    pub interval: &'a str,
+}
+
+#[derive(Debug)]
+pub struct IntervalOccurrence {
+    // index of the interval in the interval file
+    pub interval: usize,
+    pub start_cycle: u64,
+    pub end_cycle: u64,
+    // time spent in the interrupt handlers, for the intervals excluding them
+    pub interrupt_cycles: u64,
+}
+
+impl IntervalOccurrence {
+    // duration without the interrupt handlers excluded from it
+    pub fn cycles(&self) -> u64 {
+        self.end_cycle - self.start_cycle - self.interrupt_cycles
+    }
+}
+
+#[derive(Debug)]
+pub struct IntervalStats<'a> {
+    pub name: &'a str,
+    pub completions: u64,
+    // occurrences still running at the end of the capture, and starts reached while the interval was already running
+    pub unfinished: u64,
+    pub total_cycles: u64,
+    pub min_cycles: Option<u64>,
+    pub max_cycles: Option<u64>,
+}
+
+impl IntervalStats<'_> {
+    pub fn average_cycles(&self) -> Option<f64> {
+        (self.completions != 0).then(|| self.total_cycles as f64 / self.completions as f64)
+    }
 }
 
 #[derive(Debug, Default)]
@@ -14,129 +136,1146 @@ pub struct Intervals {
     intervals_info: Vec<IntervalInfo>,
     starts: HashMap<u32, Vec<usize>>,
     ends: HashMap<u32, Vec<usize>>,
+    // the function spans by address of their function
+    functions: HashMap<u32, Vec<usize>>,
+    // the running subroutines, in the main code and in the interrupt, for the function spans
+    main_stack: CallStack<()>,
+    interrupt_stack: CallStack<()>,
+    in_interrupt: bool,
+    // cycle the running interrupt handler was entered at
+    interrupt_entered_at: Option<u64>,
+    // every completed interval, in the order they ended
+    occurrences: Vec<IntervalOccurrence>,
+    // what resets the counters reset each frame
+    frame_source: FrameSource,
+    next_async_id: u64,
+    // appends "#N" to the names of the slices
+    number_names: bool,
+    // where the breakpoints that aren't part of any interval are drawn
+    breakpoint_tid: u32,
+    threads: Vec<ThreadSettings>,
+    // the processes threads are moved to, in the order they first appear in the interval file, from FIRST_CUSTOM_PID
+    processes: Vec<String>,
+    // the functions waiting for the VBlank listed in the interval file, see --idle
+    idle_functions: Vec<u32>,
 }
 
 impl Intervals {
+    pub fn set_frame_source(&mut self, frame_source: FrameSource) {
+        self.frame_source = frame_source;
+    }
+
+    pub fn set_exit_matching(&mut self, exit_matching: ExitMatching) {
+        self.main_stack = CallStack::new(exit_matching);
+        self.interrupt_stack = CallStack::new(exit_matching);
+    }
+
+    pub fn set_number_names(&mut self, number_names: bool) {
+        self.number_names = number_names;
+    }
+
+    pub fn set_breakpoint_thread(&mut self, tid: u32) {
+        self.breakpoint_tid = tid;
+    }
+
+    pub fn breakpoint_thread(&self) -> u32 {
+        self.breakpoint_tid
+    }
+
+    pub fn idle_functions(&self) -> &[u32] {
+        &self.idle_functions
+    }
+
+    // number of processes the trace of a capture can have
+    pub fn pid_count(&self) -> u32 {
+        FIRST_CUSTOM_PID + self.processes.len() as u32
+    }
+
+    // renames, reorders and moves to other processes the threads listed in the interval file
+    pub fn configure_threads(&self, trace_events: &mut Vec<TraceEvent>) {
+        for (index, process) in self.processes.iter().enumerate() {
+            trace_events.push(TraceEvent {
+                name: "process_name".into(),
+                ph: 'M',
+                ts: 0.0,
+                dur: 0.0,
+                pid: FIRST_CUSTOM_PID + index as u32,
+                tid: 0,
+                args: Some(TraceEventArgs {
+                    name: Some(process.clone()),
+                    sort_index: None,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            });
+        }
+        for settings in &self.threads {
+            let threads: Vec<(u32, u32)> = trace_events.iter()
+                .filter(|trace_event| trace_event.name == "thread_name" && trace_event.args.as_ref().and_then(|args| args.name.as_deref()) == Some(settings.name.as_str()))
+                .map(|trace_event| (trace_event.pid, trace_event.tid))
+                .collect();
+            if threads.is_empty() {
+                eprintln!("Warning: the trace has no thread named {}, its settings from the interval file are ignored", settings.name);
+            }
+            for (pid, tid) in threads {
+                let mut sorted = false;
+                for trace_event in trace_events.iter_mut().filter(|trace_event| trace_event.ph == 'M' && trace_event.pid == pid && trace_event.tid == tid) {
+                    let args = trace_event.args.get_or_insert_with(Default::default);
+                    match trace_event.name.as_ref() {
+                        "thread_name" if settings.display_name.is_some() => args.name = settings.display_name.clone(),
+                        "thread_sort_index" if settings.sort_index.is_some() => {
+                            args.sort_index = settings.sort_index;
+                            sorted = true;
+                        },
+                        _ => {},
+                    }
+                }
+                if !sorted && settings.sort_index.is_some() {
+                    trace_events.push(TraceEvent {
+                        name: "thread_sort_index".into(),
+                        ph: 'M',
+                        ts: 0.0,
+                        dur: 0.0,
+                        pid,
+                        tid,
+                        args: Some(TraceEventArgs {
+                            name: None,
+                            sort_index: settings.sort_index,
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    });
+                }
+                if let Some(process) = &settings.process {
+                    let new_pid = FIRST_CUSTOM_PID + self.processes.iter().position(|name| name == process).unwrap() as u32;
+                    for trace_event in trace_events.iter_mut().filter(|trace_event| trace_event.pid == pid && trace_event.tid == tid) {
+                        trace_event.pid = new_pid;
+                    }
+                }
+            }
+        }
+    }
+
+    // whether this address starts or ends any interval
+    pub fn contains(&self, pc: u32) -> bool {
+        self.starts.contains_key(&pc) || self.ends.contains_key(&pc)
+    }
+
+    // starts and ends the function spans, counts the interrupt time of the intervals excluding it, and resets the counters
+    // that are reset each frame if this packet starts a new frame
+    pub fn process_packet(&mut self, packet: &ProfilingPacket, trace_events: &mut Vec<TraceEvent>, mclk: f64) {
+        let cycle = packet.cycle;
+        if !self.functions.is_empty() {
+            self.follow_functions(packet, trace_events, mclk);
+        }
+        match packet.inner {
+            ProfilingPacketInner::InterruptEnter { .. } => {
+                self.in_interrupt = true;
+                self.interrupt_entered_at = Some(cycle);
+            },
+            ProfilingPacketInner::InterruptExit => {
+                for interval_info in &mut self.intervals_info {
+                    interval_info.interrupt_cycles += interrupt_cycles(interval_info, self.interrupt_entered_at, cycle);
+                }
+                self.in_interrupt = false;
+                self.interrupt_entered_at = None;
+            },
+            _ => {},
+        }
+        if !self.frame_source.is_frame_start(&packet.inner) {
+            return;
+        }
+        for interval_info in &mut self.intervals_info {
+            if interval_info.kind == (IntervalKind::Counter { reset_each_frame: true }) && interval_info.count != 0 {
+                interval_info.count = 0;
+                trace_events.push(counter_event(interval_info, cycle, mclk));
+            }
+        }
+    }
+
+    fn follow_functions(&mut self, packet: &ProfilingPacket, trace_events: &mut Vec<TraceEvent>, mclk: f64) {
+        // whatever the handler didn't return from ends with it
+        if let ProfilingPacketInner::InterruptExit = packet.inner {
+            self.interrupt_stack.clear();
+            self.end_returned_functions(trace_events, packet.cycle, mclk);
+        }
+        let change = match packet.call_stack_change() {
+            Some(change) => change,
+            None => return,
+        };
+        if let Some(exit_stack_pointer) = change.exit {
+            let stack = if self.in_interrupt { &mut self.interrupt_stack } else { &mut self.main_stack };
+            stack.exit(exit_stack_pointer).for_each(drop);
+            self.end_returned_functions(trace_events, packet.cycle, mclk);
+        }
+        let (target_subroutine, stack_pointer) = match change.enter {
+            Some(enter) => enter,
+            None => return,
+        };
+        let stack = if self.in_interrupt { &mut self.interrupt_stack } else { &mut self.main_stack };
+        stack.enter((), stack_pointer);
+        let depth = stack.len();
+        // a recursive call is part of the occurrence of the outermost one
+        for &interval_info_index in self.functions.get(&target_subroutine).unwrap_or(&vec![]) {
+            let parent_running = self.intervals_info[interval_info_index].parent.is_none_or(|parent| self.intervals_info[parent].reached_at.is_some());
+            let interval_info = &mut self.intervals_info[interval_info_index];
+            if interval_info.reached_at.is_none() {
+                if parent_running {
+                    interval_info.reached_at = Some((packet.cycle, target_subroutine));
+                    interval_info.entered_at = Some((self.in_interrupt, depth));
+                } else {
+                    eprintln!("Warning: interval {} started at cycle {} outside of its parent, ignored", interval_info.name, packet.cycle);
+                }
+            }
+        }
+    }
+
+    // ends the function spans whose call was returned from, the innermost first
+    fn end_returned_functions(&mut self, trace_events: &mut Vec<TraceEvent>, cycle: u64, mclk: f64) {
+        let depth = if self.in_interrupt { self.interrupt_stack.len() } else { self.main_stack.len() };
+        let mut returned: Vec<usize> = (0..self.intervals_info.len())
+            .filter(|&index| self.intervals_info[index].entered_at.is_some_and(|(in_interrupt, entered_depth)| in_interrupt == self.in_interrupt && entered_depth > depth))
+            .collect();
+        returned.sort_by_key(|&index| std::cmp::Reverse(self.intervals_info[index].depth));
+        for index in returned {
+            // a span ends where its function started, as there's no address to return to
+            if let Some((_cycle, pc)) = self.intervals_info[index].reached_at {
+                self.end_occurrence(index, trace_events, cycle, pc, mclk);
+            }
+        }
+    }
+
     pub fn reach(&mut self, pc: u32, trace_events: &mut Vec<TraceEvent>, cycle: u64, mclk: f64) {
         for &interval_info_index in self.ends.get(&pc).unwrap_or(&vec![]) {
             let interval_info = &mut self.intervals_info[interval_info_index];
-            if let Some(reached_at) = interval_info.reached_at {
-                let trace_event = TraceEvent {
+            if let IntervalKind::Counter { .. } = interval_info.kind {
+                interval_info.count = 0;
+                trace_events.push(counter_event(interval_info, cycle, mclk));
+            } else if interval_info.kind == IntervalKind::Async {
+                if let Some((id, mut args)) = interval_info.in_flight.pop_front() {
+                    args.end = Some((cycle, pc));
+                    self.occurrences.push(IntervalOccurrence {
+                        interval: interval_info_index,
+                        start_cycle: args.start_cycle,
+                        end_cycle: cycle,
+                        interrupt_cycles: 0,
+                    });
+                    trace_events.push(async_event(interval_info, self.number_names, 'e', id, args, mclk));
+                }
+            }
+        }
+        // the children before their parent, see read_intervals
+        for interval_info_index in self.ends.get(&pc).cloned().unwrap_or_default() {
+            if self.intervals_info[interval_info_index].kind.is_span() {
+                self.end_occurrence(interval_info_index, trace_events, cycle, pc, mclk);
+            }
+        }
+        for &interval_info_index in self.starts.get(&pc).unwrap_or(&vec![]) {
+            let parent_running = self.intervals_info[interval_info_index].parent.is_none_or(|parent| self.intervals_info[parent].reached_at.is_some());
+            let interval_info = &mut self.intervals_info[interval_info_index];
+            if let IntervalKind::Counter { .. } = interval_info.kind {
+                interval_info.count += 1;
+                trace_events.push(counter_event(interval_info, cycle, mclk));
+            } else if interval_info.kind == IntervalKind::Async {
+                let id = self.next_async_id;
+                self.next_async_id += 1;
+                let occurrence = interval_info.occurrence_count;
+                interval_info.occurrence_count += 1;
+                let args = OccurrenceArgs { occurrence, start_cycle: cycle, start_pc: pc, end: None, interrupt_cycles: None };
+                trace_events.push(async_event(interval_info, self.number_names, 'b', id, args.clone(), mclk));
+                interval_info.in_flight.push_back((id, args));
+            } else if interval_info.kind == IntervalKind::Instant {
+                trace_events.push(TraceEvent {
                     name: interval_info.name.clone().into(),
-                    ph: 'X',
-                    ts: cycle_to_us(reached_at, mclk),
-                    dur: cycle_to_us(cycle - reached_at, mclk),
+                    ph: 'i',
+                    ts: cycle_to_us(cycle, mclk),
+                    dur: 0.0,
                     pid: 0,
                     tid: interval_info.tid,
-                    args: None,
-                    s: None,
-                };
-                trace_events.push(trace_event);
-                interval_info.reached_at = None;
+                    s: Some('t'),
+                    cname: interval_info.color.clone().map(Into::into),
+                    cat: Some(CATEGORY_INTERVAL.into()),
+                    ..Default::default()
+                });
+            } else if interval_info.reached_at.is_some() {
+                interval_info.repeated_starts += 1;
+            } else if parent_running {
+                interval_info.reached_at = Some((cycle, pc));
+            } else {
+                eprintln!("Warning: interval {} started at cycle {} outside of its parent, ignored", interval_info.name, cycle);
             }
         }
-        for &interval_info_index in self.starts.get(&pc).unwrap_or(&vec![]) {
-            let interval_info = &mut self.intervals_info[interval_info_index];
-            if interval_info.reached_at.is_none() {
-                interval_info.reached_at = Some(cycle);
+    }
+
+    // ends the running occurrence of a regular interval, after the ones of its children, which can't outlive it
+    fn end_occurrence(&mut self, interval_info_index: usize, trace_events: &mut Vec<TraceEvent>, cycle: u64, pc: u32, mclk: f64) {
+        let (reached_at, start_pc) = match self.intervals_info[interval_info_index].reached_at {
+            Some(reached_at) => reached_at,
+            None => return,
+        };
+        for child in 0..self.intervals_info.len() {
+            if self.intervals_info[child].parent == Some(interval_info_index) && self.intervals_info[child].reached_at.is_some() {
+                eprintln!("Warning: interval {} was still running when its parent {} ended at cycle {}, it ends with it", self.intervals_info[child].name, self.intervals_info[interval_info_index].name, cycle);
+                self.end_occurrence(child, trace_events, cycle, pc, mclk);
             }
         }
+        let interval_info = &mut self.intervals_info[interval_info_index];
+        let occurrence = interval_info.occurrence_count;
+        interval_info.occurrence_count += 1;
+        // the handler running when it ends counts up to now
+        let interrupt_cycles = interval_info.interrupt_cycles + interrupt_cycles(interval_info, self.interrupt_entered_at, cycle);
+        let args = OccurrenceArgs {
+            occurrence,
+            start_cycle: reached_at,
+            start_pc,
+            end: Some((cycle, pc)),
+            interrupt_cycles: interval_info.exclude_interrupts.then_some(interrupt_cycles),
+        };
+        // the children are nudged inside their parent like nested subroutines, in case they start or end together
+        let (ts, dur) = nudged_slice(reached_at, cycle, interval_info.depth, mclk);
+        let trace_event = TraceEvent {
+            name: occurrence_name(interval_info, self.number_names, occurrence),
+            ph: 'X',
+            ts,
+            dur,
+            pid: 0,
+            tid: interval_info.tid,
+            args: Some(args.trace_event_args(interval_info)),
+            cname: interval_info.color.clone().map(Into::into),
+            cat: Some(CATEGORY_INTERVAL.into()),
+            ..Default::default()
+        };
+        trace_events.push(trace_event);
+        self.occurrences.push(IntervalOccurrence {
+            interval: interval_info_index,
+            start_cycle: reached_at,
+            end_cycle: cycle,
+            interrupt_cycles,
+        });
+        interval_info.reached_at = None;
+        interval_info.entered_at = None;
+        interval_info.interrupt_cycles = 0;
+    }
+
+    pub fn occurrences(&self) -> &[IntervalOccurrence] {
+        &self.occurrences
+    }
+
+    pub fn interval_name(&self, interval: usize) -> &str {
+        &self.intervals_info[interval].name
+    }
+
+    // the cycle the first finished occurrence of the intervals with this name started at
+    pub fn first_occurrence_start(&self, name: &str) -> Option<u64> {
+        self.occurrences.iter()
+            .filter(|occurrence| self.intervals_info[occurrence.interval].name == name)
+            .map(|occurrence| occurrence.start_cycle)
+            .min()
+    }
+
+    // aggregated durations of each interval, in the order of the interval file, instants and counters are left out
+    pub fn stats(&self) -> Vec<IntervalStats<'_>> {
+        let mut stats: Vec<_> = self.intervals_info.iter().map(|interval_info| IntervalStats {
+            name: &interval_info.name,
+            completions: 0,
+            unfinished: interval_info.reached_at.is_some() as u64 + interval_info.in_flight.len() as u64 + interval_info.repeated_starts,
+            total_cycles: 0,
+            min_cycles: None,
+            max_cycles: None,
+        }).collect();
+        for occurrence in &self.occurrences {
+            let cycles = occurrence.cycles();
+            let stats = &mut stats[occurrence.interval];
+            stats.completions += 1;
+            stats.total_cycles += cycles;
+            stats.min_cycles = Some(stats.min_cycles.map_or(cycles, |min_cycles| min_cycles.min(cycles)));
+            stats.max_cycles = Some(stats.max_cycles.map_or(cycles, |max_cycles| max_cycles.max(cycles)));
+        }
+        stats.into_iter()
+            .zip(&self.intervals_info)
+            .filter(|(_stats, interval_info)| interval_info.kind.is_span() || interval_info.kind == IntervalKind::Async)
+            .map(|(stats, _interval_info)| stats)
+            .collect()
     }
 
-    pub fn write_to_file(&self, output: &mut File) {
-        let addresses: HashSet<_> = self.starts.keys().copied().chain(self.ends.keys().copied()).collect();
+    pub fn write_to_file(&self, output: &mut impl Write) {
         let mut buf_writer = BufWriter::new(output);
-        for address in addresses {
-            buf_writer.write_all(&address.to_ne_bytes()).unwrap();
+        for address in self.breakpoint_addresses() {
+            buf_writer.write_all(&address.to_le_bytes()).unwrap();
         }
     }
+
+    // the addresses of the start and end points, each one once
+    fn breakpoint_addresses(&self) -> BTreeSet<u32> {
+        self.starts.keys().copied().chain(self.ends.keys().copied()).collect()
+    }
+
+    // every start and end point, by address, an address shared by several intervals or roles being listed once for each
+    pub fn breakpoints(&self) -> Vec<Breakpoint<'_>> {
+        let mut points: Vec<(u32, &'static str, usize)> = Vec::new();
+        for (role, points_of_role) in [("start", &self.starts), ("end", &self.ends)] {
+            for (&address, intervals) in points_of_role {
+                points.extend(intervals.iter().map(|&interval| (address, role, interval)));
+            }
+        }
+        // the starts before the ends of an address, then in the order of the interval file
+        points.sort_unstable_by_key(|&(address, role, interval)| (address, role != "start", interval));
+        points.into_iter().map(|(address, role, interval)| Breakpoint {
+            address: format!("{:#08x}", address),
+            role,
+            interval: &self.intervals_info[interval].name,
+        }).collect()
+    }
+
+    // the same as write_to_file, in the given format
+    pub fn write_breakpoints(&self, output: &mut impl Write, format: BreakpointFormat) {
+        match format {
+            BreakpointFormat::Binary => self.write_to_file(output),
+            BreakpointFormat::Text => {
+                let mut buf_writer = BufWriter::new(output);
+                for breakpoint in self.breakpoints() {
+                    writeln!(buf_writer, "{} {} {}", breakpoint.address, breakpoint.role, breakpoint.interval).unwrap();
+                }
+            },
+            BreakpointFormat::Json => {
+                let mut buf_writer = BufWriter::new(output);
+                serde_json::to_writer_pretty(&mut buf_writer, &self.breakpoints()).unwrap();
+                writeln!(buf_writer).unwrap();
+            },
+            // to paste in the debugger of BlastEm
+            BreakpointFormat::BlastemCommands => {
+                let mut buf_writer = BufWriter::new(output);
+                for address in self.breakpoint_addresses() {
+                    writeln!(buf_writer, "b {:#08x}", address).unwrap();
+                }
+            },
+            // for -debugscript, the numbers of the MAME debugger are hexadecimal
+            BreakpointFormat::MameScript => {
+                let mut buf_writer = BufWriter::new(output);
+                for address in self.breakpoint_addresses() {
+                    writeln!(buf_writer, "bpset {:06X}", address).unwrap();
+                }
+            },
+        }
+    }
+}
+
+pub fn write_interval_stats(output: &mut impl Write, stats: &[IntervalStats], duration_format: &DurationFormat) {
+    for stats in stats {
+        writeln!(output, "{}: {} completed, {} unfinished", stats.name, stats.completions, stats.unfinished).unwrap();
+        if let (Some(average_cycles), Some(min_cycles), Some(max_cycles)) = (stats.average_cycles(), stats.min_cycles, stats.max_cycles) {
+            writeln!(output, "  total: {}", duration_format.format_total(stats.total_cycles as f64)).unwrap();
+            writeln!(output, "  avg: {}", duration_format.format(average_cycles)).unwrap();
+            writeln!(output, "  min: {}", duration_format.format(min_cycles as f64)).unwrap();
+            writeln!(output, "  max: {}", duration_format.format(max_cycles as f64)).unwrap();
+        }
+    }
+}
+
+// durations in microseconds, empty when the interval never completed
+pub fn write_interval_stats_csv(output: &mut impl Write, stats: &[IntervalStats], mclk: f64) {
+    let mut buf_writer = BufWriter::new(output);
+    let us = |cycles: Option<f64>| cycles.map(|cycles| format!("{:.3}", cycles / mclk * 1_000_000.0)).unwrap_or_default();
+    writeln!(buf_writer, "name,completions,unfinished,total_us,avg_us,min_us,max_us").unwrap();
+    for stats in stats {
+        // the name is quoted as it may contain commas
+        writeln!(buf_writer, "\"{}\",{},{},{},{},{},{}",
+            stats.name.replace('"', "\"\""),
+            stats.completions,
+            stats.unfinished,
+            us(Some(stats.total_cycles as f64)),
+            us(stats.average_cycles()),
+            us(stats.min_cycles.map(|cycles| cycles as f64)),
+            us(stats.max_cycles.map(|cycles| cycles as f64)),
+        ).unwrap();
+    }
+}
+
+// which occurrence of an interval a slice is, where and when it started and ended
+#[derive(Debug, Clone)]
+struct OccurrenceArgs {
+    occurrence: u64,
+    start_cycle: u64,
+    start_pc: u32,
+    // (cycle, pc), unknown for the begin event of an async interval
+    end: Option<(u64, u32)>,
+    // time spent in the interrupt handlers, for the intervals excluding them
+    interrupt_cycles: Option<u64>,
+}
+
+impl OccurrenceArgs {
+    fn trace_event_args<'a>(&self, interval_info: &IntervalInfo) -> TraceEventArgs<'a> {
+        TraceEventArgs {
+            occurrence: Some(self.occurrence),
+            start_cycle: Some(self.start_cycle),
+            end_cycle: self.end.map(|(end_cycle, _end_pc)| end_cycle),
+            cycles: self.end.map(|(end_cycle, _end_pc)| end_cycle - self.start_cycle - self.interrupt_cycles.unwrap_or(0)),
+            interrupt_cycles: self.interrupt_cycles,
+            start_pc: Some(format!("{:#08x}", self.start_pc)),
+            end_pc: self.end.map(|(_end_cycle, end_pc)| format!("{:#08x}", end_pc)),
+            metadata: Some(interval_info.metadata.clone()).filter(|metadata| !metadata.is_empty()),
+            ..Default::default()
+        }
+    }
+}
+
+// time spent in the interrupt handler entered at that cycle since the running occurrence of the interval started, if it excludes them
+fn interrupt_cycles(interval_info: &IntervalInfo, interrupt_entered_at: Option<u64>, cycle: u64) -> u64 {
+    match (interval_info.exclude_interrupts, interval_info.reached_at, interrupt_entered_at) {
+        (true, Some((start_cycle, _start_pc)), Some(interrupt_entered_at)) => cycle - start_cycle.max(interrupt_entered_at),
+        _ => 0,
+    }
+}
+
+fn occurrence_name<'a>(interval_info: &IntervalInfo, number_names: bool, occurrence: u64) -> Cow<'a, str> {
+    if number_names {
+        format!("{} #{}", interval_info.name, occurrence).into()
+    } else {
+        interval_info.name.clone().into()
+    }
+}
+
+// the begin ('b') or end ('e') of an occurrence of an async interval
+fn async_event<'a>(interval_info: &IntervalInfo, number_names: bool, ph: char, id: u64, args: OccurrenceArgs, mclk: f64) -> TraceEvent<'a> {
+    TraceEvent {
+        name: occurrence_name(interval_info, number_names, args.occurrence),
+        ph,
+        ts: cycle_to_us(args.end.map_or(args.start_cycle, |(end_cycle, _end_pc)| end_cycle), mclk),
+        dur: 0.0,
+        pid: 0,
+        tid: interval_info.tid,
+        args: Some(args.trace_event_args(interval_info)),
+        cname: interval_info.color.clone().map(Into::into),
+        id: Some(id),
+        cat: Some(CATEGORY_INTERVAL.into()),
+        ..Default::default()
+    }
 }
 
-fn read_interval_elm(input: &str, symbols: &BTreeMap<String, u32>) -> Vec<u32> {
-    if let Some(&address) = symbols.get(input) {
-        return vec![address];
+fn counter_event<'a>(interval_info: &IntervalInfo, cycle: u64, mclk: f64) -> TraceEvent<'a> {
+    TraceEvent {
+        name: interval_info.name.clone().into(),
+        ph: 'C',
+        ts: cycle_to_us(cycle, mclk),
+        dur: 0.0,
+        pid: 0,
+        tid: 0,
+        args: Some(TraceEventArgs {
+            value: Some(interval_info.count),
+            ..Default::default()
+        }),
+        cname: interval_info.color.clone().map(Into::into),
+        cat: Some(CATEGORY_INTERVAL.into()),
+        ..Default::default()
+    }
+}
+
+// "file:line" of a source file
+fn source_line(input: &str) -> Option<(&str, u32)> {
+    let (file, line) = input.rsplit_once(':')?;
+    match line.parse() {
+        Ok(line) if !file.is_empty() => Some((file, line)),
+        _ => None,
+    }
+}
+
+fn read_interval_elm(input: &str, symbols: &Symbols) -> Result<Vec<u32>, String> {
+    let labels = symbols.label_to_address();
+    if let Some(&address) = labels.get(input) {
+        return Ok(vec![address]);
+    }
+    if let Some((file, line)) = source_line(input) {
+        return symbols.source_line_address(file, line).map(|address| vec![address]);
+    }
+    if let Some(addresses) = symbols.pattern_addresses(input)? {
+        return Ok(addresses);
     }
     if let Ok(address) = u32::from_str_radix(input, 16) {
-        return vec![address];
+        return Ok(vec![address]);
+    }
+    if let Some(address) = evaluate_expression(input, labels)? {
+        return Ok(vec![address]);
     }
     let mut ret = Vec::new();
     let mut prefix = String::from("mdp_label_");
     prefix.push_str(input);
     // add all symbols that start with prefix
-    for (_symbol, &address) in symbols.range(prefix.clone()..).take_while(|(symbol, _)| symbol.starts_with(&prefix)) {
+    for (_symbol, &address) in labels.range(prefix.clone()..).take_while(|(symbol, _)| symbol.starts_with(&prefix)) {
         ret.push(address);
     }
     if ret.is_empty() {
-        panic!("{} not found in the symbol file", input);
+        return Err(format!("{} not found in the symbol file", input));
     }
-    ret
+    Ok(ret)
 }
 
-pub fn read_intervals(input: &[u8], symbols: &BTreeMap<String, u32>) -> (Intervals, HashMap<String, u32>) {
+// "0x" or "$" prefixed hex, or decimal
+fn parse_number(input: &str) -> Option<u32> {
+    match input.strip_prefix("0x").or_else(|| input.strip_prefix('$')) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None if input.starts_with(|c: char| c.is_ascii_digit()) => input.parse().ok(),
+        None => None,
+    }
+}
+
+// symbols and numbers added or subtracted (Symbol+0x12, $FF0000-4), None if the input isn't an expression
+fn evaluate_expression(input: &str, symbols: &BTreeMap<String, u32>) -> Result<Option<u32>, String> {
+    let is_expression = input.starts_with("0x") || input.starts_with('$') || input.chars().skip(1).any(|c| c == '+' || c == '-');
+    if !is_expression {
+        return Ok(None);
+    }
+    let mut value: i64 = 0;
+    let mut sign = 1;
+    let mut rest = input;
+    loop {
+        let term_end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let term = rest[..term_end].trim();
+        if term.is_empty() {
+            return Err(format!("Invalid expression {}: missing term", input));
+        }
+        let term_value = match symbols.get(term) {
+            Some(&address) => address,
+            None => match parse_number(term) {
+                Some(number) => number,
+                None => return Err(format!("Invalid expression {}: {} not found in the symbol file", input, term)),
+            },
+        };
+        value += sign * i64::from(term_value);
+        if term_end == rest.len() {
+            break;
+        }
+        sign = if rest[term_end..].starts_with('+') { 1 } else { -1 };
+        rest = &rest[term_end + 1..];
+    }
+    u32::try_from(value).map(Some).map_err(|_| format!("Invalid expression {}: {:#x} is outside of the address space", input, value))
+}
+
+// an interval as written in the interval file, before its labels are resolved
+struct IntervalDefinition {
+    starts: Vec<String>,
+    ends: Vec<String>,
+    name: String,
+    thread: Option<String>,
+    color: Option<String>,
+    kind: IntervalKind,
+    metadata: BTreeMap<String, serde_json::Value>,
+    // name of the interval it is nested in
+    parent: Option<String>,
+    exclude_interrupts: bool,
+    // where it's written in a csv interval file
+    position: Option<CsvPosition>,
+}
+
+// the line of a csv interval file, and the columns (from 1) of its start and end points
+#[derive(Debug, Clone, Copy)]
+struct CsvPosition {
+    line: usize,
+    start_column: usize,
+    end_column: usize,
+}
+
+impl IntervalDefinition {
+    // "intervals.csv:12" for a line of a csv file, the file otherwise
+    fn context(&self, source: &str) -> String {
+        match self.position {
+            Some(position) => format!("{}:{}", source, position.line),
+            None => source.to_string(),
+        }
+    }
+
+    // "intervals.csv:12:5" with the column of its start (or end) points, "intervals.toml, interval NAME" otherwise
+    fn location(&self, source: &str, ends: bool) -> String {
+        match self.position {
+            Some(position) => format!("{}:{}:{}", source, position.line, if ends { position.end_column } else { position.start_column }),
+            None => format!("{}, interval {}", source, self.name),
+        }
+    }
+}
+
+// everything an interval file lists, before its labels are resolved
+#[derive(Default)]
+struct IntervalFileContents {
+    definitions: Vec<IntervalDefinition>,
+    threads: Vec<ThreadSettings>,
+    // functions waiting for the VBlank, see --idle
+    idle: Vec<String>,
+}
+
+// START POINTS,END POINTS,NAME,THREAD,PARENT lines, without end points for a span from the entry of the functions until they return
+fn read_csv_interval_definitions(input: &str, source: &str) -> Vec<IntervalDefinition> {
+    let mut definitions = Vec::new();
+    for (line_index, line) in input.split('\n').enumerate() {
+        if line.trim_start().starts_with("//") {
+            continue;
+        }
+        let fields = match split_csv_fields(line) {
+            Ok(fields) => fields,
+            Err((column, err)) => {
+                eprintln!("Warning: {}:{}:{}: {}, the line is skipped", source, line_index + 1, column, err);
+                continue;
+            },
+        };
+        if fields[0].text.is_empty() && !fields[0].quoted {
+            continue;
+        }
+        // the labels of a quoted field aren't split on semicolons
+        let points = |field: &CsvField| if field.quoted { vec![field.text.clone()] } else { field.text.split(';').map(str::to_owned).collect() };
+        let (starts, ends) = if fields.len() == 1 {
+            let elm = &fields[0].text;
+            (vec![format!("{}_start", elm)], vec![format!("{}_end", elm)])
+        } else if fields[1].text.is_empty() {
+            (points(&fields[0]), Vec::new())
+        } else {
+            (points(&fields[0]), points(&fields[1]))
+        };
+        let kind = if ends.is_empty() { IntervalKind::Function } else { IntervalKind::Interval };
+        let name = if fields.len() >= 3 {
+            fields[2].text.clone()
+        } else if fields.iter().any(|field| field.quoted) {
+            fields.iter().map(|field| field.text.as_str()).collect::<Vec<_>>().join(",")
+        } else {
+            line.to_owned()
+        };
+        let optional_field = |index: usize| fields.get(index).map(|field| field.text.clone()).filter(|field| !field.is_empty());
+        definitions.push(IntervalDefinition {
+            starts,
+            ends,
+            name,
+            thread: optional_field(3),
+            color: None,
+            kind,
+            metadata: BTreeMap::new(),
+            parent: optional_field(4),
+            exclude_interrupts: false,
+            position: Some(CsvPosition {
+                line: line_index + 1,
+                start_column: fields[0].column,
+                end_column: fields.get(1).map_or(fields[0].column, |field| field.column),
+            }),
+        });
+    }
+    definitions
+}
+
+// a field of a line of a csv interval file
+struct CsvField {
+    text: String,
+    quoted: bool,
+    // where it starts in the line, from 1
+    column: usize,
+}
+
+// splits a line on its commas, trimming the fields, a field can be quoted to contain commas and semicolons, a quote in it being doubled,
+// or the column of the problem and what it is
+fn split_csv_fields(line: &str) -> Result<Vec<CsvField>, (usize, String)> {
+    let column = |offset: usize| line[..offset].chars().count() + 1;
+    let mut fields = Vec::new();
+    let mut offset = 0;
+    loop {
+        let rest = &line[offset..];
+        let field_offset = offset + rest.len() - rest.trim_start().len();
+        let field = &line[field_offset..];
+        let quoted = match field.strip_prefix('"') {
+            Some(quoted) => quoted,
+            None => {
+                let end = field.find(',').unwrap_or(field.len());
+                fields.push(CsvField { text: field[..end].trim().to_string(), quoted: false, column: column(field_offset) });
+                if end == field.len() {
+                    return Ok(fields);
+                }
+                offset = field_offset + end + 1;
+                continue;
+            },
+        };
+        let mut text = String::new();
+        let mut chars = quoted.char_indices().peekable();
+        let closing_quote = loop {
+            match chars.next() {
+                Some((_index, '"')) if chars.peek().map(|&(_index, c)| c) == Some('"') => {
+                    text.push('"');
+                    chars.next();
+                },
+                Some((index, '"')) => break index,
+                Some((_index, c)) => text.push(c),
+                None => return Err((column(field_offset), "unterminated quoted field".to_string())),
+            }
+        };
+        fields.push(CsvField { text, quoted: true, column: column(field_offset) });
+        let after_offset = field_offset + 1 + closing_quote + 1;
+        let after = line[after_offset..].trim_start();
+        let after_offset = line.len() - after.len();
+        match after.chars().next() {
+            None => return Ok(fields),
+            Some(',') => offset = after_offset + 1,
+            Some(c) => return Err((column(after_offset), format!("unexpected {} after a quoted field, expected a comma", c))),
+        }
+    }
+}
+
+// slice details filled by md-profiler itself, which metadata can't override
+#[cfg(feature = "toml-intervals")]
+const RESERVED_ARGS: &[&str] = &[
+    "name", "sort_index", "source", "region", "savestate", "value", "dma_source", "dma_destination", "dma_length", "vdp_register", "vdp_value", "recursion_depth", "calls", "sound_argument", "heap_address", "heap_size", "heap_tag", "labels", "frame", "scanline", "latency_cycles",
+    "occurrence", "start_cycle", "end_cycle", "cycles", "interrupt_cycles", "start_pc", "end_pc",
+];
+
+#[cfg(feature = "toml-intervals")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlIntervalFile {
+    #[serde(default)]
+    interval: Vec<TomlInterval>,
+    #[serde(default)]
+    thread: Vec<TomlThread>,
+    // labels, patterns or hex addresses of the functions waiting for the VBlank
+    #[serde(default)]
+    idle: Vec<String>,
+}
+
+#[cfg(feature = "toml-intervals")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlThread {
+    // "Main thread", "Interrupts", "VDP"... or the name of a category of the interval file
+    name: String,
+    display_name: Option<String>,
+    sort_index: Option<u32>,
+    // name of the process the thread is moved to
+    process: Option<String>,
+}
+
+#[cfg(feature = "toml-intervals")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TomlPoints {
+    One(String),
+    Several(Vec<String>),
+}
+
+#[cfg(feature = "toml-intervals")]
+impl TomlPoints {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            TomlPoints::One(point) => vec![point],
+            TomlPoints::Several(points) => points,
+        }
+    }
+}
+
+#[cfg(feature = "toml-intervals")]
+#[derive(Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum TomlIntervalType {
+    // from a start point to an end point
+    #[default]
+    Interval,
+    // a marker each time a start point is reached
+    Instant,
+    // a value incremented each time a start point is reached, and reset by the end points
+    Counter,
+    // occurrences can overlap, each end point ends the oldest occurrence in flight
+    Async,
+    // from the entry of the start functions until they return, without end points
+    Function,
+}
+
+#[cfg(feature = "toml-intervals")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlInterval {
+    start: TomlPoints,
+    end: Option<TomlPoints>,
+    name: Option<String>,
+    thread: Option<String>,
+    color: Option<String>,
+    #[serde(default, rename = "type")]
+    interval_type: TomlIntervalType,
+    // counters only
+    #[serde(default)]
+    reset_each_frame: bool,
+    #[serde(default)]
+    metadata: BTreeMap<String, toml::Value>,
+    // name of the interval it is nested in
+    parent: Option<String>,
+    // regular intervals and function spans only
+    #[serde(default)]
+    exclude_interrupts: bool,
+}
+
+#[cfg(feature = "toml-intervals")]
+// [[interval]] tables with named fields, [[thread]] tables and the idle functions
+fn read_toml_interval_definitions(input: &str) -> Result<IntervalFileContents, String> {
+    let file: TomlIntervalFile = toml::from_str(input).map_err(|err| format!("Invalid interval file: {}", err))?;
+    let threads = file.thread.into_iter().map(|thread| ThreadSettings {
+        name: thread.name,
+        display_name: thread.display_name,
+        sort_index: thread.sort_index,
+        process: thread.process,
+    }).collect();
+    let definitions = file.interval.into_iter().map(|interval| {
+        let starts = interval.start.into_vec();
+        let name = interval.name.unwrap_or_else(|| starts.join(";"));
+        let kind = match interval.interval_type {
+            TomlIntervalType::Interval => IntervalKind::Interval,
+            TomlIntervalType::Instant => IntervalKind::Instant,
+            TomlIntervalType::Counter => IntervalKind::Counter { reset_each_frame: interval.reset_each_frame },
+            TomlIntervalType::Async => IntervalKind::Async,
+            TomlIntervalType::Function => IntervalKind::Function,
+        };
+        if interval.reset_each_frame && interval.interval_type != TomlIntervalType::Counter {
+            return Err(format!("Interval {} isn't a counter, it can't be reset each frame", name));
+        }
+        if interval.exclude_interrupts && !kind.is_span() {
+            return Err(format!("Interval {} has no duration, it can't exclude the interrupts", name));
+        }
+        let ends = match interval.end {
+            Some(_) if kind == IntervalKind::Function => return Err(format!("Interval {} is a function span, it ends when its function returns and can't have an end", name)),
+            Some(end) => end.into_vec(),
+            None if matches!(kind, IntervalKind::Instant | IntervalKind::Counter { .. } | IntervalKind::Function) => Vec::new(),
+            None => return Err(format!("Interval {} has no end", name)),
+        };
+        if let Some(key) = interval.metadata.keys().find(|key| RESERVED_ARGS.contains(&key.as_str())) {
+            return Err(format!("Interval {} has a metadata key {} which is already used by md-profiler", name, key));
+        }
+        Ok(IntervalDefinition {
+            starts,
+            ends,
+            name,
+            thread: interval.thread,
+            color: interval.color,
+            kind,
+            metadata: interval.metadata.into_iter()
+                .map(|(key, value)| (key, serde_json::to_value(value).unwrap()))
+                .collect(),
+            parent: interval.parent,
+            exclude_interrupts: interval.exclude_interrupts,
+            position: None,
+        })
+    }).collect::<Result<_, String>>()?;
+    Ok(IntervalFileContents {
+        definitions,
+        threads,
+        idle: file.idle,
+    })
+}
+
+#[cfg(not(feature = "toml-intervals"))]
+fn read_toml_interval_definitions(_input: &str) -> Result<IntervalFileContents, String> {
+    Err("This interval file is in the TOML format, but md-profiler was built without the toml-intervals feature".to_string())
+}
+
+// (index of the parent, number of ancestors) of each interval, children must be regular intervals or function spans nested in another one,
+// on its thread
+fn resolve_parents(definitions: &[IntervalDefinition], source: &str) -> Result<Vec<(Option<usize>, usize)>, String> {
+    let parents = definitions.iter().map(|definition| {
+        let parent_name = match &definition.parent {
+            Some(parent_name) => parent_name,
+            None => return Ok(None),
+        };
+        let parent = definitions.iter().position(|parent| &parent.name == parent_name)
+            .ok_or_else(|| format!("{}: Interval {} is nested in {}, which isn't in the interval file", definition.context(source), definition.name, parent_name))?;
+        if !definition.kind.is_span() || !definitions[parent].kind.is_span() {
+            return Err(format!("{}: Interval {} can't be nested in {}, only regular intervals and function spans can be nested", definition.context(source), definition.name, parent_name));
+        }
+        if definition.thread.is_some() {
+            return Err(format!("{}: Interval {} is nested in {}, it can't have a thread of its own", definition.context(source), definition.name, parent_name));
+        }
+        Ok(Some(parent))
+    }).collect::<Result<Vec<_>, String>>()?;
+    parents.iter().enumerate().map(|(index, &parent)| {
+        let mut depth = 0;
+        let mut ancestor = parent;
+        while let Some(ancestor_index) = ancestor {
+            depth += 1;
+            if depth > definitions.len() {
+                return Err(format!("{}: Interval {} is nested in itself", definitions[index].context(source), definitions[index].name));
+            }
+            ancestor = parents[ancestor_index];
+        }
+        Ok((parent, depth))
+    }).collect()
+}
+
+// the addresses of the labels (or patterns) of the start or end points of an interval
+fn read_interval_points(points: &[String], symbols: &Symbols) -> Result<Vec<u32>, String> {
+    let mut addresses = Vec::new();
+    for elm in points {
+        addresses.extend(read_interval_elm(elm, symbols)?);
+    }
+    Ok(addresses)
+}
+
+// a [[interval]] or [[thread]] header of a TOML interval file, which can have spaces inside the brackets,
+// a quoted name and a comment after it
+fn is_toml_table_header(line: &str) -> bool {
+    let line = line.split('#').next().unwrap().trim();
+    line.strip_prefix("[[")
+        .and_then(|line| line.strip_suffix("]]"))
+        .map(|name| name.trim().trim_matches('"'))
+        .is_some_and(|name| matches!(name, "interval" | "thread"))
+}
+
+// the interval file is named source in the messages, the lines of a csv file which can't be read and the intervals whose labels
+// can't be found are skipped with a warning
+pub fn read_intervals(input: &[u8], symbols: &Symbols, source: &str) -> Result<(Intervals, HashMap<String, u32>), String> {
+    let input = String::from_utf8_lossy(input);
+    let is_toml = input.lines().any(|line| is_toml_table_header(line) || line.split('=').next().map(str::trim) == Some("idle"));
+    let IntervalFileContents { definitions, threads, idle } = if is_toml {
+        read_toml_interval_definitions(&input).map_err(|err| format!("{}: {}", source, err))?
+    } else {
+        IntervalFileContents {
+            definitions: read_csv_interval_definitions(&input, source),
+            ..Default::default()
+        }
+    };
+    let mut idle_functions = Vec::new();
+    for elm in &idle {
+        match read_interval_elm(elm, symbols) {
+            Ok(addresses) => idle_functions.extend(addresses),
+            Err(err) => eprintln!("Warning: {}, idle function: {}, it's ignored", source, err),
+        }
+    }
+    let mut resolved_definitions = Vec::new();
+    let mut skipped: Vec<String> = Vec::new();
+    for definition in definitions {
+        let points = read_interval_points(&definition.starts, symbols).map_err(|err| (false, err))
+            .and_then(|starts| read_interval_points(&definition.ends, symbols).map(|ends| (starts, ends)).map_err(|err| (true, err)));
+        match points {
+            Ok((starts, ends)) => resolved_definitions.push((definition, starts, ends)),
+            Err((ends, err)) => {
+                eprintln!("Warning: {}: {}, the interval is skipped", definition.location(source, ends), err);
+                skipped.push(definition.name);
+            },
+        }
+    }
+    // so are the intervals nested in a skipped one
+    while let Some(index) = resolved_definitions.iter().position(|(definition, _starts, _ends)| definition.parent.as_ref().is_some_and(|parent| skipped.contains(parent))) {
+        let (definition, _starts, _ends) = resolved_definitions.remove(index);
+        eprintln!("Warning: {}: interval {} is nested in {} which was skipped, it's skipped too", definition.context(source), definition.name, definition.parent.as_deref().unwrap_or_default());
+        skipped.push(definition.name);
+    }
+    let (definitions, points): (Vec<_>, Vec<_>) = resolved_definitions.into_iter().map(|(definition, starts, ends)| (definition, (starts, ends))).unzip();
     let mut intervals_info = Vec::new();
     let mut starts: HashMap<u32, Vec<usize>> = HashMap::new();
     let mut ends: HashMap<u32, Vec<usize>> = HashMap::new();
+    let mut functions: HashMap<u32, Vec<usize>> = HashMap::new();
+    let mut processes: Vec<String> = Vec::new();
+    for process in threads.iter().filter_map(|thread| thread.process.as_ref()) {
+        if !processes.contains(process) {
+            processes.push(process.clone());
+        }
+    }
+    let parents = resolve_parents(&definitions, source)?;
     let mut custom_threads: HashMap<String, u32> = HashMap::new();
-    let mut current_new_tid = 2;
-    let input = String::from_utf8_lossy(input);
-    for line in input.split('\n') {
-        let line_elms: Vec<_> = line.split(',').collect();
+    let mut current_new_tid = FIRST_CUSTOM_TID;
+    for ((definition, (start_addresses, end_addresses)), &(parent, depth)) in definitions.into_iter().zip(points).zip(&parents) {
         let interval_index = intervals_info.len();
-        if line_elms.is_empty() || line.trim_start().starts_with("//") ||line_elms[0].trim().is_empty() {
-            continue;
+        let points = if definition.kind == IntervalKind::Function { &mut functions } else { &mut starts };
+        for interval_start in start_addresses {
+            points.entry(interval_start).or_default().push(interval_index);
         }
-        if line_elms.len() == 1 {
-            let elm = line_elms[0].trim();
-            let mut elm_start = String::from(elm);
-            let mut elm_end = elm_start.clone();
-            elm_start.push_str("_start");
-            elm_end.push_str("_end");
-            for interval_start in read_interval_elm(&elm_start, symbols) {
-                starts.entry(interval_start).or_default().push(interval_index);
-            }
-            for interval_end in read_interval_elm(&elm_end, symbols) {
-                ends.entry(interval_end).or_default().push(interval_index);
-            }
-        } else {
-            line_elms[0].trim().split(';').for_each(|elm| {
-                for interval_start in read_interval_elm(elm, symbols) {
-                    starts.entry(interval_start).or_default().push(interval_index);
-                }
-            });
-            line_elms[1].trim().split(';').for_each(|elm| {
-                for interval_end in read_interval_elm(elm, symbols) {
-                    ends.entry(interval_end).or_default().push(interval_index);
-                }
-            });
+        for interval_end in end_addresses {
+            ends.entry(interval_end).or_default().push(interval_index);
         }
-        let tid = if line_elms.len() >= 4 {
-            let custom_thread_name = line_elms[3].trim();
-            custom_threads.get(custom_thread_name).copied().unwrap_or_else(|| {
+        let tid = if let Some(custom_thread_name) = definition.thread {
+            custom_threads.get(&custom_thread_name).copied().unwrap_or_else(|| {
                 let tid = current_new_tid;
                 current_new_tid += 1;
-                custom_threads.insert(custom_thread_name.into(), tid);
+                custom_threads.insert(custom_thread_name, tid);
                 tid
             })
         } else {
             0
         };
-        let name = if line_elms.len() >= 3 {
-            line_elms[2].trim().to_owned()
-        } else {
-            line.to_owned()
-        };
         intervals_info.push(IntervalInfo {
-            name,
+            name: definition.name,
             tid,
+            color: definition.color,
+            kind: definition.kind,
+            metadata: definition.metadata,
+            parent,
+            depth,
             reached_at: None,
+            entered_at: None,
+            exclude_interrupts: definition.exclude_interrupts,
+            interrupt_cycles: 0,
+            in_flight: VecDeque::new(),
+            occurrence_count: 0,
+            repeated_starts: 0,
+            count: 0,
         });
     }
-    (
+    // the children are drawn on the thread of their outermost ancestor
+    for index in 0..intervals_info.len() {
+        let mut root = index;
+        while let Some(parent) = intervals_info[root].parent {
+            root = parent;
+        }
+        intervals_info[index].tid = intervals_info[root].tid;
+    }
+    // a point shared by a parent and its children starts the parent first and ends it last
+    for indices in starts.values_mut().chain(functions.values_mut()) {
+        indices.sort_by_key(|&index| intervals_info[index].depth);
+    }
+    for indices in ends.values_mut() {
+        indices.sort_by_key(|&index| std::cmp::Reverse(intervals_info[index].depth));
+    }
+    Ok((
         Intervals {
             intervals_info,
             starts,
             ends,
+            functions,
+            main_stack: CallStack::default(),
+            interrupt_stack: CallStack::default(),
+            in_interrupt: false,
+            interrupt_entered_at: None,
+            occurrences: Vec::new(),
+            frame_source: FrameSource::VInt,
+            next_async_id: 0,
+            number_names: false,
+            breakpoint_tid: 0,
+            threads,
+            processes,
+            idle_functions,
         },
         custom_threads
-    )
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profiling::DEFAULT_MCLK;
+
+    #[test]
+    fn writes_little_endian_breakpoints() {
+        let (intervals, _) = read_intervals(b"0x1234,0x56789a,Loop", &Symbols::default(), "test").unwrap();
+        let mut written = Vec::new();
+        intervals.write_to_file(&mut written);
+        assert_eq!(written, [0x34, 0x12, 0, 0, 0x9a, 0x78, 0x56, 0]);
+    }
+
+    #[test]
+    fn counts_repeated_starts_as_unfinished() {
+        let (mut intervals, _) = read_intervals(b"0x100,0x200,Loop", &Symbols::default(), "test").unwrap();
+        let mut trace_events = Vec::new();
+        for (cycle, pc) in [(0, 0x100), (10, 0x100), (20, 0x200), (30, 0x100)] {
+            intervals.reach(pc, &mut trace_events, cycle, DEFAULT_MCLK);
+        }
+        let stats = intervals.stats();
+        assert_eq!(stats[0].completions, 1);
+        assert_eq!(stats[0].total_cycles, 20);
+        assert_eq!(stats[0].unfinished, 2);
+    }
+
+    #[test]
+    fn detects_toml_headers() {
+        for header in ["[[interval]]", "  [[interval]] # the main loop", "[[ interval ]]", "[[\"thread\"]]"] {
+            assert!(is_toml_table_header(header), "{}", header);
+        }
+        for line in ["0x1234,0x5678,Loop", "[[intervals]]", "[interval]", "# [[interval]]"] {
+            assert!(!is_toml_table_header(line), "{}", line);
+        }
+    }
 }
@@ -0,0 +1,106 @@
+use std::io::Write;
+
+use crate::{hotspots::*, profiling::*, report::DurationFormat, symbols::Symbols};
+
+// handlers dispatched the latest listed in full
+const WORST_LATENCIES: usize = 10;
+
+// how long a handler waited between its interrupt being asserted and the CPU running it,
+// the interrupt is held back while a handler of the same or a higher level runs or the code masks it
+#[derive(Debug, Clone)]
+pub struct InterruptLatency {
+    // "VInt" or "HInt"
+    pub kind: &'static str,
+    pub assert_cycle: u64,
+    pub enter_cycle: u64,
+    pub handler: u32,
+    // innermost subroutine running when the interrupt was asserted, None in code outside of any subroutine
+    pub running: Option<u32>,
+}
+
+impl InterruptLatency {
+    pub fn cycles(&self) -> u64 {
+        self.enter_cycle.saturating_sub(self.assert_cycle)
+    }
+}
+
+// the latency of every handler dispatched for a VInt or an HInt, external interrupts have no packet marking when they were asserted
+pub fn compute_interrupt_latencies(input: &ParsedProfilingFile) -> Vec<InterruptLatency> {
+    let mut latencies = Vec::new();
+    let mut in_interrupt = false;
+    // subroutines running in the main code and in the interrupt
+    let mut main_stack: CallStack<u32> = CallStack::new(input.exit_matching);
+    let mut interrupt_stack: CallStack<u32> = CallStack::new(input.exit_matching);
+    // (kind, cycle, running subroutine) of the interrupt waiting for its handler
+    let mut pending: Option<(&'static str, u64, Option<u32>)> = None;
+    for packet in &input.packets {
+        let stack = if in_interrupt { &mut interrupt_stack } else { &mut main_stack };
+        stack.follow(packet, |subroutine| subroutine);
+        match packet.inner {
+            ProfilingPacketInner::VInt | ProfilingPacketInner::HInt => {
+                let kind = if let ProfilingPacketInner::VInt = packet.inner { "VInt" } else { "HInt" };
+                pending = Some((kind, packet.cycle, stack.last().map(|&(subroutine, _stack_pointer)| subroutine)));
+            },
+            ProfilingPacketInner::InterruptEnter { target_interrupt } => {
+                if let Some((kind, assert_cycle, running)) = pending.take() {
+                    latencies.push(InterruptLatency {
+                        kind,
+                        assert_cycle,
+                        enter_cycle: packet.cycle,
+                        handler: target_interrupt,
+                        running,
+                    });
+                }
+                in_interrupt = true;
+                interrupt_stack.clear();
+            },
+            ProfilingPacketInner::InterruptExit => in_interrupt = false,
+            _ => {},
+        }
+    }
+    latencies
+}
+
+// a counter set at each HInt handler entry, of how many cycles later than the quickest one it started, what is left
+// of the instruction running when the HInt is asserted shifting raster effects along the scanline
+pub fn hint_jitter_events<'a>(latencies: &[InterruptLatency], mclk: f64) -> Vec<TraceEvent<'a>> {
+    let hints = || latencies.iter().filter(|latency| latency.kind == "HInt");
+    let quickest = hints().map(InterruptLatency::cycles).min().unwrap_or(0);
+    hints().map(|latency| TraceEvent {
+        name: "HInt jitter (cycles)".into(),
+        ph: 'C',
+        ts: cycle_to_us(latency.enter_cycle, mclk),
+        dur: 0.0,
+        pid: 0,
+        tid: 0,
+        args: Some(TraceEventArgs {
+            value: Some(latency.cycles() - quickest),
+            ..Default::default()
+        }),
+        cat: Some(CATEGORY_INTERRUPT.into()),
+        ..Default::default()
+    }).collect()
+}
+
+// the distribution of the latencies of each kind of interrupt, and the handlers that waited the longest along with what they waited for
+pub fn write_latency_report(mut output: impl Write, latencies: &[InterruptLatency], symbols: &Symbols, duration_format: &DurationFormat) -> std::io::Result<()> {
+    if latencies.is_empty() {
+        return writeln!(output, "No VInt or HInt handler was dispatched in the capture");
+    }
+    for kind in ["VInt", "HInt"] {
+        let invocations: Vec<(u64, u64)> = latencies.iter().filter(|latency| latency.kind == kind).map(|latency| (latency.assert_cycle, latency.cycles())).collect();
+        if !invocations.is_empty() {
+            write_duration_histogram(&mut output, &format!("Latency of the {} handler", kind), &invocations, duration_format)?;
+            writeln!(output)?;
+        }
+    }
+    let mut worst: Vec<&InterruptLatency> = latencies.iter().collect();
+    worst.sort_by_key(|latency| (std::cmp::Reverse(latency.cycles()), latency.assert_cycle));
+    writeln!(output, "Longest latencies:")?;
+    for latency in worst.iter().take(WORST_LATENCIES) {
+        let running = latency.running.map_or_else(|| "no subroutine".to_string(), |running| symbols.name(running).into_owned());
+        writeln!(output, "  {} at {:.3} ms: {} before {}, asserted during {}",
+            latency.kind, cycle_to_us(latency.assert_cycle, duration_format.mclk) / 1000.0, duration_format.format(latency.cycles() as f64), symbols.name(latency.handler), running)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,33 @@
+pub mod symbols;
+pub mod profiling;
+pub mod input_format;
+pub mod intervals;
+pub mod frames;
+pub mod hotspots;
+pub mod report;
+pub mod html;
+pub mod symbol_diff;
+pub mod schema;
+pub mod cache;
+pub mod validate;
+pub mod raster;
+pub mod builder;
+pub mod progress;
+#[cfg(feature = "perfetto")]
+pub mod perfetto;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod tasks;
+pub mod dma_queue;
+pub mod presets;
+pub mod annotations;
+pub mod latency;
+pub mod sampling;
+pub mod bench;
+pub mod busy_wait;
+pub mod stack_usage;
+pub mod addons;
+#[cfg(feature = "trace-processor")]
+pub mod trace_processor;
+#[cfg(feature = "live")]
+pub mod live;
@@ -0,0 +1,191 @@
+use std::{convert::TryInto, fs::File, io::{ErrorKind, Read, Write}, net::TcpStream, time::{Duration, Instant}};
+
+use crate::profiling::{MDP_HEADER_SIZE, check_header, packet_len};
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+// a stream can start with a handshake, [MDPL][version: u16][capabilities: u16][mclk: u32][m68k divider: u32] in little endian,
+// before the mdp capture itself, the streams without it are recorded as they are
+const HANDSHAKE_MAGIC: &[u8] = b"MDPL";
+const HANDSHAKE_LEN: usize = 16;
+pub const LIVE_PROTOCOL_VERSION: u16 = 1;
+// the emulator sends heartbeat packets while it has nothing else to send, such as when the game is paused
+pub const CAPABILITY_HEARTBEAT: u16 = 1;
+const KNOWN_CAPABILITIES: u16 = CAPABILITY_HEARTBEAT;
+// [32][cycle: u32][sp: u32], only in the streams that announced CAPABILITY_HEARTBEAT, they aren't written to the mdp file
+const PACKET_HEARTBEAT: u8 = 32;
+const HEARTBEAT_LEN: usize = 9;
+// the answer to a handshake, [MDPL][version: u16][accepted: u8], for the streams that can be answered
+const HANDSHAKE_ACCEPTED: u8 = 1;
+const HANDSHAKE_REFUSED: u8 = 0;
+
+// where the capture comes from: a TCP connection can answer the handshake and time out, a named pipe can only be read
+pub trait LiveStream: Read {
+    fn reply(&mut self, _message: &[u8]) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, _timeout: Duration) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl LiveStream for TcpStream {
+    fn reply(&mut self, message: &[u8]) -> std::io::Result<()> {
+        self.write_all(message)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> std::io::Result<()> {
+        self.set_read_timeout(Some(timeout))
+    }
+}
+
+impl LiveStream for File {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handshake {
+    pub version: u16,
+    pub capabilities: u16,
+    pub mclk: u32,
+    pub m68k_divider: u32,
+}
+
+impl Handshake {
+    fn read(input: &[u8]) -> Handshake {
+        let u16_at = |i: usize| u16::from_le_bytes(input[i..i+2].try_into().unwrap());
+        let u32_at = |i: usize| u32::from_le_bytes(input[i..i+4].try_into().unwrap());
+        Handshake {
+            version: u16_at(4),
+            capabilities: u16_at(6),
+            mclk: u32_at(8),
+            m68k_divider: u32_at(12),
+        }
+    }
+
+    pub fn heartbeat(&self) -> bool {
+        self.capabilities & CAPABILITY_HEARTBEAT != 0
+    }
+}
+
+fn handshake_answer(accepted: u8) -> Vec<u8> {
+    let mut answer = HANDSHAKE_MAGIC.to_vec();
+    answer.extend_from_slice(&LIVE_PROTOCOL_VERSION.to_le_bytes());
+    answer.push(accepted);
+    answer
+}
+
+#[derive(Debug, Default)]
+pub struct LiveStats {
+    pub packets: u64,
+    pub bytes: u64,
+    pub handshake: Option<Handshake>,
+}
+
+// checks the handshake of the emulator and answers it, the peers speaking another version of the protocol are refused
+fn accept_handshake(input: &mut impl LiveStream, handshake: Handshake, timeout: Duration) -> Result<(), String> {
+    if handshake.version != LIVE_PROTOCOL_VERSION {
+        // the emulator may not wait for the answer, it's told if it does
+        let _ = input.reply(&handshake_answer(HANDSHAKE_REFUSED));
+        return Err(format!("The emulator speaks version {} of the live protocol, md-profiler speaks version {}, update the older one", handshake.version, LIVE_PROTOCOL_VERSION));
+    }
+    if handshake.capabilities & !KNOWN_CAPABILITIES != 0 {
+        eprintln!("Warning: the emulator announced capabilities md-profiler doesn't know ({:#06x}), they're ignored", handshake.capabilities & !KNOWN_CAPABILITIES);
+    }
+    input.reply(&handshake_answer(HANDSHAKE_ACCEPTED)).map_err(|err| format!("Error answering the handshake: {}", err))?;
+    if handshake.heartbeat() {
+        input.set_timeout(timeout).map_err(|err| format!("Couldn't set the timeout of the connection: {}", err))?;
+    }
+    Ok(())
+}
+
+// the clocks of the handshake have to be the ones of the capture, anything else means the stream is garbled
+fn check_handshake_clocks(handshake: &Handshake, header: &[u8]) -> Result<(), String> {
+    let mclk = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    let m68k_divider = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    if (mclk, m68k_divider) != (handshake.mclk, handshake.m68k_divider) {
+        return Err(format!("The handshake announced a mclk of {} Hz and a 68k divider of {}, but the capture has {} Hz and {}, the stream is corrupted",
+            handshake.mclk, handshake.m68k_divider, mclk, m68k_divider));
+    }
+    Ok(())
+}
+
+// copies a capture streamed by the emulator to an mdp file as it arrives, until the emulator closes the stream,
+// only whole packets are written so the output can be converted at any point of a long session. When the emulator
+// announced heartbeats in its handshake, a stream silent for longer than timeout is given up on
+pub fn record_capture(mut input: impl LiveStream, mut output: impl Write, timeout: Duration) -> Result<LiveStats, String> {
+    let mut stats = LiveStats::default();
+    let mut buffer = Vec::new();
+    let mut chunk = vec![0; 64 * 1024];
+    let mut handshake_checked = false;
+    let mut header_received = false;
+    let mut last_progress = Instant::now();
+    loop {
+        let read = match input.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(format!("Nothing received from the emulator for {} s, not even a heartbeat, it's gone or stuck", timeout.as_secs_f64()));
+            },
+            Err(err) => return Err(format!("Error receiving the capture: {}", err)),
+        };
+        buffer.extend_from_slice(&chunk[..read]);
+        if !handshake_checked {
+            if buffer.len() < HANDSHAKE_MAGIC.len() {
+                continue;
+            }
+            if buffer.starts_with(HANDSHAKE_MAGIC) {
+                if buffer.len() < HANDSHAKE_LEN {
+                    continue;
+                }
+                let handshake = Handshake::read(&buffer);
+                accept_handshake(&mut input, handshake, timeout)?;
+                stats.handshake = Some(handshake);
+                buffer.drain(..HANDSHAKE_LEN);
+            }
+            handshake_checked = true;
+        }
+        let mut complete = 0;
+        let mut packets = Vec::new();
+        if !header_received {
+            if buffer.len() < MDP_HEADER_SIZE {
+                continue;
+            }
+            check_header(&buffer).map_err(|err| format!("Invalid capture received: {}", err))?;
+            if let Some(handshake) = &stats.handshake {
+                check_handshake_clocks(handshake, &buffer)?;
+            }
+            header_received = true;
+            complete = MDP_HEADER_SIZE;
+            packets.extend_from_slice(&buffer[..MDP_HEADER_SIZE]);
+        }
+        loop {
+            if stats.handshake.is_some() && buffer.get(complete) == Some(&PACKET_HEARTBEAT) {
+                if buffer.len() - complete < HEARTBEAT_LEN {
+                    break;
+                }
+                complete += HEARTBEAT_LEN;
+                continue;
+            }
+            match packet_len(&buffer[complete..])? {
+                Some(len) => {
+                    packets.extend_from_slice(&buffer[complete..complete + len]);
+                    complete += len;
+                    stats.packets += 1;
+                },
+                None => break,
+            }
+        }
+        output.write_all(&packets).and_then(|_| output.flush()).map_err(|err| format!("Error writing the capture: {}", err))?;
+        stats.bytes += packets.len() as u64;
+        buffer.drain(..complete);
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            println!("Received {} packets ({} MB)", stats.packets, stats.bytes / 1_000_000);
+            last_progress = Instant::now();
+        }
+    }
+    if !buffer.is_empty() {
+        eprintln!("Warning: the stream ended in the middle of a packet, its last {} bytes were dropped", buffer.len());
+    }
+    Ok(stats)
+}
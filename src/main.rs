@@ -1,114 +1,2301 @@
-mod symbols;
-mod profiling;
-mod intervals;
+use std::{borrow::Cow, collections::{HashMap, HashSet}, fs::{self, File}, io::Write, path::Path, thread, time::{Duration, Instant, SystemTime}};
+use clap::{Arg, App, ArgMatches, SubCommand};
 
-use std::{borrow::Cow, fs::File, io::Read, time::Instant};
-use clap::{Arg, App};
+use md_profiler::{symbols::*, profiling::*, input_format::*, intervals::*, frames::*, hotspots::*, report::*, symbol_diff::*, validate::*, raster::*, builder::*, progress::*, tasks::*, annotations::*, dma_queue::*, presets::*, cache::*, latency::*, sampling::*, html::*, bench::*, busy_wait::*, stack_usage::*, schema::{CaptureMetadata, SCHEMA_VERSION, TraceMetadata}, status};
+#[cfg(feature = "perfetto")]
+use md_profiler::perfetto::*;
+#[cfg(feature = "sqlite")]
+use md_profiler::sqlite::*;
+#[cfg(feature = "trace-processor")]
+use md_profiler::trace_processor::*;
+#[cfg(feature = "live")]
+use md_profiler::live::*;
+#[cfg(feature = "live")]
+use std::net::TcpListener;
 
-use symbols::*;
-use profiling::*;
-use intervals::*;
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(500);
+// the symbol files looked for next to the capture when none is given, in this order
+const SYMBOL_FILE_EXTENSIONS: [&str; 5] = ["sym", "map", "lst", "elf", "nm"];
 
-fn main() -> Result<(), Cow<'static, str>> {
-    let matches = App::new("md-profiler")
-        .version(env!("CARGO_PKG_VERSION"))
-        .author("Tails8521 <tails8521@gmail.com>")
-        .about("A Sega MegaDrive/Genesis profiler")
-        .usage("md-profiler [-m <MANUAL INTERVALS>] [-s <SYMBOLS>] -i <INPUT> -o <OUTPUT>\nor\n    md-profiler -m <MANUAL INTERVALS> [-s <SYMBOLS>] -b <BREAKPOINTS OUTPUT FILE>")
-        .arg(Arg::with_name("MANUAL INTERVALS")
-            .short("m")
-            .long("manual-intervals")
+fn load_symbol_file(symbol_file: &str, raw_names: bool) -> Result<Symbols, String> {
+    let instant = Instant::now();
+    // an error rather than a panic, a rebuild can remove the file while --watch is running
+    let symbol_data = fs::read(symbol_file).map_err(|err| format!("Couldn't read {}: {}", symbol_file, err))?;
+    let mut symbols = read_symbols(&symbol_data);
+    if !raw_names {
+        symbols.demangle();
+    }
+    let elapsed = instant.elapsed();
+    status!("Parsed {} symbols from {} in {} ms", symbols.len(), symbol_file, elapsed.as_micros() as f64 / 1000.0);
+    Ok(symbols)
+}
+
+// merges the symbol files, the first one has the highest priority when several of them label the same address,
+// also returns the hash of each file
+fn load_symbols(symbol_files: &[String], raw_names: bool) -> Result<(Symbols, Vec<u64>), String> {
+    let mut symbols = Symbols::default();
+    let mut hashes = Vec::new();
+    for (i, symbol_file) in symbol_files.iter().enumerate() {
+        let file_symbols = load_symbol_file(symbol_file, raw_names)?;
+        hashes.push(file_symbols.hash);
+        if i == 0 {
+            symbols = file_symbols;
+        } else {
+            symbols.merge(file_symbols);
+        }
+    }
+    Ok((symbols, hashes))
+}
+
+fn configure_labels(symbols: &mut Symbols, matches: &ArgMatches) -> Result<(), String> {
+    symbols.set_label_policy(LabelPolicy::parse(matches.value_of("LABEL POLICY").unwrap())?);
+    symbols.set_address_naming(AddressNaming::parse(matches.value_of("ADDRESS NAMING").unwrap())?, HexFormat::parse(matches.value_of("HEX FORMAT").unwrap())?);
+    if let Some(alias_file) = matches.value_of("ALIASES") {
+        let alias_data = fs::read(alias_file).map_err(|err| format!("Couldn't read alias file {}: {}", alias_file, err))?;
+        symbols.read_aliases(&alias_data)?;
+    }
+    if let Some(region_file) = matches.value_of("REGIONS") {
+        let region_data = fs::read(region_file).map_err(|err| format!("Couldn't read region file {}: {}", region_file, err))?;
+        symbols.read_regions(&region_data)?;
+    }
+    if let Some(relocation_file) = matches.value_of("RELOCATIONS") {
+        let relocation_data = fs::read(relocation_file).map_err(|err| format!("Couldn't read relocation file {}: {}", relocation_file, err))?;
+        symbols.read_relocations(&relocation_data)?;
+    }
+    Ok(())
+}
+
+// the options selecting part of a capture, shared by the conversion, extract and the reports
+fn range_args() -> [Arg<'static, 'static>; 4] {
+    [
+        Arg::with_name("FROM")
+            .long("from")
+            .takes_value(true)
+            .help("Only keep what happens after this point in time (in ms unless suffixed with s, us or cycles)"),
+        Arg::with_name("TO")
+            .long("to")
+            .takes_value(true)
+            .help("Only keep what happens before this point in time (in ms unless suffixed with s, us or cycles)"),
+        Arg::with_name("FRAMES")
+            .long("frames")
             .takes_value(true)
-            .help("Manual intervals text file, allows you to record the time elapsed between two labels in the program"))
-        .arg(Arg::with_name("SYMBOLS")
+            .conflicts_with_all(&["FROM", "TO"])
+            .help("Only keep these frames, for instance 1200..1260 or 1200..=1259, frames are counted from the first frame start (see --frame-source)"),
+        Arg::with_name("FRAME SOURCE")
+            .long("frame-source")
+            .takes_value(true)
+            .default_value("vint")
+            .help("What starts a new frame: \"vint\" for VInt packets, \"interrupt:<HANDLER>\" for entering this interrupt handler, or a label/hex address reached by a subroutine call or manual breakpoint"),
+    ]
+}
+
+// the options overriding the clocks written in the header of the capture
+fn clock_args() -> [Arg<'static, 'static>; 2] {
+    [
+        Arg::with_name("MCLK")
+            .long("mclk")
+            .takes_value(true)
+            .help("Master clock frequency in Hz to use instead of the one in the capture header, for emulator builds writing a wrong one"),
+        Arg::with_name("M68K DIVIDER")
+            .long("m68k-divider")
+            .takes_value(true)
+            .help("68000 clock divider to use instead of the one in the capture header"),
+    ]
+}
+
+fn capture_args() -> [Arg<'static, 'static>; 3] {
+    [
+        Arg::with_name("ADDRESS MASK")
+            .long("address-mask")
+            .takes_value(true)
+            .help("Hex mask applied to the PCs and stack pointers of the 68000 before anything else, FFFFFF by default to drop the bits above its 24 bit address bus, FFFFFFFF keeps the addresses as captured"),
+        Arg::with_name("MIRROR")
+            .long("mirror")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("START-END=BASE-BASE_END in hex: the addresses from START to END mirror the ones from BASE to BASE_END and are moved there, can be repeated, replaces the default work RAM mirror E00000-FEFFFF=FF0000-FFFFFF, none for no mirror"),
+        Arg::with_name("EXIT MATCHING")
+            .long("exit-matching")
+            .takes_value(true)
+            .help("How the exits are matched with the subroutines they return from: stack (the default, the ones entered with a stack pointer at most 4 bytes above the one of the exit, or exactly an RTR/RTE frame above it), stack:SLACK (at most SLACK bytes above it, for routines returning with data left on the stack) or innermost (the innermost running subroutine, whatever the stack pointer is)"),
+    ]
+}
+
+// how the addresses of the 68000 are made canonical, from --address-mask and --mirror
+fn address_map(matches: &ArgMatches) -> Result<AddressMap, String> {
+    let mut address_map = AddressMap::default();
+    if let Some(mask) = matches.value_of("ADDRESS MASK") {
+        address_map.mask = u32::from_str_radix(mask.trim_start_matches('$'), 16).map_err(|_| "--address-mask expects a hex mask".to_string())?;
+    }
+    if let Some(mirrors) = matches.values_of("MIRROR") {
+        address_map.mirrors = mirrors.filter(|&mirror| mirror != "none").map(AddressMirror::parse).collect::<Result<_, _>>()?;
+    }
+    Ok(address_map)
+}
+
+// the output files given as "-" are written to stdout, to pipe them to another program
+const STDOUT_PATH: &str = "-";
+// and the capture given as "-" is read from stdin, to pipe it from the emulator or a decompressor
+const STDIN_PATH: &str = "-";
+const OUTPUT_ARGS: [&str; 14] = ["OUTPUT", "JSON", "PFTRACE", "HTML", "STATS", "COLLAPSED", "BREAKPOINTS OUTPUT FILE", "FRAME REPORT", "FRAME JSONL", "FRAME ATTRIBUTION", "SYMBOL DIFF", "INTERVAL CSV", "ANONYMIZE", "LATENCY REPORT"];
+// the traces written along with the -o output from the same conversion, and their format
+const EXTRA_TRACE_OUTPUTS: [(&str, &str); 3] = [("JSON", "json"), ("PFTRACE", "perfetto"), ("HTML", "html")];
+
+fn create_output(path: &str) -> std::io::Result<Box<dyn Write>> {
+    if path == STDOUT_PATH {
+        Ok(Box::new(std::io::stdout()))
+    } else {
+        Ok(Box::new(File::create(path)?))
+    }
+}
+
+// the file of a chunk of a trace split with --split-frames, trace.json.gz -> trace.002.json.gz
+fn chunk_path(output: &str, chunk: usize) -> String {
+    let compression_extension = [".gz", ".zst"].iter().copied().find(|extension| output.ends_with(extension)).unwrap_or("");
+    let path = &output[..output.len() - compression_extension.len()];
+    let (stem, extension) = match path.rfind('.') {
+        Some(dot) if !path[dot..].contains(['/', '\\']) => path.split_at(dot),
+        _ => (path, ""),
+    };
+    format!("{}.{:03}{}{}", stem, chunk, extension, compression_extension)
+}
+
+// only one output can go to stdout, and the status messages then go to stderr
+fn check_stdout_outputs(matches: &ArgMatches) -> Result<(), String> {
+    let stdout_outputs: Vec<&str> = OUTPUT_ARGS.iter().copied()
+        .filter(|&arg| matches.values_of(arg).is_some_and(|mut values| values.any(|value| value == STDOUT_PATH)))
+        .collect();
+    if stdout_outputs.len() > 1 {
+        return Err(format!("Only one output can be written to stdout, not {}", stdout_outputs.iter().map(|arg| arg.to_lowercase()).collect::<Vec<_>>().join(" and ")));
+    }
+    if !stdout_outputs.is_empty() {
+        set_status_to_stderr(true);
+    }
+    Ok(())
+}
+
+// stdin can only be read once, and --watch and --cache need a file to check
+fn check_stdin_inputs(matches: &ArgMatches, inputs: &[&str]) -> Result<(), String> {
+    if inputs.iter().filter(|&&input| input == STDIN_PATH).count() > 1 {
+        return Err("Only one capture can be read from stdin".to_string());
+    }
+    if inputs.contains(&STDIN_PATH) {
+        if let Some(option) = ["WATCH", "CACHE"].iter().find(|&&option| matches.is_present(option)) {
+            return Err(format!("--{} needs the capture in a file, it can't be used with a capture read from stdin", option.to_lowercase()));
+        }
+    }
+    Ok(())
+}
+
+// reads a capture, with the clocks given by --mclk and --m68k-divider if any
+fn read_capture(input: &str, matches: &ArgMatches) -> Result<ParsedProfilingFile, String> {
+    let instant = Instant::now();
+    let mut profiling = if input == STDIN_PATH {
+        read_capture_stream(std::io::stdin().lock(), 0, input)
+    } else {
+        let file = File::open(input).map_err(|err| format!("Couldn't open {}: {}", input, err))?;
+        let size = file.metadata().map_or(0, |metadata| metadata.len());
+        read_capture_stream(file, size, input)
+    }.map_err(|err| format!("{}: {}", input, err))?;
+    let elapsed = instant.elapsed();
+    if let Some(mclk) = matches.value_of("MCLK") {
+        profiling.mclk = mclk.parse::<u32>().map_err(|_| "--mclk expects a frequency in Hz".to_string())? as f64;
+    }
+    if let Some(m68k_divider) = matches.value_of("M68K DIVIDER") {
+        profiling.m68k_divider = m68k_divider.parse().map_err(|_| "--m68k-divider expects a number".to_string())?;
+    }
+    status!("Parsed {} input events in {} ms", profiling.packets.len(), elapsed.as_micros() as f64 / 1000.0);
+    if !profiling.capture_info.is_empty() {
+        status!("Captured from {}", profiling.capture_info.description());
+    }
+    if profiling.packets.is_empty() {
+        eprintln!("Warning: the capture doesn't contain any event, the outputs will be empty");
+    }
+    profiling.check_clocks()?;
+    profiling.resolve_clock_domains();
+    if let Some(exit_matching) = matches.value_of("EXIT MATCHING") {
+        profiling.exit_matching = ExitMatching::parse(exit_matching)?;
+    }
+    let canonicalized = profiling.canonicalize_addresses(&address_map(matches)?);
+    if canonicalized != 0 {
+        status!("Moved the addresses of {} input events out of the mirrors and unused address bits", canonicalized);
+    }
+    profiling.resolve_banked_addresses();
+    let mut overhead_model = OverheadModel::default();
+    for spec in matches.values_of("OVERHEAD").into_iter().flatten() {
+        overhead_model.configure(spec)?;
+    }
+    if !overhead_model.is_empty() {
+        let overhead = profiling.subtract_overhead(&overhead_model);
+        status!("Subtracted {} cycles ({:.3} ms) of instrumentation overhead", overhead / profiling.m68k_divider, cycle_to_us(overhead, profiling.mclk) / 1000.0);
+    }
+    Ok(profiling)
+}
+
+// the cycle range selected by --from/--to or --frames
+fn selected_cycle_range(matches: &ArgMatches, profiling: &ParsedProfilingFile, frame_source: FrameSource) -> Result<(Option<u64>, Option<u64>), String> {
+    if let Some(frames) = matches.value_of("FRAMES") {
+        let (first, end) = parse_frame_range(frames)?;
+        let (from, to) = frame_cycle_range(profiling, frame_source, first, end)?;
+        Ok((Some(from), to))
+    } else {
+        let from = matches.value_of("FROM").map(|from| parse_time(from, profiling.mclk)).transpose()?;
+        let to = matches.value_of("TO").map(|to| parse_time(to, profiling.mclk)).transpose()?;
+        if let (Some(from), Some(to)) = (from, to) {
+            if from > to {
+                return Err(format!("--from {} is after --to {}", matches.value_of("FROM").unwrap(), matches.value_of("TO").unwrap()));
+            }
+        }
+        Ok((from, to))
+    }
+}
+
+// the cycle the trace of a converted capture starts from with --time-origin, 0 without it
+fn time_origin(origin: Option<&TimeOrigin>, profiling: &ParsedProfilingFile, intervals: &Intervals) -> Result<u64, String> {
+    let origin = match origin {
+        Some(origin) => origin,
+        None => return Ok(0),
+    };
+    match origin {
+        TimeOrigin::Zero => Ok(profiling.packets.first().map_or(0, |packet| packet.cycle)),
+        TimeOrigin::VInt => profiling.packets.iter()
+            .find(|packet| matches!(packet.inner, ProfilingPacketInner::VInt))
+            .map(|packet| packet.cycle)
+            .ok_or_else(|| "The capture has no VInt to start the trace from".to_string()),
+        TimeOrigin::Interval(name) => intervals.first_occurrence_start(name)
+            .ok_or_else(|| format!("Interval {} never finished in the capture, the trace can't start from it", name)),
+    }
+}
+
+// writes the packets of the selected range of a capture to a new, smaller capture, with --reenter the subroutines
+// and interrupt handler running at the start of the range are entered again in the new capture
+fn extract(matches: &ArgMatches) -> Result<(), String> {
+    let (symbols, _symbol_file_hashes) = load_configured_symbols(matches)?;
+    let output = matches.value_of("OUTPUT").unwrap();
+    let mut profiling = read_capture(matches.value_of("INPUT").unwrap(), matches)?;
+    let frame_source = FrameSource::parse(matches.value_of("FRAME SOURCE").unwrap(), &symbols)?;
+    let (from, to) = selected_cycle_range(matches, &profiling, frame_source)?;
+    if matches.is_present("REENTER") {
+        let running = profiling.trim_cycle_range(from, to);
+        let output_file = create_output(output).expect("Couldn't create output file");
+        write_profiling_file(output_file, &profiling);
+        status!("Wrote {} input events to {}, {} of them entering what was running at the cut", profiling.packets.len(), output, running);
+    } else {
+        profiling.retain_cycle_range(from, to);
+        let output_file = create_output(output).expect("Couldn't create output file");
+        write_profiling_file(output_file, &profiling);
+        status!("Wrote {} input events to {}", profiling.packets.len(), output);
+    }
+    Ok(())
+}
+
+// appends several captures into one, one after the other
+fn concat(matches: &ArgMatches) -> Result<(), String> {
+    let output = matches.value_of("OUTPUT").unwrap();
+    let mut inputs = matches.values_of("INPUT").unwrap();
+    check_stdin_inputs(matches, &inputs.clone().collect::<Vec<_>>())?;
+    let mut profiling = read_capture(inputs.next().unwrap(), matches)?;
+    for input in inputs {
+        let shift = profiling.append(read_capture(input, matches)?).map_err(|err| format!("{}: {}", input, err))?;
+        if shift != 0 {
+            status!("Moved {} {:.3} ms later so it starts after the previous captures", input, cycle_to_us(shift, profiling.mclk) / 1000.0);
+        }
+    }
+    let output_file = create_output(output).expect("Couldn't create output file");
+    write_profiling_file(output_file, &profiling);
+    status!("Wrote {} input events to {}", profiling.packets.len(), output);
+    Ok(())
+}
+
+// the video standard given with --video-standard, or detected from the capture
+fn video_timing(matches: &ArgMatches, profiling: &ParsedProfilingFile) -> Result<VideoTiming, String> {
+    let standard = matches.value_of("VIDEO STANDARD").map(VideoStandard::parse).transpose()?;
+    Ok(VideoTiming::from_capture(profiling, standard))
+}
+
+// the preset given with --preset
+fn preset(matches: &ArgMatches) -> Result<Option<Preset>, String> {
+    matches.value_of("PRESET").map(Preset::parse).transpose()
+}
+
+// the functions of the preset found in the symbols
+fn resolve_preset_functions(symbols: &Symbols, functions: &[&str]) -> HashSet<u32> {
+    functions.iter().filter_map(|function| symbols.resolve(function)).collect()
+}
+
+// the functions given with --idle, and those of the preset
+fn resolve_idle_functions(matches: &ArgMatches, symbols: &Symbols) -> Result<HashSet<u32>, String> {
+    let mut idle_functions: HashSet<u32> = matches.values_of("IDLE").into_iter().flatten()
+        .map(|idle| symbols.resolve(idle).ok_or_else(|| format!("Idle function {} not found in the symbol file", idle)))
+        .collect::<Result<_, _>>()?;
+    if let Some(preset) = preset(matches)? {
+        idle_functions.extend(resolve_preset_functions(symbols, preset.idle_functions()));
+    }
+    Ok(idle_functions)
+}
+
+// the functions given with --fold and listed in the --fold-file, one per line
+fn resolve_folded_functions(matches: &ArgMatches, symbols: &Symbols) -> Result<HashSet<u32>, String> {
+    let mut functions: Vec<String> = matches.values_of("FOLD").into_iter().flatten().map(String::from).collect();
+    if let Some(fold_file) = matches.value_of("FOLD FILE") {
+        let fold_data = fs::read_to_string(fold_file).map_err(|err| format!("Couldn't read {}: {}", fold_file, err))?;
+        functions.extend(fold_data.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).map(String::from));
+    }
+    let mut folded_functions: HashSet<u32> = functions.iter()
+        .map(|function| symbols.resolve(function).ok_or_else(|| format!("Folded function {} not found in the symbol file", function)))
+        .collect::<Result<_, _>>()?;
+    if let Some(preset) = preset(matches)? {
+        folded_functions.extend(resolve_preset_functions(symbols, preset.folded_functions()));
+    }
+    Ok(folded_functions)
+}
+
+// each interrupt handler on its own thread, with --split-interrupts or a preset doing it
+fn split_interrupts(matches: &ArgMatches) -> Result<bool, String> {
+    Ok(matches.is_present("SPLIT INTERRUPTS") || preset(matches)?.is_some_and(Preset::split_interrupts))
+}
+
+// the functions given the same name by the aliases count as one in the reports walking the calls, the calls of the capture
+// are moved to the function standing for them, returns which function stands for which
+fn merge_aliased_functions(profiling: &mut ParsedProfilingFile, symbols: &Symbols) -> HashMap<u32, u32> {
+    let merged = symbols.merged_functions();
+    if !merged.is_empty() {
+        let changed = profiling.merge_functions(&merged);
+        status!("Merged {} functions sharing an alias into others, {} input events changed", merged.len(), changed);
+    }
+    merged
+}
+
+// the functions standing for these ones once merged
+fn merged_functions(functions: &HashSet<u32>, merged: &HashMap<u32, u32>) -> HashSet<u32> {
+    functions.iter().map(|function| merged.get(function).unwrap_or(function)).copied().collect()
+}
+
+// prints the most expensive functions of the selected range of a capture, without writing any trace
+fn top(matches: &ArgMatches) -> Result<(), String> {
+    let (symbols, _symbol_file_hashes) = load_configured_symbols(matches)?;
+    let mut profiling = read_capture(matches.value_of("INPUT").unwrap(), matches)?;
+    let frame_source = FrameSource::parse(matches.value_of("FRAME SOURCE").unwrap(), &symbols)?;
+    let (from, to) = selected_cycle_range(matches, &profiling, frame_source)?;
+    profiling.retain_cycle_range(from, to);
+    let count = matches.value_of("COUNT").unwrap().parse().map_err(|_| "--count expects a number".to_string())?;
+    let timing = video_timing(matches, &profiling)?;
+    let capture_cycles = match (profiling.packets.first(), profiling.packets.last()) {
+        (Some(first), Some(last)) => last.cycle - first.cycle,
+        _ => 0,
+    };
+    let frames = capture_cycles as f64 / timing.frame_cycles() as f64;
+    let duration_format = DurationFormat::parse(matches.value_of("UNITS").unwrap(), profiling.mclk, profiling.m68k_divider)?
+        .with_frame_cycles(Some(timing.frame_cycles()))
+        .with_capture_frames(Some(frames));
+    let merged = merge_aliased_functions(&mut profiling, &symbols);
+    let idle_functions = merged_functions(&resolve_idle_functions(matches, &symbols)?, &merged);
+    let stats = compute_function_stats(&profiling, &idle_functions);
+    let idle_cycles = idle_cycles(&profiling, &idle_functions);
+    if idle_cycles != 0 {
+        println!("Idle: {} ({:.1}% of the capture), the percentages below are of the busy time", duration_format.format_total(idle_cycles as f64), idle_cycles as f64 * 100.0 / capture_cycles as f64);
+    }
+    if matches.value_of("SORT").unwrap() == "calls" {
+        return write_call_counts(std::io::stdout().lock(), &stats, &symbols, count, &duration_format, frames).map_err(|err| err.to_string());
+    }
+    write_hotspots(std::io::stdout().lock(), &stats, &symbols, count, &duration_format, capture_cycles - idle_cycles).map_err(|err| err.to_string())
+}
+
+// prints the functions the PC samples of a capture were taken in the most, and writes their stacks for flamegraph tools
+fn samples(matches: &ArgMatches) -> Result<(), String> {
+    let (symbols, _symbol_file_hashes) = load_configured_symbols(matches)?;
+    let mut profiling = read_capture(matches.value_of("INPUT").unwrap(), matches)?;
+    let frame_source = FrameSource::parse(matches.value_of("FRAME SOURCE").unwrap(), &symbols)?;
+    let (from, to) = selected_cycle_range(matches, &profiling, frame_source)?;
+    profiling.retain_cycle_range(from, to);
+    let count = matches.value_of("COUNT").unwrap().parse().map_err(|_| "--count expects a number".to_string())?;
+    let samples = collapse_samples(&profiling, &symbols);
+    if samples.is_empty() {
+        return Err("The capture doesn't contain any PC sample, the emulator has to be set to sample the PC".to_string());
+    }
+    let output = matches.value_of("OUTPUT");
+    if let Some(output) = output {
+        let mut output_file = create_output(output).map_err(|err| format!("Couldn't create {}: {}", output, err))?;
+        write_collapsed_stacks(&mut output_file, &samples, &symbols).map_err(|err| err.to_string())?;
+        status!("Wrote {} sampled stacks to {}", samples.len(), output);
+    }
+    if output != Some(STDOUT_PATH) {
+        write_sample_hotspots(std::io::stdout().lock(), &samples, &symbols, count).map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+// prints the places the code seems to spin at, waiting for something
+fn busy_waits(matches: &ArgMatches) -> Result<(), String> {
+    let (symbols, _symbol_file_hashes) = load_configured_symbols(matches)?;
+    let mut profiling = read_capture(matches.value_of("INPUT").unwrap(), matches)?;
+    let frame_source = FrameSource::parse(matches.value_of("FRAME SOURCE").unwrap(), &symbols)?;
+    let (from, to) = selected_cycle_range(matches, &profiling, frame_source)?;
+    profiling.retain_cycle_range(from, to);
+    let count = matches.value_of("COUNT").unwrap().parse().map_err(|_| "--count expects a number".to_string())?;
+    let timing = video_timing(matches, &profiling)?;
+    let capture_cycles = match (profiling.packets.first(), profiling.packets.last()) {
+        (Some(first), Some(last)) => last.cycle - first.cycle,
+        _ => 0,
+    };
+    let frames = capture_cycles as f64 / timing.frame_cycles() as f64;
+    let duration_format = DurationFormat::parse(matches.value_of("UNITS").unwrap(), profiling.mclk, profiling.m68k_divider)?
+        .with_frame_cycles(Some(timing.frame_cycles()))
+        .with_capture_frames(Some(frames));
+    let busy_waits = find_busy_waits(&profiling);
+    write_busy_waits(std::io::stdout().lock(), &busy_waits, &symbols, count, &duration_format, capture_cycles).map_err(|err| err.to_string())
+}
+
+// prints how the time spent in each function changed between two captures
+fn compare(matches: &ArgMatches) -> Result<(), String> {
+    let (symbols, _symbol_file_hashes) = load_configured_symbols(matches)?;
+    // the build before the change may have its own symbols
+    let before_symbols = matches.value_of("BEFORE SYMBOLS").map(|before_symbol_file| load_symbol_file(before_symbol_file, matches.is_present("RAW NAMES"))).transpose()?;
+    check_stdin_inputs(matches, &[matches.value_of("BEFORE").unwrap(), matches.value_of("AFTER").unwrap()])?;
+    let mut before = read_capture(matches.value_of("BEFORE").unwrap(), matches)?;
+    let mut after = read_capture(matches.value_of("AFTER").unwrap(), matches)?;
+    let count = matches.value_of("COUNT").unwrap().parse().map_err(|_| "--count expects a number".to_string())?;
+    let timing = video_timing(matches, &after)?;
+    let duration_format = DurationFormat::parse(matches.value_of("UNITS").unwrap(), after.mclk, after.m68k_divider)?
+        .with_frame_cycles(Some(timing.frame_cycles()));
+    let capture_cycles = |profiling: &ParsedProfilingFile| match (profiling.packets.first(), profiling.packets.last()) {
+        (Some(first), Some(last)) => last.cycle - first.cycle,
+        _ => 0,
+    };
+    let before_symbols = before_symbols.as_ref().unwrap_or(&symbols);
+    let before_merged = merge_aliased_functions(&mut before, before_symbols);
+    let after_merged = merge_aliased_functions(&mut after, &symbols);
+    if let Some(function) = matches.value_of("FUNCTION") {
+        // matched by name, like the other functions
+        let resolve = |symbols: &Symbols, merged: &HashMap<u32, u32>| symbols.resolve(function)
+            .map(|address| merged.get(&address).copied().unwrap_or(address))
+            .ok_or_else(|| format!("Function {} not found in the symbol file", function));
+        let durations = |profiling: &ParsedProfilingFile, address: u32| function_invocations(profiling, address).into_iter().map(|(_start_cycle, cycles)| cycles).collect::<Vec<_>>();
+        let address = resolve(&symbols, &after_merged)?;
+        let (before_durations, after_durations) = (durations(&before, resolve(before_symbols, &before_merged)?), durations(&after, address));
+        return write_duration_comparison(std::io::stdout().lock(), &symbols.name(address), &before_durations, &after_durations, &duration_format).map_err(|err| err.to_string());
+    }
+    let comparisons = compare_function_stats(
+        &compute_function_stats(&before, &merged_functions(&resolve_idle_functions(matches, before_symbols)?, &before_merged)), before_symbols,
+        &compute_function_stats(&after, &merged_functions(&resolve_idle_functions(matches, &symbols)?, &after_merged)), &symbols,
+    );
+    println!("Captured before: {}, after: {}", duration_format.format_total(capture_cycles(&before) as f64), duration_format.format_total(capture_cycles(&after) as f64));
+    write_comparison(std::io::stdout().lock(), &comparisons, count, &duration_format).map_err(|err| err.to_string())
+}
+
+// prints the distribution of the durations of every invocation of a function
+fn histogram(matches: &ArgMatches) -> Result<(), String> {
+    let (symbols, _symbol_file_hashes) = load_configured_symbols(matches)?;
+    let function = matches.value_of("FUNCTION").unwrap();
+    let address = symbols.resolve(function).ok_or_else(|| format!("Function {} not found in the symbol file", function))?;
+    let mut profiling = read_capture(matches.value_of("INPUT").unwrap(), matches)?;
+    let address = merge_aliased_functions(&mut profiling, &symbols).get(&address).copied().unwrap_or(address);
+    let frame_source = FrameSource::parse(matches.value_of("FRAME SOURCE").unwrap(), &symbols)?;
+    let (from, to) = selected_cycle_range(matches, &profiling, frame_source)?;
+    profiling.retain_cycle_range(from, to);
+    let timing = video_timing(matches, &profiling)?;
+    let duration_format = DurationFormat::parse(matches.value_of("UNITS").unwrap(), profiling.mclk, profiling.m68k_divider)?
+        .with_frame_cycles(Some(timing.frame_cycles()));
+    let invocations = function_invocations(&profiling, address);
+    write_duration_histogram(std::io::stdout().lock(), &symbols.name(address), &invocations, &duration_format).map_err(|err| err.to_string())
+}
+
+// prints the statistics of the traversals from a start point to an end point
+fn bench(matches: &ArgMatches) -> Result<(), String> {
+    let (symbols, _symbol_file_hashes) = load_configured_symbols(matches)?;
+    let point = |name: &str| -> Result<(String, u32), String> {
+        let label = matches.value_of(name).unwrap();
+        let address = symbols.resolve(label).ok_or_else(|| format!("{} not found in the symbol file", label))?;
+        Ok((symbols.name(address).into_owned(), address))
+    };
+    let (start_name, start) = point("START")?;
+    let (end_name, end) = point("END")?;
+    let mut profiling = read_capture(matches.value_of("INPUT").unwrap(), matches)?;
+    let frame_source = FrameSource::parse(matches.value_of("FRAME SOURCE").unwrap(), &symbols)?;
+    let (from, to) = selected_cycle_range(matches, &profiling, frame_source)?;
+    profiling.retain_cycle_range(from, to);
+    let timing = video_timing(matches, &profiling)?;
+    let duration_format = DurationFormat::parse(matches.value_of("UNITS").unwrap(), profiling.mclk, profiling.m68k_divider)?
+        .with_frame_cycles(Some(timing.frame_cycles()));
+    let (traversals, restarts) = traversals(&profiling, start, end);
+    write_bench_report(std::io::stdout().lock(), &start_name, &end_name, &traversals, restarts, &duration_format).map_err(|err| err.to_string())
+}
+
+// writes the busy share of each line of each frame, and prints the busiest lines
+fn heatmap(matches: &ArgMatches) -> Result<(), String> {
+    let (symbols, _symbol_file_hashes) = load_configured_symbols(matches)?;
+    let output = matches.value_of("OUTPUT").unwrap();
+    let mut profiling = read_capture(matches.value_of("INPUT").unwrap(), matches)?;
+    let frame_source = FrameSource::parse(matches.value_of("FRAME SOURCE").unwrap(), &symbols)?;
+    let (from, to) = selected_cycle_range(matches, &profiling, frame_source)?;
+    profiling.retain_cycle_range(from, to);
+    let timing = video_timing(matches, &profiling)?;
+    let raster = Raster::from_capture(&profiling, &timing).ok_or("The capture has no VInt, the scanlines can't be placed")?;
+    let idle_functions = resolve_idle_functions(matches, &symbols)?;
+    if idle_functions.is_empty() {
+        eprintln!("Warning: without --idle, the time spent waiting for the VBlank counts as busy");
+    }
+    let heatmap = ScanlineHeatmap::from_capture(&profiling, &raster, &idle_functions);
+    if heatmap.rows.is_empty() {
+        return Err("The capture doesn't cover a whole frame".to_string());
+    }
+    let output_file = create_output(output).expect("Couldn't create output file");
+    write_scanline_heatmap_csv(output_file, &heatmap).map_err(|err| format!("Couldn't write {}: {}", output, err))?;
+    status!("Wrote the busy share of {} lines of {} frames to {}", heatmap.rows[0].len(), heatmap.rows.len(), output);
+    if output != STDOUT_PATH {
+        let mut lines: Vec<(usize, f64)> = heatmap.line_averages().into_iter().enumerate().collect();
+        lines.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        println!("Busiest lines on average:");
+        for (line, busy) in lines.iter().take(matches.value_of("COUNT").unwrap().parse().map_err(|_| "--count expects a number".to_string())?) {
+            println!("  line {:3}: {:5.1}% busy", line, busy * 100.0);
+        }
+    }
+    Ok(())
+}
+
+// prints which callers account for the calls and the time of a function
+fn callers(matches: &ArgMatches) -> Result<(), String> {
+    let (symbols, _symbol_file_hashes) = load_configured_symbols(matches)?;
+    let function = matches.value_of("FUNCTION").unwrap();
+    let address = symbols.resolve(function).ok_or_else(|| format!("Function {} not found in the symbol file", function))?;
+    let depth = matches.value_of("DEPTH").unwrap().parse().map_err(|_| "--depth expects a number".to_string())?;
+    let mut profiling = read_capture(matches.value_of("INPUT").unwrap(), matches)?;
+    let address = merge_aliased_functions(&mut profiling, &symbols).get(&address).copied().unwrap_or(address);
+    let frame_source = FrameSource::parse(matches.value_of("FRAME SOURCE").unwrap(), &symbols)?;
+    let (from, to) = selected_cycle_range(matches, &profiling, frame_source)?;
+    profiling.retain_cycle_range(from, to);
+    let timing = video_timing(matches, &profiling)?;
+    let capture_cycles = match (profiling.packets.first(), profiling.packets.last()) {
+        (Some(first), Some(last)) => last.cycle - first.cycle,
+        _ => 0,
+    };
+    let duration_format = DurationFormat::parse(matches.value_of("UNITS").unwrap(), profiling.mclk, profiling.m68k_divider)?
+        .with_frame_cycles(Some(timing.frame_cycles()))
+        .with_capture_frames(Some(capture_cycles as f64 / timing.frame_cycles() as f64));
+    let tree = caller_tree(&profiling, address, depth);
+    write_caller_tree(std::io::stdout().lock(), &symbols.name(address), &tree, &symbols, &duration_format).map_err(|err| err.to_string())
+}
+
+// lists every invocation of a function, with its frame and the functions it was called from
+fn find(matches: &ArgMatches) -> Result<(), String> {
+    let csv = matches.value_of("FIND FORMAT").unwrap() == "csv";
+    if csv {
+        // the csv is meant to be piped into scripts
+        set_status_to_stderr(true);
+    }
+    let (symbols, _symbol_file_hashes) = load_configured_symbols(matches)?;
+    let function = matches.value_of("FUNCTION").unwrap();
+    let address = symbols.resolve(function).ok_or_else(|| format!("Function {} not found in the symbol file", function))?;
+    let mut profiling = read_capture(matches.value_of("INPUT").unwrap(), matches)?;
+    let address = merge_aliased_functions(&mut profiling, &symbols).get(&address).copied().unwrap_or(address);
+    let frame_source = FrameSource::parse(matches.value_of("FRAME SOURCE").unwrap(), &symbols)?;
+    // numbered in the whole capture, like --frames
+    let frame_starts: Vec<u64> = profiling.packets.iter()
+        .filter(|packet| frame_source.is_frame_start(&packet.inner))
+        .map(|packet| packet.cycle)
+        .collect();
+    let (from, to) = selected_cycle_range(matches, &profiling, frame_source)?;
+    profiling.retain_cycle_range(from, to);
+    let timing = video_timing(matches, &profiling)?;
+    let duration_format = DurationFormat::parse(matches.value_of("UNITS").unwrap(), profiling.mclk, profiling.m68k_divider)?
+        .with_frame_cycles(Some(timing.frame_cycles()));
+    let invocations = invocations_with_callers(&profiling, address);
+    write_invocations(std::io::stdout().lock(), &symbols.name(address), &invocations, &frame_starts, &symbols, &duration_format, csv).map_err(|err| err.to_string())
+}
+
+// prints the invocations much longer than the others of their function
+fn outliers(matches: &ArgMatches) -> Result<(), String> {
+    let (symbols, _symbol_file_hashes) = load_configured_symbols(matches)?;
+    let mut profiling = read_capture(matches.value_of("INPUT").unwrap(), matches)?;
+    let frame_source = FrameSource::parse(matches.value_of("FRAME SOURCE").unwrap(), &symbols)?;
+    let (from, to) = selected_cycle_range(matches, &profiling, frame_source)?;
+    profiling.retain_cycle_range(from, to);
+    let number = |name: &str, option: &str| matches.value_of(name).map(|value| value.parse::<f64>().ok().filter(|value| value.is_finite() && *value > 0.0).ok_or_else(|| format!("{} expects a positive number", option))).transpose();
+    let threshold = match number("MEDIAN FACTOR", "--median-factor")? {
+        Some(factor) => OutlierThreshold::MedianFactor(factor),
+        None => OutlierThreshold::Sigma(number("SIGMA", "--sigma")?.unwrap()),
+    };
+    let min_invocations = matches.value_of("MIN INVOCATIONS").unwrap().parse().map_err(|_| "--min-invocations expects a number".to_string())?;
+    let count = matches.value_of("COUNT").unwrap().parse().map_err(|_| "--count expects a number".to_string())?;
+    let timing = video_timing(matches, &profiling)?;
+    let duration_format = DurationFormat::parse(matches.value_of("UNITS").unwrap(), profiling.mclk, profiling.m68k_divider)?
+        .with_frame_cycles(Some(timing.frame_cycles()));
+    let merged = merge_aliased_functions(&mut profiling, &symbols);
+    let outliers = find_outliers(&profiling, &merged_functions(&resolve_idle_functions(matches, &symbols)?, &merged), threshold, min_invocations);
+    write_outliers(std::io::stdout().lock(), &outliers, &symbols, count, &duration_format).map_err(|err| err.to_string())
+}
+
+// prints the functions running at a point in time, innermost first
+fn stack(matches: &ArgMatches) -> Result<(), String> {
+    let (symbols, _symbol_file_hashes) = load_configured_symbols(matches)?;
+    let profiling = read_capture(matches.value_of("INPUT").unwrap(), matches)?;
+    let cycle = parse_time(matches.value_of("AT").unwrap(), profiling.mclk)?;
+    match (profiling.packets.first(), profiling.packets.last()) {
+        (Some(first), Some(last)) if (first.cycle..=last.cycle).contains(&cycle) => {},
+        _ => return Err(format!("{} is outside of the capture", matches.value_of("AT").unwrap())),
+    }
+    let frame_source = FrameSource::parse(matches.value_of("FRAME SOURCE").unwrap(), &symbols)?;
+    let frame = profiling.packets.iter()
+        .filter(|packet| packet.cycle <= cycle && frame_source.is_frame_start(&packet.inner))
+        .count()
+        .checked_sub(1);
+    let timing = video_timing(matches, &profiling)?;
+    let duration_format = DurationFormat::parse(matches.value_of("UNITS").unwrap(), profiling.mclk, profiling.m68k_divider)?
+        .with_frame_cycles(Some(timing.frame_cycles()));
+    let stack = stack_at(&profiling, cycle);
+    write_stack(std::io::stdout().lock(), cycle, frame, &stack, &symbols, &duration_format).map_err(|err| err.to_string())
+}
+
+fn stack_top(matches: &ArgMatches) -> Result<Option<u32>, String> {
+    matches.value_of("STACK TOP")
+        .map(|top| u32::from_str_radix(top.trim_start_matches('$').trim_start_matches("0x"), 16).map_err(|_| "--stack-top expects a hex address".to_string()))
+        .transpose()
+}
+
+// prints how deep the stack went, and which functions used the most of it
+fn stack_usage(matches: &ArgMatches) -> Result<(), String> {
+    let (symbols, _symbol_file_hashes) = load_configured_symbols(matches)?;
+    let mut profiling = read_capture(matches.value_of("INPUT").unwrap(), matches)?;
+    let frame_source = FrameSource::parse(matches.value_of("FRAME SOURCE").unwrap(), &symbols)?;
+    let (from, to) = selected_cycle_range(matches, &profiling, frame_source)?;
+    profiling.retain_cycle_range(from, to);
+    let count = matches.value_of("COUNT").unwrap().parse().map_err(|_| "--count expects a number".to_string())?;
+    let top = stack_top(matches)?;
+    let limit: Option<u32> = matches.value_of("LIMIT").map(|limit| limit.parse().map_err(|_| "--limit expects a number of bytes".to_string())).transpose()?;
+    let usage = compute_stack_usage(&profiling, top);
+    if usage.top == 0 {
+        return Err("The capture doesn't contain any stack pointer".to_string());
+    }
+    write_stack_usage(std::io::stdout().lock(), &usage, &symbols, count, profiling.mclk).map_err(|err| err.to_string())?;
+    match limit {
+        Some(limit) if usage.max_bytes() > limit => Err(format!("The stack went {} bytes deep, over the limit of {} bytes", usage.max_bytes(), limit)),
+        _ => Ok(()),
+    }
+}
+
+// reports the structural anomalies of a capture
+fn validate(matches: &ArgMatches) -> Result<(), String> {
+    let profiling = read_capture(matches.value_of("INPUT").unwrap(), matches)?;
+    let anomalies = validate_capture(&profiling);
+    write_validation_report(std::io::stdout().lock(), &profiling, &anomalies).map_err(|err| err.to_string())?;
+    if anomalies.is_empty() {
+        println!("No anomalies found");
+        Ok(())
+    } else {
+        Err(format!("Found {} anomalies", anomalies.len()))
+    }
+}
+
+// writes a synthetic capture following a description, to test interval files and the converter without an emulator
+fn gen_test_trace(matches: &ArgMatches) -> Result<(), String> {
+    let (symbols, symbol_file_hashes) = load_configured_symbols(matches)?;
+    let description_file = matches.value_of("DESCRIPTION").unwrap();
+    let description = fs::read_to_string(description_file).map_err(|err| format!("Couldn't read {}: {}", description_file, err))?;
+    let mut builder = MdpBuilder::default();
+    if let Some(&hash) = symbol_file_hashes.first() {
+        builder.symbol_file_hash(hash);
+    }
+    builder.run_description(&description, &symbols)?;
+    let output = matches.value_of("OUTPUT").unwrap();
+    create_output(output).and_then(|mut output_file| output_file.write_all(&builder.to_bytes())).map_err(|err| format!("Couldn't write {}: {}", output, err))?;
+    status!("Wrote {} input events to {}", builder.build().packets.len(), output);
+    Ok(())
+}
+
+// records a capture streamed by the emulator over TCP or a named pipe
+#[cfg(feature = "live")]
+fn live(matches: &ArgMatches) -> Result<(), String> {
+    let output = matches.value_of("OUTPUT").unwrap();
+    let output_file = File::create(output).map_err(|err| format!("Couldn't create {}: {}", output, err))?;
+    let timeout = matches.value_of("TIMEOUT").unwrap().parse::<f64>().ok().filter(|timeout| *timeout > 0.0).ok_or("--timeout expects a number of seconds")?;
+    let timeout = Duration::from_secs_f64(timeout);
+    let stats = if let Some(address) = matches.value_of("LISTEN") {
+        let listener = TcpListener::bind(address).map_err(|err| format!("Couldn't listen on {}: {}", address, err))?;
+        status!("Waiting for the emulator on {}", listener.local_addr().map_err(|err| err.to_string())?);
+        let (stream, peer) = listener.accept().map_err(|err| format!("Error accepting a connection: {}", err))?;
+        status!("Recording the capture from {} to {}", peer, output);
+        record_capture(stream, output_file, timeout)?
+    } else {
+        let pipe = matches.value_of("PIPE").unwrap();
+        // opening a named pipe blocks until the emulator opens it too
+        status!("Waiting for the emulator on {}", pipe);
+        let pipe_file = File::open(pipe).map_err(|err| format!("Couldn't open {}: {}", pipe, err))?;
+        status!("Recording the capture from {} to {}", pipe, output);
+        record_capture(pipe_file, output_file, timeout)?
+    };
+    if let Some(handshake) = stats.handshake {
+        status!("The emulator used version {} of the live protocol{}", handshake.version, if handshake.heartbeat() { ", with heartbeats" } else { "" });
+    }
+    status!("The emulator closed the stream, wrote {} input events ({} MB) to {}", stats.packets, stats.bytes / 1_000_000, output);
+    Ok(())
+}
+
+#[cfg(not(feature = "live"))]
+fn live(_matches: &ArgMatches) -> Result<(), String> {
+    Err("md-profiler was built without the live feature".to_string())
+}
+
+// what a capture turns into, before it's written out
+struct ConvertedCapture<'a> {
+    // only the header when the trace events come from the cache
+    profiling: ParsedProfilingFile,
+    intervals: Intervals,
+    timing: VideoTiming,
+    frame_source: FrameSource,
+    idle_functions: HashSet<u32>,
+    frames: Option<Vec<FrameStats>>,
+    trace_events: Vec<TraceEvent<'a>>,
+    // the cycle the trace events start from, see --time-origin
+    time_origin: u64,
+    cache_header: Option<CacheHeader>,
+}
+
+impl ConvertedCapture<'_> {
+    fn interval_stats(&self) -> Vec<IntervalStats<'_>> {
+        match &self.cache_header {
+            Some(cache_header) => cache_header.interval_stats.iter().map(CachedIntervalStats::stats).collect(),
+            None => self.intervals.stats(),
+        }
+    }
+
+    fn pid_count(&self) -> u32 {
+        self.cache_header.as_ref().map_or_else(|| self.intervals.pid_count(), |cache_header| cache_header.pid_count)
+    }
+}
+
+// reads a capture and generates its trace events, and its frame statistics if they're needed for the reports
+fn convert_capture<'a>(matches: &ArgMatches, symbols: &'a Symbols, symbol_file_hashes: &[u64], interval_file: Option<&str>, input: &str, frame_stats: bool) -> Result<ConvertedCapture<'a>, String> {
+    let (mut intervals, mut custom_threads) = if let Some(interval_file) = interval_file {
+        let interval_data = fs::read(interval_file).map_err(|err| format!("Couldn't read {}: {}", interval_file, err))?;
+        read_intervals(&interval_data, symbols, interval_file)?
+    } else {
+        Default::default()
+    };
+
+    let origin = matches.value_of("TIME ORIGIN").map(TimeOrigin::parse).transpose()?;
+    let mut profiling = read_capture(input, matches)?;
+    if let Some(string_file) = matches.value_of("STRINGS") {
+        let strings = fs::read_to_string(string_file).map_err(|err| format!("Couldn't read {}: {}", string_file, err))?;
+        profiling.resolve_user_strings(&strings.lines().map(String::from).collect::<Vec<_>>());
+    }
+    let frame_source = FrameSource::parse(matches.value_of("FRAME SOURCE").unwrap(), symbols)?;
+    let (from, to) = selected_cycle_range(matches, &profiling, frame_source)?;
+    intervals.set_frame_source(frame_source);
+    intervals.set_exit_matching(profiling.exit_matching);
+    intervals.set_number_names(matches.is_present("NUMBER INTERVALS"));
+    if let Some(breakpoints_thread) = matches.value_of("BREAKPOINTS THREAD") {
+        let new_tid = custom_threads.values().max().map_or(FIRST_CUSTOM_TID, |tid| tid + 1);
+        let tid = *custom_threads.entry(breakpoints_thread.to_string()).or_insert(new_tid);
+        intervals.set_breakpoint_thread(tid);
+    }
+    let mut task_stacks = TaskStacks::default();
+    if let Some(task_stack_file) = matches.value_of("TASK STACKS") {
+        let task_stack_data = fs::read(task_stack_file).map_err(|err| format!("Couldn't read {}: {}", task_stack_file, err))?;
+        task_stacks = TaskStacks::read(&task_stack_data, symbols)?;
+        task_stacks.assign_threads(&mut custom_threads);
+    }
+    let mut annotations = Annotations::default();
+    if let Some(annotation_file) = matches.value_of("ANNOTATIONS") {
+        let annotation_data = fs::read(annotation_file).map_err(|err| format!("Couldn't read {}: {}", annotation_file, err))?;
+        annotations = Annotations::read(&annotation_data)?;
+        annotations.assign_thread(&mut custom_threads);
+    }
+    check_symbols_build(&profiling, symbols, symbol_file_hashes);
+    if from.is_some() || to.is_some() {
+        profiling.retain_cycle_range(from, to);
+        status!("Kept {} input events in the selected time range", profiling.packets.len());
+    }
+    let mut idle_functions = resolve_idle_functions(matches, symbols)?;
+    idle_functions.extend(intervals.idle_functions());
+    let split_interrupts = split_interrupts(matches)?;
+    let timing = video_timing(matches, &profiling)?;
+    let mut markers = MarkerStyles::default();
+    if matches.is_present("RASTER") {
+        // raster effects are timed from the HInts
+        markers.hint.scope = Some('t');
+    }
+    for spec in matches.values_of("MARKER").into_iter().flatten() {
+        markers.configure(spec)?;
+    }
+    let lag_markers = matches.is_present("LAG MARKERS") || matches.is_present("FRAME BUDGET");
+    // frame statistics are computed while the events are generated, the idle functions give them a utilization counter
+    let (frames, mut trace_events) = thread::scope(|scope| {
+        let frames = (frame_stats || !idle_functions.is_empty() || lag_markers || matches.is_present("FOCUS WORST") || matches.is_present("INTERRUPT COUNTERS")).then(|| {
+            scope.spawn(|| compute_frame_stats(&profiling, frame_source, &idle_functions, &timing))
+        });
+        let trace_events = generate_trace_events(&profiling, symbols, &mut intervals, custom_threads, &markers, split_interrupts, &task_stacks);
+        (frames.map(|frames| frames.join().unwrap()), trace_events)
+    });
+    intervals.configure_threads(&mut trace_events);
+    let time_origin = time_origin(origin.as_ref(), &profiling, &intervals)?;
+    if !annotations.is_empty() {
+        trace_events.extend(annotations.trace_events(&profiling, frame_source)?);
+    }
+    mark_idle_slices(&mut trace_events, symbols, &idle_functions);
+    if let (Some(frames), false) = (&frames, idle_functions.is_empty()) {
+        trace_events.extend(utilization_counter_events(frames, profiling.mclk));
+    }
+    if let (Some(frames), true) = (&frames, matches.is_present("INTERRUPT COUNTERS")) {
+        trace_events.extend(interrupt_counter_events(frames, profiling.mclk));
+        let (unusual_frames, usual_hints) = unusual_hint_frames(frames);
+        if let Some(frame) = unusual_frames.first() {
+            eprintln!("Warning: {} frames don't have the {} HInts most frames have, starting with frame {} at {:.3} ms which has {}",
+                unusual_frames.len(), usual_hints, frame.index, cycle_to_us(frame.start_cycle, profiling.mclk) / 1000.0, frame.hints);
+        }
+    }
+    if let Some(dma_queue) = DmaQueue::from_symbols(symbols) {
+        let (dma_queue_events, stats) = dma_queue.trace_events(&profiling, split_interrupts, &task_stacks);
+        if stats.queued != 0 {
+            status!("DMA queue: {} transfers queued, {} flushes", stats.queued, stats.flushes);
+        }
+        if stats.transfers != 0 {
+            status!("  {} bytes per flush on average, at most {}", stats.bytes / stats.flushes as u64, stats.max_flush_bytes);
+        }
+        trace_events.extend(dma_queue_events);
+    }
+    if matches.is_present("HINT JITTER") {
+        trace_events.extend(hint_jitter_events(&compute_interrupt_latencies(&profiling), profiling.mclk));
+    }
+    if matches.is_present("STACK COUNTER") || matches.is_present("STACK TOP") {
+        trace_events.extend(stack_counter_events(&profiling, stack_top(matches)?));
+    }
+    if let (Some(frames), true) = (&frames, lag_markers) {
+        let budget_cycles = match matches.value_of("FRAME BUDGET") {
+            Some(budget) => parse_time(budget, profiling.mclk)?,
+            None => timing.frame_cycles(),
+        };
+        let markers = over_budget_events(frames, budget_cycles, profiling.mclk);
+        status!("Marked {} frames over the budget of {:.3} ms", markers.len(), cycle_to_us(budget_cycles, profiling.mclk) / 1000.0);
+        trace_events.extend(markers);
+    }
+    if matches.is_present("FOCUS WORST") {
+        match frames.as_deref().and_then(worst_frame) {
+            Some(frame) => {
+                trace_events.push(worst_frame_event(frame, profiling.mclk));
+                let (start_us, end_us) = (cycle_to_us(frame.start_cycle, profiling.mclk), cycle_to_us(frame.end_cycle, profiling.mclk));
+                let origin_us = cycle_to_us(time_origin, profiling.mclk);
+                status!("Worst frame: frame {} from {:.3} ms to {:.3} ms, busy {:.3} ms", frame.index, start_us / 1000.0, end_us / 1000.0, cycle_to_us(frame.busy_cycles, profiling.mclk) / 1000.0);
+                // the timestamps of the trace processor are in nanoseconds
+                status!("  select * from slice where ts >= {} and ts < {}", ((start_us - origin_us) * 1000.0).round(), ((end_us - origin_us) * 1000.0).round());
+            },
+            None => eprintln!("Warning: the capture has no whole frame, --focus-worst is ignored"),
+        }
+    }
+    if matches.is_present("RASTER") {
+        match Raster::from_capture(&profiling, &timing) {
+            Some(raster) => annotate_raster(&mut trace_events, &raster, profiling.mclk),
+            None => eprintln!("Warning: the capture has no VInt to tell the raster position from, --raster is ignored"),
+        }
+    }
+    if origin.is_some() {
+        trace_events = rebase_trace_events(trace_events, cycle_to_us(time_origin, profiling.mclk));
+        status!("The trace starts at {:.3} ms of the capture", cycle_to_us(time_origin, profiling.mclk) / 1000.0);
+    }
+    Ok(ConvertedCapture {
+        profiling,
+        intervals,
+        timing,
+        frame_source,
+        idle_functions,
+        frames,
+        trace_events,
+        time_origin,
+        cache_header: None,
+    })
+}
+
+// the options shaping the trace events of a capture, the cache is converted again when one of them changes
+const CACHE_KEY_ARGS: [&str; 40] = [
+    "SYMBOLS", "RAW NAMES", "LABEL POLICY", "ADDRESS NAMING", "HEX FORMAT", "PREFER SYMBOLS", "ALIASES", "REGIONS", "RELOCATIONS", "PRESET", "ANONYMIZE",
+    "MANUAL INTERVALS", "MCLK", "M68K DIVIDER", "ADDRESS MASK", "MIRROR", "EXIT MATCHING", "OVERHEAD", "FROM", "TO", "FRAMES",
+    "FRAME SOURCE", "IDLE", "VIDEO STANDARD", "STRINGS", "NUMBER INTERVALS", "BREAKPOINTS THREAD", "TASK STACKS", "ANNOTATIONS",
+    "SPLIT INTERRUPTS", "MARKER", "RASTER", "LAG MARKERS", "FRAME BUDGET", "FOCUS WORST", "INTERRUPT COUNTERS", "HINT JITTER",
+    "STACK COUNTER", "STACK TOP", "TIME ORIGIN",
+];
+// the outputs made from the packets of the capture rather than from its trace events
+const CAPTURE_OUTPUT_ARGS: [&str; 6] = ["SYMBOL DIFF", "LATENCY REPORT", "SPLIT FRAMES", "STATS", "COLLAPSED", "SQLITE"];
+
+// the version of md-profiler, the size and modification time of the capture and of the files it's converted with, the symbols and the options
+fn cache_key(matches: &ArgMatches, symbols: &Symbols, symbol_files: &[String], interval_file: Option<&str>, input: &str) -> u64 {
+    let mut key = format!("{}\n{:016x}\n", env!("CARGO_PKG_VERSION"), symbols.hash);
+    let paths = conversion_paths(matches, symbol_files, interval_file, &[input]);
+    for (path, state) in paths.iter().zip(file_states(&paths)) {
+        key += &format!("{} {:?}\n", path, state);
+    }
+    for arg in CACHE_KEY_ARGS {
+        key += &format!("{} {} {:?}\n", arg, matches.occurrences_of(arg), matches.values_of(arg).map(Iterator::collect::<Vec<_>>));
+    }
+    symbol_file_hash(key.as_bytes())
+}
+
+// the symbols of another build than the one captured give subtly wrong names, the capture can tell its build by the hash of
+// its symbol file, the checksum of its ROM and its build id, and the symbol file by its hash and sometimes the other two
+fn check_symbols_build(profiling: &ParsedProfilingFile, symbols: &Symbols, symbol_file_hashes: &[u64]) {
+    if let Some(capture_hash) = profiling.symbol_file_hash {
+        if !symbol_file_hashes.is_empty() && !symbol_file_hashes.contains(&capture_hash) {
+            let hashes: Vec<_> = symbol_file_hashes.iter().map(|hash| format!("{:016x}", hash)).collect();
+            eprintln!("WARNING: the symbol file doesn't match the build that was captured (symbol file hash {}, capture recorded {:016x}), names in the trace are probably wrong!", hashes.join(", "), capture_hash);
+        }
+    }
+    let capture_info = &profiling.capture_info;
+    if let (Some(symbols_checksum), Some(capture_checksum)) = (symbols.rom_checksum, capture_info.rom_checksum) {
+        if symbols_checksum != capture_checksum {
+            eprintln!("WARNING: the symbol file comes from another build than the captured ROM (ROM checksum {:#06x} in the symbol file, {:#06x} in the capture), names in the trace are probably wrong!", symbols_checksum, capture_checksum);
+        }
+    }
+    if let (Some(symbols_build_id), Some(capture_build_id)) = (symbols.build_id.as_deref(), capture_info.build_id.as_deref()) {
+        if symbols_build_id != capture_build_id {
+            eprintln!("WARNING: the symbol file comes from another build than the captured ROM (build {} in the symbol file, {} in the capture), names in the trace are probably wrong!", format_build_id(symbols_build_id), format_build_id(capture_build_id));
+        }
+    }
+}
+
+// with --cache, the trace events of the capture are loaded from its cache when it's up to date and the outputs only need them,
+// otherwise the capture is converted and its cache written
+fn convert_cached_capture<'a>(matches: &ArgMatches, symbols: &'a Symbols, symbol_files: &[String], symbol_file_hashes: &[u64], interval_file: Option<&str>, input: &str, frame_stats: bool) -> Result<ConvertedCapture<'a>, String> {
+    if !matches.is_present("CACHE") {
+        return convert_capture(matches, symbols, symbol_file_hashes, interval_file, input, frame_stats);
+    }
+    let cache_path = Path::new(input).with_extension(CACHE_EXTENSION);
+    let key = cache_key(matches, symbols, symbol_files, interval_file, input);
+    let needs_capture = frame_stats || CAPTURE_OUTPUT_ARGS.iter().any(|&arg| matches.is_present(arg));
+    if let (false, Ok(cache_data)) = (needs_capture, fs::read(&cache_path)) {
+        match read_cache(&cache_data, key) {
+            Ok(Some((cache_header, trace_events))) => {
+                status!("Loaded {} output events from {}", trace_events.len(), cache_path.display());
+                check_symbols_build(&cache_header.capture(), symbols, symbol_file_hashes);
+                return Ok(ConvertedCapture {
+                    profiling: cache_header.capture(),
+                    intervals: Intervals::default(),
+                    timing: cache_header.timing,
+                    frame_source: FrameSource::parse(matches.value_of("FRAME SOURCE").unwrap(), symbols)?,
+                    idle_functions: resolve_idle_functions(matches, symbols)?,
+                    frames: None,
+                    trace_events,
+                    // the cached events are already rebased, and the outputs made from the packets don't use the cache
+                    time_origin: 0,
+                    cache_header: Some(cache_header),
+                });
+            },
+            Ok(None) => status!("{} is out of date, converting {} again", cache_path.display(), input),
+            Err(err) => eprintln!("Warning: couldn't read {}: {}, converting {} again", cache_path.display(), err, input),
+        }
+    }
+    let capture = convert_capture(matches, symbols, symbol_file_hashes, interval_file, input, frame_stats)?;
+    let cache_header = CacheHeader {
+        key,
+        mclk: capture.profiling.mclk,
+        m68k_divider: capture.profiling.m68k_divider,
+        symbol_file_hash: capture.profiling.symbol_file_hash,
+        capture_info: capture.profiling.capture_info.clone(),
+        timing: capture.timing,
+        pid_count: capture.intervals.pid_count(),
+        interval_stats: capture.intervals.stats().iter().map(CachedIntervalStats::from_stats).collect(),
+    };
+    match File::create(&cache_path).and_then(|cache_file| write_cache(cache_file, &cache_header, &capture.trace_events)) {
+        Ok(()) => status!("Wrote the output events to {}", cache_path.display()),
+        Err(err) => eprintln!("Warning: couldn't write {}: {}", cache_path.display(), err),
+    }
+    Ok(capture)
+}
+
+// how the trace was made, for the otherData of the json trace
+fn trace_metadata(matches: &ArgMatches, symbols: &Symbols, symbol_file_hashes: &[u64], captures: &[ConvertedCapture], inputs: &[&str]) -> TraceMetadata {
+    let hash = |hash: u64| format!("{:016x}", hash);
+    TraceMetadata {
+        schema_version: SCHEMA_VERSION,
+        symbol_file_hash: Some(symbols.hash).filter(|&symbols_hash| symbols_hash != 0).map(hash),
+        converter_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        // the paths and labels it has would give away what anonymizing hides
+        command_line: if matches.is_present("ANONYMIZE") { Vec::new() } else { std::env::args().skip(1).collect() },
+        symbol_file_hashes: symbol_file_hashes.iter().copied().map(hash).collect(),
+        captures: captures.iter().zip(inputs).map(|(capture, input)| CaptureMetadata {
+            path: input.to_string(),
+            mdp_version: MDP_VERSION,
+            mclk: capture.profiling.mclk,
+            m68k_divider: capture.profiling.m68k_divider,
+            source: Some(capture.profiling.capture_info.description()).filter(|_| !capture.profiling.capture_info.is_empty()),
+            symbol_file_hash: capture.profiling.symbol_file_hash.map(hash),
+        }).collect(),
+    }
+}
+
+// converts the captures to a json trace, along with the optional reports,
+// several captures are put side by side in the same trace, each in its own processes
+fn convert(matches: &ArgMatches, symbol_files: &[String], interval_file: Option<&str>, inputs: &[&str], output: Option<&str>) -> Result<(), String> {
+    let (mut symbols, symbol_file_hashes) = load_symbols(symbol_files, matches.is_present("RAW NAMES"))?;
+    configure_labels(&mut symbols, matches)?;
+    if let Some(name_mapping) = matches.value_of("ANONYMIZE") {
+        let mut name_mapping_file = create_output(name_mapping).expect("Couldn't create name mapping file");
+        for (anonymized_name, name) in symbols.anonymize() {
+            writeln!(name_mapping_file, "{},{}", anonymized_name, name).expect("Error writing name mapping file");
+        }
+    }
+    if inputs.len() > 1 {
+        // the reports are about a single capture
+        if let Some(option) = ["FRAME REPORT", "FRAME JSONL", "FRAME ATTRIBUTION", "SYMBOL DIFF", "INTERVAL CSV", "LATENCY REPORT", "SPLIT FRAMES", "STATS", "COLLAPSED", "HTML", "SQLITE"].iter().find(|&&option| matches.is_present(option)) {
+            return Err(format!("--{} only works with a single input", option.to_lowercase().replace(' ', "-")));
+        }
+    }
+    // --perfetto predates --format
+    let format = if matches.is_present("PERFETTO") { "perfetto" } else { matches.value_of("FORMAT").unwrap() };
+    let extra_trace_outputs: Vec<(&str, &str)> = EXTRA_TRACE_OUTPUTS.iter()
+        .filter_map(|&(arg, format)| matches.value_of(arg).map(|path| (path, format)))
+        .collect();
+    if cfg!(not(feature = "perfetto")) && ((output.is_some() && format == "perfetto") || extra_trace_outputs.iter().any(|&(_path, format)| format == "perfetto")) {
+        return Err("md-profiler was built without the perfetto feature".into());
+    }
+    // the trace processor queries the json trace
+    let json_output = match output {
+        Some(output) if format == "json" => Some(output),
+        _ => matches.value_of("JSON"),
+    };
+    if json_output == Some(STDOUT_PATH) && matches.is_present("TRACE PROCESSOR") {
+        return Err("--trace-processor needs the trace in a file, it can't be written to stdout".to_string());
+    }
+    if output.is_some() && format == "html" {
+        if inputs.len() > 1 {
+            return Err("--format html only works with a single input".into());
+        }
+        if let Some(option) = ["SPLIT FRAMES", "COMPRESS", "TRACE PROCESSOR"].iter().find(|&&option| matches.is_present(option)) {
+            return Err(format!("--{} doesn't apply to --format html", option.to_lowercase().replace(' ', "-")));
+        }
+    }
+    let compression = matches.value_of("COMPRESS").map_or(Ok(Compression::None), Compression::parse)?;
+    let json_format = JsonFormat::parse(matches.value_of("JSON FORMAT").unwrap())?;
+    let compact = matches.is_present("COMPACT");
+    let split_frames = matches.value_of("SPLIT FRAMES").map(|split_frames| {
+        split_frames.parse::<usize>().ok().filter(|&split_frames| split_frames != 0).ok_or_else(|| format!("Invalid --split-frames: {}, expected a number of frames", split_frames))
+    }).transpose()?;
+    let old_symbols = matches.value_of("COMPARE SYMBOLS").map(|old_symbol_file| {
+        let mut old_symbols = load_symbol_file(old_symbol_file, matches.is_present("RAW NAMES"))?;
+        // the aliases are meant for the current symbols, but the same policy makes the comparison fair
+        old_symbols.set_label_policy(LabelPolicy::parse(matches.value_of("LABEL POLICY").unwrap()).unwrap());
+        old_symbols.set_address_naming(AddressNaming::parse(matches.value_of("ADDRESS NAMING").unwrap()).unwrap(), HexFormat::parse(matches.value_of("HEX FORMAT").unwrap()).unwrap());
+        Ok::<_, String>(old_symbols)
+    }).transpose()?;
+    let symbol_diff = matches.value_of("SYMBOL DIFF");
+    let frame_report = matches.value_of("FRAME REPORT");
+    let frame_jsonl = matches.value_of("FRAME JSONL");
+    let frame_attribution = matches.value_of("FRAME ATTRIBUTION");
+    let attribution_frames = matches.value_of("ATTRIBUTION FRAMES").unwrap().parse().map_err(|_| "--attribution-frames expects a number".to_string())?;
+    let latency_report = matches.value_of("LATENCY REPORT");
+    let stats_output = matches.value_of("STATS");
+    let collapsed_output = matches.value_of("COLLAPSED");
+    let sqlite_output = matches.value_of("SQLITE");
+    if sqlite_output == Some(STDOUT_PATH) {
+        return Err("--sqlite writes a database file, it can't be written to stdout".to_string());
+    }
+    if cfg!(not(feature = "sqlite")) && sqlite_output.is_some() {
+        return Err("md-profiler was built without the sqlite feature".into());
+    }
+    let mut captures = Vec::new();
+    let mut trace_events = Vec::new();
+    for (index, &input) in inputs.iter().enumerate() {
+        let mut capture = convert_cached_capture(matches, &symbols, symbol_files, &symbol_file_hashes, interval_file, input, frame_report.is_some() || frame_jsonl.is_some() || frame_attribution.is_some() || format == "html" || matches.is_present("HTML") || sqlite_output.is_some())?;
+        if inputs.len() > 1 {
+            let pid_count = capture.pid_count();
+            set_capture_processes(&mut capture.trace_events, index as u32, pid_count, input);
+        }
+        trace_events.append(&mut capture.trace_events);
+        captures.push(capture);
+    }
+    let folded_functions = resolve_folded_functions(matches, &symbols)?;
+    if !folded_functions.is_empty() {
+        let folded = fold_functions(&mut trace_events, &symbols, &folded_functions);
+        status!("Folded {} slices of {} functions into their callers", folded, folded_functions.len());
+    }
+    if matches.is_present("COLLAPSE RECURSION") {
+        collapse_recursion(&mut trace_events);
+    }
+    if let Some(merge_duration) = matches.value_of("MERGE SHORT CALLS") {
+        let merge_duration_us: f64 = merge_duration.parse().ok().filter(|merge_duration: &f64| merge_duration.is_finite()).ok_or_else(|| "--merge-short-us expects a number of microseconds".to_string())?;
+        let merged = merge_short_calls(&mut trace_events, merge_duration_us);
+        status!("Merged {} consecutive calls shorter than {} us", merged, merge_duration_us);
+    }
+    let included_categories: Option<Vec<&str>> = matches.values_of("CATEGORIES").map(Iterator::collect);
+    let excluded_categories: Vec<&str> = matches.values_of("EXCLUDE CATEGORIES").into_iter().flatten().collect();
+    if included_categories.is_some() || !excluded_categories.is_empty() {
+        filter_categories(&mut trace_events, included_categories.as_deref(), &excluded_categories);
+        status!("Kept {} output events in the selected categories", trace_events.len());
+    }
+    let included_tracks: Option<Vec<&str>> = matches.values_of("ONLY TRACK").map(Iterator::collect);
+    let excluded_tracks: Vec<&str> = matches.values_of("EXCLUDE TRACK").into_iter().flatten().collect();
+    if included_tracks.is_some() || !excluded_tracks.is_empty() {
+        let dropped = filter_tracks(&mut trace_events, included_tracks.as_deref(), &excluded_tracks);
+        status!("Dropped {} output events of the other tracks", dropped);
+    }
+    if let Some(min_duration) = matches.value_of("MIN DURATION") {
+        let min_duration_us: f64 = min_duration.parse().ok().filter(|min_duration: &f64| min_duration.is_finite()).ok_or_else(|| "--min-duration-us expects a number of microseconds".to_string())?;
+        let dropped = filter_short_slices(&mut trace_events, min_duration_us);
+        status!("Dropped {} slices shorter than {} us", dropped, min_duration_us);
+    }
+    let chunks: Vec<(String, Vec<TraceEvent>)> = match (split_frames, output) {
+        (Some(split_frames), Some(output)) => {
+            let ConvertedCapture { profiling, frame_source, time_origin, .. } = &captures[0];
+            let chunk_starts: Vec<f64> = profiling.packets.iter()
+                .filter(|packet| frame_source.is_frame_start(&packet.inner) && packet.cycle >= *time_origin)
+                .map(|packet| cycle_to_us(packet.cycle - time_origin, profiling.mclk))
+                .skip(split_frames)
+                .step_by(split_frames)
+                .collect();
+            let chunks = split_trace_events(std::mem::take(&mut trace_events), &chunk_starts);
+            status!("Split the trace into {} files of {} frames", chunks.len(), split_frames);
+            chunks.into_iter().enumerate().map(|(chunk, trace_events)| (chunk_path(output, chunk), trace_events)).collect()
+        },
+        _ => Vec::new(),
+    };
+    let mut trace_outputs: Vec<(&str, &str, &[TraceEvent])> = chunks.iter().map(|(chunk, trace_events)| (chunk.as_str(), format, trace_events.as_slice())).collect();
+    if split_frames.is_none() {
+        trace_outputs.extend(output.map(|output| (output, format, trace_events.as_slice())));
+        trace_outputs.extend(extra_trace_outputs.iter().map(|&(path, format)| (path, format, trace_events.as_slice())));
+    }
+    let metadata = trace_metadata(matches, &symbols, &symbol_file_hashes, &captures, inputs);
+    let mut duration_formats = Vec::new();
+    for capture in &captures {
+        // unknown when the trace events come from the cache
+        let capture_cycles = match (capture.profiling.packets.first(), capture.profiling.packets.last()) {
+            (Some(first), Some(last)) => last.cycle - first.cycle,
+            _ => 0,
+        };
+        duration_formats.push(DurationFormat::parse(matches.value_of("UNITS").unwrap(), capture.profiling.mclk, capture.profiling.m68k_divider)?
+            .with_frame_cycles(Some(capture.timing.frame_cycles()))
+            .with_capture_frames(Some(capture_cycles as f64 / capture.timing.frame_cycles() as f64)));
+    }
+    // the trace events are made, what's left are the reports
+    let merged = merge_aliased_functions(&mut captures[0].profiling, &symbols);
+    let report_idle_functions = merged_functions(&captures[0].idle_functions, &merged);
+    // every output is written from its own thread, so extra outputs don't add up to the conversion time
+    thread::scope(|scope| {
+        let symbols = &symbols;
+        // the reports are only requested with a single capture
+        let ConvertedCapture { profiling, intervals, timing, frame_source, frames, .. } = &captures[0];
+        let idle_functions = &report_idle_functions;
+        let duration_format = &duration_formats[0];
+        // without idle functions, VInt frames always last one frame, but frames starting elsewhere can last longer
+        let detects_lag = !idle_functions.is_empty() || *frame_source != FrameSource::VInt;
+        if let (Some(frames), Some(frame_report)) = (frames, frame_report) {
+            scope.spawn(move || {
+                let mut frame_report_file = create_output(frame_report).expect("Couldn't create frame report file");
+                write_frame_report(&mut frame_report_file, frames, &profiling.savestates(), duration_format, timing, detects_lag);
+                status!("Wrote statistics for {} frames to {}", frames.len(), frame_report);
+            });
+        }
+        if let (Some(frames), Some(frame_jsonl)) = (frames, frame_jsonl) {
+            scope.spawn(move || {
+                let mut frame_jsonl_file = create_output(frame_jsonl).expect("Couldn't create frame json lines file");
+                write_frame_jsonl(&mut frame_jsonl_file, frames, symbols, intervals, profiling.mclk, detects_lag);
+                status!("Wrote {} frames to {}", frames.len(), frame_jsonl);
+            });
+        }
+        if let (Some(frames), Some(frame_attribution)) = (frames, frame_attribution) {
+            scope.spawn(move || {
+                let mut frame_attribution_file = create_output(frame_attribution).expect("Couldn't create frame attribution file");
+                write_frame_attribution(&mut frame_attribution_file, frames, symbols, intervals, duration_format, attribution_frames, detects_lag);
+                status!("Wrote the attribution of the {} worst frames to {}", attribution_frames.min(frames.len()), frame_attribution);
+            });
+        }
+        if let Some(latency_report) = latency_report {
+            scope.spawn(move || {
+                let latencies = compute_interrupt_latencies(profiling);
+                let mut latency_report_file = create_output(latency_report).expect("Couldn't create latency report file");
+                write_latency_report(&mut latency_report_file, &latencies, symbols, duration_format).expect("Error writing latency report file");
+                status!("Wrote the latencies of {} interrupt handlers to {}", latencies.len(), latency_report);
+            });
+        }
+        if let Some(stats_output) = stats_output {
+            scope.spawn(move || {
+                let stats = compute_function_stats(profiling, idle_functions);
+                let stats_file = create_output(stats_output).expect("Couldn't create function statistics file");
+                write_function_stats_csv(stats_file, &stats, symbols, profiling.mclk).expect("Error writing function statistics file");
+                status!("Wrote the statistics of {} functions to {}", stats.len(), stats_output);
+            });
+        }
+        #[cfg(feature = "sqlite")]
+        if let (Some(frames), Some(sqlite_output)) = (frames, sqlite_output) {
+            let trace_events = &trace_events;
+            scope.spawn(move || {
+                let function_stats = compute_function_stats(profiling, idle_functions);
+                let database = AnalysisDatabase {
+                    path: inputs[0],
+                    profiling,
+                    symbols,
+                    trace_events,
+                    frames,
+                    function_stats: &function_stats,
+                };
+                let sqlite_file = File::create(sqlite_output).expect("Couldn't create sqlite database");
+                write_analysis_database(sqlite_file, &database).expect("Error writing sqlite database");
+                let event_count = trace_events.iter().filter(|trace_event| trace_event.ph != 'M').count();
+                status!("Wrote {} events, {} frames and {} functions to {}", event_count, frames.len(), function_stats.len(), sqlite_output);
+            });
+        }
+        if let Some(collapsed_output) = collapsed_output {
+            scope.spawn(move || {
+                let stacks = collapse_calls(profiling, idle_functions);
+                let collapsed_file = create_output(collapsed_output).expect("Couldn't create collapsed stacks file");
+                write_collapsed_calls(collapsed_file, &stacks, symbols).expect("Error writing collapsed stacks file");
+                status!("Wrote {} stacks to {}", stacks.len(), collapsed_output);
+            });
+        }
+        if let (Some(old_symbols), Some(symbol_diff)) = (&old_symbols, symbol_diff) {
+            scope.spawn(move || {
+                let mut symbol_diff_file = create_output(symbol_diff).expect("Couldn't create symbol diff file");
+                write_symbol_diff(&mut symbol_diff_file, profiling, old_symbols, symbols);
+                status!("Wrote symbol differences to {}", symbol_diff);
+            });
+        }
+        let metadata = &metadata;
+        for &(output, format, trace_events) in &trace_outputs {
+            scope.spawn(move || {
+                let profile_json = create_output(output).expect("Couldn't create output file");
+                match format {
+                    #[cfg(feature = "perfetto")]
+                    "perfetto" => write_perfetto_trace(profile_json, trace_events, compression, compact),
+                    "html" => {
+                        let report = HtmlReport {
+                            title: inputs[0],
+                            profiling,
+                            symbols,
+                            idle_functions,
+                            frames: frames.as_deref().unwrap_or_default(),
+                            intervals,
+                            duration_format,
+                            timing,
+                            detects_lag,
+                        };
+                        write_html_report(profile_json, &report).expect("Error writing html report");
+                        status!("Wrote the html report to {}", output);
+                    },
+                    _ => write_profiling_json(profile_json, trace_events, metadata, compression, json_format, compact),
+                }
+            });
+        }
+    });
+    for ((capture, duration_format), input) in captures.iter().zip(&duration_formats).zip(inputs) {
+        let interval_stats = capture.interval_stats();
+        if !interval_stats.is_empty() {
+            if inputs.len() > 1 {
+                status!("Intervals of {}:", input);
+            } else {
+                status!("Intervals:");
+            }
+            if status_to_stderr() {
+                write_interval_stats(&mut std::io::stderr().lock(), &interval_stats, duration_format);
+            } else {
+                write_interval_stats(&mut std::io::stdout().lock(), &interval_stats, duration_format);
+            }
+        }
+        if let Some(interval_csv) = matches.value_of("INTERVAL CSV") {
+            let mut interval_csv_file = create_output(interval_csv).expect("Couldn't create interval csv file");
+            write_interval_stats_csv(&mut interval_csv_file, &interval_stats, capture.profiling.mclk);
+            status!("Wrote interval statistics to {}", interval_csv);
+        }
+    }
+    if matches.is_present("TRACE PROCESSOR") {
+        #[cfg(feature = "trace-processor")]
+        run_queries(&locate_trace_processor(matches.value_of("TRACE PROCESSOR"))?, json_output.ok_or("--trace-processor queries the json trace, give -o with the json format or --json")?)?;
+        #[cfg(not(feature = "trace-processor"))]
+        return Err("md-profiler was built without the trace-processor feature".into());
+    }
+    Ok(())
+}
+
+// modification time and size of each file, None while it can't be read
+fn file_states(paths: &[&str]) -> Vec<Option<(SystemTime, u64)>> {
+    paths.iter().map(|path| {
+        let metadata = fs::metadata(path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }).collect()
+}
+
+// the captures and every file they're converted with
+fn conversion_paths<'a>(matches: &'a ArgMatches, symbol_files: &'a [String], interval_file: Option<&'a str>, inputs: &[&'a str]) -> Vec<&'a str> {
+    let mut paths = inputs.to_vec();
+    paths.extend(symbol_files.iter().map(String::as_str));
+    paths.extend(interval_file);
+    paths.extend(matches.value_of("ALIASES"));
+    paths.extend(matches.value_of("REGIONS"));
+    paths.extend(matches.value_of("RELOCATIONS"));
+    paths.extend(matches.value_of("TASK STACKS"));
+    paths.extend(matches.value_of("ANNOTATIONS"));
+    paths.extend(matches.value_of("FOLD FILE"));
+    paths.extend(matches.value_of("STRINGS"));
+    paths
+}
+
+// converts the capture again each time it or one of the files it's converted with changes, until interrupted
+fn watch(matches: &ArgMatches, symbol_files: &[String], interval_file: Option<&str>, inputs: &[&str], output: Option<&str>) -> Result<(), String> {
+    let paths = conversion_paths(matches, symbol_files, interval_file, inputs);
+    loop {
+        let mut states = file_states(&paths);
+        // a broken interval file shouldn't end the session, the next save will be converted again
+        if let Err(err) = convert(matches, symbol_files, interval_file, inputs, output) {
+            eprintln!("Error: {}", err);
+        }
+        status!("Watching {} for changes, press Ctrl+C to stop", paths.join(", "));
+        let converted_states = states.clone();
+        // waits for a change, then for the files to stop changing so the emulator or the editor is done writing them
+        let mut changed = false;
+        loop {
+            thread::sleep(WATCH_POLL_INTERVAL);
+            let new_states = file_states(&paths);
+            if new_states != states {
+                changed = true;
+                states = new_states;
+            } else if changed && states.iter().all(Option::is_some) {
+                break;
+            }
+        }
+        let changed_paths: Vec<&str> = paths.iter().zip(states.iter().zip(&converted_states))
+            .filter(|(_path, (state, converted_state))| state != converted_state)
+            .map(|(&path, _states)| path)
+            .collect();
+        status!("{} changed, converting again", changed_paths.join(", "));
+        // a rebuild without a new capture, the symbols and the intervals resolved with them are the ones of the new build
+        if changed_paths.iter().any(|path| symbol_files.iter().any(|symbol_file| symbol_file == path)) && !changed_paths.iter().any(|path| inputs.contains(path)) {
+            eprintln!("Warning: the symbol files changed but the capture didn't, if the game was rebuilt the new addresses don't match the ones of the capture, record it again");
+        }
+    }
+}
+
+// the symbol files given with -s, the one given with --prefer-symbols first
+fn symbol_files(matches: &ArgMatches) -> Result<Vec<String>, String> {
+    let mut symbol_files: Vec<String> = matches.values_of("SYMBOLS").into_iter().flatten().map(String::from).collect();
+    if symbol_files.is_empty() {
+        if let Some(input) = matches.value_of("INPUT") {
+            symbol_files.extend(discover_symbol_file(input));
+        }
+    }
+    if symbol_files.is_empty() {
+        if let Some(preset) = preset(matches)? {
+            symbol_files.extend(discover_preset_symbol_file(preset, matches.value_of("INPUT")));
+        }
+    }
+    if let Some(preferred_symbol_file) = matches.value_of("PREFER SYMBOLS") {
+        let index = symbol_files.iter().position(|symbol_file| symbol_file == preferred_symbol_file)
+            .ok_or_else(|| format!("--prefer-symbols {} is not one of the symbol files (-s)", preferred_symbol_file))?;
+        let preferred_symbol_file = symbol_files.remove(index);
+        symbol_files.insert(0, preferred_symbol_file);
+    }
+    Ok(symbol_files)
+}
+
+// a symbol file named like the capture, game.sym for game.mdp, for the users who forgot -s
+fn discover_symbol_file(input: &str) -> Option<String> {
+    let symbol_file = SYMBOL_FILE_EXTENSIONS.iter()
+        .map(|extension| Path::new(input).with_extension(extension))
+        .find(|path| path.is_file())?
+        .to_string_lossy()
+        .into_owned();
+    status!("No symbol file given (-s), using {} found next to {}", symbol_file, input);
+    Some(symbol_file)
+}
+
+// the symbol file the build of the preset writes, in the current directory or the one of the capture
+fn discover_preset_symbol_file(preset: Preset, input: Option<&str>) -> Option<String> {
+    let mut directories = vec![Path::new(".")];
+    directories.extend(input.and_then(|input| Path::new(input).parent()));
+    let symbol_file = preset.find_symbol_file(&directories)?.to_string_lossy().into_owned();
+    status!("No symbol file given (-s), using {} written by the build", symbol_file);
+    Some(symbol_file)
+}
+
+// the symbols of the files given with the options of symbol_args, and the hash of each file
+fn load_configured_symbols(matches: &ArgMatches) -> Result<(Symbols, Vec<u64>), String> {
+    let (mut symbols, symbol_file_hashes) = load_symbols(&symbol_files(matches)?, matches.is_present("RAW NAMES"))?;
+    configure_labels(&mut symbols, matches)?;
+    Ok((symbols, symbol_file_hashes))
+}
+
+// converts the captures given with -i to the json trace given with -o, once or each time they change with --watch
+fn convert_command(matches: &ArgMatches) -> Result<(), String> {
+    let symbol_files = symbol_files(matches)?;
+    let interval_file = matches.value_of("MANUAL INTERVALS");
+    let inputs: Vec<&str> = matches.values_of("INPUT").ok_or("Missing --input (-i)")?.collect();
+    check_stdin_inputs(matches, &inputs)?;
+    let output = matches.value_of("OUTPUT");
+    if output.is_none() && !["STATS", "COLLAPSED", "SQLITE"].iter().chain(EXTRA_TRACE_OUTPUTS.iter().map(|(arg, _format)| arg)).any(|&arg| matches.is_present(arg)) {
+        return Err("Missing --output (-o)".to_string());
+    }
+    let trace_outputs: Vec<&str> = output.into_iter().chain(EXTRA_TRACE_OUTPUTS.iter().filter_map(|&(arg, _format)| matches.value_of(arg))).collect();
+    if trace_outputs.contains(&STDOUT_PATH) && matches.is_present("WATCH") {
+        return Err("--watch can't write the trace to stdout".to_string());
+    }
+    if symbol_files.is_empty() {
+        eprintln!("Warning: no symbol file given (-s) or found next to the capture, the functions are named by their addresses");
+    }
+    if matches.is_present("SPLIT FRAMES") {
+        if output == Some(STDOUT_PATH) {
+            return Err("--split-frames writes several files, it can't write the trace to stdout".to_string());
+        }
+        if let Some(option) = ["WATCH", "TRACE PROCESSOR"].iter().find(|&&option| matches.is_present(option)) {
+            return Err(format!("--{} needs the trace in a single file, it can't be used with --split-frames", option.to_lowercase().replace(' ', "-")));
+        }
+        if let Some(option) = EXTRA_TRACE_OUTPUTS.iter().map(|&(option, _format)| option).chain(["SQLITE"]).find(|&option| matches.is_present(option)) {
+            return Err(format!("--{} writes the whole trace, it can't be used with --split-frames", option.to_lowercase()));
+        }
+    }
+    if matches.is_present("WATCH") {
+        watch(matches, &symbol_files, interval_file, &inputs, output)
+    } else {
+        convert(matches, &symbol_files, interval_file, &inputs, output)
+    }
+}
+
+// writes the addresses of the start and end points of the interval file to a breakpoint file for BlastEm
+fn write_breakpoints(matches: &ArgMatches, output: &str) -> Result<(), String> {
+    let (symbols, _symbol_file_hashes) = load_configured_symbols(matches)?;
+    let interval_file = matches.value_of("MANUAL INTERVALS").unwrap();
+    let interval_data = fs::read(interval_file).map_err(|err| format!("Couldn't read {}: {}", interval_file, err))?;
+    let (intervals, _custom_threads) = read_intervals(&interval_data, &symbols, interval_file)?;
+    let mut breakpoint_file = create_output(output).expect("Couldn't create output file");
+    let format = BreakpointFormat::parse(matches.value_of("BREAKPOINT FORMAT").unwrap_or("binary"))?;
+    intervals.write_breakpoints(&mut breakpoint_file, format);
+    status!("Wrote breakpoint addresses to {}", output);
+    Ok(())
+}
+
+// the options loading the symbol files and choosing how addresses are named, shared by the subcommands
+fn symbol_args() -> [Arg<'static, 'static>; 10] {
+    [
+        Arg::with_name("SYMBOLS")
             .short("s")
             .long("symbols")
             .takes_value(true)
-            .help("Symbol file, not stricly required, but strongly recommended to make sense of the trace"))
-        .arg(Arg::with_name("INPUT")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Symbol file, can be repeated to merge several files, the first ones have priority when they label the same address"),
+        Arg::with_name("RAW NAMES")
+            .long("raw-names")
+            .help("Keep the C++ and Rust symbol names mangled"),
+        Arg::with_name("LABEL POLICY")
+            .long("label-policy")
+            .takes_value(true)
+            .default_value("last")
+            .possible_values(&["last", "global", "shortest"])
+            .help("Which label is shown when an address has several: the last one defined, the last one that isn't a local label, or the shortest one"),
+        Arg::with_name("ADDRESS NAMING")
+            .long("address-naming")
+            .takes_value(true)
+            .default_value("nearest")
+            .possible_values(&["nearest", "region", "hex"])
+            .help("How the addresses without a label are named: after the nearest label before them with an offset, the same falling back to their region or memory area (ROM, Work RAM, VDP, Z80 area...) and address, or their bare address"),
+        Arg::with_name("HEX FORMAT")
+            .long("hex-format")
+            .takes_value(true)
+            .default_value("0x")
+            .help("How the addresses without a label are written: \"0x\" (0xff0120), \"$\" ($FF0120) or \"h\" (FF0120h), optionally followed by a number of digits to pad them to, such as \"$6\""),
+        Arg::with_name("PREFER SYMBOLS")
+            .long("prefer-symbols")
+            .takes_value(true)
+            .requires("SYMBOLS")
+            .help("One of the symbol files (-s) whose labels have priority over the other ones"),
+        Arg::with_name("ALIASES")
+            .long("aliases")
+            .takes_value(true)
+            .help("Text file with one \"LABEL OR HEX ADDRESS,NAME\" line per address whose name you want to choose"),
+        Arg::with_name("REGIONS")
+            .long("regions")
+            .takes_value(true)
+            .help("Text file with one \"START,END,NAME\" or \"START,END,NAME,COLOR\" line per address range (module, ROM bank...), the slices of the functions in a range are colored and tagged with its name"),
+        Arg::with_name("RELOCATIONS")
+            .long("relocations")
+            .takes_value(true)
+            .help("Text file with one \"ROM START,ROM END,RAM START\" line per block of code copied from ROM to RAM and run from there, so its RAM addresses are named after the ROM labels"),
+        Arg::with_name("PRESET")
+            .long("preset")
+            .takes_value(true)
+            .possible_values(&PRESETS)
+            .help("Defaults for the projects of a toolchain: sgdk finds out/symbol.txt when no symbol file is given, marks VDP_waitVSync and the other VBlank waits as idle, folds the library's wrappers such as SYS_doVBlankProcess and draws each interrupt handler on its own thread"),
+    ]
+}
+
+// the capture of the subcommands working on a single one
+fn input_arg() -> Arg<'static, 'static> {
+    Arg::with_name("INPUT")
+        .short("i")
+        .long("input")
+        .takes_value(true)
+        .required(true)
+        .help("Input mdp file generated by BlastEm")
+}
+
+// how the durations of the text reports are written, the default depends on the report
+fn units_arg(default: &'static str) -> Arg<'static, 'static> {
+    Arg::with_name("UNITS")
+        .long("units")
+        .takes_value(true)
+        .default_value(default)
+        .help("Comma separated list of units used for durations: cycles (68000 cycles), us (microseconds), frame (percent of a frame)")
+}
+
+// the cost of the instrumentation, shared by the conversion and the hotspot reports
+fn overhead_arg() -> Arg<'static, 'static> {
+    Arg::with_name("OVERHEAD")
+        .long("overhead")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("68000 cycles the instrumentation of the game spends on a kind of packet, as KIND=CYCLES with KIND one of enter, exit, tail-call, interrupt-enter, interrupt-exit, breakpoint, user-event, sound-command, alloc, free, pc-sample, trap-enter, trap-exit or exception, they are taken out of the timeline so the durations are the ones of the uninstrumented game, can be repeated")
+}
+
+// the functions waiting for the VBlank, shared by the conversion and the hotspot reports
+fn idle_arg() -> Arg<'static, 'static> {
+    Arg::with_name("IDLE")
+        .long("idle")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("Label or hex address of a function where the game waits for VBlank, can be repeated, time spent there is considered idle: it's left out of the busy time of the frame statistics and of the hotspots, and its slices are grayed out in the trace")
+}
+
+// the options of the conversion to a json trace, available as the convert subcommand and without any subcommand
+fn convert_args() -> Vec<Arg<'static, 'static>> {
+    let mut args = vec![
+        Arg::with_name("INPUT")
             .short("i")
             .long("input")
             .takes_value(true)
-            .help("Input mdp file generated by BlastEm"))
-        .arg(Arg::with_name("OUTPUT")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Input mdp file generated by BlastEm, can be repeated to put several captures side by side in the trace"),
+        Arg::with_name("OUTPUT")
             .short("o")
             .long("output")
             .takes_value(true)
-            .help("Output json file"))
+            .help("Output json file (or Perfetto trace or html report), - for stdout"),
+        Arg::with_name("MANUAL INTERVALS")
+            .short("m")
+            .long("manual-intervals")
+            .takes_value(true)
+            .help("Manual intervals text file, allows you to record the time elapsed between two labels in the program"),
+        idle_arg(),
+        overhead_arg(),
+        Arg::with_name("FRAME REPORT")
+            .long("frame-report")
+            .takes_value(true)
+            .help("Output text file for per-frame statistics (busy time percentiles, lag frames and worst frames)"),
+        Arg::with_name("FRAME ATTRIBUTION")
+            .long("frame-attribution")
+            .takes_value(true)
+            .help("Output text file breaking down what the worst frames spent their time on compared to a typical frame: the functions and intervals taking the most time above their median"),
+        Arg::with_name("ATTRIBUTION FRAMES")
+            .long("attribution-frames")
+            .takes_value(true)
+            .default_value("5")
+            .help("Number of worst frames broken down by --frame-attribution"),
+        Arg::with_name("FRAME JSONL")
+            .long("frame-jsonl")
+            .takes_value(true)
+            .help("Output json lines file with one json object per frame (utilization, top functions, interrupt time and interval occupancy)"),
+        Arg::with_name("INTERRUPT COUNTERS")
+            .long("interrupt-counters")
+            .help("Adds counters of the number of HInts and VInts asserted during each frame, and warns about the frames with another number of HInts than most frames"),
+        Arg::with_name("HINT JITTER")
+            .long("hint-jitter")
+            .help("Adds a counter of how many cycles later than the quickest one each HInt handler started, the jitter of raster effects"),
+        Arg::with_name("STACK COUNTER")
+            .long("stack-counter")
+            .help("Adds a counter of the stack pointer of the 68000 over time, taken from the packets"),
+        Arg::with_name("STACK TOP")
+            .long("stack-top")
+            .takes_value(true)
+            .help("Hex address of the top of the stack, the initial stack pointer of the vector table, makes the counter of --stack-counter the bytes of stack used, implies --stack-counter"),
+        Arg::with_name("TIME ORIGIN")
+            .long("time-origin")
+            .takes_value(true)
+            .help("Where the timeline of the trace starts: \"zero\" for the first event of the capture, \"vint\" for the first VInt so frames start at neat offsets, or \"interval:<NAME>\" for the start of the first occurrence of this interval, what happens before it is left out"),
+        Arg::with_name("LAG MARKERS")
+            .long("lag-markers")
+            .help("Marks the frames busy for longer than the frame budget, or which missed the VBlank, with a red \"LAG FRAME\" instant at their start"),
+        Arg::with_name("FRAME BUDGET")
+            .long("frame-budget")
+            .takes_value(true)
+            .help("Busy time above which --lag-markers marks a frame, such as \"14ms\" or \"12000us\" (one frame of the video standard by default), implies --lag-markers"),
+        Arg::with_name("FOCUS WORST")
+            .long("focus-worst")
+            .help("Marks the start of the frame with the most busy time in the trace, and prints its time range and a trace processor query selecting it"),
+        Arg::with_name("LATENCY REPORT")
+            .long("latency-report")
+            .takes_value(true)
+            .help("Output text file with the distribution of the interrupt latencies (cycles between a VInt or HInt and the start of its handler) and the longest ones"),
+        units_arg("cycles,us,frame")
+            .help("Comma separated list of units used for durations in text reports: cycles (68000 cycles), us (microseconds), frame (percent of a frame)"),
+        Arg::with_name("VIDEO STANDARD")
+            .long("video-standard")
+            .takes_value(true)
+            .help("Which video standard the capture was made in, ntsc or pal, to know how long a frame lasts (detected from the master clock of the capture by default)"),
+        Arg::with_name("COMPARE SYMBOLS")
+            .long("compare-symbols")
+            .takes_value(true)
+            .requires("SYMBOL DIFF")
+            .help("Older symbol file to compare the symbol file (-s) with, see --symbol-diff"),
+        Arg::with_name("SYMBOL DIFF")
+            .long("symbol-diff")
+            .takes_value(true)
+            .requires("COMPARE SYMBOLS")
+            .help("Output text file listing which addresses reached in the capture were renamed, newly resolved or are still unresolved between --compare-symbols and the symbol file (-s)"),
+        Arg::with_name("NUMBER INTERVALS")
+            .long("number-intervals")
+            .help("Appends the occurrence index to the names of the interval slices (\"MyInterval #12\"), every slice also has it in its details"),
+        Arg::with_name("INTERVAL CSV")
+            .long("interval-csv")
+            .takes_value(true)
+            .requires("MANUAL INTERVALS")
+            .help("Output csv file with the statistics of each manual interval (completions, total, average, min and max durations in microseconds)"),
+        Arg::with_name("BREAKPOINTS THREAD")
+            .long("breakpoints-thread")
+            .takes_value(true)
+            .help("Category to draw the breakpoints that aren't part of any manual interval in (as markers named after their address), by default they're drawn on the main thread, the name of a category from the interval file can be used"),
+        Arg::with_name("MARKER")
+            .long("marker")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("How a kind of marker (vint, hint, savestate, breakpoint or vdp-register) is drawn: KIND=off to leave them out, KIND=SCOPE or KIND=SCOPE:COLOR, SCOPE being global (across the whole trace), process or thread, and COLOR one of the color names of the viewers, can be repeated"),
+        Arg::with_name("RASTER")
+            .long("raster")
+            .help("Add the frame and scanline each event starts on to its details, and draw the HInts as markers named after their line (the beam position is deduced from the first VInt, the VDP mode and whether the capture is PAL or NTSC)"),
+        Arg::with_name("SPLIT INTERRUPTS")
+            .long("split-interrupts")
+            .help("Draws the VInt, HInt and external interrupt handlers on their own threads rather than all on the Interrupts thread"),
+        Arg::with_name("TASK STACKS")
+            .long("task-stacks")
+            .takes_value(true)
+            .help("File with one START,END,NAME line per stack of the cooperative tasks (coroutines) of the game, the code running on each stack is drawn on its own thread"),
+        Arg::with_name("ANNOTATIONS")
+            .long("annotations")
+            .takes_value(true)
+            .help("File with one WHEN,LABEL[,DURATION] line per note taken during the capture (WHEN being a time such as 2:05 or 1500ms, or frame N), drawn on an Annotations thread"),
+        Arg::with_name("TRACE PROCESSOR")
+            .long("trace-processor")
+            .takes_value(true)
+            .min_values(0)
+            .help("Runs a few SQL queries (frames, top slices by total and self time) on the json trace with Perfetto's trace_processor_shell and prints their results, the path of trace_processor_shell can be given, otherwise it's searched in the PATH"),
+        Arg::with_name("CATEGORIES")
+            .long("categories")
+            .takes_value(true)
+            .use_delimiter(true)
+            .possible_values(CATEGORIES)
+            .help("Only writes the events of these comma separated categories to the json trace"),
+        Arg::with_name("EXCLUDE CATEGORIES")
+            .long("exclude-categories")
+            .takes_value(true)
+            .use_delimiter(true)
+            .possible_values(CATEGORIES)
+            .help("Leaves the events of these comma separated categories out of the json trace"),
+        Arg::with_name("ONLY TRACK")
+            .long("only-track")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Only writes the events of the thread or process with this name (\"Main thread\", \"Interrupts\", a thread of the interval file...), can be repeated"),
+        Arg::with_name("EXCLUDE TRACK")
+            .long("exclude-track")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Leaves the events of the thread or process with this name out of the trace, can be repeated"),
+        Arg::with_name("COLLAPSE RECURSION")
+            .long("collapse-recursion")
+            .help("Merges the slices of a function called right from itself into the slice of the outermost call, with the recursion depth in its details"),
+        Arg::with_name("FOLD")
+            .long("fold")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Label or hex address of a function (trampoline, wrapper, dispatch stub...) whose slices are left out of the trace, what it calls is then nested right in its caller and its own time is part of the self time of its caller, can be repeated"),
+        Arg::with_name("FOLD FILE")
+            .long("fold-file")
+            .takes_value(true)
+            .help("Text file with one function to fold per line (see --fold), lines starting with # are ignored"),
+        Arg::with_name("STRINGS")
+            .long("strings")
+            .takes_value(true)
+            .help("Text file with the string table of the game, line N being the text of the user events written with the index N"),
+        Arg::with_name("MERGE SHORT CALLS")
+            .long("merge-short-us")
+            .takes_value(true)
+            .help("Merges the consecutive calls of the same function from the same caller which each last less than this many microseconds into a single slice, with the number of calls in its details, to shrink the traces of games calling helpers in loops"),
+        Arg::with_name("MIN DURATION")
+            .long("min-duration-us")
+            .takes_value(true)
+            .help("Leaves the function slices shorter than this many microseconds out of the json trace, their time is then part of the self time of their caller, to shrink the traces of games calling tiny functions a lot"),
+        Arg::with_name("COMPRESS")
+            .long("compress")
+            .takes_value(true)
+            .possible_values(&["gz", "zst"])
+            .help("Compresses the json output, Perfetto and chrome://tracing load .json.gz files directly"),
+        Arg::with_name("JSON FORMAT")
+            .long("json-format")
+            .takes_value(true)
+            .possible_values(&["object", "array", "lines"])
+            .default_value("object")
+            .help("object writes the events along with the symbol file hash, array only writes the events, one per line, which the viewers still load when the conversion is interrupted, lines writes one event per line (JSON Lines) for jq and other tools"),
+        Arg::with_name("FORMAT")
+            .long("format")
+            .takes_value(true)
+            .possible_values(&["json", "perfetto", "html"])
+            .default_value("json")
+            .help("json for a trace, perfetto for a Perfetto protobuf trace, the same as --perfetto, or html for a standalone report (summary, busy time of each frame, hotspots, intervals and worst frames) to read without a trace viewer"),
+        Arg::with_name("PERFETTO")
+            .long("perfetto")
+            .help("Writes a Perfetto protobuf trace (.pftrace) instead of json, with a track per thread, counter tracks with their units and a track of the frames"),
+        Arg::with_name("JSON")
+            .long("json")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Also writes the json trace to this file, from the same conversion as the -o output"),
+        Arg::with_name("PFTRACE")
+            .long("pftrace")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Also writes the Perfetto protobuf trace (see --perfetto) to this file, from the same conversion as the -o output"),
+        Arg::with_name("HTML")
+            .long("html")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Also writes the html report (see --format) to this file, from the same conversion as the -o output"),
+        Arg::with_name("STATS")
+            .long("stats")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Output csv file with the calls, total and self time in microseconds of each function, the --idle functions and what they call being left out as in the top subcommand"),
+        Arg::with_name("SQLITE")
+            .long("sqlite")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Writes the trace events, the frames and the statistics of each function to a SQLite database (see the README for its tables), to query several captures with SQL"),
+        Arg::with_name("COLLAPSED")
+            .long("collapsed")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Output file with the self time of each call stack in master clock cycles, in the collapsed format of flamegraph.pl, inferno and speedscope"),
+        Arg::with_name("SPLIT FRAMES")
+            .long("split-frames")
+            .takes_value(true)
+            .value_name("FRAMES")
+            .help("Writes the trace to several files of this many frames each, trace.000.json, trace.001.json... for captures too long for the viewers to open in one file, each file has the names of the processes and threads and the values of the counters at its start"),
+        Arg::with_name("COMPACT")
+            .long("compact")
+            .help("Writes a smaller trace with the same information: json timestamps and durations rounded to the nanosecond and no duration on the events that have none, or interned event names, categories and annotation names in a Perfetto trace"),
+        Arg::with_name("ANONYMIZE")
+            .long("anonymize")
+            .takes_value(true)
+            .help("Names the functions func_001, func_002... in the trace and the reports instead of their labels, and writes which label each name stands for to this csv file, to share a trace without revealing the internals of the game"),
+        Arg::with_name("CACHE")
+            .long("cache")
+            .help("Saves the trace events of each capture next to it (game.mdpc for game.mdp), and loads them instead of converting the capture again as long as it, the symbols, the interval file and the options shaping the events (--from, --idle, --marker...) are unchanged, so only the output options can change; the outputs made from the capture itself (frame report, html, --stats...) still convert it"),
+        Arg::with_name("WATCH")
+            .long("watch")
+            .requires_all(&["INPUT", "OUTPUT"])
+            .help("Keeps running and converts the capture again whenever it, the symbol files, the interval, alias or region file change"),
+    ];
+    args.extend(symbol_args());
+    args.extend(range_args());
+    args.extend(clock_args());
+    args.extend(capture_args());
+    args
+}
+
+fn main() -> Result<(), Cow<'static, str>> {
+    let matches = App::new("md-profiler")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author("Tails8521 <tails8521@gmail.com>")
+        .about("A Sega MegaDrive/Genesis profiler")
+        .usage("md-profiler [-m <MANUAL INTERVALS>] [-s <SYMBOLS>] -i <INPUT> -o <OUTPUT>\nor\n    md-profiler -m <MANUAL INTERVALS> [-s <SYMBOLS>] -b <BREAKPOINTS OUTPUT FILE>\nor\n    md-profiler <SUBCOMMAND> [OPTIONS], see md-profiler help <SUBCOMMAND>")
+        .args(&convert_args())
         .arg(Arg::with_name("BREAKPOINTS OUTPUT FILE")
             .short("b")
             .long("breakpoints-output")
             .takes_value(true)
             .help("Output breakpoint file for BlastEm"))
+        .arg(Arg::with_name("NO PROGRESS")
+            .long("no-progress")
+            .global(true)
+            .help("Don't show the progress of reading, parsing, converting and writing on stderr nor how long each of these took"))
+        .subcommand(SubCommand::with_name("convert")
+            .about("Converts captures to a json trace for Perfetto or chrome://tracing, along with the optional reports, the same as without any subcommand")
+            .usage("md-profiler convert [-m <MANUAL INTERVALS>] [-s <SYMBOLS>] -i <INPUT> -o <OUTPUT>")
+            .args(&convert_args()))
+        .subcommand(SubCommand::with_name("breakpoints")
+            .about("Writes the addresses of the start and end points of an interval file to a breakpoint file for BlastEm")
+            .usage("md-profiler breakpoints -m <MANUAL INTERVALS> [-s <SYMBOLS>] -o <OUTPUT>")
+            .arg(Arg::with_name("MANUAL INTERVALS")
+                .short("m")
+                .long("manual-intervals")
+                .takes_value(true)
+                .required(true)
+                .help("Manual intervals text file"))
+            .arg(Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .required(true)
+                .help("Output breakpoint file for BlastEm"))
+            .arg(Arg::with_name("BREAKPOINT FORMAT")
+                .long("format")
+                .takes_value(true)
+                .default_value("binary")
+                .help("binary for the mbp command of BlastEm, text (one address, start or end, and interval name per line) or json to review the breakpoints, blastem for commands of the BlastEm debugger, or mame for a MAME debugger script"))
+            .args(&symbol_args()))
+        .subcommand(SubCommand::with_name("extract")
+            .about("Writes the packets of a time or frame range of a capture to a new, smaller mdp file")
+            .usage("md-profiler extract -i <INPUT> -o <OUTPUT> [--from <FROM>] [--to <TO>] [--frames <FRAMES>] [--reenter]")
+            .arg(input_arg())
+            .arg(Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .required(true)
+                .help("Output mdp file"))
+            .arg(Arg::with_name("REENTER")
+                .long("reenter")
+                .help("Enters again the subroutines and interrupt handler running at the start of the range, on its first cycle, instead of losing the slices open at the cut"))
+            .args(&symbol_args())
+            .args(&range_args())
+            .args(&clock_args())
+            .args(&capture_args()))
+        .subcommand(SubCommand::with_name("concat")
+            .about("Appends several captures made with the same clocks into one mdp file, each one starting after the previous ones")
+            .usage("md-profiler concat -i <INPUT>... -o <OUTPUT>")
+            .arg(input_arg()
+                .multiple(true)
+                .number_of_values(1)
+                .help("Input mdp file generated by BlastEm, repeated for each capture in the order they're appended"))
+            .arg(Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .required(true)
+                .help("Output mdp file"))
+            .args(&clock_args())
+            .args(&capture_args()))
+        .subcommand(SubCommand::with_name("top")
+            .about("Prints the most expensive functions by total and by self time, without writing any trace")
+            .usage("md-profiler top -i <INPUT> [-s <SYMBOLS>] [-n <COUNT>]")
+            .arg(input_arg())
+            .arg(Arg::with_name("COUNT")
+                .short("n")
+                .long("count")
+                .takes_value(true)
+                .default_value("20")
+                .help("Number of functions listed"))
+            .arg(Arg::with_name("SORT")
+                .long("sort")
+                .takes_value(true)
+                .possible_values(&["time", "calls"])
+                .default_value("time")
+                .help("time to list the functions by total and by self time, calls to list them by number of calls with their average time per call"))
+            .arg(units_arg("cycles,us,frame"))
+            .arg(Arg::with_name("VIDEO STANDARD")
+                .long("video-standard")
+                .takes_value(true)
+                .help("Which video standard the capture was made in, ntsc or pal, to know how long a frame lasts (detected from the master clock of the capture by default)"))
+            .arg(idle_arg())
+            .arg(overhead_arg())
+            .args(&symbol_args())
+            .args(&range_args())
+            .args(&clock_args())
+            .args(&capture_args()))
+        .subcommand(SubCommand::with_name("samples")
+            .about("Prints the functions the most PC samples of a capture were taken in, and writes the sampled stacks in the collapsed format of flamegraph tools")
+            .usage("md-profiler samples -i <INPUT> [-s <SYMBOLS>] [-o <OUTPUT>] [-n <COUNT>]")
+            .arg(input_arg())
+            .arg(Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("Output text file with one \"outer;inner;sampled count\" line per sampled stack, for flamegraph.pl, inferno or speedscope"))
+            .arg(Arg::with_name("COUNT")
+                .short("n")
+                .long("count")
+                .takes_value(true)
+                .default_value("20")
+                .help("Number of functions listed"))
+            .args(&symbol_args())
+            .args(&range_args())
+            .args(&clock_args())
+            .args(&capture_args()))
+        .subcommand(SubCommand::with_name("busy-waits")
+            .about("Prints the places the code seems to spin at, waiting for the VDP or a flag: runs of short calls to the same function, and PC samples or breakpoints staying within a few bytes, as candidates for --idle")
+            .usage("md-profiler busy-waits -i <INPUT> [-s <SYMBOLS>] [-n <COUNT>]")
+            .arg(input_arg())
+            .arg(Arg::with_name("COUNT")
+                .short("n")
+                .long("count")
+                .takes_value(true)
+                .default_value("10")
+                .help("Number of busy waits listed"))
+            .arg(units_arg("cycles,us,frame"))
+            .arg(Arg::with_name("VIDEO STANDARD")
+                .long("video-standard")
+                .takes_value(true)
+                .help("Which video standard the capture was made in, ntsc or pal, to know how long a frame lasts (detected from the master clock of the capture by default)"))
+            .args(&symbol_args())
+            .args(&range_args())
+            .args(&clock_args())
+            .args(&capture_args()))
+        .subcommand(SubCommand::with_name("compare")
+            .about("Prints a markdown table of the functions whose call count, total or self time changed the most between two captures, regressions first")
+            .usage("md-profiler compare <BEFORE> <AFTER> [-s <SYMBOLS>] [--before-symbols <SYMBOLS>] [-n <COUNT>] [--function <FUNCTION>]")
+            .arg(Arg::with_name("BEFORE")
+                .index(1)
+                .required(true)
+                .help("Mdp file captured before the change"))
+            .arg(Arg::with_name("AFTER")
+                .index(2)
+                .required(true)
+                .help("Mdp file captured after the change"))
+            .arg(Arg::with_name("BEFORE SYMBOLS")
+                .long("before-symbols")
+                .takes_value(true)
+                .help("Symbol file of the build captured before the change, functions are matched by name so they can move between the builds"))
+            .arg(Arg::with_name("FUNCTION")
+                .long("function")
+                .takes_value(true)
+                .help("Label (or hex address) of a function to compare the durations of every invocation of, instead of listing the functions, tells whether they changed significantly (Mann-Whitney U test) along with a confidence interval of the change of the median"))
+            .arg(Arg::with_name("COUNT")
+                .short("n")
+                .long("count")
+                .takes_value(true)
+                .default_value("20")
+                .help("Number of functions listed"))
+            .arg(units_arg("cycles"))
+            .arg(Arg::with_name("VIDEO STANDARD")
+                .long("video-standard")
+                .takes_value(true)
+                .help("Which video standard the captures were made in, ntsc or pal, to know how long a frame lasts (detected from the master clock of the capture by default)"))
+            .arg(idle_arg())
+            .arg(overhead_arg())
+            .args(&symbol_args())
+            .args(&clock_args())
+            .args(&capture_args()))
+        .subcommand(SubCommand::with_name("histogram")
+            .about("Prints the percentiles and a histogram of the durations of every invocation of a function")
+            .usage("md-profiler histogram -i <INPUT> -s <SYMBOLS> --function <FUNCTION>")
+            .arg(input_arg())
+            .arg(Arg::with_name("FUNCTION")
+                .short("f")
+                .long("function")
+                .takes_value(true)
+                .required(true)
+                .help("Label or hex address of the function"))
+            .arg(units_arg("cycles,us,frame"))
+            .arg(Arg::with_name("VIDEO STANDARD")
+                .long("video-standard")
+                .takes_value(true)
+                .help("Which video standard the capture was made in, ntsc or pal, to know how long a frame lasts (detected from the master clock of the capture by default)"))
+            .arg(overhead_arg())
+            .args(&symbol_args())
+            .args(&range_args())
+            .args(&clock_args())
+            .args(&capture_args()))
+        .subcommand(SubCommand::with_name("bench")
+            .about("Prints the mean, standard deviation, percentiles, best and worst of the durations from every pass through a start point to the next pass through an end point")
+            .usage("md-profiler bench -i <INPUT> -s <SYMBOLS> --start <START> --end <END>")
+            .arg(input_arg())
+            .arg(Arg::with_name("START")
+                .long("start")
+                .takes_value(true)
+                .required(true)
+                .help("Label or hex address of the start point, reached when a subroutine or handler starts there or a breakpoint is hit there"))
+            .arg(Arg::with_name("END")
+                .long("end")
+                .takes_value(true)
+                .required(true)
+                .help("Label or hex address of the end point, the same as the start point to measure from one pass to the next"))
+            .arg(units_arg("cycles,us,frame"))
+            .arg(Arg::with_name("VIDEO STANDARD")
+                .long("video-standard")
+                .takes_value(true)
+                .help("Which video standard the capture was made in, ntsc or pal, to know how long a frame lasts (detected from the master clock of the capture by default)"))
+            .arg(overhead_arg())
+            .args(&symbol_args())
+            .args(&range_args())
+            .args(&clock_args())
+            .args(&capture_args()))
+        .subcommand(SubCommand::with_name("callers")
+            .about("Prints which callers, and which callers of theirs, account for the calls and the time of a function")
+            .usage("md-profiler callers -i <INPUT> -s <SYMBOLS> --function <FUNCTION> [--depth <DEPTH>]")
+            .arg(input_arg())
+            .arg(Arg::with_name("FUNCTION")
+                .short("f")
+                .long("function")
+                .takes_value(true)
+                .required(true)
+                .help("Label or hex address of the function"))
+            .arg(Arg::with_name("DEPTH")
+                .long("depth")
+                .takes_value(true)
+                .default_value("3")
+                .help("How many levels of callers are listed"))
+            .arg(units_arg("cycles,us,frame"))
+            .arg(Arg::with_name("VIDEO STANDARD")
+                .long("video-standard")
+                .takes_value(true)
+                .help("Which video standard the capture was made in, ntsc or pal, to know how long a frame lasts (detected from the master clock of the capture by default)"))
+            .arg(overhead_arg())
+            .args(&symbol_args())
+            .args(&range_args())
+            .args(&clock_args())
+            .args(&capture_args()))
+        .subcommand(SubCommand::with_name("find")
+            .about("Lists every invocation of a function with its start time, duration, frame and the functions it was called from")
+            .usage("md-profiler find -i <INPUT> -s <SYMBOLS> --function <FUNCTION> [--format <FORMAT>]")
+            .arg(input_arg())
+            .arg(Arg::with_name("FUNCTION")
+                .short("f")
+                .long("function")
+                .takes_value(true)
+                .required(true)
+                .help("Label or hex address of the function"))
+            .arg(Arg::with_name("FIND FORMAT")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["table", "csv"])
+                .default_value("table")
+                .help("table to read, or csv (start and duration in microseconds, duration in 68000 cycles, frame and the callers separated by semicolons, the outermost first) for scripts and spreadsheets"))
+            .arg(units_arg("cycles,us,frame")
+                .help("Comma separated list of units used for durations in the table: cycles (68000 cycles), us (microseconds), frame (percent of a frame)"))
+            .arg(Arg::with_name("VIDEO STANDARD")
+                .long("video-standard")
+                .takes_value(true)
+                .help("Which video standard the capture was made in, ntsc or pal, to know how long a frame lasts (detected from the master clock of the capture by default)"))
+            .arg(overhead_arg())
+            .args(&symbol_args())
+            .args(&range_args())
+            .args(&clock_args())
+            .args(&capture_args()))
+        .subcommand(SubCommand::with_name("outliers")
+            .about("Lists the invocations of every function much longer than its other invocations, telling those made long by the interrupts preempting them from the slow paths")
+            .usage("md-profiler outliers -i <INPUT> -s <SYMBOLS> [--sigma <SIGMA> | --median-factor <FACTOR>] [-n <COUNT>]")
+            .arg(input_arg())
+            .arg(Arg::with_name("SIGMA")
+                .long("sigma")
+                .takes_value(true)
+                .default_value("3")
+                .help("An invocation is an outlier when it lasts longer than the mean of its function plus this many standard deviations"))
+            .arg(Arg::with_name("MEDIAN FACTOR")
+                .long("median-factor")
+                .takes_value(true)
+                .help("An invocation is an outlier when it lasts longer than the median of its function times this factor, instead of --sigma"))
+            .arg(Arg::with_name("MIN INVOCATIONS")
+                .long("min-invocations")
+                .takes_value(true)
+                .default_value("10")
+                .help("Functions called fewer times than this are left out, too few invocations make for meaningless statistics"))
+            .arg(Arg::with_name("COUNT")
+                .short("n")
+                .long("count")
+                .takes_value(true)
+                .default_value("20")
+                .help("Number of outliers listed"))
+            .arg(units_arg("cycles,us"))
+            .arg(Arg::with_name("VIDEO STANDARD")
+                .long("video-standard")
+                .takes_value(true)
+                .help("Which video standard the capture was made in, ntsc or pal, to know how long a frame lasts (detected from the master clock of the capture by default)"))
+            .arg(idle_arg())
+            .arg(overhead_arg())
+            .args(&symbol_args())
+            .args(&range_args())
+            .args(&clock_args())
+            .args(&capture_args()))
+        .subcommand(SubCommand::with_name("stack")
+            .about("Prints the call stack at a point in time, with the interrupt handler running then if any, and when each function was entered")
+            .usage("md-profiler stack -i <INPUT> -s <SYMBOLS> --at <TIME>")
+            .arg(input_arg())
+            .arg(Arg::with_name("AT")
+                .long("at")
+                .takes_value(true)
+                .required(true)
+                .help("Point in time, as in the trace (in ms unless suffixed with s, us or cycles)"))
+            .arg(units_arg("cycles,us"))
+            .arg(Arg::with_name("VIDEO STANDARD")
+                .long("video-standard")
+                .takes_value(true)
+                .help("Which video standard the capture was made in, ntsc or pal, to know how long a frame lasts (detected from the master clock of the capture by default)"))
+            // only --frame-source applies, to tell the frame
+            .arg(range_args()[3].clone())
+            .arg(overhead_arg())
+            .args(&symbol_args())
+            .args(&clock_args())
+            .args(&capture_args()))
+        .subcommand(SubCommand::with_name("stack-usage")
+            .about("Prints the deepest the stack went in a capture with the functions running then, and the functions using the most stack below their entry, as seen by the stack pointers of the packets")
+            .usage("md-profiler stack-usage -i <INPUT> [-s <SYMBOLS>] [-n <COUNT>] [--stack-top <ADDRESS>] [--limit <BYTES>]")
+            .arg(input_arg())
+            .arg(Arg::with_name("COUNT")
+                .short("n")
+                .long("count")
+                .takes_value(true)
+                .default_value("20")
+                .help("Number of functions listed"))
+            .arg(Arg::with_name("STACK TOP")
+                .long("stack-top")
+                .takes_value(true)
+                .help("Hex address of the top of the stack, the initial stack pointer of the vector table (the highest stack pointer of the capture by default)"))
+            .arg(Arg::with_name("LIMIT")
+                .long("limit")
+                .takes_value(true)
+                .help("Size of the stack in bytes, exits with an error when the capture went deeper"))
+            .args(&symbol_args())
+            .args(&range_args())
+            .args(&clock_args())
+            .args(&capture_args()))
+        .subcommand(SubCommand::with_name("heatmap")
+            .about("Writes a csv of the share of each scanline of each frame the CPU was busy, and prints the busiest scanlines")
+            .usage("md-profiler heatmap -i <INPUT> -o <OUTPUT> [--idle <FUNCTION>]")
+            .arg(input_arg())
+            .arg(Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .required(true)
+                .help("Output csv file, a row per frame and a column per scanline"))
+            .arg(Arg::with_name("COUNT")
+                .short("n")
+                .long("count")
+                .takes_value(true)
+                .default_value("10")
+                .help("Number of scanlines to print"))
+            .arg(Arg::with_name("VIDEO STANDARD")
+                .long("video-standard")
+                .takes_value(true)
+                .help("Which video standard the capture was made in, ntsc or pal, to know how many lines a frame has (detected from the master clock of the capture by default)"))
+            .arg(idle_arg())
+            .arg(overhead_arg())
+            .args(&symbol_args())
+            .args(&range_args())
+            .args(&clock_args())
+            .args(&capture_args()))
+        .subcommand(SubCommand::with_name("validate")
+            .about("Checks a capture for structural anomalies (unbalanced or unmatched enters and exits, cycles going backwards, stack pointer inconsistencies...) and reports each of them with its cycle")
+            .arg(input_arg())
+            .args(&clock_args())
+            .args(&capture_args()))
+        .subcommand(SubCommand::with_name("gen-test-trace")
+            .about("Writes a synthetic mdp file from a description of the calls, interrupts and waits it contains, to test interval files or scripts without an emulator capture")
+            .arg(Arg::with_name("DESCRIPTION")
+                .index(1)
+                .required(true)
+                .help("Text file with one command per line: wait CYCLES, call TARGET, return, tail-call TARGET, interrupt TARGET, interrupt-return, vint, hint, breakpoint TARGET, savestate PATH, and repeat COUNT ... end blocks"))
+            .arg(Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .required(true)
+                .help("Output mdp file"))
+            .args(&symbol_args()))
+        .subcommand(SubCommand::with_name("live")
+            .about("Records a capture streamed by the emulator in real time to an mdp file, which only ever contains whole packets so it can be converted while the session goes on")
+            .usage("md-profiler live --listen <ADDRESS> -o <OUTPUT>\nor\n    md-profiler live --pipe <PIPE> -o <OUTPUT>")
+            .arg(Arg::with_name("LISTEN")
+                .long("listen")
+                .takes_value(true)
+                .required_unless("PIPE")
+                .conflicts_with("PIPE")
+                .help("TCP address to wait for the emulator on, such as 127.0.0.1:8521"))
+            .arg(Arg::with_name("PIPE")
+                .long("pipe")
+                .takes_value(true)
+                .help("Named pipe (or any file) the emulator writes the capture to"))
+            .arg(Arg::with_name("TIMEOUT")
+                .long("timeout")
+                .takes_value(true)
+                .default_value("10")
+                .help("Seconds without receiving anything after which a TCP connection whose emulator announced heartbeats is given up on"))
+            .arg(Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .required(true)
+                .help("Output mdp file")))
         .get_matches();
 
+    if matches.is_present("NO PROGRESS") || matches.subcommand().1.is_some_and(|subcommand_matches| subcommand_matches.is_present("NO PROGRESS")) {
+        md_profiler::progress::set_enabled(false);
+    }
+    check_stdout_outputs(&matches)?;
+    if let Some(subcommand_matches) = matches.subcommand().1 {
+        check_stdout_outputs(subcommand_matches)?;
+    }
+    if let Some(extract_matches) = matches.subcommand_matches("extract") {
+        return extract(extract_matches).map_err(Into::into);
+    }
+    if let Some(concat_matches) = matches.subcommand_matches("concat") {
+        return concat(concat_matches).map_err(Into::into);
+    }
+    if let Some(top_matches) = matches.subcommand_matches("top") {
+        return top(top_matches).map_err(Into::into);
+    }
+    if let Some(samples_matches) = matches.subcommand_matches("samples") {
+        return samples(samples_matches).map_err(Into::into);
+    }
+    if let Some(busy_waits_matches) = matches.subcommand_matches("busy-waits") {
+        return busy_waits(busy_waits_matches).map_err(Into::into);
+    }
+    if let Some(compare_matches) = matches.subcommand_matches("compare") {
+        return compare(compare_matches).map_err(Into::into);
+    }
+    if let Some(histogram_matches) = matches.subcommand_matches("histogram") {
+        return histogram(histogram_matches).map_err(Into::into);
+    }
+    if let Some(bench_matches) = matches.subcommand_matches("bench") {
+        return bench(bench_matches).map_err(Into::into);
+    }
+    if let Some(callers_matches) = matches.subcommand_matches("callers") {
+        return callers(callers_matches).map_err(Into::into);
+    }
+    if let Some(find_matches) = matches.subcommand_matches("find") {
+        return find(find_matches).map_err(Into::into);
+    }
+    if let Some(outliers_matches) = matches.subcommand_matches("outliers") {
+        return outliers(outliers_matches).map_err(Into::into);
+    }
+    if let Some(stack_matches) = matches.subcommand_matches("stack") {
+        return stack(stack_matches).map_err(Into::into);
+    }
+    if let Some(stack_usage_matches) = matches.subcommand_matches("stack-usage") {
+        return stack_usage(stack_usage_matches).map_err(Into::into);
+    }
+    if let Some(heatmap_matches) = matches.subcommand_matches("heatmap") {
+        return heatmap(heatmap_matches).map_err(Into::into);
+    }
+    if let Some(validate_matches) = matches.subcommand_matches("validate") {
+        return validate(validate_matches).map_err(Into::into);
+    }
+    if let Some(gen_test_trace_matches) = matches.subcommand_matches("gen-test-trace") {
+        return gen_test_trace(gen_test_trace_matches).map_err(Into::into);
+    }
+    if let Some(live_matches) = matches.subcommand_matches("live") {
+        return live(live_matches).map_err(Into::into);
+    }
+    if let Some(convert_matches) = matches.subcommand_matches("convert") {
+        return convert_command(convert_matches).map_err(Into::into);
+    }
+    if let Some(breakpoints_matches) = matches.subcommand_matches("breakpoints") {
+        return write_breakpoints(breakpoints_matches, breakpoints_matches.value_of("OUTPUT").unwrap()).map_err(Into::into);
+    }
 
-    match (matches.value_of("MANUAL INTERVALS"), matches.value_of("SYMBOLS"), matches.value_of("INPUT"), matches.value_of("OUTPUT"), matches.value_of("BREAKPOINTS OUTPUT FILE"),) {
-        (interval_file, symbol_file, Some(input), Some(output), None) => {
-            let symbols = if let Some(symbol_file) = symbol_file {
-                let mut symbol_data = Vec::new();
-                let instant = Instant::now();
-                File::open(symbol_file).expect("Couldn't open symbol file").read_to_end(&mut symbol_data).expect("Error reading symbol file");
-                let symbols = read_symbols(&symbol_data);
-                let elapsed = instant.elapsed();
-                println!("Parsed {} symbols in {} ms", symbols.label_to_address.len(), elapsed.as_micros() as f64 / 1000.0);
-                symbols
-            } else {
-                Default::default()
-            };
-            let (mut intervals, custom_threads) = if let Some(interval_file) = interval_file {
-                let mut interval_data = Vec::new();
-                File::open(interval_file).expect("Couldn't open symbol file").read_to_end(&mut interval_data).expect("Error reading symbol file");
-                read_intervals(&interval_data, &symbols.label_to_address)
-            } else {
-                Default::default()
-            };
-        
-            let mut profiling_data = Vec::new();
-            let instant = Instant::now();
-            File::open(input).expect("Couldn't open input file").read_to_end(&mut profiling_data).expect("Error reading input file");
-            let profiling = read_profiling_file(&profiling_data);
-            let elapsed = instant.elapsed();
-            println!("Parsed {} input events in {} ms", profiling.packets.len(), elapsed.as_micros() as f64 / 1000.0);
-            let mut profile_json = File::create(output).expect("Couldn't create output file");
-            generate_profiling_json(&mut profile_json, &profiling, &symbols, &mut intervals, custom_threads);
-            Ok(())
-        },
-        (_interval_file, _symbol_file, Some(_input), None, _breakpoints_output) => {
-            Err("Missing --output (-o)".into())
-        },
-        (_interval_file, _symbol_file, None, Some(_output), _breakpoints_output) => {
-            Err("Missing --input (-i)".into())
-        },
-        (_interval_file, _symbol_file, Some(_input), Some(_output), Some(_breakpoints_output)) => {
+    // without any subcommand, converts, or writes the breakpoint file with -b
+    match (matches.value_of("MANUAL INTERVALS"), matches.is_present("INPUT"), matches.is_present("OUTPUT"), matches.value_of("BREAKPOINTS OUTPUT FILE")) {
+        (_interval_file, true, true, Some(_breakpoints_output)) => {
             Err("--breakpoints-output (-b) cannot be used with --intput (-i) and --output (-o)".into())
         },
-        (Some(interval_file), symbol_file, None, None, Some(breakpoints_output)) => {
-            let symbols = if let Some(symbol_file) = symbol_file {
-                let mut symbol_data = Vec::new();
-                let instant = Instant::now();
-                File::open(symbol_file).expect("Couldn't open symbol file").read_to_end(&mut symbol_data).expect("Error reading symbol file");
-                let symbols = read_symbols(&symbol_data);
-                let elapsed = instant.elapsed();
-                println!("Parsed {} symbols in {} ms", symbols.label_to_address.len(), elapsed.as_micros() as f64 / 1000.0);
-                symbols
-            } else {
-                Default::default()
-            };
-            let mut interval_data = Vec::new();
-            File::open(interval_file).expect("Couldn't open symbol file").read_to_end(&mut interval_data).expect("Error reading symbol file");
-            let (intervals, _custom_threads) = read_intervals(&interval_data, &symbols.label_to_address);
-            let mut breakpoint_file = File::create(breakpoints_output).expect("Couldn't create output file");
-            intervals.write_to_file(&mut breakpoint_file);
-            println!("Wrote breakpoint addresses to {}", breakpoints_output);
-            Ok(())
+        (Some(_interval_file), false, false, Some(breakpoints_output)) => {
+            write_breakpoints(&matches, breakpoints_output).map_err(Into::into)
         },
-        (None, _symbol_file, _input, _output, Some(_breakpoints_output)) => {
+        (None, _input, _output, Some(_breakpoints_output)) => {
             Err("--breakpoints-output (-b) requires --manual-intervals (-m)".into())
         },
-        _ => {
+        (_interval_file, false, false, None) => {
             eprintln!("{}", matches.usage());
             Ok(())
-        }
+        },
+        _ => convert_command(&matches).map_err(Into::into),
     }
 }
@@ -0,0 +1,546 @@
+use std::{borrow::Cow, collections::{BTreeMap, HashMap}, io::{BufWriter, Write}};
+
+use crate::{profiling::*, progress::{Progress, ProgressWriter, Stopwatch}};
+
+// field numbers of the messages of Perfetto's trace.proto that are written
+const TRACE_PACKET: u32 = 1;
+const PACKET_TIMESTAMP: u32 = 8;
+const PACKET_SEQUENCE_ID: u32 = 10;
+const PACKET_TRACK_EVENT: u32 = 11;
+const PACKET_INTERNED_DATA: u32 = 12;
+const PACKET_SEQUENCE_FLAGS: u32 = 13;
+const PACKET_TRACK_DESCRIPTOR: u32 = 60;
+const TRACK_UUID: u32 = 1;
+const TRACK_NAME: u32 = 2;
+const TRACK_PARENT_UUID: u32 = 5;
+const TRACK_COUNTER: u32 = 8;
+const TRACK_CHILD_ORDERING: u32 = 11;
+const TRACK_SIBLING_ORDER_RANK: u32 = 12;
+const COUNTER_UNIT: u32 = 3;
+const EVENT_CATEGORY_IIDS: u32 = 3;
+const EVENT_DEBUG_ANNOTATIONS: u32 = 4;
+const EVENT_TYPE: u32 = 9;
+const EVENT_NAME_IID: u32 = 10;
+const EVENT_TRACK_UUID: u32 = 11;
+const EVENT_CATEGORIES: u32 = 22;
+const EVENT_NAME: u32 = 23;
+const EVENT_COUNTER_VALUE: u32 = 30;
+const EVENT_FLOW_IDS: u32 = 47;
+const EVENT_TERMINATING_FLOW_IDS: u32 = 48;
+const ANNOTATION_NAME_IID: u32 = 1;
+const ANNOTATION_BOOL: u32 = 2;
+const ANNOTATION_UINT: u32 = 3;
+const ANNOTATION_INT: u32 = 4;
+const ANNOTATION_DOUBLE: u32 = 5;
+const ANNOTATION_STRING: u32 = 6;
+const ANNOTATION_NAME: u32 = 10;
+const INTERNED_EVENT_CATEGORIES: u32 = 1;
+const INTERNED_EVENT_NAMES: u32 = 2;
+const INTERNED_DEBUG_ANNOTATION_NAMES: u32 = 3;
+const INTERNED_IID: u32 = 1;
+const INTERNED_NAME: u32 = 2;
+
+// every packet is written on the same sequence, so the strings interned with --compact last the whole trace
+const SEQUENCE_ID: u64 = 1;
+const SEQ_INCREMENTAL_STATE_CLEARED: u64 = 1;
+const SEQ_NEEDS_INCREMENTAL_STATE: u64 = 2;
+const CHILD_ORDERING_EXPLICIT: u64 = 3;
+const TYPE_SLICE_BEGIN: u64 = 1;
+const TYPE_SLICE_END: u64 = 2;
+const TYPE_INSTANT: u64 = 3;
+const TYPE_COUNTER: u64 = 4;
+
+// CounterDescriptor.Unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CounterUnit {
+    TimeNs = 1,
+    Count = 2,
+    SizeBytes = 3,
+}
+
+const WIRE_VARINT: u32 = 0;
+const WIRE_FIXED64: u32 = 1;
+const WIRE_LENGTH_DELIMITED: u32 = 2;
+
+// a protobuf message being encoded, fields are appended in any order
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn tag(&mut self, field: u32, wire_type: u32) {
+        self.varint((field << 3 | wire_type) as u64);
+    }
+
+    fn uint(&mut self, field: u32, value: u64) -> &mut Message {
+        self.tag(field, WIRE_VARINT);
+        self.varint(value);
+        self
+    }
+
+    // negative values take 10 bytes, as int64 (not sint64) fields do
+    fn int(&mut self, field: u32, value: i64) -> &mut Message {
+        self.uint(field, value as u64)
+    }
+
+    fn fixed64(&mut self, field: u32, value: u64) -> &mut Message {
+        self.tag(field, WIRE_FIXED64);
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn double(&mut self, field: u32, value: f64) -> &mut Message {
+        self.fixed64(field, value.to_bits())
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) -> &mut Message {
+        self.tag(field, WIRE_LENGTH_DELIMITED);
+        self.varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    fn string(&mut self, field: u32, value: &str) -> &mut Message {
+        self.bytes(field, value.as_bytes())
+    }
+
+    fn message(&mut self, field: u32, value: &Message) -> &mut Message {
+        self.bytes(field, &value.0)
+    }
+}
+
+// with --compact, the event names, categories and annotation names are written once, in the interned data of the
+// first packet using them, and referred to by their iid afterwards
+#[derive(Default)]
+struct Interning {
+    event_names: HashMap<String, u64>,
+    event_categories: HashMap<String, u64>,
+    debug_annotation_names: HashMap<String, u64>,
+    // the strings interned by the packet being encoded
+    interned_data: Message,
+}
+
+impl Interning {
+    fn iid(iids: &mut HashMap<String, u64>, interned_data: &mut Message, field: u32, value: &str) -> u64 {
+        if let Some(&iid) = iids.get(value) {
+            return iid;
+        }
+        // 0 isn't a valid iid
+        let iid = iids.len() as u64 + 1;
+        iids.insert(value.to_string(), iid);
+        let mut entry = Message::default();
+        entry.uint(INTERNED_IID, iid).string(INTERNED_NAME, value);
+        interned_data.message(field, &entry);
+        iid
+    }
+
+    fn event_name(&mut self, name: &str) -> u64 {
+        Interning::iid(&mut self.event_names, &mut self.interned_data, INTERNED_EVENT_NAMES, name)
+    }
+
+    fn event_category(&mut self, category: &str) -> u64 {
+        Interning::iid(&mut self.event_categories, &mut self.interned_data, INTERNED_EVENT_CATEGORIES, category)
+    }
+
+    fn debug_annotation_name(&mut self, name: &str) -> u64 {
+        Interning::iid(&mut self.debug_annotation_names, &mut self.interned_data, INTERNED_DEBUG_ANNOTATION_NAMES, name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum TrackKey<'a> {
+    Global,
+    Process(u32),
+    Thread(u32, u32),
+    // async intervals of a thread, on as many lanes as there are overlapping occurrences
+    AsyncLane(u32, u32, usize),
+    Counter(u32, &'a str),
+    Frames(u32),
+    FrameDuration(u32),
+    DmaLength(u32),
+}
+
+struct Track<'a> {
+    uuid: u64,
+    parent_uuid: Option<u64>,
+    name: Cow<'a, str>,
+    sort_index: Option<u32>,
+    counter_unit: Option<CounterUnit>,
+    // set on the processes with sorted threads
+    explicit_ordering: bool,
+}
+
+#[derive(Default)]
+struct Tracks<'a> {
+    tracks: Vec<Track<'a>>,
+    uuids: HashMap<TrackKey<'a>, u64>,
+    process_names: HashMap<u32, &'a str>,
+    thread_names: HashMap<(u32, u32), &'a str>,
+    thread_sort_indexes: HashMap<(u32, u32), u32>,
+}
+
+impl<'a> Tracks<'a> {
+    fn uuid(&mut self, key: TrackKey<'a>) -> u64 {
+        if let Some(&uuid) = self.uuids.get(&key) {
+            return uuid;
+        }
+        let (parent_uuid, name, sort_index, counter_unit): (_, Cow<str>, _, _) = match key {
+            TrackKey::Global => (None, "Global".into(), None, None),
+            TrackKey::Process(pid) => (None, self.process_names.get(&pid).map_or_else(|| format!("Process {}", pid).into(), |&name| name.into()), None, None),
+            TrackKey::Thread(pid, tid) => {
+                let sort_index = self.thread_sort_indexes.get(&(pid, tid)).copied();
+                let process = self.uuid(TrackKey::Process(pid));
+                if sort_index.is_some() {
+                    let index = self.tracks.iter().position(|track| track.uuid == process).unwrap();
+                    self.tracks[index].explicit_ordering = true;
+                }
+                (Some(process), self.thread_names.get(&(pid, tid)).map_or_else(|| format!("Thread {}", tid).into(), |&name| name.into()), sort_index, None)
+            },
+            TrackKey::AsyncLane(pid, tid, _lane) => (Some(self.uuid(TrackKey::Thread(pid, tid))), "Async intervals".into(), None, None),
+            TrackKey::Counter(pid, name) => (Some(self.uuid(TrackKey::Process(pid))), name.into(), None, Some(CounterUnit::Count)),
+            TrackKey::Frames(pid) => (Some(self.uuid(TrackKey::Process(pid))), "Frames".into(), None, None),
+            TrackKey::FrameDuration(pid) => (Some(self.uuid(TrackKey::Process(pid))), "Frame duration".into(), None, Some(CounterUnit::TimeNs)),
+            TrackKey::DmaLength(pid) => (Some(self.uuid(TrackKey::Process(pid))), "DMA length".into(), None, Some(CounterUnit::SizeBytes)),
+        };
+        // 0 isn't a valid uuid
+        let uuid = self.tracks.len() as u64 + 1;
+        self.tracks.push(Track { uuid, parent_uuid, name, sort_index, counter_unit, explicit_ordering: false });
+        self.uuids.insert(key, uuid);
+        uuid
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum EventData {
+    None,
+    // index of the json event it comes from, for its name, category and args
+    TraceEvent(usize),
+    Frame(usize),
+    Counter(i64),
+}
+
+// kept small, there are about two per json event
+struct Event {
+    // in nanoseconds
+    ts: u64,
+    track_uuid: u64,
+    event_type: u64,
+    data: EventData,
+}
+
+impl Event {
+    fn new(ts: u64, track_uuid: u64, event_type: u64, data: EventData) -> Event {
+        Event { ts, track_uuid, event_type, data }
+    }
+
+    fn counter(ts: u64, track_uuid: u64, value: i64) -> Event {
+        Event::new(ts, track_uuid, TYPE_COUNTER, EventData::Counter(value))
+    }
+}
+
+// an occurrence of an async interval
+struct AsyncSpan {
+    begin: u64,
+    end: Option<u64>,
+    // of the 'b' json event
+    index: usize,
+}
+
+// index of the json event of a slice or instant -> the flow arrows ('s' or 'f', id) leaving or reaching it
+type Flows = HashMap<usize, Vec<(char, u64)>>;
+
+fn ns(us: f64) -> u64 {
+    (us * 1000.0).round().max(0.0) as u64
+}
+
+// the slices, flow arrows and instants of a thread, the slices are turned into properly nested begins and ends
+fn thread_events(trace_events: &[TraceEvent], mut indexes: Vec<usize>, track_uuid: u64, events: &mut Vec<Event>, flows: &mut Flows) {
+    // at the same timestamp, parents start before their children, and flow arrows and instants happen in the innermost slice
+    let rank = |trace_event: &TraceEvent| match trace_event.ph {
+        'X' => 0,
+        's' | 'f' => 1,
+        _ => 2,
+    };
+    indexes.sort_by(|&a, &b| {
+        let (a, b) = (&trace_events[a], &trace_events[b]);
+        a.ts.total_cmp(&b.ts).then(rank(a).cmp(&rank(b))).then(b.dur.total_cmp(&a.dur))
+    });
+    // (end, index of the json event) of the open slices
+    let mut open: Vec<(u64, usize)> = Vec::new();
+    for index in indexes {
+        let trace_event = &trace_events[index];
+        let ts = ns(trace_event.ts);
+        while let Some(&(end, _begin)) = open.last().filter(|&&(end, _begin)| end <= ts) {
+            events.push(Event::new(end, track_uuid, TYPE_SLICE_END, EventData::None));
+            open.pop();
+        }
+        match trace_event.ph {
+            'X' => {
+                // children can't outlive their parent, even with rounding
+                let end = open.last().map_or(u64::MAX, |&(end, _begin)| end).min(ts + ns(trace_event.dur));
+                open.push((end, index));
+                events.push(Event::new(ts, track_uuid, TYPE_SLICE_BEGIN, EventData::TraceEvent(index)));
+            },
+            's' | 'f' => {
+                let slice = match open.last() {
+                    Some(&(_end, slice)) => slice,
+                    None => {
+                        events.push(Event::new(ts, track_uuid, TYPE_INSTANT, EventData::TraceEvent(index)));
+                        index
+                    },
+                };
+                flows.entry(slice).or_default().push((trace_event.ph, trace_event.id.unwrap_or_default()));
+            },
+            _ => events.push(Event::new(ts, track_uuid, TYPE_INSTANT, EventData::TraceEvent(index))),
+        }
+    }
+    while let Some((end, _begin)) = open.pop() {
+        events.push(Event::new(end, track_uuid, TYPE_SLICE_END, EventData::None));
+    }
+}
+
+// the events of the json trace laid out on Perfetto tracks: a track per process, with a child track per thread,
+// lanes for the async intervals, counters with their units, and the frames (from one VInt to the next) and their duration
+fn perfetto_events<'a>(trace_events: &'a [TraceEvent<'a>]) -> (Tracks<'a>, Vec<Event>, Flows) {
+    let mut tracks = Tracks::default();
+    for trace_event in trace_events.iter().filter(|trace_event| trace_event.ph == 'M') {
+        let args = trace_event.args.as_ref();
+        match trace_event.name.as_ref() {
+            "process_name" => if let Some(name) = args.and_then(|args| args.name.as_deref()) {
+                tracks.process_names.insert(trace_event.pid, name);
+            },
+            "thread_name" => if let Some(name) = args.and_then(|args| args.name.as_deref()) {
+                tracks.thread_names.insert((trace_event.pid, trace_event.tid), name);
+            },
+            "thread_sort_index" => if let Some(sort_index) = args.and_then(|args| args.sort_index) {
+                tracks.thread_sort_indexes.insert((trace_event.pid, trace_event.tid), sort_index);
+            },
+            _ => {},
+        }
+    }
+    // the named threads come first, in the order of the trace
+    for trace_event in trace_events.iter().filter(|trace_event| trace_event.ph == 'M' && trace_event.name == "thread_name") {
+        tracks.uuid(TrackKey::Thread(trace_event.pid, trace_event.tid));
+    }
+    // about a begin and an end per slice
+    let mut events = Vec::with_capacity(trace_events.len() * 2);
+    let mut flows = Flows::new();
+    let mut thread_indexes: BTreeMap<(u32, u32), Vec<usize>> = BTreeMap::new();
+    // (pid, tid) -> occurrences of async intervals, and (category, id) -> where the ones yet to end are
+    let mut async_spans: BTreeMap<(u32, u32), Vec<AsyncSpan>> = BTreeMap::new();
+    let mut open_async_spans = HashMap::new();
+    let mut vints: BTreeMap<u32, Vec<u64>> = BTreeMap::new();
+    // the process instants about frames (lag frames) go on the frame track, inside the frame they start
+    let mut frame_instants: Vec<(u32, u64, usize)> = Vec::new();
+    for (index, trace_event) in trace_events.iter().enumerate() {
+        let ts = ns(trace_event.ts);
+        let thread = (trace_event.pid, trace_event.tid);
+        if trace_event.ph == 'i' && trace_event.name == "VInt" && trace_event.cat.as_deref() == Some(CATEGORY_FRAME) {
+            vints.entry(trace_event.pid).or_default().push(ts);
+        }
+        match (trace_event.ph, trace_event.s) {
+            ('M', _) => {},
+            ('i', Some('g')) => {
+                let track_uuid = tracks.uuid(TrackKey::Global);
+                events.push(Event::new(ts, track_uuid, TYPE_INSTANT, EventData::TraceEvent(index)));
+            },
+            ('i', Some('p')) if trace_event.cat.as_deref() == Some(CATEGORY_FRAME) => frame_instants.push((trace_event.pid, ts, index)),
+            ('i', Some('p')) => {
+                let track_uuid = tracks.uuid(TrackKey::Process(trace_event.pid));
+                events.push(Event::new(ts, track_uuid, TYPE_INSTANT, EventData::TraceEvent(index)));
+            },
+            ('C', _) => {
+                let track_uuid = tracks.uuid(TrackKey::Counter(trace_event.pid, &trace_event.name));
+                let value = trace_event.args.as_ref().and_then(|args| args.value).unwrap_or_default();
+                events.push(Event::counter(ts, track_uuid, value as i64));
+            },
+            ('b', _) => {
+                let spans = async_spans.entry(thread).or_default();
+                open_async_spans.insert((trace_event.cat.as_deref(), trace_event.id.unwrap_or_default()), (thread, spans.len()));
+                spans.push(AsyncSpan { begin: ts, end: None, index });
+            },
+            ('e', _) => {
+                if let Some((thread, span)) = open_async_spans.remove(&(trace_event.cat.as_deref(), trace_event.id.unwrap_or_default())) {
+                    async_spans.get_mut(&thread).unwrap()[span].end = Some(ts);
+                }
+            },
+            _ => {
+                if let Some(dma_length) = trace_event.args.as_ref().and_then(|args| args.dma_length).filter(|_| trace_event.ph == 'X') {
+                    let track_uuid = tracks.uuid(TrackKey::DmaLength(trace_event.pid));
+                    events.push(Event::counter(ts, track_uuid, dma_length as i64));
+                    events.push(Event::counter(ts + ns(trace_event.dur), track_uuid, 0));
+                }
+                thread_indexes.entry(thread).or_default().push(index);
+            },
+        }
+    }
+    for ((pid, tid), indexes) in thread_indexes {
+        let track_uuid = tracks.uuid(TrackKey::Thread(pid, tid));
+        thread_events(trace_events, indexes, track_uuid, &mut events, &mut flows);
+    }
+    let last_ts = events.iter().map(|event| event.ts).max().unwrap_or_default();
+    for ((pid, tid), mut spans) in async_spans {
+        spans.sort_by_key(|span| span.begin);
+        // end of the last occurrence of each lane, an occurrence goes on the first lane that is free by then
+        let mut lanes: Vec<u64> = Vec::new();
+        for AsyncSpan { begin, end, index } in spans {
+            // occurrences that never end last until the end of the trace
+            let end = end.unwrap_or(last_ts).max(begin);
+            let lane = lanes.iter().position(|&lane_end| lane_end <= begin).unwrap_or_else(|| {
+                lanes.push(0);
+                lanes.len() - 1
+            });
+            lanes[lane] = end;
+            let track_uuid = tracks.uuid(TrackKey::AsyncLane(pid, tid, lane));
+            events.push(Event::new(begin, track_uuid, TYPE_SLICE_BEGIN, EventData::TraceEvent(index)));
+            events.push(Event::new(end, track_uuid, TYPE_SLICE_END, EventData::None));
+        }
+    }
+    for (pid, vints) in vints {
+        for (frame, window) in vints.windows(2).enumerate() {
+            let frames_uuid = tracks.uuid(TrackKey::Frames(pid));
+            events.push(Event::new(window[0], frames_uuid, TYPE_SLICE_BEGIN, EventData::Frame(frame)));
+            events.push(Event::new(window[1], frames_uuid, TYPE_SLICE_END, EventData::None));
+            let duration_uuid = tracks.uuid(TrackKey::FrameDuration(pid));
+            events.push(Event::counter(window[0], duration_uuid, (window[1] - window[0]) as i64));
+        }
+    }
+    for (pid, ts, index) in frame_instants {
+        let frames_uuid = tracks.uuid(TrackKey::Frames(pid));
+        events.push(Event::new(ts, frames_uuid, TYPE_INSTANT, EventData::TraceEvent(index)));
+    }
+    // each track's events are already in order, the stable sort keeps it for those sharing a timestamp
+    events.sort_by_key(|event| event.ts);
+    (tracks, events, flows)
+}
+
+fn track_descriptor_packet(track: &Track) -> Message {
+    let mut descriptor = Message::default();
+    descriptor.uint(TRACK_UUID, track.uuid).string(TRACK_NAME, &track.name);
+    if let Some(parent_uuid) = track.parent_uuid {
+        descriptor.uint(TRACK_PARENT_UUID, parent_uuid);
+    }
+    if let Some(counter_unit) = track.counter_unit {
+        let mut counter = Message::default();
+        counter.uint(COUNTER_UNIT, counter_unit as u64);
+        descriptor.message(TRACK_COUNTER, &counter);
+    }
+    if track.explicit_ordering {
+        descriptor.uint(TRACK_CHILD_ORDERING, CHILD_ORDERING_EXPLICIT);
+    }
+    if let Some(sort_index) = track.sort_index {
+        descriptor.int(TRACK_SIBLING_ORDER_RANK, sort_index as i64);
+    }
+    let mut packet = Message::default();
+    packet.uint(PACKET_SEQUENCE_ID, SEQUENCE_ID).message(PACKET_TRACK_DESCRIPTOR, &descriptor);
+    packet
+}
+
+// the args become debug annotations, which trace_processor puts in its args table
+fn debug_annotations(args: &TraceEventArgs, track_event: &mut Message, mut interning: Option<&mut Interning>) {
+    let Ok(serde_json::Value::Object(args)) = serde_json::to_value(args) else {
+        return;
+    };
+    for (name, value) in args {
+        let mut annotation = Message::default();
+        match interning.as_deref_mut() {
+            Some(interning) => annotation.uint(ANNOTATION_NAME_IID, interning.debug_annotation_name(&name)),
+            None => annotation.string(ANNOTATION_NAME, &name),
+        };
+        match value {
+            serde_json::Value::Bool(value) => annotation.uint(ANNOTATION_BOOL, value as u64),
+            serde_json::Value::Number(number) => match (number.as_u64(), number.as_i64()) {
+                (Some(value), _) => annotation.uint(ANNOTATION_UINT, value),
+                (None, Some(value)) => annotation.int(ANNOTATION_INT, value),
+                (None, None) => annotation.double(ANNOTATION_DOUBLE, number.as_f64().unwrap_or_default()),
+            },
+            serde_json::Value::String(value) => annotation.string(ANNOTATION_STRING, &value),
+            value => annotation.string(ANNOTATION_STRING, &value.to_string()),
+        };
+        track_event.message(EVENT_DEBUG_ANNOTATIONS, &annotation);
+    }
+}
+
+fn track_event_packet(event: &Event, trace_events: &[TraceEvent], flows: &Flows, mut interning: Option<&mut Interning>) -> Message {
+    let mut track_event = Message::default();
+    track_event.uint(EVENT_TYPE, event.event_type).uint(EVENT_TRACK_UUID, event.track_uuid);
+    match event.data {
+        EventData::None => {},
+        EventData::TraceEvent(index) => {
+            let trace_event = &trace_events[index];
+            match interning.as_deref_mut() {
+                Some(interning) => track_event.uint(EVENT_NAME_IID, interning.event_name(&trace_event.name)),
+                None => track_event.string(EVENT_NAME, &trace_event.name),
+            };
+            if let Some(category) = &trace_event.cat {
+                match interning.as_deref_mut() {
+                    Some(interning) => track_event.uint(EVENT_CATEGORY_IIDS, interning.event_category(category)),
+                    None => track_event.string(EVENT_CATEGORIES, category),
+                };
+            }
+            if let Some(args) = &trace_event.args {
+                debug_annotations(args, &mut track_event, interning.as_deref_mut());
+            }
+            for &(ph, id) in flows.get(&index).into_iter().flatten() {
+                track_event.fixed64(if ph == 's' { EVENT_FLOW_IDS } else { EVENT_TERMINATING_FLOW_IDS }, id);
+            }
+        },
+        EventData::Frame(frame) => {
+            track_event.string(EVENT_NAME, &format!("Frame {}", frame));
+            match interning.as_deref_mut() {
+                Some(interning) => track_event.uint(EVENT_CATEGORY_IIDS, interning.event_category(CATEGORY_FRAME)),
+                None => track_event.string(EVENT_CATEGORIES, CATEGORY_FRAME),
+            };
+        },
+        EventData::Counter(value) => {
+            track_event.int(EVENT_COUNTER_VALUE, value);
+        },
+    }
+    let mut packet = Message::default();
+    packet.uint(PACKET_TIMESTAMP, event.ts).uint(PACKET_SEQUENCE_ID, SEQUENCE_ID).message(PACKET_TRACK_EVENT, &track_event);
+    if let Some(interning) = interning {
+        packet.uint(PACKET_SEQUENCE_FLAGS, SEQ_NEEDS_INCREMENTAL_STATE);
+        let interned_data = std::mem::take(&mut interning.interned_data);
+        if !interned_data.0.is_empty() {
+            packet.message(PACKET_INTERNED_DATA, &interned_data);
+        }
+    }
+    packet
+}
+
+fn write_packets(output: &mut dyn Write, trace_events: &[TraceEvent], compact: bool) -> std::io::Result<()> {
+    let (tracks, events, flows) = perfetto_events(trace_events);
+    let mut trace = Message::default();
+    let mut first_packet = Message::default();
+    first_packet.uint(PACKET_SEQUENCE_ID, SEQUENCE_ID).uint(PACKET_SEQUENCE_FLAGS, SEQ_INCREMENTAL_STATE_CLEARED);
+    trace.message(TRACE_PACKET, &first_packet);
+    for track in &tracks.tracks {
+        trace.message(TRACE_PACKET, &track_descriptor_packet(track));
+    }
+    output.write_all(&trace.0)?;
+    let mut interning = compact.then(Interning::default);
+    // the packets are written as they are encoded rather than as one huge message
+    for event in &events {
+        let mut trace = Message::default();
+        trace.message(TRACE_PACKET, &track_event_packet(event, trace_events, &flows, interning.as_mut()));
+        output.write_all(&trace.0)?;
+    }
+    Ok(())
+}
+
+// a Perfetto protobuf trace (.pftrace) rather than json
+pub fn write_perfetto_trace(output: impl Write, trace_events: &[TraceEvent], compression: Compression, compact: bool) {
+    let stopwatch = Stopwatch::start();
+    let mut writer = ProgressWriter::new(output, Progress::new("Writing", "bytes", 0));
+    write_compressed(BufWriter::new(&mut writer), compression, |output| write_packets(output, trace_events, compact)).expect("Error writing perfetto trace");
+    let written = writer.written();
+    writer.finish();
+    crate::status!("Wrote {} MB of perfetto trace in {} ms", written / 1_000_000, stopwatch.elapsed_ms());
+}
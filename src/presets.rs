@@ -0,0 +1,54 @@
+use std::path::{Path, PathBuf};
+
+// the defaults of the projects built with a given toolchain, what the user gives on the command line still comes first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    Sgdk,
+}
+
+pub const PRESETS: [&str; 1] = ["sgdk"];
+
+impl Preset {
+    pub fn parse(preset: &str) -> Result<Preset, String> {
+        match preset {
+            "sgdk" => Ok(Preset::Sgdk),
+            _ => Err(format!("Unknown preset: {}, expected one of {}", preset, PRESETS.join(", "))),
+        }
+    }
+
+    // the symbol files the build writes, relative to the project directory, in order of preference
+    fn symbol_files(self) -> &'static [&'static str] {
+        match self {
+            Preset::Sgdk => &["out/symbol.txt", "symbol.txt", "out.map", "out/out.map"],
+        }
+    }
+
+    // the functions waiting for the VBlank, those missing from the symbols (older or newer library) are skipped
+    pub fn idle_functions(self) -> &'static [&'static str] {
+        match self {
+            // SYS_doVBlankProcess does its work then waits in one of these, depending on the version of SGDK
+            Preset::Sgdk => &["VDP_waitVSync", "VDP_waitVInt", "VDP_waitVBlank"],
+        }
+    }
+
+    // the wrappers only calling another function of the library, folded into their caller
+    pub fn folded_functions(self) -> &'static [&'static str] {
+        match self {
+            Preset::Sgdk => &["SYS_doVBlankProcess", "VDP_drawText", "VDP_clearText"],
+        }
+    }
+
+    // whether each interrupt handler gets its own thread
+    pub fn split_interrupts(self) -> bool {
+        match self {
+            Preset::Sgdk => true,
+        }
+    }
+
+    // the first symbol file of the build found in one of these directories
+    pub fn find_symbol_file(self, directories: &[&Path]) -> Option<PathBuf> {
+        directories.iter()
+            .flat_map(|directory| self.symbol_files().iter().map(move |symbol_file| directory.join(symbol_file)))
+            .find(|path| path.is_file())
+    }
+}
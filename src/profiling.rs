@@ -1,9 +1,65 @@
-use std::{borrow::{Borrow, Cow}, collections::HashMap, convert::TryInto, fs::File, io::BufWriter, time::Instant};
-use serde::Serialize;
+use std::{borrow::Cow, collections::{BTreeMap, HashMap, HashSet}, convert::TryInto, io::{BufWriter, ErrorKind, Read, Write}};
+use serde::{Serialize, Serializer, ser::SerializeMap};
 
-use crate::{intervals::Intervals, symbols::Symbols};
+use crate::{intervals::Intervals, progress::{Progress, ProgressWriter, Stopwatch}, tasks::TaskStacks, schema::TraceMetadata, symbols::Symbols};
 
-const MDP_VERSION: u8 = 1;
+pub const MDP_VERSION: u8 = 1;
+pub const MDP_HEADER_SIZE: usize = 256;
+// optional fields of the header describing the capture, zeroed when the emulator doesn't fill them
+const HEADER_ROM_NAME: std::ops::Range<usize> = 20..68;
+const HEADER_ROM_CHECKSUM: std::ops::Range<usize> = 68..70;
+const HEADER_EMULATOR: std::ops::Range<usize> = 70..102;
+const HEADER_CAPTURED_AT: std::ops::Range<usize> = 102..110;
+const HEADER_SH2_CLOCK: std::ops::Range<usize> = 110..114;
+const HEADER_SUB_CPU_MCLK: std::ops::Range<usize> = 114..118;
+const HEADER_SUB_CPU_DIVIDER: std::ops::Range<usize> = 118..122;
+// a 4 bytes clock and a 4 bytes divider for each ClockDomain, see DomainClock
+const HEADER_CLOCK_DOMAINS: usize = 122;
+// the length of the build id of the ROM, then the id itself
+const HEADER_BUILD_ID_LEN: usize = 154;
+const HEADER_BUILD_ID: std::ops::Range<usize> = 155..175;
+// NTSC timings, used for captures aborted before their header was written and for synthetic captures
+pub const DEFAULT_MCLK: f64 = 53_693_175.0;
+// anything outside of these ranges is a broken header, the MegaDrive runs at about 53 MHz with a divider of 7
+const MIN_MCLK: f64 = 1_000_000.0;
+const MAX_MCLK: f64 = 1_000_000_000.0;
+const MAX_M68K_DIVIDER: u64 = 64;
+pub const DEFAULT_M68K_DIVIDER: u64 = 7;
+// the sub CPU of the Mega CD is a 68000 at 12.5 MHz, a quarter of the 50 MHz clock of the Mega CD
+pub const DEFAULT_SUB_CPU_MCLK: f64 = 50_000_000.0;
+pub const DEFAULT_SUB_CPU_DIVIDER: u64 = 4;
+// the Z80 runs at the master clock divided by 15, the VDP draws a pixel every 8 cycles of the master clock in H40 mode
+pub const DEFAULT_Z80_DIVIDER: u64 = 15;
+pub const DEFAULT_VDP_DIVIDER: u64 = 8;
+
+// categories ("cat") of the events, which the viewers and --categories/--exclude-categories can filter on
+pub const CATEGORY_SUBROUTINE: &str = "subroutine";
+pub const CATEGORY_INTERRUPT: &str = "interrupt";
+pub const CATEGORY_TRAP: &str = "trap";
+pub const CATEGORY_DMA: &str = "dma";
+pub const CATEGORY_FRAME: &str = "frame";
+pub const CATEGORY_INTERVAL: &str = "interval";
+pub const CATEGORY_MARKER: &str = "marker";
+pub const CATEGORY_VDP: &str = "vdp";
+pub const CATEGORY_Z80: &str = "z80";
+// the slices of the idle functions, see mark_idle_slices
+pub const CATEGORY_IDLE: &str = "idle";
+// which CPU of the Mega CD owns its word RAM
+pub const CATEGORY_WORD_RAM: &str = "word_ram";
+// the commands sent to the sound driver
+pub const CATEGORY_SOUND: &str = "sound";
+// the allocations of the game and the heap counter
+pub const CATEGORY_HEAP: &str = "heap";
+// the events the game code wrote with a text of its own
+pub const CATEGORY_USER: &str = "user";
+// the address errors, illegal instructions... the CPU took an exception for
+pub const CATEGORY_EXCEPTION: &str = "exception";
+// the counters of the variables watched by the emulator
+pub const CATEGORY_WATCH: &str = "watch";
+// the notes of the annotation file
+pub const CATEGORY_ANNOTATION: &str = "annotation";
+pub const CATEGORIES: &[&str] = &[CATEGORY_SUBROUTINE, CATEGORY_INTERRUPT, CATEGORY_TRAP, CATEGORY_DMA, CATEGORY_FRAME, CATEGORY_INTERVAL, CATEGORY_MARKER, CATEGORY_VDP, CATEGORY_Z80, CATEGORY_IDLE, CATEGORY_WORD_RAM, CATEGORY_SOUND, CATEGORY_HEAP, CATEGORY_USER, CATEGORY_EXCEPTION, CATEGORY_WATCH, CATEGORY_ANNOTATION];
+const IDLE_COLOR: &str = "grey";
 
 const PROFILER_PACKET_SUBROUTINE_ENTER: u8 =  0;
 const PROFILER_PACKET_SUBROUTINE_EXIT: u8 =   1;
@@ -13,131 +69,2287 @@ const PROFILER_PACKET_HINT: u8 =              4;
 const PROFILER_PACKET_VINT: u8 =              5;
 const PROFILER_PACKET_ADJUST_CYCLES: u8 =     6;
 const PROFILER_PACKET_MANUAL_BREAKPOINT: u8 = 7;
+const PROFILER_PACKET_SAVESTATE: u8 =         8;
+const PROFILER_PACKET_DMA: u8 =               9;
+const PROFILER_PACKET_TAIL_CALL: u8 =         10;
+const PROFILER_PACKET_DMA_START: u8 =         11;
+const PROFILER_PACKET_DMA_END: u8 =           12;
+const PROFILER_PACKET_VDP_REGISTER_WRITE: u8 = 13;
+const PROFILER_PACKET_Z80_BUS_REQUEST: u8 =   14;
+const PROFILER_PACKET_Z80_BUS_RELEASE: u8 =   15;
+const PROFILER_PACKET_BANK_SWITCH: u8 =       16;
+// a packet of one of the SH2s of the 32X, wrapping one of the packets above
+const PROFILER_PACKET_SH2: u8 =               17;
+// a packet of the sub CPU of the Mega CD, wrapping one of the packets above
+const PROFILER_PACKET_SUB_CPU: u8 =           18;
+const PROFILER_PACKET_WORD_RAM: u8 =          19;
+const PROFILER_PACKET_SOUND_COMMAND: u8 =     20;
+const PROFILER_PACKET_ALLOC: u8 =             21;
+const PROFILER_PACKET_FREE: u8 =              22;
+const PROFILER_PACKET_USER_EVENT: u8 =        23;
+const PROFILER_PACKET_USER_EVENT_INDEX: u8 =  24;
+// a subroutine enter followed by the values of the registers the emulator was asked to record
+const PROFILER_PACKET_SUBROUTINE_ENTER_REGISTERS: u8 = 25;
+const PROFILER_PACKET_PC_SAMPLE: u8 =         26;
+// a packet of the 68000 timeline whose cycle is counted by the clock of another part of the console, see ClockDomain
+const PROFILER_PACKET_CLOCK_DOMAIN: u8 =      27;
+// a TRAP #n instruction entered the handler of its vector, until the RTE of the next TRAP_EXIT
+const PROFILER_PACKET_TRAP_ENTER: u8 =        28;
+const PROFILER_PACKET_TRAP_EXIT: u8 =         29;
+const PROFILER_PACKET_EXCEPTION: u8 =         30;
+const PROFILER_PACKET_MEMORY_WATCH: u8 =      31;
+
+// tids 0 and 1 are the main and interrupt threads, VDP DMA transfers and register writes get their own thread
+pub const VDP_TID: u32 = 2;
+// with --split-interrupts, tid 1 only gets the VInt handlers, HInt and external interrupt handlers get their own thread
+pub const HINT_TID: u32 = 3;
+pub const EXTERNAL_INTERRUPT_TID: u32 = 4;
+// the user events written by the game code
+pub const USER_EVENTS_TID: u32 = 5;
+// the categories of the interval file and --breakpoints-thread
+pub const FIRST_CUSTOM_TID: u32 = 6;
+// the Z80 gets its own process, everything else is on the 68000 one (pid 0)
+pub const Z80_PID: u32 = 1;
+// the bus requests are on the first thread of the Z80 process, the sound driver commands on the second one
+pub const SOUND_TID: u32 = 1;
+// so do the SH2s of the 32X, see Sh2Cpu
+pub const SH2_MASTER_PID: u32 = 2;
+pub const SH2_SLAVE_PID: u32 = 3;
+// and the sub CPU of the Mega CD, along with the owner of the word RAM
+pub const SUB_CPU_PID: u32 = 4;
+// the processes of the interval file
+pub const FIRST_CUSTOM_PID: u32 = 5;
+
+// Packets are processed in file order, so when a subroutine returns and another one is called on the same cycle,
+// or a subroutine is called on the very cycle its parent starts, the slices share a timestamp and viewers can't tell
+// which one is nested in which. To keep the nesting unambiguous, each slice is shrunk on both ends by this fraction
+// of a cycle per nesting level in the emitted timestamps (cycle counts used by the reports are untouched):
+// a child always starts after and ends before its parent, and a slice always starts after its previous sibling ended.
+// 1/16 of a master clock cycle is still about a nanosecond, the resolution of the viewers
+const SLICE_NUDGE_CYCLES: f64 = 1.0 / 16.0;
+// caps the total nudge to a few cycles, well below the duration of a single 68000 instruction
+const MAX_NUDGE_DEPTH: usize = 64;
+// the SSF2 mapper splits the 4MB of the 68000 ROM space in 8 windows of 512KB, each mapped to any 512KB bank of the ROM
+// but the first one, which always maps bank 0
+const SSF2_WINDOWS: usize = 8;
+const SSF2_WINDOW_BITS: u32 = 19;
+
+#[derive(Debug)]
+pub struct ProfilingPacket {
+    pub cycle: u64,
+    pub stack_pointer: u32,
+    pub inner: ProfilingPacketInner,
+}
+
+#[derive(Debug)]
+pub enum ProfilingPacketInner {
+    // with the (register, value) of the registers recorded on entry, see register_name
+    SubroutineEnter { target_subroutine: u32, registers: Vec<(u8, u32)> },
+    SubroutineExit,
+    InterruptEnter { target_interrupt: u32 },
+    InterruptExit,
+    HInt,
+    VInt,
+    ManualBreakpoint { pc: u32 },
+    // the emulator saved a savestate at this point of the capture
+    Savestate { path: String },
+    // a DMA transfer from the 68000 bus (ROM or RAM) halted the 68000 from this point for that many cycles
+    Dma { source: u32, halted_cycles: u32 },
+    // a JMP to a subroutine label, from the subroutine that was running: that one ends here and the target
+    // takes its place, returning to its caller (tail calls, jump table dispatch)
+    TailCall { target_subroutine: u32 },
+    // the VDP started a DMA transfer of length bytes, it runs until the next DmaEnd
+    DmaStart { source: u32, destination: u32, length: u32, dma_type: DmaType },
+    DmaEnd,
+    // the 68000 wrote this value to a VDP register
+    VdpRegisterWrite { register: u8, value: u8 },
+    // the 68000 requested the Z80 bus (BUSREQ), the Z80 is stopped until the next Z80BusRelease
+    Z80BusRequest,
+    Z80BusRelease,
+    // the 68000 wrote to the SSF2 mapper register of a window, mapping this bank of the ROM in it
+    BankSwitch { window: u8, bank: u8 },
+    // the word RAM of the Mega CD was handed over, it stays that way until the next WordRam
+    WordRam { mode: WordRamMode },
+    // the game sent a command to its sound driver, with the sound effect, song or sample it is about
+    SoundCommand { command: SoundCommand, argument: u16 },
+    // the allocator of the game handed out size bytes at this address, tag is whatever the game uses to tell its allocations apart
+    Alloc { address: u32, size: u32, tag: u16 },
+    // the allocation at this address was freed
+    Free { address: u32 },
+    // the game code wrote this text, as a marker or the begin or end of a slice
+    UserEvent { kind: UserEventKind, text: String },
+    // the same with the index of the text in the string table of the game, see resolve_user_strings
+    UserEventIndex { kind: UserEventKind, index: u16 },
+    // the emulator sampled the PC of the 68000, see sampling.rs
+    PcSample { pc: u32 },
+    // a TRAP #vector instruction jumped to this handler, which runs like a subroutine until the next TrapExit,
+    // the stack pointer being the one before the exception frame was pushed
+    TrapEnter { vector: u8, handler: u32 },
+    // the RTE of a TRAP handler, the stack pointer being the one before it popped the exception frame
+    TrapExit,
+    // the CPU took an exception for an error of the instruction at this PC (or was interrupted there, for a spurious interrupt)
+    Exception { exception: CpuException, pc: u32 },
+    // the watched variable of size bytes at this address holds this value, written by the emulator when it changes or once per frame
+    MemoryWatch { address: u32, size: u8, value: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserEventKind {
+    Instant,
+    // a slice lasting until the next End that isn't the End of a slice nested in it
+    Begin,
+    End,
+}
+
+impl UserEventKind {
+    fn from_byte(byte: u8) -> Result<UserEventKind, String> {
+        match byte {
+            0 => Ok(UserEventKind::Instant),
+            1 => Ok(UserEventKind::Begin),
+            2 => Ok(UserEventKind::End),
+            x => Err(format!("Unknown user event kind: {}", x)),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            UserEventKind::Instant => 0,
+            UserEventKind::Begin => 1,
+            UserEventKind::End => 2,
+        }
+    }
+}
+
+// the exceptions of the 68000 that mean something went wrong, as opposed to the interrupts and TRAPs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CpuException {
+    AddressError,
+    IllegalInstruction,
+    DivideByZero,
+    SpuriousInterrupt,
+    // the other vectors, drawn with their number
+    Other(u8),
+}
+
+impl CpuException {
+    fn from_byte(byte: u8) -> CpuException {
+        match byte {
+            0 => CpuException::AddressError,
+            1 => CpuException::IllegalInstruction,
+            2 => CpuException::DivideByZero,
+            3 => CpuException::SpuriousInterrupt,
+            x => CpuException::Other(x),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            CpuException::AddressError => 0,
+            CpuException::IllegalInstruction => 1,
+            CpuException::DivideByZero => 2,
+            CpuException::SpuriousInterrupt => 3,
+            CpuException::Other(x) => x,
+        }
+    }
+
+    pub fn name(self) -> Cow<'static, str> {
+        match self {
+            CpuException::AddressError => "Address error".into(),
+            CpuException::IllegalInstruction => "Illegal instruction".into(),
+            CpuException::DivideByZero => "Divide by zero".into(),
+            CpuException::SpuriousInterrupt => "Spurious interrupt".into(),
+            CpuException::Other(exception) => format!("Exception {}", exception).into(),
+        }
+    }
+}
+
+// what the game asked its sound driver (XGM or custom) to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundCommand {
+    PlaySfx,
+    // the driver advanced the music by one tick
+    MusicTick,
+    // a PCM sample plays until the next PcmStop or PcmStart
+    PcmStart,
+    PcmStop,
+    // the commands specific to a driver, drawn with their number
+    Other(u8),
+}
+
+impl SoundCommand {
+    fn from_byte(byte: u8) -> SoundCommand {
+        match byte {
+            0 => SoundCommand::PlaySfx,
+            1 => SoundCommand::MusicTick,
+            2 => SoundCommand::PcmStart,
+            3 => SoundCommand::PcmStop,
+            x => SoundCommand::Other(x),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            SoundCommand::PlaySfx => 0,
+            SoundCommand::MusicTick => 1,
+            SoundCommand::PcmStart => 2,
+            SoundCommand::PcmStop => 3,
+            SoundCommand::Other(x) => x,
+        }
+    }
+
+    pub fn name(self, argument: u16) -> String {
+        match self {
+            SoundCommand::PlaySfx => format!("Play SFX {}", argument),
+            SoundCommand::MusicTick => "Music tick".to_string(),
+            SoundCommand::PcmStart => format!("PCM {}", argument),
+            SoundCommand::PcmStop => "PCM stop".to_string(),
+            SoundCommand::Other(command) => format!("Sound command {} ({})", command, argument),
+        }
+    }
+}
+
+// who can access the word RAM of the Mega CD
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WordRamMode {
+    // 2M mode, all of it belongs to one of the CPUs, the other one waits until it is handed over
+    Main,
+    Sub,
+    // 1M mode, each CPU gets one half
+    Split,
+}
+
+impl WordRamMode {
+    fn from_byte(byte: u8) -> Result<WordRamMode, String> {
+        match byte {
+            0 => Ok(WordRamMode::Main),
+            1 => Ok(WordRamMode::Sub),
+            2 => Ok(WordRamMode::Split),
+            x => Err(format!("Unknown word RAM mode: {}", x)),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            WordRamMode::Main => 0,
+            WordRamMode::Sub => 1,
+            WordRamMode::Split => 2,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WordRamMode::Main => "Word RAM owned by the main CPU",
+            WordRamMode::Sub => "Word RAM owned by the sub CPU",
+            WordRamMode::Split => "Word RAM split (1M mode)",
+        }
+    }
+}
+
+// the registers of the 68000 as numbered in the packets, D0 to D7 then A0 to A7
+pub fn register_name(register: u8) -> &'static str {
+    const REGISTER_NAMES: [&str; 16] = ["d0", "d1", "d2", "d3", "d4", "d5", "d6", "d7", "a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"];
+    REGISTER_NAMES.get(register as usize).copied().unwrap_or("?")
+}
+
+pub fn vdp_register_name(register: u8) -> Cow<'static, str> {
+    let name = match register {
+        0x00 => "Mode set 1",
+        0x01 => "Mode set 2",
+        0x02 => "Scroll A address",
+        0x03 => "Window address",
+        0x04 => "Scroll B address",
+        0x05 => "Sprite table address",
+        0x06 => "Sprite pattern base (128K)",
+        0x07 => "Background color",
+        0x0A => "HInt counter",
+        0x0B => "Mode set 3",
+        0x0C => "Mode set 4",
+        0x0D => "HScroll table address",
+        0x0E => "Nametable pattern base (128K)",
+        0x0F => "Auto increment",
+        0x10 => "Scroll size",
+        0x11 => "Window H position",
+        0x12 => "Window V position",
+        0x13 => "DMA length low",
+        0x14 => "DMA length high",
+        0x15 => "DMA source low",
+        0x16 => "DMA source mid",
+        0x17 => "DMA source high",
+        _ => return Cow::Owned(format!("VDP register {}", register)),
+    };
+    Cow::Borrowed(name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaType {
+    // from the 68000 bus, which halts the 68000
+    ToVram,
+    ToCram,
+    ToVsram,
+    VramFill,
+    VramCopy,
+}
+
+impl DmaType {
+    fn from_byte(byte: u8) -> Result<DmaType, String> {
+        match byte {
+            0 => Ok(DmaType::ToVram),
+            1 => Ok(DmaType::ToCram),
+            2 => Ok(DmaType::ToVsram),
+            3 => Ok(DmaType::VramFill),
+            4 => Ok(DmaType::VramCopy),
+            x => Err(format!("Unknown DMA type: {}", x)),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            DmaType::ToVram => 0,
+            DmaType::ToCram => 1,
+            DmaType::ToVsram => 2,
+            DmaType::VramFill => 3,
+            DmaType::VramCopy => 4,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DmaType::ToVram => "DMA to VRAM",
+            DmaType::ToCram => "DMA to CRAM",
+            DmaType::ToVsram => "DMA to VSRAM",
+            DmaType::VramFill => "VRAM fill",
+            DmaType::VramCopy => "VRAM copy",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TraceEventArgs<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort_index: Option<u32>,
+    // "file:line" where the function is defined
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<Cow<'a, str>>,
+    // name of the region (see --regions) the function belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub savestate: Option<Cow<'a, str>>,
+    // value of a counter event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<u64>,
+    // index of the occurrence of an interval, and its raw start and end cycles
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub occurrence: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_cycle: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_cycle: Option<u64>,
+    // duration in master clock cycles, and the addresses that started and ended an interval
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cycles: Option<u64>,
+    // cycles spent in the interrupt handlers by an interval excluding them, left out of its duration
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interrupt_cycles: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_pc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_pc: Option<String>,
+    // source address of a DMA transfer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dma_source: Option<String>,
+    // VDP address and size in bytes of a DMA transfer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dma_destination: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dma_length: Option<u32>,
+    // number and value of a VDP register write
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vdp_register: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vdp_value: Option<String>,
+    // levels of recursion merged into this slice, see collapse_recursion
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recursion_depth: Option<u32>,
+    // number of consecutive short calls merged into this slice, see merge_short_calls
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub calls: Option<u64>,
+    // the sound effect, song or sample of a sound driver command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sound_argument: Option<u16>,
+    // address, size in bytes and tag of an allocation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heap_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heap_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heap_tag: Option<u16>,
+    // where the capture of a process comes from, see CaptureInfo
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<String>,
+    // frame and line the beam was on when the event started, see --raster
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scanline: Option<u64>,
+    // master clock cycles between the VInt or HInt and the start of its handler, see --latency-report
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_cycles: Option<u64>,
+    // interrupts taken while a slice of the main code ran, and the master clock cycles their handlers took from it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interrupts: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preempted_cycles: Option<u64>,
+    // user defined key/values of an interval
+    #[serde(flatten)]
+    pub metadata: Option<BTreeMap<String, serde_json::Value>>,
+    // registers recorded when entering a subroutine, by name
+    #[serde(flatten)]
+    pub registers: Option<BTreeMap<&'static str, String>>,
+}
+
+// the fields left out of a literal with ..Default::default() are left out of the json
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TraceEvent<'a> {
+    pub name: Cow<'a, str>,
+    pub ph: char,
+    pub ts: f64,
+    pub dur: f64,
+    pub pid: u32,
+    pub tid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub args: Option<TraceEventArgs<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub s: Option<char>,
+    // one of the color names reserved by the trace viewers ("good", "bad", "terrible", "yellow"...)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cname: Option<Cow<'a, str>>,
+    // async events ('b' and 'e') with the same category and id belong to the same span,
+    // and flow events ('s' and 'f') with the same category and id to the same arrow
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cat: Option<Cow<'a, str>>,
+}
+
+
+// Custom post-processing of the generated events, before they get serialized.
+// Every event is passed to the processor which pushes what should replace it in the output:
+// the event itself, possibly modified, nothing to drop it, or several events to split it.
+pub fn process_trace_events<'a>(trace_events: Vec<TraceEvent<'a>>, mut processor: impl FnMut(TraceEvent<'a>, &mut Vec<TraceEvent<'a>>)) -> Vec<TraceEvent<'a>> {
+    let mut output = Vec::with_capacity(trace_events.len());
+    for trace_event in trace_events {
+        processor(trace_event, &mut output);
+    }
+    output
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfilingJson<'a> {
+    trace_events: TraceEvents<'a>,
+    display_time_unit: &'a str,
+    other_data: &'a TraceMetadata,
+}
+
+// where a capture comes from, each field is only known if the emulator recorded it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CaptureInfo {
+    // as in the ROM header
+    pub rom_name: Option<String>,
+    pub rom_checksum: Option<u16>,
+    // name and version
+    pub emulator: Option<String>,
+    // unix time in seconds
+    pub captured_at: Option<u64>,
+    // any id the build of the ROM carries (a GNU build id, a build number...), up to 20 bytes
+    pub build_id: Option<Vec<u8>>,
+}
+
+impl CaptureInfo {
+    fn read(header: &[u8]) -> CaptureInfo {
+        CaptureInfo {
+            rom_name: header_string(&header[HEADER_ROM_NAME]),
+            rom_checksum: Some(u16::from_le_bytes(header[HEADER_ROM_CHECKSUM].try_into().unwrap())).filter(|&checksum| checksum != 0),
+            emulator: header_string(&header[HEADER_EMULATOR]),
+            captured_at: Some(u64::from_le_bytes(header[HEADER_CAPTURED_AT].try_into().unwrap())).filter(|&time| time != 0),
+            build_id: Some(&header[HEADER_BUILD_ID][..(header[HEADER_BUILD_ID_LEN] as usize).min(HEADER_BUILD_ID.len())])
+                .filter(|build_id| !build_id.is_empty())
+                .map(<[u8]>::to_vec),
+        }
+    }
+
+    fn write(&self, header: &mut [u8]) {
+        for (range, string) in [(HEADER_ROM_NAME, &self.rom_name), (HEADER_EMULATOR, &self.emulator)] {
+            let bytes = string.as_deref().unwrap_or_default().as_bytes();
+            let len = bytes.len().min(range.len());
+            header[range.start..range.start + len].copy_from_slice(&bytes[..len]);
+        }
+        header[HEADER_ROM_CHECKSUM].copy_from_slice(&self.rom_checksum.unwrap_or(0).to_le_bytes());
+        header[HEADER_CAPTURED_AT].copy_from_slice(&self.captured_at.unwrap_or(0).to_le_bytes());
+        let build_id = self.build_id.as_deref().unwrap_or_default();
+        let len = build_id.len().min(HEADER_BUILD_ID.len());
+        header[HEADER_BUILD_ID_LEN] = len as u8;
+        header[HEADER_BUILD_ID.start..HEADER_BUILD_ID.start + len].copy_from_slice(&build_id[..len]);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == CaptureInfo::default()
+    }
+
+    // "SONIC THE HEDGEHOG (checksum 0x264a), build 1f3a9c02, BlastEm 0.6.3, captured 2026-10-15 12:34:56 UTC", with the known fields
+    pub fn description(&self) -> String {
+        let mut parts = Vec::new();
+        match (&self.rom_name, self.rom_checksum) {
+            (Some(rom_name), Some(checksum)) => parts.push(format!("{} (checksum {:#06x})", rom_name, checksum)),
+            (Some(rom_name), None) => parts.push(rom_name.clone()),
+            (None, Some(checksum)) => parts.push(format!("ROM checksum {:#06x}", checksum)),
+            (None, None) => {},
+        }
+        parts.extend(self.build_id.as_deref().map(|build_id| format!("build {}", format_build_id(build_id))));
+        parts.extend(self.emulator.clone());
+        parts.extend(self.captured_at.map(|time| format!("captured {}", format_unix_time(time))));
+        parts.join(", ")
+    }
+}
+
+// in hex, like the build ids of the GNU tools
+pub fn format_build_id(build_id: &[u8]) -> String {
+    build_id.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// NUL or space padded text field of the header
+fn header_string(bytes: &[u8]) -> Option<String> {
+    let end = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+    let string = String::from_utf8_lossy(&bytes[..end]).trim().to_string();
+    (!string.is_empty()).then_some(string)
+}
+
+// "YYYY-MM-DD HH:MM:SS UTC"
+fn format_unix_time(time: u64) -> String {
+    let (days, seconds) = (time / 86400, time % 86400);
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+pub struct ParsedProfilingFile {
+    pub packets: Vec<ProfilingPacket>,
+    pub mclk: f64,
+    pub m68k_divider: u64,
+    // the packets of the master and slave SH2s of the 32X, each with its own clock and timeline, see Sh2Cpu
+    pub sh2_packets: [Vec<ProfilingPacket>; 2],
+    // of the SH2s, which the cycles of their packets are counted in, see sh2_clock()
+    pub sh2_clock: Option<f64>,
+    // the packets of the sub CPU of the Mega CD, on its own timeline counted in cycles of its master clock
+    pub sub_cpu_packets: Vec<ProfilingPacket>,
+    // see sub_cpu_mclk() and sub_cpu_divider()
+    pub sub_cpu_mclk: Option<f64>,
+    pub sub_cpu_divider: Option<u64>,
+    // the clocks recorded in the header for each ClockDomain, see domain_clock()
+    pub domain_clocks: [DomainClock; ClockDomain::ALL.len()],
+    // the packets timed by another clock than the one of the 68000, until resolve_clock_domains() moves them to packets
+    pub domain_packets: Vec<(ClockDomain, ProfilingPacket)>,
+    // hash of the symbol file of the build that was running when the capture was made, if the emulator recorded it
+    pub symbol_file_hash: Option<u64>,
+    pub capture_info: CaptureInfo,
+    // how the exits of the 68000 are matched with the subroutines they return from, see --exit-matching
+    pub exit_matching: ExitMatching,
+}
+
+impl ParsedProfilingFile {
+    // (cycle, path) of the savestates made during the capture
+    pub fn savestates(&self) -> Vec<(u64, &str)> {
+        self.packets.iter().filter_map(|packet| match &packet.inner {
+            ProfilingPacketInner::Savestate { path } => Some((packet.cycle, path.as_str())),
+            _ => None,
+        }).collect()
+    }
+
+    // a zero or absurd clock would turn every timestamp into garbage, NaN or infinity
+    pub fn check_clocks(&self) -> Result<(), String> {
+        if !(MIN_MCLK..=MAX_MCLK).contains(&self.mclk) {
+            return Err(format!("The capture says the master clock runs at {} Hz, which can't be right, use --mclk to give the right one (53693175 for NTSC, 53203424 for PAL)", self.mclk));
+        }
+        if !(1..=MAX_M68K_DIVIDER).contains(&self.m68k_divider) {
+            return Err(format!("The capture says the 68000 runs at the master clock divided by {}, which can't be right, use --m68k-divider to give the right one (7)", self.m68k_divider));
+        }
+        if self.sh2_packets.iter().any(|packets| !packets.is_empty()) && !(MIN_MCLK..=MAX_MCLK).contains(&self.sh2_clock()) {
+            return Err(format!("The capture says the SH2s run at {} Hz, which can't be right", self.sh2_clock()));
+        }
+        if !self.sub_cpu_packets.is_empty() {
+            if !(MIN_MCLK..=MAX_MCLK).contains(&self.sub_cpu_mclk()) {
+                return Err(format!("The capture says the Mega CD clock runs at {} Hz, which can't be right", self.sub_cpu_mclk()));
+            }
+            if !(1..=MAX_M68K_DIVIDER).contains(&self.sub_cpu_divider()) {
+                return Err(format!("The capture says the sub CPU runs at the Mega CD clock divided by {}, which can't be right", self.sub_cpu_divider()));
+            }
+        }
+        for domain in ClockDomain::ALL.iter().copied().filter(|&domain| self.domain_packets.iter().any(|&(packet_domain, _)| packet_domain == domain)) {
+            let (clock, divider) = self.domain_clock(domain);
+            if !(MIN_MCLK..=MAX_MCLK).contains(&clock) {
+                return Err(format!("The capture says the {} clock runs at {} Hz, which can't be right", domain.name(), clock));
+            }
+            if !(1..=MAX_M68K_DIVIDER).contains(&divider) {
+                return Err(format!("The capture says the {} counts its cycles at its clock divided by {}, which can't be right", domain.name(), divider));
+            }
+        }
+        Ok(())
+    }
+
+    // moves the packets timed by another clock to the timeline of the 68000, in the order of their master clock cycle,
+    // once the clocks are known
+    pub fn resolve_clock_domains(&mut self) {
+        if self.domain_packets.is_empty() {
+            return;
+        }
+        let domain_packets = std::mem::take(&mut self.domain_packets);
+        for (domain, mut packet) in domain_packets {
+            packet.cycle = self.domain_cycle_to_mclk(domain, packet.cycle);
+            self.packets.push(packet);
+        }
+        // stable, the packets of a cycle stay in file order
+        self.packets.sort_by_key(|packet| packet.cycle);
+    }
+
+    // makes the PCs, stack pointers and allocated addresses of the 68000 canonical, returns the number of packets changed
+    pub fn canonicalize_addresses(&mut self, address_map: &AddressMap) -> usize {
+        let mut changed = 0;
+        for packet in &mut self.packets {
+            let stack_pointer = address_map.canonical_stack_pointer(packet.stack_pointer);
+            let mut packet_changed = stack_pointer != packet.stack_pointer;
+            packet.stack_pointer = stack_pointer;
+            match &mut packet.inner {
+                ProfilingPacketInner::SubroutineEnter { target_subroutine: address, .. }
+                | ProfilingPacketInner::TailCall { target_subroutine: address }
+                | ProfilingPacketInner::InterruptEnter { target_interrupt: address }
+                | ProfilingPacketInner::ManualBreakpoint { pc: address }
+                | ProfilingPacketInner::PcSample { pc: address }
+                | ProfilingPacketInner::TrapEnter { handler: address, .. }
+                | ProfilingPacketInner::Exception { pc: address, .. }
+                | ProfilingPacketInner::Alloc { address, .. }
+                | ProfilingPacketInner::Free { address }
+                | ProfilingPacketInner::MemoryWatch { address, .. } => {
+                    let canonical = address_map.canonical(*address);
+                    packet_changed |= canonical != *address;
+                    *address = canonical;
+                },
+                _ => {},
+            }
+            if packet_changed {
+                changed += 1;
+            }
+        }
+        changed
+    }
+
+    // makes the calls to the functions merged into another one (see Symbols::merged_functions) calls to that one, so the reports
+    // walking the calls count them as a single function, returns the number of packets changed
+    pub fn merge_functions(&mut self, merged: &HashMap<u32, u32>) -> usize {
+        let mut changed = 0;
+        for packet in &mut self.packets {
+            match &mut packet.inner {
+                ProfilingPacketInner::SubroutineEnter { target_subroutine: address, .. }
+                | ProfilingPacketInner::TailCall { target_subroutine: address }
+                | ProfilingPacketInner::InterruptEnter { target_interrupt: address }
+                | ProfilingPacketInner::TrapEnter { handler: address, .. } => {
+                    if let Some(&function) = merged.get(address) {
+                        *address = function;
+                        changed += 1;
+                    }
+                },
+                _ => {},
+            }
+        }
+        changed
+    }
+
+    // the PCs of a game using the SSF2 mapper depend on the banks mapped when they were reached, they're translated to
+    // ROM addresses (bank * 512KB + offset in the window), which the symbols refer to, and the bank switches are dropped
+    // so the packets can be cut or written back without losing the mapping
+    pub fn resolve_banked_addresses(&mut self) {
+        if !self.packets.iter().any(|packet| matches!(packet.inner, ProfilingPacketInner::BankSwitch { .. })) {
+            return;
+        }
+        let mut banks: [u32; SSF2_WINDOWS] = std::array::from_fn(|window| window as u32);
+        for packet in &mut self.packets {
+            match &mut packet.inner {
+                ProfilingPacketInner::BankSwitch { window, bank } => {
+                    match banks.get_mut(*window as usize) {
+                        Some(mapped_bank) if *window != 0 => *mapped_bank = *bank as u32,
+                        _ => eprintln!("Warning: bank switch of the SSF2 window {} at cycle {}, which doesn't exist or can't be switched, ignored", window, packet.cycle),
+                    }
+                },
+                ProfilingPacketInner::SubroutineEnter { target_subroutine: address, .. }
+                | ProfilingPacketInner::TailCall { target_subroutine: address }
+                | ProfilingPacketInner::InterruptEnter { target_interrupt: address }
+                | ProfilingPacketInner::ManualBreakpoint { pc: address }
+                | ProfilingPacketInner::PcSample { pc: address }
+                | ProfilingPacketInner::TrapEnter { handler: address, .. }
+                | ProfilingPacketInner::Exception { pc: address, .. } => {
+                    if let Some(&bank) = banks.get((*address >> SSF2_WINDOW_BITS) as usize) {
+                        *address = bank << SSF2_WINDOW_BITS | *address & ((1 << SSF2_WINDOW_BITS) - 1);
+                    }
+                },
+                _ => {},
+            }
+        }
+        self.packets.retain(|packet| !matches!(packet.inner, ProfilingPacketInner::BankSwitch { .. }));
+    }
+
+    // removes the cycles spent by the instrumentation from the timeline of the 68000: the cycles of every packet are moved back
+    // by the overhead of the packets before it, so the durations are the ones of the game without the instrumentation,
+    // an overhead larger than the time until the next packet only removes that time, returns the master clock cycles removed
+    pub fn subtract_overhead(&mut self, model: &OverheadModel) -> u64 {
+        let mut overhead = 0;
+        let mut removed = 0;
+        let mut last_cycle = 0;
+        for packet in &mut self.packets {
+            let cycle = packet.cycle.saturating_sub(overhead).max(last_cycle);
+            removed = packet.cycle - cycle;
+            overhead = removed;
+            packet.cycle = cycle;
+            last_cycle = cycle;
+            if let Some(&cycles) = OverheadModel::kind(&packet.inner).and_then(|kind| model.cycles.get(kind)) {
+                overhead += cycles * self.m68k_divider;
+            }
+        }
+        removed
+    }
+
+    // replaces the string table indices of the user events with their text, the string of index N being line N of the table
+    pub fn resolve_user_strings(&mut self, strings: &[String]) {
+        let mut unknown_indices = BTreeMap::new();
+        for packet in &mut self.packets {
+            if let ProfilingPacketInner::UserEventIndex { kind, index } = packet.inner {
+                match strings.get(index as usize) {
+                    Some(text) => packet.inner = ProfilingPacketInner::UserEvent { kind, text: text.clone() },
+                    None => *unknown_indices.entry(index).or_insert(0) += 1,
+                }
+            }
+        }
+        for (index, count) in unknown_indices {
+            eprintln!("Warning: the string table has no string {}, used by {} user events", index, count);
+        }
+    }
+
+    // appends the packets of a capture made with the same clocks, moved later if needed so they start after the last
+    // packet of this one, returns how many master clock cycles they were moved by
+    pub fn append(&mut self, mut other: ParsedProfilingFile) -> Result<u64, String> {
+        if other.mclk != self.mclk || other.m68k_divider != self.m68k_divider {
+            return Err(format!("The master clock and 68000 divider of the captures don't match ({} Hz / {} and {} Hz / {})", self.mclk, self.m68k_divider, other.mclk, other.m68k_divider));
+        }
+        if other.sh2_clock() != self.sh2_clock() || other.sub_cpu_mclk() != self.sub_cpu_mclk() || other.sub_cpu_divider() != self.sub_cpu_divider() {
+            return Err("The SH2 or Mega CD clocks of the captures don't match".to_string());
+        }
+        if other.symbol_file_hash != self.symbol_file_hash {
+            eprintln!("Warning: the captures were made with different builds, the symbols only match one of them");
+        }
+        let shift = match (self.packets.last(), other.packets.first()) {
+            (Some(last), Some(first)) => (last.cycle + 1).saturating_sub(first.cycle),
+            _ => 0,
+        };
+        let sh2_shift = self.mclk_to_domain_cycle(ClockDomain::Sh2, shift);
+        let sub_cpu_shift = (shift as f64 * self.sub_cpu_mclk() / self.mclk).round() as u64;
+        for packet in &mut other.packets {
+            packet.cycle += shift;
+        }
+        self.packets.append(&mut other.packets);
+        for (packets, other_packets) in self.sh2_packets.iter_mut().zip(&mut other.sh2_packets) {
+            for packet in other_packets.iter_mut() {
+                packet.cycle += sh2_shift;
+            }
+            packets.append(other_packets);
+        }
+        for packet in &mut other.sub_cpu_packets {
+            packet.cycle += sub_cpu_shift;
+        }
+        self.sub_cpu_packets.append(&mut other.sub_cpu_packets);
+        Ok(shift)
+    }
+
+    // the same, but the subroutines and interrupt handler running at the start of the range are entered again on its first
+    // cycle so their slices aren't lost, returns how many were
+    pub fn trim_cycle_range(&mut self, from: Option<u64>, to: Option<u64>) -> usize {
+        let running = from.map_or_else(Vec::new, |from| self.running_frames(from));
+        self.retain_cycle_range(from, to);
+        let count = running.len();
+        self.packets.splice(..0, running);
+        count
+    }
+
+    // the cycle of the exit (or tail call) returning from the subroutine (or TRAP handler) entered by this packet,
+    // None if it's still running at the end of the capture
+    fn subroutine_end(&self, index: usize, task_stacks: &TaskStacks) -> Option<u64> {
+        let task = task_stacks.task(self.packets[index].stack_pointer);
+        // the subroutine, and the ones entered since
+        let mut stack = CallStack::new(self.exit_matching);
+        if let Some((_subroutine, stack_pointer)) = self.packets[index].call_stack_change().and_then(|change| change.enter) {
+            stack.enter((), stack_pointer);
+        }
+        // the returns of the other tasks are on other stacks
+        let packets = self.packets[index + 1..].iter().filter(|packet| task_stacks.is_empty() || task_stacks.task(packet.stack_pointer) == task);
+        for packet in packets {
+            if let Some(change) = packet.call_stack_change() {
+                if let Some(exit_stack_pointer) = change.exit {
+                    stack.exit(exit_stack_pointer).for_each(drop);
+                    if stack.is_empty() {
+                        return Some(packet.cycle);
+                    }
+                }
+                if let Some((_subroutine, stack_pointer)) = change.enter {
+                    stack.enter((), stack_pointer);
+                }
+            }
+        }
+        None
+    }
+
+    // the packets entering the subroutines and interrupt handler running at this cycle, as if they were entered on it,
+    // outermost first: the ones of the main code, then the interrupt handler and the ones it called
+    fn running_frames(&self, cycle: u64) -> Vec<ProfilingPacket> {
+        let mut main_stack: CallStack<ProfilingPacket> = CallStack::new(self.exit_matching);
+        let mut interrupt_stack: CallStack<ProfilingPacket> = CallStack::new(self.exit_matching);
+        let mut in_interrupt = false;
+        for packet in self.packets.iter().take_while(|packet| packet.cycle < cycle) {
+            let stack = if in_interrupt { &mut interrupt_stack } else { &mut main_stack };
+            if let Some(change) = packet.call_stack_change() {
+                if let Some(exit_stack_pointer) = change.exit {
+                    stack.exit(exit_stack_pointer).for_each(drop);
+                }
+                if let Some((target_subroutine, stack_pointer)) = change.enter {
+                    let inner = match &packet.inner {
+                        ProfilingPacketInner::SubroutineEnter { registers, .. } => ProfilingPacketInner::SubroutineEnter { target_subroutine, registers: registers.clone() },
+                        &ProfilingPacketInner::TrapEnter { vector, handler } => ProfilingPacketInner::TrapEnter { vector, handler },
+                        // the target of a tail call
+                        _ => ProfilingPacketInner::SubroutineEnter { target_subroutine, registers: Vec::new() },
+                    };
+                    stack.enter(ProfilingPacket { cycle, stack_pointer, inner }, stack_pointer);
+                }
+            }
+            match &packet.inner {
+                ProfilingPacketInner::InterruptEnter { target_interrupt } => {
+                    in_interrupt = true;
+                    interrupt_stack.clear();
+                    interrupt_stack.enter(ProfilingPacket {
+                        cycle,
+                        stack_pointer: packet.stack_pointer,
+                        inner: ProfilingPacketInner::InterruptEnter { target_interrupt: *target_interrupt },
+                    }, packet.stack_pointer);
+                },
+                ProfilingPacketInner::InterruptExit => {
+                    in_interrupt = false;
+                    interrupt_stack.clear();
+                },
+                _ => {},
+            }
+        }
+        main_stack.into_iter().chain(interrupt_stack).map(|(packet, _stack_pointer)| packet).collect()
+    }
+
+    // drops the packets outside of the [from, to] cycle range, slices that were open at the cut are lost
+    pub fn retain_cycle_range(&mut self, from: Option<u64>, to: Option<u64>) {
+        retain_packet_range(&mut self.packets, from, to);
+        let to_sh2_cycle = |cycle: u64| self.mclk_to_domain_cycle(ClockDomain::Sh2, cycle);
+        let (sh2_from, sh2_to) = (from.map(to_sh2_cycle), to.map(to_sh2_cycle));
+        for packets in &mut self.sh2_packets {
+            retain_packet_range(packets, sh2_from, sh2_to);
+        }
+        let to_sub_cpu_cycle = |cycle: u64| (cycle as f64 * self.sub_cpu_mclk() / self.mclk).round() as u64;
+        let (sub_cpu_from, sub_cpu_to) = (from.map(to_sub_cpu_cycle), to.map(to_sub_cpu_cycle));
+        retain_packet_range(&mut self.sub_cpu_packets, sub_cpu_from, sub_cpu_to);
+    }
+
+    // the SH2s of NTSC and PAL consoles run at 3/7 of the master clock, unless the emulator recorded otherwise
+    pub fn sh2_clock(&self) -> f64 {
+        self.sh2_clock.unwrap_or(self.mclk * 3.0 / 7.0)
+    }
+
+    // the master clock cycle of this cycle of the SH2s, both clocks count from the power on
+    pub fn sh2_cycle_to_mclk(&self, cycle: u64) -> u64 {
+        self.domain_cycle_to_mclk(ClockDomain::Sh2, cycle)
+    }
+
+    // (clock in Hz, divider) of a clock domain, its cycles are counted at the clock divided by the divider,
+    // the header only records the ones that differ from the defaults of the MegaDrive
+    pub fn domain_clock(&self, domain: ClockDomain) -> (f64, u64) {
+        let (clock, divider) = match domain {
+            ClockDomain::M68k => (self.mclk, self.m68k_divider),
+            ClockDomain::Z80 => (self.mclk, DEFAULT_Z80_DIVIDER),
+            ClockDomain::Vdp => (self.mclk, DEFAULT_VDP_DIVIDER),
+            ClockDomain::Sh2 => (self.sh2_clock(), 1),
+        };
+        let recorded = self.domain_clocks[domain.index()];
+        (recorded.clock.unwrap_or(clock), recorded.divider.unwrap_or(divider))
+    }
+
+    // the master clock cycle of this cycle of a clock domain, every clock counts from the power on
+    pub fn domain_cycle_to_mclk(&self, domain: ClockDomain, cycle: u64) -> u64 {
+        let (clock, divider) = self.domain_clock(domain);
+        (cycle as f64 * divider as f64 * self.mclk / clock).round() as u64
+    }
+
+    // the cycle of a clock domain at this master clock cycle
+    pub fn mclk_to_domain_cycle(&self, domain: ClockDomain, cycle: u64) -> u64 {
+        let (clock, divider) = self.domain_clock(domain);
+        (cycle as f64 * clock / (divider as f64 * self.mclk)).round() as u64
+    }
+
+    // the timestamp of this cycle of a clock domain, the common timeline of every part of the console
+    pub fn domain_cycle_to_us(&self, domain: ClockDomain, cycle: u64) -> f64 {
+        cycle_to_us(self.domain_cycle_to_mclk(domain, cycle), self.mclk)
+    }
+
+    pub fn sub_cpu_mclk(&self) -> f64 {
+        self.sub_cpu_mclk.unwrap_or(DEFAULT_SUB_CPU_MCLK)
+    }
+
+    pub fn sub_cpu_divider(&self) -> u64 {
+        self.sub_cpu_divider.unwrap_or(DEFAULT_SUB_CPU_DIVIDER)
+    }
+
+    // the master clock cycle of the MegaDrive of this cycle of the Mega CD clock, both count from the power on
+    pub fn sub_cpu_cycle_to_mclk(&self, cycle: u64) -> u64 {
+        (cycle as f64 * self.mclk / self.sub_cpu_mclk()).round() as u64
+    }
+}
+
+fn retain_packet_range(packets: &mut Vec<ProfilingPacket>, from: Option<u64>, to: Option<u64>) {
+    if let Some(to) = to {
+        let end = packets.partition_point(|packet| packet.cycle <= to);
+        packets.truncate(end);
+    }
+    if let Some(from) = from {
+        let start = packets.partition_point(|packet| packet.cycle < from);
+        packets.drain(..start);
+    }
+}
+
+// the SH2s of the 32X run the same kinds of code as the 68000, their packets are kept apart as they have their own clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sh2Cpu {
+    Master,
+    Slave,
+}
+
+impl Sh2Cpu {
+    pub const ALL: [Sh2Cpu; 2] = [Sh2Cpu::Master, Sh2Cpu::Slave];
+
+    fn from_byte(byte: u8) -> Result<Sh2Cpu, String> {
+        match byte {
+            0 => Ok(Sh2Cpu::Master),
+            1 => Ok(Sh2Cpu::Slave),
+            x => Err(format!("Unknown SH2: {}", x)),
+        }
+    }
+
+    // index in ParsedProfilingFile::sh2_packets, and the byte of the packets
+    pub fn index(self) -> usize {
+        match self {
+            Sh2Cpu::Master => 0,
+            Sh2Cpu::Slave => 1,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Sh2Cpu::Master => "SH2 master",
+            Sh2Cpu::Slave => "SH2 slave",
+        }
+    }
+
+    pub fn pid(self) -> u32 {
+        match self {
+            Sh2Cpu::Master => SH2_MASTER_PID,
+            Sh2Cpu::Slave => SH2_SLAVE_PID,
+        }
+    }
+}
+
+// the clocks the cycles of the packets can be counted in: besides the ones of the 68000, an emulator can time the packets
+// of the 68000 timeline with the clock of the part of the console that caused them, see PROFILER_PACKET_CLOCK_DOMAIN
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockDomain {
+    M68k,
+    Z80,
+    Vdp,
+    Sh2,
+}
+
+impl ClockDomain {
+    pub const ALL: [ClockDomain; 4] = [ClockDomain::M68k, ClockDomain::Z80, ClockDomain::Vdp, ClockDomain::Sh2];
+
+    fn from_byte(byte: u8) -> Result<ClockDomain, String> {
+        ClockDomain::ALL.get(byte as usize).copied().ok_or_else(|| format!("Unknown clock domain: {}", byte))
+    }
+
+    // index in ParsedProfilingFile::domain_clocks and in the header, and the byte of the packets
+    pub fn index(self) -> usize {
+        match self {
+            ClockDomain::M68k => 0,
+            ClockDomain::Z80 => 1,
+            ClockDomain::Vdp => 2,
+            ClockDomain::Sh2 => 3,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ClockDomain::M68k => "68000",
+            ClockDomain::Z80 => "Z80",
+            ClockDomain::Vdp => "VDP",
+            ClockDomain::Sh2 => "SH2",
+        }
+    }
+}
+
+// the clock of a domain as recorded in the header, None for the fields the emulator left zeroed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DomainClock {
+    pub clock: Option<f64>,
+    pub divider: Option<u64>,
+}
+
+impl DomainClock {
+    fn read(header: &[u8], domain: ClockDomain) -> DomainClock {
+        let start = HEADER_CLOCK_DOMAINS + domain.index() * 8;
+        DomainClock {
+            clock: Some(u32::from_le_bytes(header[start..start + 4].try_into().unwrap())).filter(|&clock| clock != 0).map(|clock| clock as f64),
+            divider: Some(u32::from_le_bytes(header[start + 4..start + 8].try_into().unwrap())).filter(|&divider| divider != 0).map(|divider| divider as u64),
+        }
+    }
+
+    fn write(self, header: &mut [u8], domain: ClockDomain) {
+        let start = HEADER_CLOCK_DOMAINS + domain.index() * 8;
+        header[start..start + 4].copy_from_slice(&(self.clock.unwrap_or(0.0) as u32).to_le_bytes());
+        header[start + 4..start + 8].copy_from_slice(&(self.divider.unwrap_or(0) as u32).to_le_bytes());
+    }
+}
+
+pub fn cycle_to_us(cycle: u64, mclk: f64) -> f64 {
+    cycle as f64 / mclk * 1_000_000.0
+}
+
+pub fn us_to_cycle(us: f64, mclk: f64) -> u64 {
+    (us * mclk / 1_000_000.0).round() as u64
+}
+
+// parses a point in time such as "1500", "1500ms", "1.5s", "250us" or "80000000cycles", milliseconds if there is no unit
+pub fn parse_time(time: &str, mclk: f64) -> Result<u64, String> {
+    let time = time.trim();
+    let split_at = time.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(time.len());
+    let (value, unit) = time.split_at(split_at);
+    let value = value.trim();
+    let error = || format!("Invalid time: {}, expected a number followed by s, ms, us or cycles", time);
+    if unit == "cycles" {
+        return value.parse::<u64>().map_err(|_| error());
+    }
+    let value: f64 = value.parse().map_err(|_| error())?;
+    if !value.is_finite() || value < 0.0 {
+        return Err(error());
+    }
+    let us = match unit {
+        "s" => value * 1_000_000.0,
+        "ms" | "" => value * 1000.0,
+        "us" => value,
+        _ => return Err(error()),
+    };
+    Ok(us_to_cycle(us, mclk))
+}
+
+// size in bytes of the packet at the start of this buffer, None if the buffer doesn't hold all of it yet
+pub fn packet_len(input: &[u8]) -> Result<Option<usize>, String> {
+    // type, cycle and stack pointer
+    const COMMON_LEN: usize = 9;
+    let packet_type = match input.first() {
+        Some(&packet_type) => packet_type,
+        None => return Ok(None),
+    };
+    let payload = input.get(COMMON_LEN..).unwrap_or_default();
+    let len = match packet_type {
+        // the SH2 and the type of the wrapped packet, then its payload
+        PROFILER_PACKET_SH2 => match payload.get(1) {
+            Some(&inner_type) => 2 + code_flow_payload_len(inner_type)?,
+            None => return Ok(None),
+        },
+        // the type of the wrapped packet, then its payload
+        PROFILER_PACKET_SUB_CPU => match payload.first() {
+            Some(&inner_type) => 1 + code_flow_payload_len(inner_type)?,
+            None => return Ok(None),
+        },
+        // the clock domain and the type of the wrapped packet, then its payload
+        PROFILER_PACKET_CLOCK_DOMAIN => match payload.get(1) {
+            Some(&inner_type) => match clock_domain_payload_len(inner_type, &payload[2..])? {
+                Some(len) => 2 + len,
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        },
+        packet_type => match payload_len(packet_type, payload)? {
+            Some(len) => len,
+            None => return Ok(None),
+        },
+    };
+    Ok((input.len() >= COMMON_LEN + len).then_some(COMMON_LEN + len))
+}
+
+// size in bytes of the payload of a packet of this type, None if the buffer doesn't hold enough of it to tell
+fn payload_len(packet_type: u8, payload: &[u8]) -> Result<Option<usize>, String> {
+    let len = match packet_type {
+        PROFILER_PACKET_SUBROUTINE_ENTER
+        | PROFILER_PACKET_INTERRUPT_ENTER
+        | PROFILER_PACKET_MANUAL_BREAKPOINT
+        | PROFILER_PACKET_TAIL_CALL
+        | PROFILER_PACKET_PC_SAMPLE => 4,
+        PROFILER_PACKET_SUBROUTINE_EXIT
+        | PROFILER_PACKET_INTERRUPT_EXIT
+        | PROFILER_PACKET_HINT
+        | PROFILER_PACKET_VINT
+        | PROFILER_PACKET_ADJUST_CYCLES
+        | PROFILER_PACKET_DMA_END
+        | PROFILER_PACKET_Z80_BUS_REQUEST
+        | PROFILER_PACKET_Z80_BUS_RELEASE
+        | PROFILER_PACKET_TRAP_EXIT => 0,
+        PROFILER_PACKET_VDP_REGISTER_WRITE
+        | PROFILER_PACKET_BANK_SWITCH => 2,
+        PROFILER_PACKET_WORD_RAM => 1,
+        PROFILER_PACKET_SOUND_COMMAND => 3,
+        PROFILER_PACKET_ALLOC => 10,
+        PROFILER_PACKET_FREE => 4,
+        PROFILER_PACKET_USER_EVENT => match payload.get(1..3) {
+            Some(text_len) => 3 + u16::from_le_bytes(text_len.try_into().unwrap()) as usize,
+            None => return Ok(None),
+        },
+        PROFILER_PACKET_USER_EVENT_INDEX => 3,
+        // the target, the mask of the recorded registers, then their values
+        PROFILER_PACKET_SUBROUTINE_ENTER_REGISTERS => match payload.get(4..6) {
+            Some(mask) => 6 + 4 * u16::from_le_bytes(mask.try_into().unwrap()).count_ones() as usize,
+            None => return Ok(None),
+        },
+        PROFILER_PACKET_SAVESTATE => match payload.get(..2) {
+            Some(path_len) => 2 + u16::from_le_bytes(path_len.try_into().unwrap()) as usize,
+            None => return Ok(None),
+        },
+        PROFILER_PACKET_DMA => 8,
+        PROFILER_PACKET_DMA_START => 13,
+        PROFILER_PACKET_TRAP_ENTER
+        | PROFILER_PACKET_EXCEPTION => 5,
+        PROFILER_PACKET_MEMORY_WATCH => 9,
+        x => return Err(format!("Unknown packet type: {}", x)),
+    };
+    Ok(Some(len))
+}
+
+// a clock domain packet wraps any packet of the 68000 timeline, but the ones changing how the cycles are counted
+fn clock_domain_payload_len(packet_type: u8, payload: &[u8]) -> Result<Option<usize>, String> {
+    match packet_type {
+        PROFILER_PACKET_ADJUST_CYCLES
+        | PROFILER_PACKET_SH2
+        | PROFILER_PACKET_SUB_CPU
+        | PROFILER_PACKET_CLOCK_DOMAIN => Err(format!("Packets of type {} can't be in a clock domain packet", packet_type)),
+        packet_type => payload_len(packet_type, payload),
+    }
+}
+
+// the SH2 and sub CPU packets only wrap the packets of the code flow
+fn code_flow_payload_len(packet_type: u8) -> Result<usize, String> {
+    match packet_type {
+        PROFILER_PACKET_SUBROUTINE_ENTER
+        | PROFILER_PACKET_INTERRUPT_ENTER
+        | PROFILER_PACKET_MANUAL_BREAKPOINT
+        | PROFILER_PACKET_TAIL_CALL => Ok(4),
+        PROFILER_PACKET_SUBROUTINE_EXIT
+        | PROFILER_PACKET_INTERRUPT_EXIT => Ok(0),
+        x => Err(format!("Unknown SH2 or sub CPU packet type: {}", x)),
+    }
+}
+
+// the payload of a packet of this type, i is moved past it
+fn read_packet_inner(packet_type: u8, input: &[u8], i: &mut usize) -> Result<ProfilingPacketInner, String> {
+    Ok(match packet_type {
+        PROFILER_PACKET_SUBROUTINE_ENTER => {
+            let target_subroutine = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+            *i += 4;
+            ProfilingPacketInner::SubroutineEnter {
+                target_subroutine,
+                registers: Vec::new()
+            }
+        },
+        PROFILER_PACKET_SUBROUTINE_ENTER_REGISTERS => {
+            let target_subroutine = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+            *i += 4;
+            let mask = u16::from_le_bytes(input[*i..*i+2].try_into().unwrap());
+            *i += 2;
+            let registers = (0..16).filter(|register| mask & 1 << register != 0).map(|register| {
+                let value = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+                *i += 4;
+                (register, value)
+            }).collect();
+            ProfilingPacketInner::SubroutineEnter {
+                target_subroutine,
+                registers
+            }
+        },
+        PROFILER_PACKET_SUBROUTINE_EXIT => ProfilingPacketInner::SubroutineExit,
+        PROFILER_PACKET_INTERRUPT_ENTER => {
+            let target_interrupt = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+            *i += 4;
+            ProfilingPacketInner::InterruptEnter {
+                target_interrupt
+            }
+        },
+        PROFILER_PACKET_INTERRUPT_EXIT => ProfilingPacketInner::InterruptExit,
+        PROFILER_PACKET_HINT => ProfilingPacketInner::HInt,
+        PROFILER_PACKET_VINT => ProfilingPacketInner::VInt,
+        PROFILER_PACKET_MANUAL_BREAKPOINT => {
+            let pc = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+            *i += 4;
+            ProfilingPacketInner::ManualBreakpoint {
+                pc
+            }
+        }
+        PROFILER_PACKET_SAVESTATE => {
+            let path_len = u16::from_le_bytes(input[*i..*i+2].try_into().unwrap()) as usize;
+            *i += 2;
+            let path = String::from_utf8_lossy(&input[*i..*i+path_len]).into_owned();
+            *i += path_len;
+            ProfilingPacketInner::Savestate {
+                path
+            }
+        }
+        PROFILER_PACKET_DMA => {
+            let source = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+            *i += 4;
+            let halted_cycles = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+            *i += 4;
+            ProfilingPacketInner::Dma {
+                source,
+                halted_cycles
+            }
+        }
+        PROFILER_PACKET_TAIL_CALL => {
+            let target_subroutine = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+            *i += 4;
+            ProfilingPacketInner::TailCall {
+                target_subroutine
+            }
+        }
+        PROFILER_PACKET_DMA_START => {
+            let source = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+            *i += 4;
+            let destination = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+            *i += 4;
+            let length = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+            *i += 4;
+            let dma_type = DmaType::from_byte(input[*i])?;
+            *i += 1;
+            ProfilingPacketInner::DmaStart {
+                source,
+                destination,
+                length,
+                dma_type
+            }
+        }
+        PROFILER_PACKET_DMA_END => ProfilingPacketInner::DmaEnd,
+        PROFILER_PACKET_VDP_REGISTER_WRITE => {
+            let register = input[*i];
+            let value = input[*i+1];
+            *i += 2;
+            ProfilingPacketInner::VdpRegisterWrite {
+                register,
+                value
+            }
+        }
+        PROFILER_PACKET_Z80_BUS_REQUEST => ProfilingPacketInner::Z80BusRequest,
+        PROFILER_PACKET_Z80_BUS_RELEASE => ProfilingPacketInner::Z80BusRelease,
+        PROFILER_PACKET_BANK_SWITCH => {
+            let window = input[*i];
+            let bank = input[*i+1];
+            *i += 2;
+            ProfilingPacketInner::BankSwitch {
+                window,
+                bank
+            }
+        }
+        PROFILER_PACKET_WORD_RAM => {
+            let mode = WordRamMode::from_byte(input[*i])?;
+            *i += 1;
+            ProfilingPacketInner::WordRam {
+                mode
+            }
+        }
+        PROFILER_PACKET_SOUND_COMMAND => {
+            let command = SoundCommand::from_byte(input[*i]);
+            let argument = u16::from_le_bytes(input[*i+1..*i+3].try_into().unwrap());
+            *i += 3;
+            ProfilingPacketInner::SoundCommand {
+                command,
+                argument
+            }
+        }
+        PROFILER_PACKET_ALLOC => {
+            let address = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+            *i += 4;
+            let size = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+            *i += 4;
+            let tag = u16::from_le_bytes(input[*i..*i+2].try_into().unwrap());
+            *i += 2;
+            ProfilingPacketInner::Alloc {
+                address,
+                size,
+                tag
+            }
+        }
+        PROFILER_PACKET_FREE => {
+            let address = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+            *i += 4;
+            ProfilingPacketInner::Free {
+                address
+            }
+        }
+        PROFILER_PACKET_USER_EVENT => {
+            let kind = UserEventKind::from_byte(input[*i])?;
+            let text_len = u16::from_le_bytes(input[*i+1..*i+3].try_into().unwrap()) as usize;
+            *i += 3;
+            let text = String::from_utf8_lossy(&input[*i..*i+text_len]).into_owned();
+            *i += text_len;
+            ProfilingPacketInner::UserEvent {
+                kind,
+                text
+            }
+        }
+        PROFILER_PACKET_PC_SAMPLE => {
+            let pc = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+            *i += 4;
+            ProfilingPacketInner::PcSample {
+                pc
+            }
+        }
+        PROFILER_PACKET_USER_EVENT_INDEX => {
+            let kind = UserEventKind::from_byte(input[*i])?;
+            let index = u16::from_le_bytes(input[*i+1..*i+3].try_into().unwrap());
+            *i += 3;
+            ProfilingPacketInner::UserEventIndex {
+                kind,
+                index
+            }
+        }
+        PROFILER_PACKET_TRAP_ENTER => {
+            let vector = input[*i];
+            let handler = u32::from_le_bytes(input[*i+1..*i+5].try_into().unwrap());
+            *i += 5;
+            ProfilingPacketInner::TrapEnter {
+                vector,
+                handler
+            }
+        }
+        PROFILER_PACKET_TRAP_EXIT => ProfilingPacketInner::TrapExit,
+        PROFILER_PACKET_EXCEPTION => {
+            let exception = CpuException::from_byte(input[*i]);
+            let pc = u32::from_le_bytes(input[*i+1..*i+5].try_into().unwrap());
+            *i += 5;
+            ProfilingPacketInner::Exception {
+                exception,
+                pc
+            }
+        }
+        PROFILER_PACKET_MEMORY_WATCH => {
+            let address = u32::from_le_bytes(input[*i..*i+4].try_into().unwrap());
+            let size = input[*i+4];
+            let value = u32::from_le_bytes(input[*i+5..*i+9].try_into().unwrap());
+            *i += 9;
+            ProfilingPacketInner::MemoryWatch {
+                address,
+                size,
+                value
+            }
+        }
+        x => return Err(format!("Unknown packet type: {}", x)),
+    })
+}
+
+// checks the magic bytes and the format version at the start of a capture, and that its header is complete
+pub fn check_header(input: &[u8]) -> Result<(), String> {
+    if !input.starts_with(b"MDP") {
+        return Err("Not an MDP file: it doesn't start with \"MDP\"".to_string());
+    }
+    match input.get(3) {
+        Some(&version) if version > MDP_VERSION => {
+            return Err(format!("This file is from a newer mdp format (v{}) than the one this version of md-profiler reads (v{}), please upgrade md-profiler", version, MDP_VERSION));
+        },
+        Some(&version) if version < MDP_VERSION => return Err(format!("Unknown mdp format version v{}", version)),
+        _ => {},
+    }
+    if input.len() < MDP_HEADER_SIZE {
+        return Err(format!("This file is only {} bytes long, it doesn't even contain a complete header ({} bytes), the capture was probably aborted", input.len(), MDP_HEADER_SIZE));
+    }
+    Ok(())
+}
+
+pub fn read_profiling_file(input: &[u8]) -> Result<ParsedProfilingFile, String> {
+    read_profiling_stream(input, input.len() as u64)
+}
+
+// reads a capture a chunk at a time as it comes from a file or a pipe, size is only used for the progress, 0 when unknown
+pub fn read_profiling_stream(mut reader: impl Read, size: u64) -> Result<ParsedProfilingFile, String> {
+    let mut header = Vec::with_capacity(MDP_HEADER_SIZE);
+    reader.by_ref().take(MDP_HEADER_SIZE as u64).read_to_end(&mut header).map_err(|err| format!("Error reading the capture: {}", err))?;
+    check_header(&header)?;
+    let mut packets = Vec::new();
+    let mut cycle_offset = 0;
+    let mclk = u32::from_le_bytes(header[4..8].try_into().unwrap()) as f64;
+    let m68k_divider = u32::from_le_bytes(header[8..12].try_into().unwrap()) as u64;
+    let symbol_file_hash = match u64::from_le_bytes(header[12..20].try_into().unwrap()) {
+        0 => None,
+        hash => Some(hash),
+    };
+    let sh2_clock = match u32::from_le_bytes(header[HEADER_SH2_CLOCK].try_into().unwrap()) {
+        0 => None,
+        sh2_clock => Some(sh2_clock as f64),
+    };
+    let sub_cpu_mclk = match u32::from_le_bytes(header[HEADER_SUB_CPU_MCLK].try_into().unwrap()) {
+        0 => None,
+        sub_cpu_mclk => Some(sub_cpu_mclk as f64),
+    };
+    let sub_cpu_divider = match u32::from_le_bytes(header[HEADER_SUB_CPU_DIVIDER].try_into().unwrap()) {
+        0 => None,
+        sub_cpu_divider => Some(sub_cpu_divider as u64),
+    };
+    let capture_info = CaptureInfo::read(&header);
+    let domain_clocks = ClockDomain::ALL.map(|domain| DomainClock::read(&header, domain));
+    let mut sh2_packets: [Vec<ProfilingPacket>; 2] = Default::default();
+    // (last 32 bit cycle, cycles before it wrapped around) of each SH2
+    let mut sh2_cycles = [(0u32, 0u64); 2];
+    let mut sub_cpu_packets = Vec::new();
+    // the same for the sub CPU
+    let mut sub_cpu_cycles = (0u32, 0u64);
+    let mut domain_packets = Vec::new();
+    // and each clock domain
+    let mut domain_cycles = [(0u32, 0u64); ClockDomain::ALL.len()];
+    let mut buffer = Vec::new();
+    let mut chunk = vec![0; 64 * 1024];
+    let mut bytes_read = MDP_HEADER_SIZE as u64;
+    let mut progress = Progress::new("Parsing", "bytes", size);
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(format!("Error reading the capture: {}", err)),
+        };
+        buffer.extend_from_slice(&chunk[..read]);
+        bytes_read += read as u64;
+        progress.set(bytes_read);
+        let mut complete = 0;
+        while let Some(len) = packet_len(&buffer[complete..])? {
+            let input = &buffer[complete..complete + len];
+            complete += len;
+            let mut i = 0;
+            let packet_type = input[i];
+            i += 1;
+            let cycle32 = u32::from_le_bytes(input[i..i+4].try_into().unwrap());
+            i += 4;
+            let cycle = cycle_offset + cycle32 as u64;
+            let stack_pointer = u32::from_le_bytes(input[i..i+4].try_into().unwrap());
+            i += 4;
+            let inner = match packet_type {
+                PROFILER_PACKET_ADJUST_CYCLES => {
+                    cycle_offset += cycle32 as u64;
+                    continue;
+                },
+                PROFILER_PACKET_SH2 => {
+                    let cpu = Sh2Cpu::from_byte(input[i])?;
+                    let inner_type = input[i + 1];
+                    i += 2;
+                    code_flow_payload_len(inner_type)?;
+                    let inner = read_packet_inner(inner_type, input, &mut i)?;
+                    // each SH2 counts its own cycles in 32 bits, they only go backwards when they wrap around
+                    let (last_cycle32, sh2_cycle_offset) = &mut sh2_cycles[cpu.index()];
+                    if cycle32 < *last_cycle32 {
+                        *sh2_cycle_offset += 1 << 32;
+                    }
+                    *last_cycle32 = cycle32;
+                    sh2_packets[cpu.index()].push(ProfilingPacket {
+                        cycle: *sh2_cycle_offset + cycle32 as u64,
+                        stack_pointer,
+                        inner,
+                    });
+                    continue;
+                },
+                PROFILER_PACKET_SUB_CPU => {
+                    let inner_type = input[i];
+                    i += 1;
+                    code_flow_payload_len(inner_type)?;
+                    let inner = read_packet_inner(inner_type, input, &mut i)?;
+                    let (last_cycle32, sub_cpu_cycle_offset) = &mut sub_cpu_cycles;
+                    if cycle32 < *last_cycle32 {
+                        *sub_cpu_cycle_offset += 1 << 32;
+                    }
+                    *last_cycle32 = cycle32;
+                    sub_cpu_packets.push(ProfilingPacket {
+                        cycle: *sub_cpu_cycle_offset + cycle32 as u64,
+                        stack_pointer,
+                        inner,
+                    });
+                    continue;
+                },
+                PROFILER_PACKET_CLOCK_DOMAIN => {
+                    let domain = ClockDomain::from_byte(input[i])?;
+                    let inner_type = input[i + 1];
+                    i += 2;
+                    clock_domain_payload_len(inner_type, &input[i..])?;
+                    let inner = read_packet_inner(inner_type, input, &mut i)?;
+                    let (last_cycle32, domain_cycle_offset) = &mut domain_cycles[domain.index()];
+                    if cycle32 < *last_cycle32 {
+                        *domain_cycle_offset += 1 << 32;
+                    }
+                    *last_cycle32 = cycle32;
+                    domain_packets.push((domain, ProfilingPacket {
+                        cycle: *domain_cycle_offset + cycle32 as u64,
+                        stack_pointer,
+                        inner,
+                    }));
+                    continue;
+                },
+                packet_type => read_packet_inner(packet_type, input, &mut i)?,
+            };
+            let packet = ProfilingPacket {
+                cycle,
+                stack_pointer,
+                inner
+            };
+            packets.push(packet);
+        }
+        buffer.drain(..complete);
+    }
+    if !buffer.is_empty() {
+        eprintln!("Warning: the capture ends in the middle of a packet, its last {} bytes were left out", buffer.len());
+    }
+    progress.set(bytes_read);
+    progress.finish();
+    Ok(ParsedProfilingFile {
+        packets,
+        mclk,
+        m68k_divider,
+        sh2_packets,
+        sh2_clock,
+        sub_cpu_packets,
+        sub_cpu_mclk,
+        sub_cpu_divider,
+        domain_clocks,
+        domain_packets,
+        symbol_file_hash,
+        capture_info,
+        exit_matching: ExitMatching::default(),
+    })
+}
+
+// timestamp and duration of a slice nested at this depth, see SLICE_NUDGE_CYCLES
+pub(crate) fn nudged_slice(start_cycle: u64, end_cycle: u64, depth: usize, mclk: f64) -> (f64, f64) {
+    let nudge = depth.min(MAX_NUDGE_DEPTH) as f64 * SLICE_NUDGE_CYCLES;
+    let duration = ((end_cycle - start_cycle) as f64 - 2.0 * nudge).max(0.0);
+    (
+        (start_cycle as f64 + nudge) / mclk * 1_000_000.0,
+        duration / mclk * 1_000_000.0,
+    )
+}
+
+// nesting depth of a slice starting at this cycle, given the end cycles of the slices still open on its thread
+fn slice_depth(open_slices: &mut Vec<u64>, start_cycle: u64, end_cycle: u64) -> usize {
+    while open_slices.last().is_some_and(|&open_end_cycle| open_end_cycle <= start_cycle) {
+        open_slices.pop();
+    }
+    let depth = open_slices.len();
+    open_slices.push(end_cycle);
+    depth
+}
+
+// writes the packets back in the mdp format, the cycle counts are rebuilt from the absolute cycles,
+// with ADJUST_CYCLES packets whenever they don't fit in 32 bits anymore
+pub fn write_profiling_file(output: impl Write, input: &ParsedProfilingFile) {
+    let mut buf_writer = BufWriter::new(output);
+    let mut header = [0; MDP_HEADER_SIZE];
+    header[..3].copy_from_slice(b"MDP");
+    header[3] = MDP_VERSION;
+    header[4..8].copy_from_slice(&(input.mclk as u32).to_le_bytes());
+    header[8..12].copy_from_slice(&(input.m68k_divider as u32).to_le_bytes());
+    header[12..20].copy_from_slice(&input.symbol_file_hash.unwrap_or(0).to_le_bytes());
+    header[HEADER_SH2_CLOCK].copy_from_slice(&(input.sh2_clock.unwrap_or(0.0) as u32).to_le_bytes());
+    header[HEADER_SUB_CPU_MCLK].copy_from_slice(&(input.sub_cpu_mclk.unwrap_or(0.0) as u32).to_le_bytes());
+    header[HEADER_SUB_CPU_DIVIDER].copy_from_slice(&(input.sub_cpu_divider.unwrap_or(0) as u32).to_le_bytes());
+    for domain in ClockDomain::ALL {
+        input.domain_clocks[domain.index()].write(&mut header, domain);
+    }
+    input.capture_info.write(&mut header);
+    buf_writer.write_all(&header).unwrap();
+    let mut cycle_offset = 0;
+    for packet in &input.packets {
+        while packet.cycle - cycle_offset > u32::MAX as u64 {
+            let adjustment = (packet.cycle - cycle_offset).min(u32::MAX as u64) as u32;
+            buf_writer.write_all(&[PROFILER_PACKET_ADJUST_CYCLES]).unwrap();
+            buf_writer.write_all(&adjustment.to_le_bytes()).unwrap();
+            buf_writer.write_all(&packet.stack_pointer.to_le_bytes()).unwrap();
+            cycle_offset += adjustment as u64;
+        }
+        buf_writer.write_all(&[packet_type(&packet.inner)]).unwrap();
+        buf_writer.write_all(&((packet.cycle - cycle_offset) as u32).to_le_bytes()).unwrap();
+        buf_writer.write_all(&packet.stack_pointer.to_le_bytes()).unwrap();
+        write_packet_payload(&mut buf_writer, &packet.inner);
+    }
+    // the SH2 and sub CPU packets after the ones of the 68000, they're on their own timelines,
+    // their cycles are written in 32 bits, the reader counts their wrap arounds
+    for cpu in Sh2Cpu::ALL {
+        for packet in &input.sh2_packets[cpu.index()] {
+            buf_writer.write_all(&[PROFILER_PACKET_SH2]).unwrap();
+            buf_writer.write_all(&(packet.cycle as u32).to_le_bytes()).unwrap();
+            buf_writer.write_all(&packet.stack_pointer.to_le_bytes()).unwrap();
+            buf_writer.write_all(&[cpu.index() as u8, packet_type(&packet.inner)]).unwrap();
+            write_packet_payload(&mut buf_writer, &packet.inner);
+        }
+    }
+    for packet in &input.sub_cpu_packets {
+        buf_writer.write_all(&[PROFILER_PACKET_SUB_CPU]).unwrap();
+        buf_writer.write_all(&(packet.cycle as u32).to_le_bytes()).unwrap();
+        buf_writer.write_all(&packet.stack_pointer.to_le_bytes()).unwrap();
+        buf_writer.write_all(&[packet_type(&packet.inner)]).unwrap();
+        write_packet_payload(&mut buf_writer, &packet.inner);
+    }
+    // and the ones not moved to the timeline of the 68000 yet, with the cycles of their clock
+    for (domain, packet) in &input.domain_packets {
+        buf_writer.write_all(&[PROFILER_PACKET_CLOCK_DOMAIN]).unwrap();
+        buf_writer.write_all(&(packet.cycle as u32).to_le_bytes()).unwrap();
+        buf_writer.write_all(&packet.stack_pointer.to_le_bytes()).unwrap();
+        buf_writer.write_all(&[domain.index() as u8, packet_type(&packet.inner)]).unwrap();
+        write_packet_payload(&mut buf_writer, &packet.inner);
+    }
+    buf_writer.flush().expect("Error writing mdp file");
+}
+
+// the type byte of the packets of this kind
+fn packet_type(inner: &ProfilingPacketInner) -> u8 {
+    match inner {
+        ProfilingPacketInner::SubroutineEnter { registers, .. } if !registers.is_empty() => PROFILER_PACKET_SUBROUTINE_ENTER_REGISTERS,
+        ProfilingPacketInner::SubroutineEnter { .. } => PROFILER_PACKET_SUBROUTINE_ENTER,
+        ProfilingPacketInner::SubroutineExit => PROFILER_PACKET_SUBROUTINE_EXIT,
+        ProfilingPacketInner::InterruptEnter { .. } => PROFILER_PACKET_INTERRUPT_ENTER,
+        ProfilingPacketInner::InterruptExit => PROFILER_PACKET_INTERRUPT_EXIT,
+        ProfilingPacketInner::HInt => PROFILER_PACKET_HINT,
+        ProfilingPacketInner::VInt => PROFILER_PACKET_VINT,
+        ProfilingPacketInner::ManualBreakpoint { .. } => PROFILER_PACKET_MANUAL_BREAKPOINT,
+        ProfilingPacketInner::Savestate { .. } => PROFILER_PACKET_SAVESTATE,
+        ProfilingPacketInner::Dma { .. } => PROFILER_PACKET_DMA,
+        ProfilingPacketInner::TailCall { .. } => PROFILER_PACKET_TAIL_CALL,
+        ProfilingPacketInner::DmaStart { .. } => PROFILER_PACKET_DMA_START,
+        ProfilingPacketInner::DmaEnd => PROFILER_PACKET_DMA_END,
+        ProfilingPacketInner::VdpRegisterWrite { .. } => PROFILER_PACKET_VDP_REGISTER_WRITE,
+        ProfilingPacketInner::Z80BusRequest => PROFILER_PACKET_Z80_BUS_REQUEST,
+        ProfilingPacketInner::Z80BusRelease => PROFILER_PACKET_Z80_BUS_RELEASE,
+        ProfilingPacketInner::BankSwitch { .. } => PROFILER_PACKET_BANK_SWITCH,
+        ProfilingPacketInner::WordRam { .. } => PROFILER_PACKET_WORD_RAM,
+        ProfilingPacketInner::SoundCommand { .. } => PROFILER_PACKET_SOUND_COMMAND,
+        ProfilingPacketInner::Alloc { .. } => PROFILER_PACKET_ALLOC,
+        ProfilingPacketInner::Free { .. } => PROFILER_PACKET_FREE,
+        ProfilingPacketInner::UserEvent { .. } => PROFILER_PACKET_USER_EVENT,
+        ProfilingPacketInner::UserEventIndex { .. } => PROFILER_PACKET_USER_EVENT_INDEX,
+        ProfilingPacketInner::PcSample { .. } => PROFILER_PACKET_PC_SAMPLE,
+        ProfilingPacketInner::TrapEnter { .. } => PROFILER_PACKET_TRAP_ENTER,
+        ProfilingPacketInner::TrapExit => PROFILER_PACKET_TRAP_EXIT,
+        ProfilingPacketInner::Exception { .. } => PROFILER_PACKET_EXCEPTION,
+        ProfilingPacketInner::MemoryWatch { .. } => PROFILER_PACKET_MEMORY_WATCH,
+    }
+}
+
+// what follows the cycle and the stack pointer, see read_packet_inner
+fn write_packet_payload(output: &mut impl Write, inner: &ProfilingPacketInner) {
+    match inner {
+        ProfilingPacketInner::SubroutineEnter { target_subroutine, registers } if !registers.is_empty() => {
+            output.write_all(&target_subroutine.to_le_bytes()).unwrap();
+            let mask = registers.iter().fold(0u16, |mask, &(register, _value)| mask | 1 << register);
+            output.write_all(&mask.to_le_bytes()).unwrap();
+            for (_register, value) in registers {
+                output.write_all(&value.to_le_bytes()).unwrap();
+            }
+        },
+        ProfilingPacketInner::SubroutineEnter { target_subroutine: address, .. }
+        | ProfilingPacketInner::TailCall { target_subroutine: address }
+        | ProfilingPacketInner::InterruptEnter { target_interrupt: address }
+        | ProfilingPacketInner::ManualBreakpoint { pc: address }
+        | ProfilingPacketInner::PcSample { pc: address } => {
+            output.write_all(&address.to_le_bytes()).unwrap();
+        },
+        ProfilingPacketInner::Savestate { path } => {
+            output.write_all(&(path.len() as u16).to_le_bytes()).unwrap();
+            output.write_all(path.as_bytes()).unwrap();
+        },
+        ProfilingPacketInner::Dma { source, halted_cycles } => {
+            output.write_all(&source.to_le_bytes()).unwrap();
+            output.write_all(&halted_cycles.to_le_bytes()).unwrap();
+        },
+        ProfilingPacketInner::DmaStart { source, destination, length, dma_type } => {
+            output.write_all(&source.to_le_bytes()).unwrap();
+            output.write_all(&destination.to_le_bytes()).unwrap();
+            output.write_all(&length.to_le_bytes()).unwrap();
+            output.write_all(&[dma_type.to_byte()]).unwrap();
+        },
+        ProfilingPacketInner::VdpRegisterWrite { register, value }
+        | ProfilingPacketInner::BankSwitch { window: register, bank: value } => {
+            output.write_all(&[*register, *value]).unwrap();
+        },
+        ProfilingPacketInner::WordRam { mode } => {
+            output.write_all(&[mode.to_byte()]).unwrap();
+        },
+        ProfilingPacketInner::SoundCommand { command, argument } => {
+            output.write_all(&[command.to_byte()]).unwrap();
+            output.write_all(&argument.to_le_bytes()).unwrap();
+        },
+        ProfilingPacketInner::Alloc { address, size, tag } => {
+            output.write_all(&address.to_le_bytes()).unwrap();
+            output.write_all(&size.to_le_bytes()).unwrap();
+            output.write_all(&tag.to_le_bytes()).unwrap();
+        },
+        ProfilingPacketInner::Free { address } => {
+            output.write_all(&address.to_le_bytes()).unwrap();
+        },
+        ProfilingPacketInner::UserEvent { kind, text } => {
+            output.write_all(&[kind.to_byte()]).unwrap();
+            output.write_all(&(text.len() as u16).to_le_bytes()).unwrap();
+            output.write_all(text.as_bytes()).unwrap();
+        },
+        ProfilingPacketInner::UserEventIndex { kind, index } => {
+            output.write_all(&[kind.to_byte()]).unwrap();
+            output.write_all(&index.to_le_bytes()).unwrap();
+        },
+        ProfilingPacketInner::TrapEnter { vector, handler } => {
+            output.write_all(&[*vector]).unwrap();
+            output.write_all(&handler.to_le_bytes()).unwrap();
+        },
+        ProfilingPacketInner::Exception { exception, pc } => {
+            output.write_all(&[exception.to_byte()]).unwrap();
+            output.write_all(&pc.to_le_bytes()).unwrap();
+        },
+        ProfilingPacketInner::MemoryWatch { address, size, value } => {
+            output.write_all(&address.to_le_bytes()).unwrap();
+            output.write_all(&[*size]).unwrap();
+            output.write_all(&value.to_le_bytes()).unwrap();
+        },
+        _ => {},
+    }
+}
 
-#[derive(Debug)]
-pub struct ProfilingPacket {
-    pub cycle: u64,
-    pub stack_pointer: u32,
-    pub inner: ProfilingPacketInner,
+// where a function is defined and which region it belongs to
+pub(crate) fn function_args(symbols: &Symbols, address: u32) -> Option<TraceEventArgs<'_>> {
+    let source = symbols.source_location(address);
+    let region = symbols.region(address);
+    (source.is_some() || region.is_some()).then(|| TraceEventArgs {
+        source: source.map(Into::into),
+        region: region.map(|region| region.name.as_str().into()),
+        ..Default::default()
+    })
 }
 
-#[derive(Debug)]
-pub enum ProfilingPacketInner {
-    SubroutineEnter { target_subroutine: u32 },
-    SubroutineExit,
-    InterruptEnter { target_interrupt: u32 },
-    InterruptExit,
-    HInt,
-    VInt,
-    ManualBreakpoint { pc: u32 },
+// function_args of an interrupt handler, along with how long it waited to be dispatched
+fn interrupt_args<'a>(input: &ParsedProfilingFile, index: usize, symbols: &'a Symbols, target_interrupt: u32) -> Option<TraceEventArgs<'a>> {
+    let mut args = function_args(symbols, target_interrupt);
+    if let Some(asserted) = asserted_interrupt(input, index) {
+        args.get_or_insert_with(Default::default).latency_cycles = Some(input.packets[index].cycle.saturating_sub(asserted.cycle));
+    }
+    args
 }
 
-#[derive(Debug, Serialize)]
-pub struct TraceEventArgs {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    name: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    sort_index: Option<u32>
+// the interrupt handlers of a capture, to tell how often and how long they preempted each slice of the main code
+struct Preemptions {
+    // cycles the interrupts were entered at, nested ones included
+    enters: Vec<u64>,
+    // (enter, exit) cycles of the outermost handlers, in order
+    spans: Vec<(u64, u64)>,
+    // cycles of the spans before each one
+    preceding_cycles: Vec<u64>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct TraceEvent<'a> {
-    pub name: Cow<'a, str>,
-    pub ph: char,
-    pub ts: f64,
-    pub dur: f64,
-    pub pid: u32,
-    pub tid: u32,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub args: Option<TraceEventArgs>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub s: Option<char>
+impl Preemptions {
+    fn new(input: &ParsedProfilingFile, last_cycle: u64) -> Preemptions {
+        let mut preemptions = Preemptions { enters: Vec::new(), spans: Vec::new(), preceding_cycles: Vec::new() };
+        let mut entered_at = None;
+        for packet in &input.packets {
+            match packet.inner {
+                ProfilingPacketInner::InterruptEnter { .. } => {
+                    preemptions.enters.push(packet.cycle);
+                    entered_at.get_or_insert(packet.cycle);
+                },
+                ProfilingPacketInner::InterruptExit => if let Some(enter_cycle) = entered_at.take() {
+                    preemptions.spans.push((enter_cycle, packet.cycle));
+                },
+                _ => {},
+            }
+        }
+        preemptions.spans.extend(entered_at.map(|enter_cycle| (enter_cycle, last_cycle)));
+        let mut cycles = 0;
+        for &(enter_cycle, exit_cycle) in &preemptions.spans {
+            preemptions.preceding_cycles.push(cycles);
+            cycles += exit_cycle - enter_cycle;
+        }
+        preemptions.preceding_cycles.push(cycles);
+        preemptions
+    }
+
+    // the interrupts taken from start_cycle to end_cycle, and the cycles their handlers took in that range
+    fn between(&self, start_cycle: u64, end_cycle: u64) -> (u64, u64) {
+        let interrupts = self.enters.partition_point(|&cycle| cycle < end_cycle) - self.enters.partition_point(|&cycle| cycle < start_cycle);
+        let first = self.spans.partition_point(|&(_enter_cycle, exit_cycle)| exit_cycle <= start_cycle);
+        let last = self.spans.partition_point(|&(enter_cycle, _exit_cycle)| enter_cycle < end_cycle);
+        if first >= last {
+            return (interrupts as u64, 0);
+        }
+        // the spans running across the ends of the range only count inside it
+        let cycles = self.preceding_cycles[last] - self.preceding_cycles[first]
+            - start_cycle.saturating_sub(self.spans[first].0)
+            - self.spans[last - 1].1.saturating_sub(end_cycle);
+        (interrupts as u64, cycles)
+    }
+
+    // adds the preemptions of a slice of the main code to its args, when it was preempted
+    fn annotate(&self, args: &mut Option<TraceEventArgs>, start_cycle: u64, end_cycle: u64) {
+        let (interrupts, cycles) = self.between(start_cycle, end_cycle);
+        if interrupts != 0 {
+            let args = args.get_or_insert_with(Default::default);
+            args.interrupts = Some(interrupts);
+            args.preempted_cycles = Some(cycles);
+        }
+    }
 }
 
+// the 68000 cycles the instrumentation of the game spends on each kind of packet,
+// the kinds being the names of --overhead
+#[derive(Debug, Clone, Default)]
+pub struct OverheadModel {
+    cycles: HashMap<&'static str, u64>,
+}
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ProfilingJson<'a> {
-    trace_events: Vec<TraceEvent<'a>>,
-    display_time_unit: &'a str,
+impl OverheadModel {
+    const KINDS: [&'static str; 14] = ["enter", "exit", "tail-call", "interrupt-enter", "interrupt-exit", "breakpoint", "user-event", "sound-command", "alloc", "free", "pc-sample", "trap-enter", "trap-exit", "exception"];
+
+    fn kind(inner: &ProfilingPacketInner) -> Option<&'static str> {
+        match inner {
+            ProfilingPacketInner::SubroutineEnter { .. } => Some("enter"),
+            ProfilingPacketInner::SubroutineExit => Some("exit"),
+            ProfilingPacketInner::TailCall { .. } => Some("tail-call"),
+            ProfilingPacketInner::InterruptEnter { .. } => Some("interrupt-enter"),
+            ProfilingPacketInner::InterruptExit => Some("interrupt-exit"),
+            ProfilingPacketInner::ManualBreakpoint { .. } => Some("breakpoint"),
+            ProfilingPacketInner::UserEvent { .. } | ProfilingPacketInner::UserEventIndex { .. } => Some("user-event"),
+            ProfilingPacketInner::SoundCommand { .. } => Some("sound-command"),
+            ProfilingPacketInner::Alloc { .. } => Some("alloc"),
+            ProfilingPacketInner::Free { .. } => Some("free"),
+            ProfilingPacketInner::PcSample { .. } => Some("pc-sample"),
+            ProfilingPacketInner::TrapEnter { .. } => Some("trap-enter"),
+            ProfilingPacketInner::TrapExit => Some("trap-exit"),
+            ProfilingPacketInner::Exception { .. } => Some("exception"),
+            _ => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cycles.is_empty()
+    }
+
+    // "KIND=CYCLES", CYCLES being 68000 cycles
+    pub fn configure(&mut self, spec: &str) -> Result<(), String> {
+        let error = || format!("Invalid overhead: {}, expected KIND=CYCLES with KIND one of {}", spec, OverheadModel::KINDS.join(", "));
+        let (kind, cycles) = spec.split_once('=').ok_or_else(error)?;
+        let kind = OverheadModel::KINDS.iter().find(|&&known_kind| known_kind == kind.trim()).ok_or_else(error)?;
+        let cycles = cycles.trim().parse().map_err(|_| error())?;
+        self.cycles.insert(kind, cycles);
+        Ok(())
+    }
 }
 
-pub struct ParsedProfilingFile {
-    pub packets: Vec<ProfilingPacket>,
-    pub mclk: f64,
-    pub m68k_divider: u64,
+// the stack frame popped by an RTR (condition codes and return address) or by an RTE (status register and return address)
+const RTR_FRAME_SIZE: u32 = 6;
+
+// how the exits are matched with the running subroutines they return from, the interrupt handlers always end with their interrupt exit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitMatching {
+    // an exit returns from the subroutines entered with a stack pointer at most this many bytes above its own, 4 being
+    // the return address the RTS (or the JMP of a tail call) has yet to pop, or exactly an RTR or RTE frame above it
+    StackPointer { slack: u32 },
+    // an exit returns from the innermost running subroutine whatever the stack pointer is, like on the SH2s,
+    // for calling conventions juggling with the stack
+    Innermost,
 }
 
-pub fn cycle_to_us(cycle: u64, mclk: f64) -> f64 {
-    cycle as f64 / mclk * 1_000_000.0
+impl Default for ExitMatching {
+    fn default() -> Self {
+        ExitMatching::StackPointer { slack: 4 }
+    }
 }
 
-pub fn read_profiling_file(input: &[u8]) -> ParsedProfilingFile {
-    let mut packets = Vec::new();
-    let mut cycle_offset = 0;
-    let version = input[3];
-    if version != MDP_VERSION {
-        eprintln!("Warning: this file is using mdp file format version {} but this application is using version {}", version, MDP_VERSION);
-    }
-    let mclk = u32::from_ne_bytes(input[4..8].try_into().unwrap()) as f64;
-    let m68k_divider = u32::from_ne_bytes(input[8..12].try_into().unwrap()) as u64;
-    // advance past the header
-    let mut i = 256;
-    while i < input.len() {
-        let packet_type = input[i];
-        i += 1;
-        let cycle32 = u32::from_ne_bytes(input[i..i+4].try_into().unwrap());
-        i += 4;
-        let cycle = cycle_offset + cycle32 as u64;
-        let stack_pointer = u32::from_ne_bytes(input[i..i+4].try_into().unwrap());
-        i += 4;
-        let inner = match packet_type {
-            PROFILER_PACKET_SUBROUTINE_ENTER => {
-                let target_subroutine = u32::from_ne_bytes(input[i..i+4].try_into().unwrap());
-                i += 4;
-                ProfilingPacketInner::SubroutineEnter {
-                    target_subroutine
-                }
+impl ExitMatching {
+    // "stack", "stack:SLACK" (in bytes) or "innermost"
+    pub fn parse(spec: &str) -> Result<ExitMatching, String> {
+        let error = || format!("Invalid exit matching: {}, expected stack, stack:SLACK (in bytes) or innermost", spec);
+        match spec.split_once(':') {
+            None if spec == "stack" => Ok(ExitMatching::default()),
+            None if spec == "innermost" => Ok(ExitMatching::Innermost),
+            Some(("stack", slack)) => slack.trim().parse().map(|slack| ExitMatching::StackPointer { slack }).map_err(|_| error()),
+            _ => Err(error()),
+        }
+    }
+
+    // whether an exit with this stack pointer returns from a subroutine entered with that one
+    pub fn returns(&self, exit_stack_pointer: u32, entered_stack_pointer: u32) -> bool {
+        match *self {
+            ExitMatching::StackPointer { slack } => {
+                exit_stack_pointer.wrapping_add(slack) >= entered_stack_pointer
+                    || exit_stack_pointer.wrapping_add(RTR_FRAME_SIZE) == entered_stack_pointer
             },
-            PROFILER_PACKET_SUBROUTINE_EXIT => ProfilingPacketInner::SubroutineExit,
-            PROFILER_PACKET_INTERRUPT_ENTER => {
-                let target_interrupt = u32::from_ne_bytes(input[i..i+4].try_into().unwrap());
-                i += 4;
-                ProfilingPacketInner::InterruptEnter {
-                    target_interrupt
-                }
+            ExitMatching::Innermost => true,
+        }
+    }
+
+    // how many of the running subroutines, given by the stack pointers they were entered with from the innermost,
+    // an exit with this stack pointer returns from
+    pub fn returning(&self, exit_stack_pointer: u32, entered_stack_pointers: impl Iterator<Item = u32>) -> usize {
+        let returning = entered_stack_pointers.take_while(|&entered_stack_pointer| self.returns(exit_stack_pointer, entered_stack_pointer)).count();
+        match self {
+            ExitMatching::StackPointer { .. } => returning,
+            ExitMatching::Innermost => returning.min(1),
+        }
+    }
+}
+
+// what a subroutine or TRAP packet does to the call stack: an exit returns from the subroutines its stack pointer says,
+// an enter pushes one, and the JMP of a tail call does both
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallStackChange {
+    // the stack pointer of the exit
+    pub exit: Option<u32>,
+    // the subroutine (or TRAP handler) entered, and the stack pointer it's entered with
+    pub enter: Option<(u32, u32)>,
+}
+
+impl ProfilingPacket {
+    pub fn call_stack_change(&self) -> Option<CallStackChange> {
+        match self.inner {
+            // a TRAP handler counts as a subroutine called by the code making the TRAP
+            ProfilingPacketInner::SubroutineEnter { target_subroutine, .. } | ProfilingPacketInner::TrapEnter { handler: target_subroutine, .. } => {
+                Some(CallStackChange { exit: None, enter: Some((target_subroutine, self.stack_pointer)) })
             },
-            PROFILER_PACKET_INTERRUPT_EXIT => ProfilingPacketInner::InterruptExit,
-            PROFILER_PACKET_HINT => ProfilingPacketInner::HInt,
-            PROFILER_PACKET_VINT => ProfilingPacketInner::VInt,
-            PROFILER_PACKET_ADJUST_CYCLES => {
-                cycle_offset += cycle32 as u64;
-                continue;
+            ProfilingPacketInner::SubroutineExit | ProfilingPacketInner::TrapExit => Some(CallStackChange { exit: Some(self.stack_pointer), enter: None }),
+            // the target is entered as if called by the caller of the subroutine that jumps, with its stack pointer
+            ProfilingPacketInner::TailCall { target_subroutine } => {
+                Some(CallStackChange { exit: Some(self.stack_pointer), enter: Some((target_subroutine, self.stack_pointer + 4)) })
             },
-            PROFILER_PACKET_MANUAL_BREAKPOINT => {
-                let pc = u32::from_ne_bytes(input[i..i+4].try_into().unwrap());
-                i += 4;
-                ProfilingPacketInner::ManualBreakpoint {
-                    pc
-                }
+            _ => None,
+        }
+    }
+}
+
+// the subroutines running in the main code or in an interrupt handler, outermost first, with what the walker keeps about
+// each one and the stack pointer it was entered with
+#[derive(Debug, Clone)]
+pub struct CallStack<T> {
+    exit_matching: ExitMatching,
+    running: Vec<(T, u32)>,
+}
+
+impl<T> CallStack<T> {
+    pub fn new(exit_matching: ExitMatching) -> CallStack<T> {
+        CallStack { exit_matching, running: Vec::new() }
+    }
+
+    pub fn enter(&mut self, subroutine: T, stack_pointer: u32) {
+        self.running.push((subroutine, stack_pointer));
+    }
+
+    // how many of the running subroutines an exit with this stack pointer returns from
+    pub fn returning(&self, exit_stack_pointer: u32) -> usize {
+        self.exit_matching.returning(exit_stack_pointer, self.running.iter().rev().map(|&(_, stack_pointer)| stack_pointer))
+    }
+
+    // pops the subroutines an exit with this stack pointer returns from, innermost first
+    pub fn exit(&mut self, exit_stack_pointer: u32) -> impl Iterator<Item = (T, u32)> + '_ {
+        let returned = self.running.len() - self.returning(exit_stack_pointer);
+        self.running.drain(returned..).rev()
+    }
+
+    pub fn pop(&mut self) -> Option<(T, u32)> {
+        self.running.pop()
+    }
+
+    pub fn clear(&mut self) {
+        self.running.clear();
+    }
+
+    // follows a subroutine or TRAP packet, for the walkers that only need to know what's running
+    pub fn follow(&mut self, packet: &ProfilingPacket, entered: impl FnOnce(u32) -> T) {
+        if let Some(change) = packet.call_stack_change() {
+            if let Some(exit_stack_pointer) = change.exit {
+                self.exit(exit_stack_pointer).for_each(drop);
+            }
+            if let Some((subroutine, stack_pointer)) = change.enter {
+                self.enter(entered(subroutine), stack_pointer);
             }
-            x => panic!("Unknown packet type: {}", x)
+        }
+    }
+}
+
+impl<T> Default for CallStack<T> {
+    fn default() -> CallStack<T> {
+        CallStack::new(ExitMatching::default())
+    }
+}
+
+impl<T> IntoIterator for CallStack<T> {
+    type Item = (T, u32);
+    type IntoIter = std::vec::IntoIter<(T, u32)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.running.into_iter()
+    }
+}
+
+impl<T> std::ops::Deref for CallStack<T> {
+    type Target = [(T, u32)];
+
+    fn deref(&self) -> &[(T, u32)] {
+        &self.running
+    }
+}
+
+impl<T> std::ops::DerefMut for CallStack<T> {
+    fn deref_mut(&mut self) -> &mut [(T, u32)] {
+        &mut self.running
+    }
+}
+
+// a range of addresses mirroring another one, such as the work RAM repeated every 64KB from $E00000
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressMirror {
+    start: u32,
+    end: u32,
+    base: u32,
+    size: u32,
+}
+
+impl AddressMirror {
+    pub const WORK_RAM: AddressMirror = AddressMirror { start: 0xE00000, end: 0xFEFFFF, base: 0xFF0000, size: 0x10000 };
+
+    // "START-END=BASE-BASE_END" in hex, the range START-END repeating BASE-BASE_END
+    pub fn parse(spec: &str) -> Result<AddressMirror, String> {
+        let error = || format!("Invalid mirror: {}, expected START-END=BASE-BASE_END in hex, such as E00000-FEFFFF=FF0000-FFFFFF", spec);
+        let range = |range: &str| -> Option<(u32, u32)> {
+            let (start, end) = range.split_once('-')?;
+            let start = u32::from_str_radix(start.trim().trim_start_matches('$'), 16).ok()?;
+            let end = u32::from_str_radix(end.trim().trim_start_matches('$'), 16).ok()?;
+            (start <= end).then_some((start, end))
+        };
+        let (mirror, base) = spec.split_once('=').ok_or_else(error)?;
+        let (start, end) = range(mirror).ok_or_else(error)?;
+        let (base, base_end) = range(base).ok_or_else(error)?;
+        Ok(AddressMirror { start, end, base, size: base_end - base + 1 })
+    }
+
+    fn canonical(&self, address: u32) -> Option<u32> {
+        (self.start..=self.end).contains(&address).then(|| self.base + (address - self.start) % self.size)
+    }
+}
+
+// the canonical addresses of the 68000: the bits above its 24 bit address bus are dropped, then the mirrors are moved
+// to the range they repeat, so a PC or a stack pointer reported in a mirror is the same as the one it mirrors
+#[derive(Debug, Clone)]
+pub struct AddressMap {
+    pub mask: u32,
+    pub mirrors: Vec<AddressMirror>,
+}
+
+impl Default for AddressMap {
+    fn default() -> Self {
+        AddressMap { mask: 0xFFFFFF, mirrors: vec![AddressMirror::WORK_RAM] }
+    }
+}
+
+impl AddressMap {
+    pub fn canonical(&self, address: u32) -> u32 {
+        let address = address & self.mask;
+        self.mirrors.iter().find_map(|mirror| mirror.canonical(address)).unwrap_or(address)
+    }
+
+    // the stack pointer points above the top of the stack, it's the address of the byte below that is canonical,
+    // so an empty stack at $1000000 stays above the work RAM instead of wrapping around to 0
+    pub fn canonical_stack_pointer(&self, stack_pointer: u32) -> u32 {
+        self.canonical(stack_pointer.wrapping_sub(1)).wrapping_add(1)
+    }
+}
+
+// the packets drawn as instant markers, see MarkerStyles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkerKind {
+    VInt,
+    HInt,
+    Savestate,
+    // manual breakpoints that aren't part of any interval
+    Breakpoint,
+    VdpRegisterWrite,
+}
+
+impl MarkerKind {
+    pub fn parse(kind: &str) -> Result<MarkerKind, String> {
+        match kind {
+            "vint" => Ok(MarkerKind::VInt),
+            "hint" => Ok(MarkerKind::HInt),
+            "savestate" => Ok(MarkerKind::Savestate),
+            "breakpoint" => Ok(MarkerKind::Breakpoint),
+            "vdp-register" => Ok(MarkerKind::VdpRegisterWrite),
+            _ => Err(format!("Unknown marker: {}, expected vint, hint, savestate, breakpoint or vdp-register", kind)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MarkerStyle {
+    // 'g' (global), 'p' (process) or 't' (thread), not drawn if None
+    pub scope: Option<char>,
+    // one of the color names reserved by the trace viewers, their default color if None
+    pub color: Option<String>,
+}
+
+impl MarkerStyle {
+    fn new(scope: char) -> MarkerStyle {
+        MarkerStyle {
+            scope: Some(scope),
+            color: None,
+        }
+    }
+
+    fn cname<'a>(&self) -> Option<Cow<'a, str>> {
+        self.color.clone().map(Cow::Owned)
+    }
+}
+
+// how each kind of marker is drawn, VInts and savestates span the whole trace by default,
+// the other markers stay on their thread and HInts aren't drawn
+#[derive(Debug, Clone)]
+pub struct MarkerStyles {
+    pub vint: MarkerStyle,
+    pub hint: MarkerStyle,
+    pub savestate: MarkerStyle,
+    pub breakpoint: MarkerStyle,
+    pub vdp_register_write: MarkerStyle,
+}
+
+impl Default for MarkerStyles {
+    fn default() -> MarkerStyles {
+        MarkerStyles {
+            vint: MarkerStyle::new('g'),
+            hint: MarkerStyle::default(),
+            savestate: MarkerStyle::new('g'),
+            breakpoint: MarkerStyle::new('t'),
+            vdp_register_write: MarkerStyle::new('t'),
+        }
+    }
+}
+
+impl MarkerStyles {
+    pub fn get_mut(&mut self, kind: MarkerKind) -> &mut MarkerStyle {
+        match kind {
+            MarkerKind::VInt => &mut self.vint,
+            MarkerKind::HInt => &mut self.hint,
+            MarkerKind::Savestate => &mut self.savestate,
+            MarkerKind::Breakpoint => &mut self.breakpoint,
+            MarkerKind::VdpRegisterWrite => &mut self.vdp_register_write,
+        }
+    }
+
+    // "KIND=off", "KIND=SCOPE" or "KIND=SCOPE:COLOR", SCOPE being global, process or thread
+    pub fn configure(&mut self, spec: &str) -> Result<(), String> {
+        let error = || format!("Invalid marker style: {}, expected KIND=off, KIND=SCOPE or KIND=SCOPE:COLOR", spec);
+        let (kind, style) = spec.split_once('=').ok_or_else(error)?;
+        let kind = MarkerKind::parse(kind.trim())?;
+        if style.trim() == "off" {
+            self.get_mut(kind).scope = None;
+            return Ok(());
+        }
+        let (scope, color) = match style.split_once(':') {
+            Some((scope, color)) => (scope, Some(color.trim().to_string())),
+            None => (style, None),
+        };
+        let scope = match scope.trim() {
+            "global" => 'g',
+            "process" => 'p',
+            "thread" => 't',
+            _ => return Err(error()),
         };
-        let packet = ProfilingPacket {
-            cycle,
-            stack_pointer,
-            inner
+        *self.get_mut(kind) = MarkerStyle {
+            scope: Some(scope),
+            color: color.filter(|color| !color.is_empty()),
         };
-        packets.push(packet);
+        Ok(())
     }
-    ParsedProfilingFile {
-        packets,
-        mclk,
-        m68k_divider
+}
+
+// the thread of the handler entered at this index: the handler of the last VInt or HInt asserted since the previous handler,
+// or an external interrupt if none was, everything goes to the Interrupts thread unless the interrupts are split
+pub(crate) fn interrupt_tid(input: &ParsedProfilingFile, index: usize, split_interrupts: bool) -> u32 {
+    if !split_interrupts {
+        return 1;
     }
+    match asserted_interrupt(input, index).map(|packet| &packet.inner) {
+        Some(ProfilingPacketInner::VInt) => 1,
+        Some(ProfilingPacketInner::HInt) => HINT_TID,
+        _ => EXTERNAL_INTERRUPT_TID,
+    }
+}
+
+// the VInt or HInt packet the handler entered at this index was dispatched for,
+// None for external interrupts, which have no packet of their own, and when another handler was dispatched since
+pub fn asserted_interrupt(input: &ParsedProfilingFile, index: usize) -> Option<&ProfilingPacket> {
+    input.packets[..index].iter().rev()
+        .find(|packet| matches!(packet.inner, ProfilingPacketInner::VInt | ProfilingPacketInner::HInt | ProfilingPacketInner::InterruptEnter { .. }))
+        .filter(|packet| !matches!(packet.inner, ProfilingPacketInner::InterruptEnter { .. }))
+}
+
+// a flow arrow from what was running when an interrupt was asserted (the packet at this index) to the start of its handler,
+// which is delayed while the interrupts are masked, no arrow if another interrupt is asserted before any handler runs
+pub fn interrupt_flow_events<'a>(input: &ParsedProfilingFile, index: usize, name: &'static str, split_interrupts: bool, task_stacks: &TaskStacks) -> Vec<TraceEvent<'a>> {
+    let handler = input.packets.iter().enumerate().skip(index + 1)
+        .take_while(|(_, packet)| !matches!(packet.inner, ProfilingPacketInner::VInt | ProfilingPacketInner::HInt))
+        .find(|(_, packet)| matches!(packet.inner, ProfilingPacketInner::InterruptEnter { .. }));
+    let (handler_index, handler) = match handler {
+        Some(handler) => handler,
+        None => return Vec::new(),
+    };
+    // None outside of interrupts, the main code then runs on the thread of its task
+    let running_interrupt = input.packets[..index].iter().enumerate().rev().find_map(|(i, packet)| match packet.inner {
+        ProfilingPacketInner::InterruptEnter { .. } => Some(Some(interrupt_tid(input, i, split_interrupts))),
+        ProfilingPacketInner::InterruptExit => Some(None),
+        _ => None,
+    }).flatten();
+    let running_tid = running_interrupt.unwrap_or_else(|| task_stacks.tid(input.packets[index].stack_pointer));
+    // the start binds to the slice running on its thread, and the end to the next slice of the handler's thread, the handler
+    [('s', input.packets[index].cycle, running_tid), ('f', handler.cycle, interrupt_tid(input, handler_index, split_interrupts))].iter().map(|&(ph, cycle, tid)| TraceEvent {
+        name: name.into(),
+        ph,
+        ts: cycle_to_us(cycle, input.mclk),
+        dur: 0.0,
+        pid: 0,
+        tid,
+        id: Some(index as u64),
+        cat: Some(CATEGORY_INTERRUPT.into()),
+        ..Default::default()
+    }).collect()
 }
 
-pub fn generate_profiling_json(mut output: &mut File, input: &ParsedProfilingFile, symbols: &Symbols, intervals: &mut Intervals, custom_threads: HashMap<String, u32>) {
+pub fn generate_trace_events<'a>(input: &ParsedProfilingFile, symbols: &'a Symbols, intervals: &mut Intervals, custom_threads: HashMap<String, u32>, markers: &MarkerStyles, split_interrupts: bool, task_stacks: &TaskStacks) -> Vec<TraceEvent<'a>> {
     let mut trace_events = vec![
         TraceEvent {
             name: "process_name".into(),
@@ -149,8 +2361,9 @@ pub fn generate_profiling_json(mut output: &mut File, input: &ParsedProfilingFil
             args: Some(TraceEventArgs {
                 name: Some("M68000".into()),
                 sort_index: None,
+                ..Default::default()
             }),
-            s: None,
+            ..Default::default()
         },
         TraceEvent {
             name: "thread_name".into(),
@@ -162,8 +2375,9 @@ pub fn generate_profiling_json(mut output: &mut File, input: &ParsedProfilingFil
             args: Some(TraceEventArgs {
                 name: Some("Main thread".into()),
                 sort_index: None,
+                ..Default::default()
             }),
-            s: None,
+            ..Default::default()
         },
         TraceEvent {
             name: "thread_name".into(),
@@ -173,10 +2387,11 @@ pub fn generate_profiling_json(mut output: &mut File, input: &ParsedProfilingFil
             pid: 0,
             tid: 1,
             args: Some(TraceEventArgs {
-                name: Some("Interrupts".into()),
+                name: Some(if split_interrupts { "VInt handler" } else { "Interrupts" }.into()),
                 sort_index: None,
+                ..Default::default()
             }),
-            s: None,
+            ..Default::default()
         },
         TraceEvent {
             name: "thread_sort_index".into(),
@@ -187,9 +2402,10 @@ pub fn generate_profiling_json(mut output: &mut File, input: &ParsedProfilingFil
             tid: 0,
             args: Some(TraceEventArgs {
                 name: None,
-                sort_index: Some(0)
+                sort_index: Some(0),
+                ..Default::default()
             }),
-            s: None,
+            ..Default::default()
         },
         TraceEvent {
             name: "thread_sort_index".into(),
@@ -200,77 +2416,315 @@ pub fn generate_profiling_json(mut output: &mut File, input: &ParsedProfilingFil
             tid: 1,
             args: Some(TraceEventArgs {
                 name: None,
-                sort_index: Some(1)
+                sort_index: Some(1),
+                ..Default::default()
             }),
-            s: None,
+            ..Default::default()
         },
     ];
+    // in tid order rather than the order of the map, so the same inputs always give the same trace
+    let mut custom_threads: Vec<_> = custom_threads.into_iter().collect();
+    custom_threads.sort_by_key(|&(_, tid)| tid);
     for (name, tid) in custom_threads {
         trace_events.push(
             TraceEvent {
-                name: "thread_name".into(),
+                name: "thread_name".into(),
+                ph: 'M',
+                ts: 0.0,
+                dur: 0.0,
+                pid: 0,
+                tid,
+                args: Some(TraceEventArgs {
+                    name: Some(name),
+                    sort_index: None,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+        trace_events.push(
+            TraceEvent {
+                name: "thread_sort_index".into(),
+                ph: 'M',
+                ts: 0.0,
+                dur: 0.0,
+                pid: 0,
+                tid,
+                args: Some(TraceEventArgs {
+                    name: None,
+                    sort_index: Some(tid),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        )
+    }
+    if input.packets.iter().any(|packet| matches!(packet.inner, ProfilingPacketInner::DmaStart { .. } | ProfilingPacketInner::VdpRegisterWrite { .. })) {
+        trace_events.push(
+            TraceEvent {
+                name: "thread_name".into(),
+                ph: 'M',
+                ts: 0.0,
+                dur: 0.0,
+                pid: 0,
+                tid: VDP_TID,
+                args: Some(TraceEventArgs {
+                    name: Some("VDP".into()),
+                    sort_index: None,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+        trace_events.push(
+            TraceEvent {
+                name: "thread_sort_index".into(),
+                ph: 'M',
+                ts: 0.0,
+                dur: 0.0,
+                pid: 0,
+                tid: VDP_TID,
+                args: Some(TraceEventArgs {
+                    name: None,
+                    sort_index: Some(VDP_TID),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        );
+    }
+    if input.packets.iter().any(|packet| matches!(packet.inner, ProfilingPacketInner::UserEvent { .. } | ProfilingPacketInner::UserEventIndex { .. })) {
+        trace_events.push(
+            TraceEvent {
+                name: "thread_name".into(),
+                ph: 'M',
+                ts: 0.0,
+                dur: 0.0,
+                pid: 0,
+                tid: USER_EVENTS_TID,
+                args: Some(TraceEventArgs {
+                    name: Some("User events".into()),
+                    sort_index: None,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+        trace_events.push(
+            TraceEvent {
+                name: "thread_sort_index".into(),
+                ph: 'M',
+                ts: 0.0,
+                dur: 0.0,
+                pid: 0,
+                tid: USER_EVENTS_TID,
+                args: Some(TraceEventArgs {
+                    name: None,
+                    sort_index: Some(USER_EVENTS_TID),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        );
+    }
+    if split_interrupts {
+        let handler_tids: HashSet<u32> = input.packets.iter().enumerate()
+            .filter(|(_, packet)| matches!(packet.inner, ProfilingPacketInner::InterruptEnter { .. }))
+            .map(|(i, _)| interrupt_tid(input, i, true))
+            .collect();
+        for (tid, name) in [(HINT_TID, "HInt handler"), (EXTERNAL_INTERRUPT_TID, "External")] {
+            if !handler_tids.contains(&tid) {
+                continue;
+            }
+            trace_events.push(
+                TraceEvent {
+                    name: "thread_name".into(),
+                    ph: 'M',
+                    ts: 0.0,
+                    dur: 0.0,
+                    pid: 0,
+                    tid,
+                    args: Some(TraceEventArgs {
+                        name: Some(name.into()),
+                        sort_index: None,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            );
+            // right below the VInt handlers
+            trace_events.push(
+                TraceEvent {
+                    name: "thread_sort_index".into(),
+                    ph: 'M',
+                    ts: 0.0,
+                    dur: 0.0,
+                    pid: 0,
+                    tid,
+                    args: Some(TraceEventArgs {
+                        name: None,
+                        sort_index: Some(1),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }
+            );
+        }
+    }
+    if !input.capture_info.is_empty() {
+        trace_events.push(
+            TraceEvent {
+                name: "process_labels".into(),
                 ph: 'M',
                 ts: 0.0,
                 dur: 0.0,
                 pid: 0,
-                tid,
+                tid: 0,
                 args: Some(TraceEventArgs {
-                    name: Some(name),
+                    labels: Some(input.capture_info.description()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+    }
+    let z80_bus_requests = input.packets.iter().any(|packet| matches!(packet.inner, ProfilingPacketInner::Z80BusRequest));
+    let sound_commands = input.packets.iter().any(|packet| matches!(packet.inner, ProfilingPacketInner::SoundCommand { .. }));
+    if z80_bus_requests || sound_commands {
+        trace_events.push(
+            TraceEvent {
+                name: "process_name".into(),
+                ph: 'M',
+                ts: 0.0,
+                dur: 0.0,
+                pid: Z80_PID,
+                tid: 0,
+                args: Some(TraceEventArgs {
+                    name: Some("Z80".into()),
                     sort_index: None,
+                    ..Default::default()
                 }),
-                s: None,
+                ..Default::default()
             },
         );
+    }
+    if z80_bus_requests {
         trace_events.push(
             TraceEvent {
-                name: "thread_sort_index".into(),
+                name: "thread_name".into(),
                 ph: 'M',
                 ts: 0.0,
                 dur: 0.0,
-                pid: 0,
-                tid,
+                pid: Z80_PID,
+                tid: 0,
                 args: Some(TraceEventArgs {
-                    name: None,
-                    sort_index: Some(tid)
+                    name: Some("Bus".into()),
+                    sort_index: None,
+                    ..Default::default()
                 }),
-                s: None,
-            }
-        )
+                ..Default::default()
+            },
+        );
+    }
+    if sound_commands {
+        trace_events.push(
+            TraceEvent {
+                name: "thread_name".into(),
+                ph: 'M',
+                ts: 0.0,
+                dur: 0.0,
+                pid: Z80_PID,
+                tid: SOUND_TID,
+                args: Some(TraceEventArgs {
+                    name: Some("Sound".into()),
+                    sort_index: None,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
     }
-    let last_cycle = input.packets.last().unwrap().cycle + 1;
+    let metadata_events = trace_events.len();
+    let last_cycle = input.packets.last().map_or(0, |packet| packet.cycle + 1);
+    let preemptions = Preemptions::new(input, last_cycle);
     let mut tid = 0;
-    let instant = Instant::now();
+    let mut in_interrupt = false;
+    // size of the live allocations by address, and their total
+    let mut allocations: HashMap<u32, u32> = HashMap::new();
+    let mut heap_bytes: u64 = 0;
+    let mut unknown_frees = 0;
+    // how many times each exception was taken at each PC
+    let mut exceptions: BTreeMap<(CpuException, u32), u64> = BTreeMap::new();
+    // user event begins whose end hasn't been reached yet
+    let mut open_user_slices = 0;
+    // end cycles of the slices still open on each thread
+    let mut open_slices: HashMap<u32, Vec<u64>> = HashMap::new();
+    let stopwatch = Stopwatch::start();
+    let mut progress = Progress::new("Converting", "packets", input.packets.len() as u64);
     for (i, packet) in input.packets.iter().enumerate() {
+        progress.set(i as u64);
+        intervals.process_packet(packet, &mut trace_events, input.mclk);
+        if !in_interrupt {
+            tid = task_stacks.tid(packet.stack_pointer);
+        }
         match packet.inner {
-            ProfilingPacketInner::SubroutineEnter { target_subroutine } => {
-                let mut end_cycle = last_cycle;
-                for matching_packet in &input.packets[i+1..] {
-                    if let ProfilingPacketInner::SubroutineExit = matching_packet.inner {
-                        // + 4 because the RTS hasn't been executed yet so the PC has yet to be popped off the stack
-                        if matching_packet.stack_pointer + 4 >= packet.stack_pointer {
-                            end_cycle = matching_packet.cycle;
-                            break;
-                        }
+            ProfilingPacketInner::SubroutineEnter { target_subroutine, .. }
+            | ProfilingPacketInner::TailCall { target_subroutine } => {
+                let end_cycle = input.subroutine_end(i, task_stacks).unwrap_or(last_cycle);
+                let name = symbols.name(target_subroutine);
+                let depth = slice_depth(open_slices.entry(tid).or_default(), packet.cycle, end_cycle);
+                let (ts, dur) = nudged_slice(packet.cycle, end_cycle, depth, input.mclk);
+                let mut args = function_args(symbols, target_subroutine);
+                if !in_interrupt {
+                    preemptions.annotate(&mut args, packet.cycle, end_cycle);
+                }
+                if let ProfilingPacketInner::SubroutineEnter { registers, .. } = &packet.inner {
+                    if !registers.is_empty() {
+                        args.get_or_insert_with(Default::default).registers = Some(registers.iter()
+                            .map(|&(register, value)| (register_name(register), format!("{:#010x}", value)))
+                            .collect());
                     }
                 }
-                let name = match symbols.address_to_label.get(&target_subroutine) {
-                    Some(labels) => Cow::Borrowed(labels.last().unwrap().borrow()),
-                    None => Cow::Owned(format!("{:#x}", target_subroutine)),
-                };
                 let trace_event = TraceEvent {
                     name,
                     ph: 'X',
-                    ts: cycle_to_us(packet.cycle, input.mclk),
-                    dur: cycle_to_us(end_cycle - packet.cycle, input.mclk),
+                    ts,
+                    dur,
+                    pid: 0,
+                    tid,
+                    args,
+                    cname: symbols.region(target_subroutine).map(|region| region.color.as_str().into()),
+                    cat: Some(CATEGORY_SUBROUTINE.into()),
+                    ..Default::default()
+                };
+                trace_events.push(trace_event);
+            },
+            // a TRAP handler is a system call on the thread of the code that made it, in its own category
+            ProfilingPacketInner::TrapEnter { vector, handler } => {
+                let end_cycle = input.subroutine_end(i, task_stacks).unwrap_or(last_cycle);
+                let depth = slice_depth(open_slices.entry(tid).or_default(), packet.cycle, end_cycle);
+                let (ts, dur) = nudged_slice(packet.cycle, end_cycle, depth, input.mclk);
+                let mut args = function_args(symbols, handler);
+                if !in_interrupt {
+                    preemptions.annotate(&mut args, packet.cycle, end_cycle);
+                }
+                let trace_event = TraceEvent {
+                    name: format!("TRAP #{}: {}", vector, symbols.name(handler)).into(),
+                    ph: 'X',
+                    ts,
+                    dur,
                     pid: 0,
                     tid,
-                    args: None,
-                    s: None,
+                    args,
+                    cname: symbols.region(handler).map(|region| region.color.as_str().into()),
+                    cat: Some(CATEGORY_TRAP.into()),
+                    ..Default::default()
                 };
                 trace_events.push(trace_event);
             },
             ProfilingPacketInner::InterruptEnter { target_interrupt} => {
-                tid = 1;
+                tid = interrupt_tid(input, i, split_interrupts);
+                in_interrupt = true;
                 let mut end_cycle = last_cycle;
                 for matching_packet in &input.packets[i+1..] {
                     if let ProfilingPacketInner::InterruptExit = matching_packet.inner {
@@ -278,65 +2732,916 @@ pub fn generate_profiling_json(mut output: &mut File, input: &ParsedProfilingFil
                         break;
                     }
                 }
-                let name = match symbols.address_to_label.get(&target_interrupt) {
-                    Some(labels) => Cow::Borrowed(labels.last().unwrap().borrow()),
-                    None => Cow::Owned(format!("{:#x}", target_interrupt)),
-                };
+                let name = symbols.name(target_interrupt);
+                let depth = slice_depth(open_slices.entry(tid).or_default(), packet.cycle, end_cycle);
+                let (ts, dur) = nudged_slice(packet.cycle, end_cycle, depth, input.mclk);
                 let trace_event = TraceEvent {
                     name,
                     ph: 'X',
-                    ts: cycle_to_us(packet.cycle, input.mclk),
-                    dur: cycle_to_us(end_cycle - packet.cycle, input.mclk),
+                    ts,
+                    dur,
                     pid: 0,
                     tid,
-                    args: None,
-                    s: None,
+                    args: interrupt_args(input, i, symbols, target_interrupt),
+                    cname: symbols.region(target_interrupt).map(|region| region.color.as_str().into()),
+                    cat: Some(CATEGORY_INTERRUPT.into()),
+                    ..Default::default()
                 };
                 trace_events.push(trace_event);
             },
             ProfilingPacketInner::InterruptExit => {
-                tid = 0;
+                in_interrupt = false;
+                tid = task_stacks.tid(packet.stack_pointer);
+            },
+            ProfilingPacketInner::HInt => {
+                if let Some(scope) = markers.hint.scope {
+                    let trace_event = TraceEvent {
+                        name: "HInt".into(),
+                        ph: 'i',
+                        ts: cycle_to_us(packet.cycle, input.mclk),
+                        dur: 0.0,
+                        pid: 0,
+                        tid: if split_interrupts { HINT_TID } else { 1 },
+                        s: Some(scope),
+                        cname: markers.hint.cname(),
+                        cat: Some(CATEGORY_FRAME.into()),
+                        ..Default::default()
+                    };
+                    trace_events.push(trace_event);
+                }
+                trace_events.extend(interrupt_flow_events(input, i, "HInt", split_interrupts, task_stacks));
             },
-            // ProfilingPacketInner::HInt => {
-            //     let trace_event = TraceEvent {
-            //         name: "HInt".into(),
-            //         ph: 'i',
-            //         ts: cycle_to_us(packet.cycle, input.mclk),
-            //         dur: 0.0,
-            //         pid: 0,
-            //         tid: 1,
-            //         args: None,
-            //         s: Some('g'),
-            //     };
-            //     trace_events.push(trace_event);
-            // },
             ProfilingPacketInner::VInt => {
+                if let Some(scope) = markers.vint.scope {
+                    let trace_event = TraceEvent {
+                        name: "VInt".into(),
+                        ph: 'i',
+                        ts: cycle_to_us(packet.cycle, input.mclk),
+                        dur: 0.0,
+                        pid: 0,
+                        tid: 1,
+                        s: Some(scope),
+                        cname: markers.vint.cname(),
+                        cat: Some(CATEGORY_FRAME.into()),
+                        ..Default::default()
+                    };
+                    trace_events.push(trace_event);
+                }
+                trace_events.extend(interrupt_flow_events(input, i, "VInt", split_interrupts, task_stacks));
+            },
+            // a breakpoint placed in the emulator by hand rather than from the interval file is drawn as a marker
+            ProfilingPacketInner::ManualBreakpoint { pc } if !intervals.contains(pc) => {
+                if let Some(scope) = markers.breakpoint.scope {
+                    let trace_event = TraceEvent {
+                        name: symbols.name(pc),
+                        ph: 'i',
+                        ts: cycle_to_us(packet.cycle, input.mclk),
+                        dur: 0.0,
+                        pid: 0,
+                        tid: intervals.breakpoint_thread(),
+                        args: function_args(symbols, pc),
+                        s: Some(scope),
+                        cname: markers.breakpoint.cname(),
+                        cat: Some(CATEGORY_MARKER.into()),
+                        ..Default::default()
+                    };
+                    trace_events.push(trace_event);
+                }
+            }
+            ProfilingPacketInner::ManualBreakpoint { pc } => {
+                intervals.reach(pc, &mut trace_events, packet.cycle, input.mclk);
+            }
+            // nested in whatever was running, so the halted cycles don't count as its self time
+            ProfilingPacketInner::Dma { source, halted_cycles } => {
+                let end_cycle = packet.cycle + halted_cycles as u64;
+                let depth = slice_depth(open_slices.entry(tid).or_default(), packet.cycle, end_cycle);
+                let (ts, dur) = nudged_slice(packet.cycle, end_cycle, depth, input.mclk);
+                let trace_event = TraceEvent {
+                    name: "CPU halted (DMA)".into(),
+                    ph: 'X',
+                    ts,
+                    dur,
+                    pid: 0,
+                    tid,
+                    args: Some(TraceEventArgs {
+                        dma_source: Some(format!("{:#08x}", source)),
+                        ..Default::default()
+                    }),
+                    cname: Some("bad".into()),
+                    cat: Some(CATEGORY_DMA.into()),
+                    ..Default::default()
+                };
+                trace_events.push(trace_event);
+            }
+            // transfers don't overlap, the VDP runs one at a time
+            ProfilingPacketInner::DmaStart { source, destination, length, dma_type } => {
+                let end_cycle = input.packets[i+1..].iter()
+                    .find(|matching_packet| matches!(matching_packet.inner, ProfilingPacketInner::DmaEnd))
+                    .map_or(last_cycle, |matching_packet| matching_packet.cycle);
+                let (ts, dur) = nudged_slice(packet.cycle, end_cycle, 0, input.mclk);
+                let trace_event = TraceEvent {
+                    name: dma_type.name().into(),
+                    ph: 'X',
+                    ts,
+                    dur,
+                    pid: 0,
+                    tid: VDP_TID,
+                    args: Some(TraceEventArgs {
+                        // fills have no source
+                        dma_source: (dma_type != DmaType::VramFill).then(|| format!("{:#08x}", source)),
+                        dma_destination: Some(format!("{:#06x}", destination)),
+                        dma_length: Some(length),
+                        ..Default::default()
+                    }),
+                    cat: Some(CATEGORY_DMA.into()),
+                    ..Default::default()
+                };
+                trace_events.push(trace_event);
+            }
+            // the sound driver can't run while the 68000 holds its bus
+            ProfilingPacketInner::Z80BusRequest => {
+                let end_cycle = input.packets[i+1..].iter()
+                    .find(|matching_packet| matches!(matching_packet.inner, ProfilingPacketInner::Z80BusRelease))
+                    .map_or(last_cycle, |matching_packet| matching_packet.cycle);
+                let (ts, dur) = nudged_slice(packet.cycle, end_cycle, 0, input.mclk);
+                let trace_event = TraceEvent {
+                    name: "Bus held by the 68000".into(),
+                    ph: 'X',
+                    ts,
+                    dur,
+                    pid: Z80_PID,
+                    tid: 0,
+                    cat: Some(CATEGORY_Z80.into()),
+                    ..Default::default()
+                };
+                trace_events.push(trace_event);
+            }
+            // a PCM sample is drawn as a slice until it is stopped or replaced, the other commands as instants
+            ProfilingPacketInner::SoundCommand { command: SoundCommand::PcmStop, .. } => {},
+            ProfilingPacketInner::SoundCommand { command, argument } => {
+                let (ph, ts, dur) = if command == SoundCommand::PcmStart {
+                    let end_cycle = input.packets[i+1..].iter()
+                        .find(|matching_packet| matches!(matching_packet.inner, ProfilingPacketInner::SoundCommand { command: SoundCommand::PcmStart | SoundCommand::PcmStop, .. }))
+                        .map_or(last_cycle, |matching_packet| matching_packet.cycle);
+                    let (ts, dur) = nudged_slice(packet.cycle, end_cycle, 0, input.mclk);
+                    ('X', ts, dur)
+                } else {
+                    ('i', cycle_to_us(packet.cycle, input.mclk), 0.0)
+                };
                 let trace_event = TraceEvent {
-                    name: "VInt".into(),
+                    name: command.name(argument).into(),
+                    ph,
+                    ts,
+                    dur,
+                    pid: Z80_PID,
+                    tid: SOUND_TID,
+                    args: (command != SoundCommand::MusicTick).then(|| TraceEventArgs {
+                        sound_argument: Some(argument),
+                        ..Default::default()
+                    }),
+                    s: (ph == 'i').then_some('t'),
+                    cat: Some(CATEGORY_SOUND.into()),
+                    ..Default::default()
+                };
+                trace_events.push(trace_event);
+            }
+            ProfilingPacketInner::Alloc { address, .. } | ProfilingPacketInner::Free { address } => {
+                let (name, size, tag) = match packet.inner {
+                    ProfilingPacketInner::Alloc { size, tag, .. } => {
+                        // an address handed out again without being freed, the game leaked it or freed it without telling
+                        if let Some(previous_size) = allocations.insert(address, size) {
+                            heap_bytes -= previous_size as u64;
+                        }
+                        heap_bytes += size as u64;
+                        (format!("Alloc {} bytes", size), Some(size), Some(tag))
+                    },
+                    _ => match allocations.remove(&address) {
+                        Some(size) => {
+                            heap_bytes -= size as u64;
+                            (format!("Free {} bytes", size), Some(size), None)
+                        },
+                        // allocated before the capture started
+                        None => {
+                            unknown_frees += 1;
+                            ("Free".to_string(), None, None)
+                        },
+                    },
+                };
+                trace_events.push(TraceEvent {
+                    name: name.into(),
                     ph: 'i',
                     ts: cycle_to_us(packet.cycle, input.mclk),
                     dur: 0.0,
                     pid: 0,
-                    tid: 1,
-                    args: None,
-                    s: Some('g'),
+                    tid,
+                    args: Some(TraceEventArgs {
+                        heap_address: Some(format!("{:#08x}", address)),
+                        heap_size: size,
+                        heap_tag: tag,
+                        ..Default::default()
+                    }),
+                    s: Some('t'),
+                    cat: Some(CATEGORY_HEAP.into()),
+                    ..Default::default()
+                });
+                trace_events.push(TraceEvent {
+                    name: "Heap bytes".into(),
+                    ph: 'C',
+                    ts: cycle_to_us(packet.cycle, input.mclk),
+                    dur: 0.0,
+                    pid: 0,
+                    tid: 0,
+                    args: Some(TraceEventArgs {
+                        value: Some(heap_bytes),
+                        ..Default::default()
+                    }),
+                    cat: Some(CATEGORY_HEAP.into()),
+                    ..Default::default()
+                });
+            }
+            ProfilingPacketInner::UserEvent { kind: UserEventKind::End, .. } | ProfilingPacketInner::UserEventIndex { kind: UserEventKind::End, .. } => {
+                if open_user_slices == 0 {
+                    eprintln!("Warning: user event end at cycle {} without a begin, ignored", packet.cycle);
+                } else {
+                    open_user_slices -= 1;
+                }
+            }
+            ProfilingPacketInner::UserEvent { kind, .. } | ProfilingPacketInner::UserEventIndex { kind, .. } => {
+                let name: Cow<str> = match &packet.inner {
+                    ProfilingPacketInner::UserEvent { text, .. } => text.clone().into(),
+                    // no string table was given, or it doesn't have this one
+                    ProfilingPacketInner::UserEventIndex { index, .. } => format!("String {}", index).into(),
+                    _ => unreachable!(),
+                };
+                let trace_event = if kind == UserEventKind::Begin {
+                    // the end of this slice is the first end not closing a slice nested in it
+                    let mut nested = 0;
+                    let end_cycle = input.packets[i+1..].iter().find(|matching_packet| match matching_packet.inner {
+                        ProfilingPacketInner::UserEvent { kind: UserEventKind::Begin, .. } | ProfilingPacketInner::UserEventIndex { kind: UserEventKind::Begin, .. } => {
+                            nested += 1;
+                            false
+                        },
+                        ProfilingPacketInner::UserEvent { kind: UserEventKind::End, .. } | ProfilingPacketInner::UserEventIndex { kind: UserEventKind::End, .. } => {
+                            nested -= 1;
+                            nested < 0
+                        },
+                        _ => false,
+                    }).map_or(last_cycle, |matching_packet| matching_packet.cycle);
+                    open_user_slices += 1;
+                    let depth = slice_depth(open_slices.entry(USER_EVENTS_TID).or_default(), packet.cycle, end_cycle);
+                    let (ts, dur) = nudged_slice(packet.cycle, end_cycle, depth, input.mclk);
+                    TraceEvent {
+                        name,
+                        ph: 'X',
+                        ts,
+                        dur,
+                        pid: 0,
+                        tid: USER_EVENTS_TID,
+                        cat: Some(CATEGORY_USER.into()),
+                        ..Default::default()
+                    }
+                } else {
+                    TraceEvent {
+                        name,
+                        ph: 'i',
+                        ts: cycle_to_us(packet.cycle, input.mclk),
+                        dur: 0.0,
+                        pid: 0,
+                        tid: USER_EVENTS_TID,
+                        s: Some('t'),
+                        cat: Some(CATEGORY_USER.into()),
+                        ..Default::default()
+                    }
                 };
                 trace_events.push(trace_event);
-            },
-            ProfilingPacketInner::ManualBreakpoint { pc } => {
-                intervals.reach(pc, &mut trace_events, packet.cycle, input.mclk);
+            }
+            ProfilingPacketInner::VdpRegisterWrite { register, value } => {
+                if let Some(scope) = markers.vdp_register_write.scope {
+                    let trace_event = TraceEvent {
+                        name: vdp_register_name(register),
+                        ph: 'i',
+                        ts: cycle_to_us(packet.cycle, input.mclk),
+                        dur: 0.0,
+                        pid: 0,
+                        tid: VDP_TID,
+                        args: Some(TraceEventArgs {
+                            vdp_register: Some(register),
+                            vdp_value: Some(format!("{:#04x}", value)),
+                            ..Default::default()
+                        }),
+                        s: Some(scope),
+                        cname: markers.vdp_register_write.cname(),
+                        cat: Some(CATEGORY_VDP.into()),
+                        ..Default::default()
+                    };
+                    trace_events.push(trace_event);
+                }
+            }
+            // drawn whatever the markers, an exception handler quietly firing thousands of times is worth noticing
+            ProfilingPacketInner::Exception { exception, pc } => {
+                *exceptions.entry((exception, pc)).or_insert(0) += 1;
+                trace_events.push(TraceEvent {
+                    name: format!("{} at {}", exception.name(), symbols.name(pc)).into(),
+                    ph: 'i',
+                    ts: cycle_to_us(packet.cycle, input.mclk),
+                    dur: 0.0,
+                    pid: 0,
+                    tid,
+                    args: function_args(symbols, pc),
+                    s: Some('t'),
+                    cname: Some("terrible".into()),
+                    cat: Some(CATEGORY_EXCEPTION.into()),
+                    ..Default::default()
+                });
+            }
+            // one counter per watched variable, named after its label, the nearest label being some unrelated code for most of the RAM
+            ProfilingPacketInner::MemoryWatch { address, size, value } => {
+                let mask = if size >= 4 { u32::MAX } else { (1 << (8 * size as u32)) - 1 };
+                let name = match symbols.label(address) {
+                    Some(label) => label.to_string(),
+                    None => format!("{:#08x}", address),
+                };
+                trace_events.push(TraceEvent {
+                    name: name.into(),
+                    ph: 'C',
+                    ts: cycle_to_us(packet.cycle, input.mclk),
+                    dur: 0.0,
+                    pid: 0,
+                    tid: 0,
+                    args: Some(TraceEventArgs {
+                        value: Some((value & mask) as u64),
+                        ..Default::default()
+                    }),
+                    cat: Some(CATEGORY_WATCH.into()),
+                    ..Default::default()
+                });
+            }
+            ProfilingPacketInner::Savestate { ref path } => {
+                if let Some(scope) = markers.savestate.scope {
+                    let trace_event = TraceEvent {
+                        name: "Savestate".into(),
+                        ph: 'i',
+                        ts: cycle_to_us(packet.cycle, input.mclk),
+                        dur: 0.0,
+                        pid: 0,
+                        tid: 0,
+                        args: Some(TraceEventArgs {
+                            savestate: Some(path.clone().into()),
+                            ..Default::default()
+                        }),
+                        s: Some(scope),
+                        cname: markers.savestate.cname(),
+                        cat: Some(CATEGORY_MARKER.into()),
+                        ..Default::default()
+                    };
+                    trace_events.push(trace_event);
+                }
             }
 
             _ => {},
         }
     }
-    let elapsed = instant.elapsed();
-    println!("Generated {} output events in {} ms", trace_events.len(), elapsed.as_micros() as f64 / 1000.0);
-    let instant = Instant::now();
-    serde_json::ser::to_writer(BufWriter::new(&mut output), &ProfilingJson {
-        trace_events,
-        display_time_unit: "ms",
+    // interval slices are only pushed once their end is reached, the stable sort puts them back in place
+    // while keeping the file order for events that share a timestamp
+    if unknown_frees != 0 {
+        eprintln!("Warning: {} frees of addresses that weren't allocated during the capture, the heap counter doesn't include the allocations made before it started", unknown_frees);
+    }
+    for ((exception, pc), count) in exceptions {
+        eprintln!("Warning: {} at {}, {} in the capture", exception.name(), symbols.name(pc), count);
+    }
+    progress.set(input.packets.len() as u64);
+    progress.finish();
+    trace_events[metadata_events..].sort_by(|a, b| a.ts.total_cmp(&b.ts));
+    trace_events.extend(crate::addons::generate_sh2_trace_events(input, symbols));
+    trace_events.extend(crate::addons::generate_sub_cpu_trace_events(input, symbols));
+    crate::status!("Generated {} output events in {} ms", trace_events.len(), stopwatch.elapsed_ms());
+    trace_events
+}
+
+// moves the events of a capture to its own processes, named after the capture, so several captures can be put side by side
+pub fn set_capture_processes(trace_events: &mut [TraceEvent], capture_index: u32, pids_per_capture: u32, capture_name: &str) {
+    for trace_event in trace_events.iter_mut() {
+        trace_event.pid += capture_index * pids_per_capture;
+        // ids are global to the trace, not to a process
+        trace_event.id = trace_event.id.map(|id| id | (capture_index as u64) << 48);
+        if trace_event.name == "process_name" {
+            if let Some(TraceEventArgs { name: Some(name), .. }) = &mut trace_event.args {
+                *name = format!("{} ({})", name, capture_name);
+            }
+        }
+    }
+}
+
+// moves the slices of the idle functions (see --idle) to the idle category and grays them out, so the time spent waiting stands apart from the work,
+// the interrupts don't wait for the VBlank, an idle function they call is left alone
+pub fn mark_idle_slices(trace_events: &mut [TraceEvent], symbols: &Symbols, idle_functions: &HashSet<u32>) {
+    if idle_functions.is_empty() {
+        return;
+    }
+    let idle_names: HashSet<_> = idle_functions.iter().map(|&address| symbols.name(address)).collect();
+    for trace_event in trace_events.iter_mut() {
+        let interrupt_thread = matches!(trace_event.tid, 1 | HINT_TID | EXTERNAL_INTERRUPT_TID);
+        if trace_event.ph == 'X' && trace_event.pid == 0 && !interrupt_thread && trace_event.cat.as_deref() == Some(CATEGORY_SUBROUTINE) && idle_names.contains(&trace_event.name) {
+            trace_event.cat = Some(CATEGORY_IDLE.into());
+            trace_event.cname = Some(IDLE_COLOR.into());
+        }
+    }
+}
+
+// merges the slices nested right in a slice of the same function into it, so recursive functions show up as a single slice,
+// with the number of merged levels in its args
+pub fn collapse_recursion(trace_events: &mut Vec<TraceEvent>) {
+    // (index of the slice the nested ones are merged into, end of the slice, recursion depth) of the slices open on each thread
+    let mut open_slices = HashMap::new();
+    let mut merged = vec![false; trace_events.len()];
+    for index in 0..trace_events.len() {
+        let trace_event = &trace_events[index];
+        if trace_event.ph != 'X' {
+            continue;
+        }
+        let stack: &mut Vec<(usize, f64, u32)> = open_slices.entry((trace_event.pid, trace_event.tid)).or_default();
+        while stack.last().is_some_and(|&(_index, end, _depth)| end <= trace_event.ts) {
+            stack.pop();
+        }
+        let end = trace_event.ts + trace_event.dur;
+        match stack.last() {
+            Some(&(parent, _end, depth)) if trace_events[parent].name == trace_event.name && trace_events[parent].cat == trace_event.cat => {
+                merged[index] = true;
+                stack.push((parent, end, depth + 1));
+                let args = trace_events[parent].args.get_or_insert_with(Default::default);
+                args.recursion_depth = Some(args.recursion_depth.unwrap_or(1).max(depth + 1));
+            },
+            _ => stack.push((index, end, 1)),
+        }
+    }
+    let mut merged = merged.into_iter();
+    trace_events.retain(|_trace_event| !merged.next().unwrap());
+}
+
+// drops the slices of these functions (trampolines, dispatch stubs...), what they called moves up to their caller and their own time shows up as its self time,
+// returns how many slices were dropped
+pub fn fold_functions(trace_events: &mut Vec<TraceEvent>, symbols: &Symbols, folded_functions: &HashSet<u32>) -> usize {
+    let folded_names: HashSet<_> = folded_functions.iter().map(|&address| symbols.name(address)).collect();
+    let len = trace_events.len();
+    trace_events.retain(|trace_event| trace_event.ph != 'X' || trace_event.cat.as_deref() != Some(CATEGORY_SUBROUTINE) || !folded_names.contains(&trace_event.name));
+    len - trace_events.len()
+}
+
+// merges the runs of consecutive calls of the same function from the same caller which each last less than this into a single slice,
+// from the start of the first call to the end of the last one, with the number of calls in its args, returns how many slices were merged away
+pub fn merge_short_calls(trace_events: &mut Vec<TraceEvent>, max_duration_us: f64) -> usize {
+    // (index, end) of the subroutine slices open on each thread, a slice merged into a run is replaced by the slice of the run
+    let mut open_slices: HashMap<(u32, u32), Vec<(usize, f64)>> = HashMap::new();
+    // last call made by each caller (None at the top of a thread), and whether it's part of a run of short calls
+    let mut last_calls: HashMap<(u32, u32, Option<usize>), (usize, bool)> = HashMap::new();
+    let mut calls = vec![1u64; trace_events.len()];
+    let mut merged = vec![false; trace_events.len()];
+    for index in 0..trace_events.len() {
+        let trace_event = &trace_events[index];
+        if trace_event.ph != 'X' || trace_event.cat.as_deref() != Some(CATEGORY_SUBROUTINE) {
+            continue;
+        }
+        let thread = (trace_event.pid, trace_event.tid);
+        let stack = open_slices.entry(thread).or_default();
+        while stack.last().is_some_and(|&(_index, end)| end <= trace_event.ts) {
+            stack.pop();
+        }
+        let caller = stack.last().map(|&(caller, _end)| caller);
+        let short = trace_event.dur < max_duration_us;
+        let end = trace_event.ts + trace_event.dur;
+        let last_call = last_calls.get(&(thread.0, thread.1, caller)).copied();
+        match last_call {
+            Some((run, true)) if short && trace_events[run].name == trace_event.name => {
+                merged[index] = true;
+                calls[run] += 1;
+                trace_events[run].dur = end - trace_events[run].ts;
+                // what this call called is nested in the run
+                stack.push((run, end));
+            },
+            _ => {
+                last_calls.insert((thread.0, thread.1, caller), (index, short));
+                stack.push((index, end));
+            },
+        }
+    }
+    for (trace_event, &calls) in trace_events.iter_mut().zip(&calls) {
+        if calls > 1 {
+            trace_event.args.get_or_insert_with(Default::default).calls = Some(calls);
+        }
+    }
+    let mut merged = merged.into_iter();
+    let len = trace_events.len();
+    trace_events.retain(|_trace_event| !merged.next().unwrap());
+    len - trace_events.len()
+}
+
+// drops the slices shorter than this, their time shows up as self time of the slice they were nested in,
+// the intervals are kept whatever their duration as they were asked for, returns how many slices were dropped
+pub fn filter_short_slices(trace_events: &mut Vec<TraceEvent>, min_duration_us: f64) -> usize {
+    let len = trace_events.len();
+    trace_events.retain(|trace_event| trace_event.ph != 'X' || trace_event.dur >= min_duration_us || trace_event.cat.as_deref() == Some(CATEGORY_INTERVAL));
+    len - trace_events.len()
+}
+
+// keeps the events of the included categories (every category if None) that aren't excluded, and the metadata events
+pub fn filter_categories(trace_events: &mut Vec<TraceEvent>, included: Option<&[&str]>, excluded: &[&str]) {
+    trace_events.retain(|trace_event| {
+        let cat = match &trace_event.cat {
+            Some(cat) => cat,
+            None => return true,
+        };
+        included.is_none_or(|included| included.contains(&cat.as_ref())) && !excluded.contains(&cat.as_ref())
+    });
+}
+
+// keeps the events of the threads named in included (every thread if None) that aren't named in excluded, a thread also goes by
+// the name of its process, so a whole process can be kept or left out, the processes left without any event lose their names too,
+// returns how many events were dropped
+pub fn filter_tracks(trace_events: &mut Vec<TraceEvent>, included: Option<&[&str]>, excluded: &[&str]) -> usize {
+    let event_name = |trace_event: &TraceEvent, name: &str| if trace_event.ph == 'M' && trace_event.name == name {
+        trace_event.args.as_ref().and_then(|args| args.name.clone())
+    } else {
+        None
+    };
+    let thread_names: HashMap<(u32, u32), String> = trace_events.iter()
+        .filter_map(|trace_event| event_name(trace_event, "thread_name").map(|name| ((trace_event.pid, trace_event.tid), name)))
+        .collect();
+    let process_names: HashMap<u32, String> = trace_events.iter()
+        .filter_map(|trace_event| event_name(trace_event, "process_name").map(|name| (trace_event.pid, name)))
+        .collect();
+    let kept = |pid: u32, tid: u32| {
+        let names = [thread_names.get(&(pid, tid)), process_names.get(&pid)];
+        let named = |names_given: &[&str]| names.iter().flatten().any(|name| names_given.contains(&name.as_str()));
+        included.is_none_or(named) && !named(excluded)
+    };
+    let len = trace_events.len();
+    // the metadata of the processes go once their threads are filtered
+    trace_events.retain(|trace_event| (trace_event.ph == 'M' && trace_event.name.starts_with("process_")) || kept(trace_event.pid, trace_event.tid));
+    let kept_processes: HashSet<u32> = trace_events.iter()
+        .filter(|trace_event| !(trace_event.ph == 'M' && trace_event.name.starts_with("process_")))
+        .map(|trace_event| trace_event.pid)
+        .collect();
+    trace_events.retain(|trace_event| kept_processes.contains(&trace_event.pid));
+    len - trace_events.len()
+}
+
+// splits the events into chunks covering the time ranges starting at these timestamps (the first chunk covers what comes
+// before the first one), each with the metadata events so its processes and threads keep their names, the counters set to
+// their value at its start, and the slices running across its boundaries cut at them
+pub fn split_trace_events<'a>(trace_events: Vec<TraceEvent<'a>>, chunk_starts: &[f64]) -> Vec<Vec<TraceEvent<'a>>> {
+    let mut chunks: Vec<Vec<TraceEvent>> = (0..=chunk_starts.len()).map(|_| Vec::new()).collect();
+    for chunk in &mut chunks {
+        chunk.extend(trace_events.iter().filter(|trace_event| trace_event.ph == 'M').cloned());
+    }
+    let mut counter_events: Vec<&TraceEvent> = trace_events.iter().filter(|trace_event| trace_event.ph == 'C').collect();
+    counter_events.sort_by(|a, b| a.ts.total_cmp(&b.ts));
+    let mut counter_events = counter_events.into_iter().peekable();
+    // the last value of each counter of each process
+    let mut counter_values: BTreeMap<(u32, &str), &TraceEvent> = BTreeMap::new();
+    for (chunk, &start) in chunks[1..].iter_mut().zip(chunk_starts) {
+        while let Some(counter_event) = counter_events.next_if(|counter_event| counter_event.ts < start) {
+            counter_values.insert((counter_event.pid, &counter_event.name), counter_event);
+        }
+        chunk.extend(counter_values.values().map(|&counter_event| TraceEvent { ts: start, ..counter_event.clone() }));
+    }
+    let chunk_start = |chunk: usize| if chunk == 0 { f64::NEG_INFINITY } else { chunk_starts[chunk - 1] };
+    let chunk_end = |chunk: usize| chunk_starts.get(chunk).copied().unwrap_or(f64::INFINITY);
+    for trace_event in trace_events {
+        let first_chunk = chunk_starts.partition_point(|&start| start <= trace_event.ts);
+        match trace_event.ph {
+            'M' => {},
+            'X' => {
+                let end = trace_event.ts + trace_event.dur;
+                let last_chunk = chunk_starts.partition_point(|&start| start < end).max(first_chunk);
+                for (chunk, chunk_events) in chunks.iter_mut().enumerate().take(last_chunk).skip(first_chunk) {
+                    let ts = trace_event.ts.max(chunk_start(chunk));
+                    chunk_events.push(TraceEvent { ts, dur: chunk_end(chunk) - ts, ..trace_event.clone() });
+                }
+                let ts = trace_event.ts.max(chunk_start(last_chunk));
+                chunks[last_chunk].push(TraceEvent { ts, dur: end - ts, ..trace_event });
+            },
+            _ => chunks[first_chunk].push(trace_event),
+        }
+    }
+    chunks
+}
+
+// the point of a capture its trace starts from, so the traces of captures started at different times line up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimeOrigin {
+    // the first packet of the capture
+    Zero,
+    // the first VInt, so the frames start at whole multiples of the frame duration
+    VInt,
+    // the start of the first occurrence of this interval
+    Interval(String),
+}
+
+impl TimeOrigin {
+    // "zero", "vint" or "interval:<name>"
+    pub fn parse(origin: &str) -> Result<TimeOrigin, String> {
+        match origin {
+            "zero" => Ok(TimeOrigin::Zero),
+            "vint" => Ok(TimeOrigin::VInt),
+            _ => match origin.strip_prefix("interval:") {
+                Some(name) if !name.is_empty() => Ok(TimeOrigin::Interval(name.to_string())),
+                _ => Err(format!("Invalid time origin: {}, expected zero, vint or interval:<NAME>", origin)),
+            },
+        }
+    }
+}
+
+// moves the events so the trace starts at origin_us, what happened before it is left out like the first chunk of split_trace_events:
+// the slices running across it are cut at it and the counters start with their value then
+pub fn rebase_trace_events(trace_events: Vec<TraceEvent>, origin_us: f64) -> Vec<TraceEvent> {
+    let mut trace_events = split_trace_events(trace_events, &[origin_us]).pop().unwrap();
+    for trace_event in trace_events.iter_mut().filter(|trace_event| trace_event.ph != 'M') {
+        trace_event.ts -= origin_us;
+    }
+    trace_events
+}
+
+// the trace viewers load gzip compressed json directly
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    #[cfg(feature = "compression")]
+    Gzip,
+    #[cfg(feature = "compression")]
+    Zstd,
+}
+
+impl Compression {
+    pub fn parse(compression: &str) -> Result<Compression, String> {
+        match compression {
+            "none" => Ok(Compression::None),
+            #[cfg(feature = "compression")]
+            "gz" => Ok(Compression::Gzip),
+            #[cfg(feature = "compression")]
+            "zst" => Ok(Compression::Zstd),
+            #[cfg(not(feature = "compression"))]
+            "gz" | "zst" => Err("md-profiler was built without the compression feature".to_string()),
+            _ => Err(format!("Invalid compression: {}, expected gz or zst", compression)),
+        }
+    }
+}
+
+// the JSON Object Format keeps the symbol file hash and the schema version along with the events,
+// the JSON Array Format only has the events, one per line, and the viewers still load it when it's cut short,
+// JSON Lines has one event per line without the brackets, for jq and the tools that read a document per line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonFormat {
+    Object,
+    Array,
+    Lines,
+}
+
+impl JsonFormat {
+    pub fn parse(format: &str) -> Result<JsonFormat, String> {
+        match format {
+            "object" => Ok(JsonFormat::Object),
+            "array" => Ok(JsonFormat::Array),
+            "lines" => Ok(JsonFormat::Lines),
+            _ => Err(format!("Invalid json format: {}, expected object, array or lines", format)),
+        }
+    }
+}
+
+// the events of the trace, written as they are or with CompactTraceEvent
+#[derive(Debug)]
+struct TraceEvents<'a> {
+    events: &'a [TraceEvent<'a>],
+    compact: bool,
+}
+
+impl Serialize for TraceEvents<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.compact {
+            serializer.collect_seq(self.events.iter().map(CompactTraceEvent))
+        } else {
+            serializer.collect_seq(self.events)
+        }
+    }
+}
+
+// an event with its timestamp and duration rounded to the nanosecond, the resolution of the viewers (and of the
+// Perfetto output), which still keeps the nudged slices apart (see SLICE_NUDGE_CYCLES), and without its duration when
+// it has none
+struct CompactTraceEvent<'a>(&'a TraceEvent<'a>);
+
+impl Serialize for CompactTraceEvent<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let event = self.0;
+        let round = |us: f64| (us * 1000.0).round() / 1000.0;
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("name", &event.name)?;
+        map.serialize_entry("ph", &event.ph)?;
+        map.serialize_entry("ts", &round(event.ts))?;
+        if event.dur != 0.0 {
+            map.serialize_entry("dur", &round(event.dur))?;
+        }
+        map.serialize_entry("pid", &event.pid)?;
+        map.serialize_entry("tid", &event.tid)?;
+        if let Some(args) = &event.args {
+            map.serialize_entry("args", args)?;
+        }
+        if let Some(s) = &event.s {
+            map.serialize_entry("s", s)?;
+        }
+        if let Some(cname) = &event.cname {
+            map.serialize_entry("cname", cname)?;
+        }
+        if let Some(id) = &event.id {
+            map.serialize_entry("id", id)?;
+        }
+        if let Some(cat) = &event.cat {
+            map.serialize_entry("cat", cat)?;
+        }
+        map.end()
+    }
+}
+
+fn write_json_array(output: &mut dyn Write, trace_events: &[TraceEvent], compact: bool) -> std::io::Result<()> {
+    output.write_all(b"[\n")?;
+    for (i, trace_event) in trace_events.iter().enumerate() {
+        if i > 0 {
+            output.write_all(b",\n")?;
+        }
+        if compact {
+            serde_json::to_writer(&mut *output, &CompactTraceEvent(trace_event))?;
+        } else {
+            serde_json::to_writer(&mut *output, trace_event)?;
+        }
+    }
+    output.write_all(b"\n]\n")
+}
+
+fn write_json_lines(output: &mut dyn Write, trace_events: &[TraceEvent], compact: bool) -> std::io::Result<()> {
+    for trace_event in trace_events {
+        if compact {
+            serde_json::to_writer(&mut *output, &CompactTraceEvent(trace_event))?;
+        } else {
+            serde_json::to_writer(&mut *output, trace_event)?;
+        }
+        output.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_compressed(mut output: impl Write, compression: Compression, write_json: impl FnOnce(&mut dyn Write) -> std::io::Result<()>) -> std::io::Result<()> {
+    match compression {
+        Compression::None => {
+            write_json(&mut output)?;
+            output.flush()
+        },
+        #[cfg(feature = "compression")]
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+            write_json(&mut encoder)?;
+            encoder.finish()?.flush()
+        },
+        #[cfg(feature = "compression")]
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(output, 0)?;
+            write_json(&mut encoder)?;
+            encoder.finish()?.flush()
+        },
+    }
+}
+
+// the metadata only fits in the JSON Object Format
+pub fn write_profiling_json(output: impl Write, trace_events: &[TraceEvent], metadata: &TraceMetadata, compression: Compression, format: JsonFormat, compact: bool) {
+    let stopwatch = Stopwatch::start();
+    let mut writer = ProgressWriter::new(output, Progress::new("Writing", "bytes", 0));
+    write_compressed(BufWriter::new(&mut writer), compression, |output| match format {
+        JsonFormat::Object => serde_json::to_writer(output, &ProfilingJson {
+            trace_events: TraceEvents { events: trace_events, compact },
+            display_time_unit: "ms",
+            other_data: metadata,
+        }).map_err(std::io::Error::from),
+        JsonFormat::Array => write_json_array(output, trace_events, compact),
+        JsonFormat::Lines => write_json_lines(output, trace_events, compact),
     }).expect("Error writing json file");
-    let elapsed = instant.elapsed();
-    println!("Wrote {} MB of json in {} ms", output.metadata().unwrap().len() / 1_000_000, elapsed.as_micros() as f64 / 1000.0);
-}
\ No newline at end of file
+    let written = writer.written();
+    writer.finish();
+    crate::status!("Wrote {} MB of json in {} ms", written / 1_000_000, stopwatch.elapsed_ms());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a capture written by hand in little endian: a call to 0x1234 and its return
+    fn little_endian_capture() -> Vec<u8> {
+        let mut capture = vec![0; MDP_HEADER_SIZE];
+        capture[..3].copy_from_slice(b"MDP");
+        capture[3] = MDP_VERSION;
+        capture[4..8].copy_from_slice(&[0xf7, 0x4a, 0x33, 0x03]);
+        capture[8..12].copy_from_slice(&[7, 0, 0, 0]);
+        capture.extend_from_slice(&[PROFILER_PACKET_SUBROUTINE_ENTER, 0x10, 0x27, 0, 0, 0xfe, 0xff, 0xff, 0, 0x34, 0x12, 0, 0]);
+        capture.extend_from_slice(&[PROFILER_PACKET_SUBROUTINE_EXIT, 0x20, 0x4e, 0, 0, 0xfa, 0xff, 0xff, 0]);
+        capture
+    }
+
+    #[test]
+    fn reads_little_endian() {
+        let parsed = read_profiling_file(&little_endian_capture()).unwrap();
+        assert_eq!(parsed.mclk, 53693175.0);
+        assert_eq!(parsed.m68k_divider, 7);
+        assert_eq!(parsed.packets.len(), 2);
+        assert_eq!(parsed.packets[0].cycle, 10000);
+        assert_eq!(parsed.packets[0].stack_pointer, 0xfffffe);
+        match &parsed.packets[0].inner {
+            ProfilingPacketInner::SubroutineEnter { target_subroutine, .. } => assert_eq!(*target_subroutine, 0x1234),
+            inner => panic!("Expected a subroutine enter, got {:?}", inner),
+        }
+        assert_eq!(parsed.packets[1].cycle, 20000);
+        assert_eq!(parsed.packets[1].stack_pointer, 0xfffffa);
+    }
+
+    #[test]
+    fn round_trips() {
+        let capture = little_endian_capture();
+        let mut written = Vec::new();
+        write_profiling_file(&mut written, &read_profiling_file(&capture).unwrap());
+        assert_eq!(written, capture);
+    }
+
+    // a reader handing out one byte at a time, so the packets are split between the chunks
+    struct BytePerRead<'a>(&'a [u8]);
+
+    impl Read for BytePerRead<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((&byte, rest)), Some(first)) => {
+                    *first = byte;
+                    self.0 = rest;
+                    Ok(1)
+                },
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn tail_call_slices() {
+        // 0x200 jumps to 0x300, which calls 0x400 and returns to the caller of 0x200
+        let mut builder = crate::builder::MdpBuilder::default();
+        builder.call(0x200).wait(100);
+        builder.tail_call(0x300).unwrap().wait(100).call(0x400).wait(100);
+        builder.ret().unwrap().wait(100).ret().unwrap();
+        let capture = builder.build();
+        let symbols = Symbols::default();
+        let trace_events = generate_trace_events(&capture, &symbols, &mut Intervals::default(), HashMap::new(), &MarkerStyles::default(), false, &TaskStacks::default());
+        let slice = |address: u32| {
+            let name = symbols.name(address);
+            let slices: Vec<_> = trace_events.iter().filter(|trace_event| trace_event.ph == 'X' && trace_event.name == name).collect();
+            assert_eq!(slices.len(), 1, "{}", name);
+            (slices[0].ts, slices[0].ts + slices[0].dur)
+        };
+        let (jumping_start, jumping_end) = slice(0x200);
+        let (target_start, target_end) = slice(0x300);
+        let (callee_start, callee_end) = slice(0x400);
+        assert!(jumping_end <= target_start);
+        assert!(jumping_start < jumping_end);
+        assert!(target_start < callee_start && callee_end < target_end);
+        assert!((target_end - cycle_to_us(capture.packets.last().unwrap().cycle, capture.mclk)).abs() < 0.01);
+    }
+
+    #[test]
+    fn call_stack_follows_tail_calls() {
+        // 0x200 calls 0x300 which jumps to 0x400, whose RTS returns to 0x200
+        let packet = |stack_pointer: u32, inner: ProfilingPacketInner| ProfilingPacket { cycle: 0, stack_pointer, inner };
+        let mut stack = CallStack::new(ExitMatching::default());
+        stack.follow(&packet(0xfffffc, ProfilingPacketInner::SubroutineEnter { target_subroutine: 0x200, registers: Vec::new() }), |subroutine| subroutine);
+        stack.follow(&packet(0xfffff8, ProfilingPacketInner::SubroutineEnter { target_subroutine: 0x300, registers: Vec::new() }), |subroutine| subroutine);
+        stack.follow(&packet(0xfffff4, ProfilingPacketInner::TailCall { target_subroutine: 0x400 }), |subroutine| subroutine);
+        assert_eq!(&stack[..], [(0x200, 0xfffffc), (0x400, 0xfffff8)]);
+        let returned: Vec<(u32, u32)> = stack.exit(0xfffff4).collect();
+        assert_eq!(returned, [(0x400, 0xfffff8)]);
+        assert_eq!(stack.len(), 1);
+    }
+
+    #[test]
+    fn corrupt_user_event_is_an_error() {
+        let mut capture = little_endian_capture();
+        capture.extend_from_slice(&[PROFILER_PACKET_USER_EVENT, 0x30, 0x75, 0, 0, 0xfe, 0xff, 0xff, 0, 9, 0, 0]);
+        assert_eq!(read_profiling_file(&capture).err().unwrap(), "Unknown user event kind: 9");
+    }
+
+    #[test]
+    fn corrupt_sh2_packet_is_an_error() {
+        let mut capture = little_endian_capture();
+        capture.extend_from_slice(&[PROFILER_PACKET_SH2, 0x30, 0x75, 0, 0, 0, 0, 0, 0, 9, PROFILER_PACKET_SUBROUTINE_EXIT]);
+        assert_eq!(read_profiling_file(&capture).err().unwrap(), "Unknown SH2: 9");
+    }
+
+    #[test]
+    fn reads_a_stream() {
+        let capture = little_endian_capture();
+        let parsed = read_profiling_stream(BytePerRead(&capture), 0).unwrap();
+        let mut written = Vec::new();
+        write_profiling_file(&mut written, &parsed);
+        assert_eq!(written, capture);
+    }
+}
@@ -0,0 +1,170 @@
+use std::{io::{IsTerminal, Write}, sync::atomic::{AtomicBool, Ordering}, time::Duration};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::Instant;
+
+// the bar is redrawn at most this often, and the clock only checked every thousandth of the total
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+const CHECKS_PER_PHASE: u64 = 1000;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+// --no-progress, the phases are then neither drawn nor timed
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+// when an output is written to stdout, the status messages go to stderr so they don't end up in it
+pub fn set_status_to_stderr(status_to_stderr: bool) {
+    STATUS_TO_STDERR.store(status_to_stderr, Ordering::Relaxed);
+}
+
+pub fn status_to_stderr() -> bool {
+    STATUS_TO_STDERR.load(Ordering::Relaxed)
+}
+
+// println! for the status messages (what was parsed, written, how long it took), see set_status_to_stderr
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::progress::status_to_stderr() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+// measures how long a phase took, wasm32-unknown-unknown has no clock (Instant::now panics there), it always reads 0 then
+#[derive(Debug, Clone, Copy)]
+pub struct Stopwatch {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    start: Instant,
+}
+
+impl Stopwatch {
+    pub fn start() -> Stopwatch {
+        Stopwatch {
+            #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+            start: Instant::now(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+        return self.start.elapsed();
+        #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+        return Duration::ZERO;
+    }
+
+    // for the status messages
+    pub fn elapsed_ms(&self) -> f64 {
+        self.elapsed().as_micros() as f64 / 1000.0
+    }
+}
+
+// progress of a long phase (parsing, converting, writing) on stderr, so it doesn't mix with what is written to stdout,
+// the bar is only drawn when stderr is a terminal, the time the phase took is always reported
+pub struct Progress {
+    phase: &'static str,
+    unit: &'static str,
+    // 0 when it isn't known beforehand, only the amount done is shown then
+    total: u64,
+    done: u64,
+    next_check: u64,
+    start: Stopwatch,
+    last_draw: Stopwatch,
+    enabled: bool,
+    draw: bool,
+}
+
+impl Progress {
+    pub fn new(phase: &'static str, unit: &'static str, total: u64) -> Progress {
+        let enabled = ENABLED.load(Ordering::Relaxed);
+        let now = Stopwatch::start();
+        Progress {
+            phase,
+            unit,
+            total,
+            done: 0,
+            next_check: 0,
+            start: now,
+            last_draw: now,
+            enabled,
+            draw: enabled && std::io::stderr().is_terminal(),
+        }
+    }
+
+    pub fn set(&mut self, done: u64) {
+        self.done = done;
+        if !self.draw || done < self.next_check {
+            return;
+        }
+        self.next_check = done + (self.total / CHECKS_PER_PHASE).max(1);
+        if self.last_draw.elapsed() >= REDRAW_INTERVAL {
+            self.last_draw = Stopwatch::start();
+            self.redraw();
+        }
+    }
+
+    pub fn add(&mut self, amount: u64) {
+        self.set(self.done + amount);
+    }
+
+    fn redraw(&self) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let line = if self.total == 0 {
+            format!("{}: {} {}, {:.0} s elapsed", self.phase, self.done, self.unit, elapsed)
+        } else {
+            let fraction = self.done as f64 / self.total as f64;
+            let eta = if fraction > 0.0 { elapsed / fraction - elapsed } else { 0.0 };
+            format!("{}: {:.1}% ({}/{} {}), {:.0} s left", self.phase, fraction * 100.0, self.done, self.total, self.unit, eta)
+        };
+        // \x1b[K clears what is left of a longer previous line
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "\r{}\x1b[K", line);
+        let _ = stderr.flush();
+    }
+
+    pub fn finish(self) {
+        if self.draw {
+            eprint!("\r\x1b[K");
+        }
+        if self.enabled {
+            eprintln!("{}: {} {} in {} ms", self.phase, self.done, self.unit, self.start.elapsed_ms());
+        }
+    }
+}
+
+// counts the bytes going through a writer
+pub struct ProgressWriter<W: Write> {
+    inner: W,
+    progress: Progress,
+}
+
+impl<W: Write> ProgressWriter<W> {
+    pub fn new(inner: W, progress: Progress) -> ProgressWriter<W> {
+        ProgressWriter { inner, progress }
+    }
+
+    pub fn written(&self) -> u64 {
+        self.progress.done
+    }
+
+    pub fn finish(self) -> W {
+        self.progress.finish();
+        self.inner
+    }
+}
+
+impl<W: Write> Write for ProgressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.progress.add(written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
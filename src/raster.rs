@@ -0,0 +1,236 @@
+use std::{collections::HashSet, io::Write};
+
+use crate::profiling::*;
+
+// every line lasts the same number of master clock cycles, in H32 and H40 alike
+const LINE_CYCLES: u64 = 3420;
+const NTSC_LINES: u64 = 262;
+const PAL_LINES: u64 = 313;
+// halfway between the NTSC (53693175 Hz) and PAL (53203424 Hz) master clocks
+const PAL_MCLK_THRESHOLD: f64 = 53_450_000.0;
+// the VInt fires at the start of the first line of the VBlank
+const V28_VBLANK_LINE: u64 = 224;
+const V30_VBLANK_LINE: u64 = 240;
+// M2 bit of mode register 2, set for 30 cell (240 lines) displays
+const MODE_REGISTER_2: u8 = 0x01;
+const V30_BIT: u8 = 0x08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoStandard {
+    Ntsc,
+    Pal,
+}
+
+impl VideoStandard {
+    // "ntsc" or "pal"
+    pub fn parse(standard: &str) -> Result<VideoStandard, String> {
+        match standard {
+            "ntsc" => Ok(VideoStandard::Ntsc),
+            "pal" => Ok(VideoStandard::Pal),
+            _ => Err(format!("Unknown video standard: {}, expected ntsc or pal", standard)),
+        }
+    }
+
+    // PAL consoles run on a slower master clock
+    pub fn detect(mclk: f64) -> VideoStandard {
+        if mclk < PAL_MCLK_THRESHOLD { VideoStandard::Pal } else { VideoStandard::Ntsc }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            VideoStandard::Ntsc => "NTSC",
+            VideoStandard::Pal => "PAL",
+        }
+    }
+
+    pub fn lines_per_frame(self) -> u64 {
+        match self {
+            VideoStandard::Ntsc => NTSC_LINES,
+            VideoStandard::Pal => PAL_LINES,
+        }
+    }
+}
+
+// the frame layout of a capture: how many lines a frame has and where the VBlank starts
+#[derive(Debug, Clone, Copy)]
+pub struct VideoTiming {
+    pub standard: VideoStandard,
+    // 30 cell (240 lines) display, only possible in PAL
+    pub v30: bool,
+}
+
+impl VideoTiming {
+    // the standard is detected from the master clock unless given, and the display mode is the one set before the first VInt
+    pub fn from_capture(input: &ParsedProfilingFile, standard: Option<VideoStandard>) -> VideoTiming {
+        let first_vint = input.packets.iter().position(|packet| matches!(packet.inner, ProfilingPacketInner::VInt)).unwrap_or(input.packets.len());
+        let v30 = input.packets[..first_vint].iter().rev().find_map(|packet| match packet.inner {
+            ProfilingPacketInner::VdpRegisterWrite { register: MODE_REGISTER_2, value } => Some(value & V30_BIT != 0),
+            _ => None,
+        }).unwrap_or(false);
+        VideoTiming {
+            standard: standard.unwrap_or_else(|| VideoStandard::detect(input.mclk)),
+            v30,
+        }
+    }
+
+    // in master clock cycles, like the timestamps of the packets
+    pub fn frame_cycles(&self) -> u64 {
+        self.standard.lines_per_frame() * LINE_CYCLES
+    }
+
+    pub fn vblank_line(&self) -> u64 {
+        if self.v30 { V30_VBLANK_LINE } else { V28_VBLANK_LINE }
+    }
+
+    pub fn vblank_lines(&self) -> u64 {
+        self.standard.lines_per_frame() - self.vblank_line()
+    }
+
+    pub fn vblank_cycles(&self) -> u64 {
+        self.vblank_lines() * LINE_CYCLES
+    }
+}
+
+// maps the cycles of a capture to the frame and line the beam was on
+#[derive(Debug, Clone, Copy)]
+pub struct Raster {
+    // cycle of the start of line 0 of the first frame of the capture
+    first_frame_start: u64,
+    lines_per_frame: u64,
+}
+
+impl Raster {
+    // the position of the beam is anchored on the first VInt, None if the capture has none
+    pub fn from_capture(input: &ParsedProfilingFile, timing: &VideoTiming) -> Option<Raster> {
+        let first_vint = input.packets.iter().find(|packet| matches!(packet.inner, ProfilingPacketInner::VInt))?;
+        let frame_cycles = timing.frame_cycles();
+        // the capture may start before line 0 of the frame of the first VInt, frames are counted from the one it starts in
+        let first_cycle = input.packets[0].cycle;
+        let vint_frame_start = first_vint.cycle.saturating_sub(timing.vblank_line() * LINE_CYCLES);
+        let frames_before = vint_frame_start.saturating_sub(first_cycle).div_ceil(frame_cycles);
+        Some(Raster {
+            first_frame_start: vint_frame_start.saturating_sub(frames_before * frame_cycles),
+            lines_per_frame: timing.standard.lines_per_frame(),
+        })
+    }
+
+    // (frame, scanline) of this cycle
+    pub fn position(&self, cycle: u64) -> (u64, u64) {
+        let line = cycle.saturating_sub(self.first_frame_start) / LINE_CYCLES;
+        (line / self.lines_per_frame, line % self.lines_per_frame)
+    }
+
+    // the cycle line 0 of this frame starts on
+    pub fn frame_start(&self, frame: u64) -> u64 {
+        self.first_frame_start + frame * self.lines_per_frame * LINE_CYCLES
+    }
+}
+
+// the busy cycles of each line of the frames the capture covers entirely, the busy time being the same as in the frame
+// statistics: everything but the time spent in the idle functions outside of interrupts
+#[derive(Debug)]
+pub struct ScanlineHeatmap {
+    // index of the first frame of the rows, as counted by Raster
+    pub first_frame: u64,
+    // a row per frame, with the busy master clock cycles of each of its lines
+    pub rows: Vec<Vec<u64>>,
+}
+
+impl ScanlineHeatmap {
+    pub fn from_capture(input: &ParsedProfilingFile, raster: &Raster, idle_functions: &HashSet<u32>) -> ScanlineHeatmap {
+        let (first_cycle, last_cycle) = match (input.packets.first(), input.packets.last()) {
+            (Some(first), Some(last)) => (first.cycle, last.cycle),
+            _ => return ScanlineHeatmap { first_frame: 0, rows: Vec::new() },
+        };
+        let (mut first_frame, _line) = raster.position(first_cycle);
+        if raster.frame_start(first_frame) < first_cycle {
+            first_frame += 1;
+        }
+        let (end_frame, _line) = raster.position(last_cycle);
+        let mut rows = vec![vec![0; raster.lines_per_frame as usize]; end_frame.saturating_sub(first_frame) as usize];
+        let mut add_busy_cycles = |start: u64, end: u64| {
+            let mut cycle = start.max(raster.frame_start(first_frame));
+            while cycle < end.min(raster.frame_start(end_frame)) {
+                let (frame, line) = raster.position(cycle);
+                let line_end = (raster.frame_start(frame) + (line + 1) * LINE_CYCLES).min(end);
+                rows[(frame - first_frame) as usize][line as usize] += line_end - cycle;
+                cycle = line_end;
+            }
+        };
+        let mut in_interrupt = false;
+        // the subroutines running in the main code, and how many of them were already running when the idle function was entered
+        let mut main_stack: CallStack<()> = CallStack::new(input.exit_matching);
+        let mut idle_depth: Option<usize> = None;
+        let mut last_cycle = first_cycle;
+        for packet in &input.packets {
+            if in_interrupt || idle_depth.is_none() {
+                add_busy_cycles(last_cycle, packet.cycle);
+            }
+            last_cycle = packet.cycle;
+            match packet.call_stack_change() {
+                Some(change) if !in_interrupt => {
+                    if let Some(exit_stack_pointer) = change.exit {
+                        main_stack.exit(exit_stack_pointer).for_each(drop);
+                        if idle_depth.is_some_and(|depth| depth >= main_stack.len()) {
+                            idle_depth = None;
+                        }
+                    }
+                    if let Some((target_subroutine, stack_pointer)) = change.enter {
+                        if idle_depth.is_none() && idle_functions.contains(&target_subroutine) {
+                            idle_depth = Some(main_stack.len());
+                        }
+                        main_stack.enter((), stack_pointer);
+                    }
+                },
+                _ => {},
+            }
+            match packet.inner {
+                ProfilingPacketInner::InterruptEnter { .. } => in_interrupt = true,
+                ProfilingPacketInner::InterruptExit => in_interrupt = false,
+                _ => {},
+            }
+        }
+        ScanlineHeatmap { first_frame, rows }
+    }
+
+    // the average busy share of each line over every frame
+    pub fn line_averages(&self) -> Vec<f64> {
+        let lines = self.rows.first().map_or(0, Vec::len);
+        (0..lines).map(|line| {
+            let busy_cycles: u64 = self.rows.iter().map(|row| row[line]).sum();
+            busy_cycles as f64 / (self.rows.len() as u64 * LINE_CYCLES) as f64
+        }).collect()
+    }
+}
+
+// a row per frame and a column per line, with the percentage of the line the CPU was busy
+pub fn write_scanline_heatmap_csv(output: impl Write, heatmap: &ScanlineHeatmap) -> std::io::Result<()> {
+    let mut buf_writer = std::io::BufWriter::new(output);
+    let lines = heatmap.rows.first().map_or(0, Vec::len);
+    write!(buf_writer, "frame")?;
+    for line in 0..lines {
+        write!(buf_writer, ",{}", line)?;
+    }
+    writeln!(buf_writer)?;
+    for (index, row) in heatmap.rows.iter().enumerate() {
+        write!(buf_writer, "{}", heatmap.first_frame + index as u64)?;
+        for &busy_cycles in row {
+            write!(buf_writer, ",{:.1}", busy_cycles as f64 * 100.0 / LINE_CYCLES as f64)?;
+        }
+        writeln!(buf_writer)?;
+    }
+    buf_writer.flush()
+}
+
+// the frame and scanline each event starts on, in its args (flow arrows aside), HInt markers are named after their line
+pub fn annotate_raster(trace_events: &mut [TraceEvent], raster: &Raster, mclk: f64) {
+    for trace_event in trace_events.iter_mut().filter(|trace_event| !matches!(trace_event.ph, 'M' | 's' | 'f')) {
+        let (frame, scanline) = raster.position(us_to_cycle(trace_event.ts, mclk));
+        if trace_event.ph == 'i' && trace_event.name == "HInt" {
+            trace_event.name = format!("HInt line {}", scanline).into();
+        }
+        let args = trace_event.args.get_or_insert_with(Default::default);
+        args.frame = Some(frame);
+        args.scanline = Some(scanline);
+    }
+}
@@ -0,0 +1,85 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DurationUnit {
+    // 68000 cycles
+    Cycles,
+    Microseconds,
+    // percentage of the duration of a frame
+    FramePercent,
+}
+
+// how durations are rendered in text reports, several units can be displayed at once
+#[derive(Debug, Clone)]
+pub struct DurationFormat {
+    pub units: Vec<DurationUnit>,
+    pub mclk: f64,
+    pub m68k_divider: u64,
+    // in master clock cycles, FramePercent is skipped when unknown
+    pub frame_cycles: Option<u64>,
+    // number of frames the capture lasts, FramePercent is skipped from the totals when unknown
+    pub capture_frames: Option<f64>,
+}
+
+impl DurationFormat {
+    // comma separated list of "cycles", "us" and "frame"
+    pub fn parse(units: &str, mclk: f64, m68k_divider: u64) -> Result<DurationFormat, String> {
+        let units = units.split(',')
+            .map(|unit| match unit.trim() {
+                "cycles" => Ok(DurationUnit::Cycles),
+                "us" => Ok(DurationUnit::Microseconds),
+                "frame" => Ok(DurationUnit::FramePercent),
+                unit => Err(format!("Unknown duration unit: {}, expected cycles, us or frame", unit)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(DurationFormat {
+            units,
+            mclk,
+            m68k_divider,
+            frame_cycles: None,
+            capture_frames: None,
+        })
+    }
+
+    pub fn with_frame_cycles(self, frame_cycles: Option<u64>) -> DurationFormat {
+        DurationFormat {
+            frame_cycles,
+            ..self
+        }
+    }
+
+    pub fn with_capture_frames(self, capture_frames: Option<f64>) -> DurationFormat {
+        DurationFormat {
+            capture_frames,
+            ..self
+        }
+    }
+
+    // cycles are master clock cycles, like the timestamps of the packets
+    pub fn format(&self, cycles: f64) -> String {
+        self.format_parts(cycles, false)
+    }
+
+    // for the time spent over the whole capture, a percentage of a frame only makes sense averaged over its frames
+    pub fn format_total(&self, cycles: f64) -> String {
+        self.format_parts(cycles, true)
+    }
+
+    fn format_parts(&self, cycles: f64, total: bool) -> String {
+        let mut parts = Vec::new();
+        for unit in &self.units {
+            match unit {
+                DurationUnit::Cycles => parts.push(format!("{:.0} cycles", cycles / self.m68k_divider.max(1) as f64)),
+                DurationUnit::Microseconds => parts.push(format!("{:.1} us", cycles / self.mclk * 1_000_000.0)),
+                DurationUnit::FramePercent => {
+                    if let Some(frame_cycles) = self.frame_cycles {
+                        if !total {
+                            parts.push(format!("{:.1}% of a frame", cycles / frame_cycles as f64 * 100.0));
+                        } else if let Some(frames) = self.capture_frames.filter(|&frames| frames > 0.0) {
+                            parts.push(format!("{:.1}% of a frame on average", cycles / frames / frame_cycles as f64 * 100.0));
+                        }
+                    }
+                },
+            }
+        }
+        parts.join(" / ")
+    }
+}
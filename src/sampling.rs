@@ -0,0 +1,96 @@
+use std::{collections::{BTreeMap, HashMap}, io::Write};
+
+use crate::{profiling::*, symbols::Symbols};
+
+// a frame of a sampled stack: a function, or a PC outside of any known function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SampleFrame {
+    Function(u32),
+    Unknown,
+}
+
+impl SampleFrame {
+    // the function of the nearest label before the PC
+    fn of_pc(pc: u32, symbols: &Symbols) -> SampleFrame {
+        symbols.nearest_label(pc).map_or(SampleFrame::Unknown, |(address, _label)| SampleFrame::Function(address))
+    }
+
+    pub fn name(self, symbols: &Symbols) -> String {
+        match self {
+            SampleFrame::Function(address) => symbols.name(address).into_owned(),
+            SampleFrame::Unknown => "[unknown]".to_string(),
+        }
+    }
+}
+
+// the number of PC samples taken with each stack, outermost frame first: the subroutines and interrupt handlers
+// the capture shows running when the sample was taken (if it also has their enter and exit packets), then the sampled function
+pub fn collapse_samples(input: &ParsedProfilingFile, symbols: &Symbols) -> BTreeMap<Vec<SampleFrame>, u64> {
+    let mut samples = BTreeMap::new();
+    let mut in_interrupt = false;
+    // subroutines running in the main code and in the interrupt
+    let mut main_stack: CallStack<u32> = CallStack::new(input.exit_matching);
+    let mut interrupt_stack: CallStack<u32> = CallStack::new(input.exit_matching);
+    for packet in &input.packets {
+        let stack = if in_interrupt { &mut interrupt_stack } else { &mut main_stack };
+        stack.follow(packet, |subroutine| subroutine);
+        match packet.inner {
+            ProfilingPacketInner::InterruptEnter { target_interrupt } => {
+                in_interrupt = true;
+                interrupt_stack.clear();
+                // the handler returns with its interrupt exit, never with a subroutine exit
+                interrupt_stack.enter(target_interrupt, u32::MAX);
+            },
+            ProfilingPacketInner::InterruptExit => in_interrupt = false,
+            ProfilingPacketInner::PcSample { pc } => {
+                let mut frames: Vec<SampleFrame> = stack.iter().map(|&(function, _stack_pointer)| SampleFrame::Function(function)).collect();
+                let sampled = SampleFrame::of_pc(pc, symbols);
+                if frames.last() != Some(&sampled) {
+                    frames.push(sampled);
+                }
+                *samples.entry(frames).or_insert(0) += 1;
+            },
+            _ => {},
+        }
+    }
+    samples
+}
+
+// one "outer;inner;sampled count" line per stack, the format of flamegraph.pl, inferno and speedscope
+pub fn write_collapsed_stacks(mut output: impl Write, samples: &BTreeMap<Vec<SampleFrame>, u64>, symbols: &Symbols) -> std::io::Result<()> {
+    for (frames, count) in samples {
+        let names: Vec<String> = frames.iter().map(|frame| frame.name(symbols)).collect();
+        writeln!(output, "{} {}", names.join(";"), count)?;
+    }
+    Ok(())
+}
+
+// the count functions with the most samples where they were the sampled function (self) and anywhere in the stack (total)
+pub fn write_sample_hotspots(mut output: impl Write, samples: &BTreeMap<Vec<SampleFrame>, u64>, symbols: &Symbols, count: usize) -> std::io::Result<()> {
+    let sample_count: u64 = samples.values().sum();
+    if sample_count == 0 {
+        return writeln!(output, "The capture doesn't contain any PC sample");
+    }
+    let mut self_samples: HashMap<SampleFrame, u64> = HashMap::new();
+    let mut total_samples: HashMap<SampleFrame, u64> = HashMap::new();
+    for (frames, &samples) in samples {
+        *self_samples.entry(*frames.last().unwrap()).or_default() += samples;
+        // recursive functions are only counted once per sample
+        let mut seen = frames.clone();
+        seen.sort_unstable();
+        seen.dedup();
+        for frame in seen {
+            *total_samples.entry(frame).or_default() += samples;
+        }
+    }
+    writeln!(output, "{} PC samples", sample_count)?;
+    for (title, samples) in [("total", total_samples), ("self", self_samples)] {
+        let mut by_samples: Vec<_> = samples.into_iter().collect();
+        by_samples.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        writeln!(output, "Top {} functions by {} samples:", count, title)?;
+        for (frame, samples) in by_samples.iter().take(count) {
+            writeln!(output, "  {:5.1}% {} ({} samples)", *samples as f64 * 100.0 / sample_count as f64, frame.name(symbols), samples)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+// Serde types of the machine-readable outputs, so tools consuming them can share these definitions.
+// Fields may be added in later versions of the same schema, consumers should ignore the ones they don't know about,
+// SCHEMA_VERSION only changes when existing fields are renamed, removed or change meaning.
+use std::{borrow::Cow, collections::BTreeMap};
+
+use serde::{Deserialize, Serialize};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+// "otherData" of the json trace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceMetadata {
+    pub schema_version: u32,
+    // FNV-1a hash of the symbol file used for the conversion, as 16 hex digits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol_file_hash: Option<String>,
+    // version of md-profiler that made the trace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub converter_version: Option<String>,
+    // arguments md-profiler was run with, left out of anonymized traces
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub command_line: Vec<String>,
+    // hash of each symbol file, in the order they were given
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub symbol_file_hashes: Vec<String>,
+    // in the order of the processes of the trace
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub captures: Vec<CaptureMetadata>,
+}
+
+// how a capture of the trace was recorded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureMetadata {
+    pub path: String,
+    pub mdp_version: u8,
+    // master clock in Hz and its divider for the 68000
+    pub mclk: f64,
+    pub m68k_divider: u64,
+    // ROM, emulator and date, if the emulator recorded them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    // hash of the symbol file of the build that was captured, if the emulator recorded it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol_file_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionTimeJson<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    pub self_us: f64,
+}
+
+// a line of --frame-jsonl
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameJson<'a> {
+    pub schema_version: u32,
+    pub frame: usize,
+    pub start_ms: f64,
+    pub duration_us: f64,
+    pub busy_us: f64,
+    // busy time / frame duration, between 0 and 1
+    pub utilization: f64,
+    // only known when idle functions were given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lag: Option<bool>,
+    pub interrupt_us: f64,
+    // time the 68000 was halted by DMA transfers
+    #[serde(default)]
+    pub dma_halt_us: f64,
+    // by self time, the most expensive first
+    #[serde(borrow)]
+    pub top_functions: Vec<FunctionTimeJson<'a>>,
+    // time covered by each interval during this frame
+    #[serde(borrow)]
+    pub intervals: BTreeMap<Cow<'a, str>, f64>,
+}
@@ -0,0 +1,616 @@
+use std::{borrow::Cow, collections::{HashMap, HashSet}, io::{self, BufWriter, Seek, SeekFrom, Write}};
+
+use crate::{frames::FrameStats, hotspots::FunctionStats, profiling::{cycle_to_us, format_build_id, ParsedProfilingFile, TraceEvent}, symbols::Symbols};
+
+// the database is written in the SQLite 3 file format, https://www.sqlite.org/fileformat.html,
+// a table b-tree per table without indexes, the queries can create the ones they need
+const PAGE_SIZE: usize = 4096;
+// the header of the database takes the start of the first page, the root of the schema table
+const DATABASE_HEADER_SIZE: usize = 100;
+const LEAF_HEADER_SIZE: usize = 8;
+const INTERIOR_HEADER_SIZE: usize = 12;
+const TABLE_LEAF_PAGE: u8 = 0x0d;
+const TABLE_INTERIOR_PAGE: u8 = 0x05;
+// the version of SQLite the file claims to be written by
+const SQLITE_VERSION_NUMBER: u32 = 3_045_000;
+
+// the tables, in the order they're written, see the README for what their columns hold
+const CAPTURE_TABLE: &str = "CREATE TABLE capture(path TEXT, rom_name TEXT, rom_checksum INTEGER, build_id TEXT, emulator TEXT, captured_at INTEGER, \
+    mclk REAL, m68k_divider INTEGER, symbol_file_hash TEXT, md_profiler_version TEXT)";
+const THREADS_TABLE: &str = "CREATE TABLE threads(pid INTEGER, tid INTEGER, process TEXT, thread TEXT)";
+const EVENTS_TABLE: &str = "CREATE TABLE events(ts_us REAL, dur_us REAL, name TEXT, category TEXT, phase TEXT, pid INTEGER, tid INTEGER, args TEXT)";
+const FRAMES_TABLE: &str = "CREATE TABLE frames(frame INTEGER, start_us REAL, duration_us REAL, busy_us REAL, interrupt_us REAL, dma_halt_us REAL, \
+    hints INTEGER, vints INTEGER, missed_vblanks INTEGER, lag INTEGER)";
+const FRAME_FUNCTIONS_TABLE: &str = "CREATE TABLE frame_functions(frame INTEGER, address INTEGER, name TEXT, self_us REAL)";
+const FUNCTIONS_TABLE: &str = "CREATE TABLE functions(address INTEGER, name TEXT, calls INTEGER, total_us REAL, self_us REAL)";
+
+// what the database is made from, a single converted capture
+pub struct AnalysisDatabase<'a> {
+    pub path: &'a str,
+    pub profiling: &'a ParsedProfilingFile,
+    pub symbols: &'a Symbols,
+    // the trace as written to the other outputs
+    pub trace_events: &'a [TraceEvent<'a>],
+    pub frames: &'a [FrameStats],
+    pub function_stats: &'a [FunctionStats],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value<'a> {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(Cow<'a, str>),
+}
+
+impl Value<'_> {
+    // the serial type of the record format and the bytes of the value
+    fn encode(&self, body: &mut Vec<u8>) -> u64 {
+        match self {
+            Value::Null => 0,
+            Value::Integer(0) => 8,
+            Value::Integer(1) => 9,
+            &Value::Integer(value) => {
+                let (serial_type, len) = match value {
+                    -0x80..=0x7f => (1, 1),
+                    -0x8000..=0x7fff => (2, 2),
+                    -0x80_0000..=0x7f_ffff => (3, 3),
+                    -0x8000_0000..=0x7fff_ffff => (4, 4),
+                    -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+                    _ => (6, 8),
+                };
+                body.extend_from_slice(&value.to_be_bytes()[8 - len..]);
+                serial_type
+            },
+            Value::Real(value) => {
+                body.extend_from_slice(&value.to_be_bytes());
+                7
+            },
+            Value::Text(text) => {
+                body.extend_from_slice(text.as_bytes());
+                text.len() as u64 * 2 + 13
+            },
+        }
+    }
+}
+
+fn text<'a>(text: impl Into<Cow<'a, str>>) -> Value<'a> {
+    Value::Text(text.into())
+}
+
+fn optional_text<'a>(text: Option<impl Into<Cow<'a, str>>>) -> Value<'a> {
+    text.map_or(Value::Null, |text| Value::Text(text.into()))
+}
+
+// big endian, 7 bits per byte with the high bit set on all but the last one, and a 9th byte of 8 bits
+fn write_varint(output: &mut Vec<u8>, value: u64) {
+    if value >> 56 != 0 {
+        let mut bytes = [0; 9];
+        bytes[8] = value as u8;
+        let mut rest = value >> 8;
+        for byte in bytes[..8].iter_mut().rev() {
+            *byte = (rest & 0x7f) as u8 | 0x80;
+            rest >>= 7;
+        }
+        output.extend_from_slice(&bytes);
+        return;
+    }
+    let mut bytes = Vec::new();
+    let mut rest = value;
+    loop {
+        bytes.push((rest & 0x7f) as u8);
+        rest >>= 7;
+        if rest == 0 {
+            break;
+        }
+    }
+    let last = bytes.len() - 1;
+    output.extend(bytes.iter().rev().enumerate().map(|(i, &byte)| if i < last { byte | 0x80 } else { byte }));
+}
+
+fn varint_len(value: u64) -> usize {
+    let mut bytes = Vec::new();
+    write_varint(&mut bytes, value);
+    bytes.len()
+}
+
+// a row of a table, its header lists the serial types of its values
+fn record(values: &[Value]) -> Vec<u8> {
+    let mut serial_types = Vec::new();
+    let mut body = Vec::new();
+    for value in values {
+        write_varint(&mut serial_types, value.encode(&mut body));
+    }
+    // the size of the header counts its own varint
+    let mut header_len = serial_types.len() + 1;
+    if varint_len(header_len as u64) != 1 {
+        header_len += varint_len(header_len as u64 + 1) - 1;
+    }
+    let mut record = Vec::with_capacity(header_len + body.len());
+    write_varint(&mut record, header_len as u64);
+    record.extend_from_slice(&serial_types);
+    record.extend_from_slice(&body);
+    record
+}
+
+// the pages are written as soon as they're complete, but the first one, written last once the root of every table is known
+struct PageWriter<W: Write + Seek> {
+    output: BufWriter<W>,
+    page_count: u32,
+}
+
+impl<W: Write + Seek> PageWriter<W> {
+    fn new(output: W) -> io::Result<Self> {
+        let mut output = BufWriter::new(output);
+        output.write_all(&[0; PAGE_SIZE])?;
+        Ok(PageWriter { output, page_count: 1 })
+    }
+
+    // returns the number of the page, counted from 1
+    fn write_page(&mut self, page: &[u8]) -> io::Result<u32> {
+        debug_assert_eq!(page.len(), PAGE_SIZE);
+        self.output.write_all(page)?;
+        self.page_count += 1;
+        Ok(self.page_count)
+    }
+
+    // the payload that doesn't fit in its cell goes to a chain of overflow pages, returns the first one
+    fn write_overflow(&mut self, payload: &[u8]) -> io::Result<u32> {
+        let chunks: Vec<&[u8]> = payload.chunks(PAGE_SIZE - 4).collect();
+        let first = self.page_count + 1;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut page = vec![0; PAGE_SIZE];
+            let next = if i + 1 < chunks.len() { self.page_count + 2 } else { 0 };
+            page[..4].copy_from_slice(&next.to_be_bytes());
+            page[4..4 + chunk.len()].copy_from_slice(chunk);
+            self.write_page(&page)?;
+        }
+        Ok(first)
+    }
+
+    fn finish(mut self, first_page: &[u8]) -> io::Result<()> {
+        self.output.seek(SeekFrom::Start(0))?;
+        self.output.write_all(first_page)?;
+        self.output.flush()
+    }
+}
+
+// a b-tree page with these cells, the first page leaves room for the database header
+fn btree_page(page_type: u8, cells: &[Vec<u8>], right_child: Option<u32>, first_page: bool) -> Vec<u8> {
+    let mut page = vec![0; PAGE_SIZE];
+    let header_start = if first_page { DATABASE_HEADER_SIZE } else { 0 };
+    let header_size = if right_child.is_some() { INTERIOR_HEADER_SIZE } else { LEAF_HEADER_SIZE };
+    let mut content_start = PAGE_SIZE;
+    for (i, cell) in cells.iter().enumerate() {
+        content_start -= cell.len();
+        page[content_start..content_start + cell.len()].copy_from_slice(cell);
+        let pointer = header_start + header_size + 2 * i;
+        page[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+    }
+    page[header_start] = page_type;
+    page[header_start + 3..header_start + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    page[header_start + 5..header_start + 7].copy_from_slice(&(content_start as u16).to_be_bytes());
+    if let Some(right_child) = right_child {
+        page[header_start + 8..header_start + 12].copy_from_slice(&right_child.to_be_bytes());
+    }
+    page
+}
+
+// the rows of a table are appended to leaf pages, the interior pages above them are made once every row is in
+struct TableWriter {
+    cells: Vec<Vec<u8>>,
+    // bytes of the current leaf page taken by its header, its cells and their pointers
+    used: usize,
+    // of the last row inserted
+    rowid: u64,
+    // page and largest rowid of each leaf page written
+    leaves: Vec<(u32, u64)>,
+}
+
+impl TableWriter {
+    fn new() -> Self {
+        TableWriter { cells: Vec::new(), used: LEAF_HEADER_SIZE, rowid: 0, leaves: Vec::new() }
+    }
+
+    fn insert(&mut self, pages: &mut PageWriter<impl Write + Seek>, values: &[Value]) -> io::Result<()> {
+        let cell = leaf_cell(pages, self.rowid + 1, &record(values))?;
+        if self.used + cell.len() + 2 > PAGE_SIZE {
+            self.write_leaf(pages)?;
+        }
+        self.rowid += 1;
+        self.used += cell.len() + 2;
+        self.cells.push(cell);
+        Ok(())
+    }
+
+    // the rows are numbered in order, the last one written is the largest of the page
+    fn write_leaf(&mut self, pages: &mut PageWriter<impl Write + Seek>) -> io::Result<()> {
+        let page = pages.write_page(&btree_page(TABLE_LEAF_PAGE, &self.cells, None, false))?;
+        self.leaves.push((page, self.rowid));
+        self.cells.clear();
+        self.used = LEAF_HEADER_SIZE;
+        Ok(())
+    }
+
+    // writes the pages left and returns the root page of the table
+    fn finish(mut self, pages: &mut PageWriter<impl Write + Seek>) -> io::Result<u32> {
+        if !self.cells.is_empty() || self.leaves.is_empty() {
+            self.write_leaf(pages)?;
+        }
+        // a cell per child but the last one, the right child of the page, with the largest rowid of the child as a varint of at most 9 bytes
+        const MAX_CHILDREN: usize = (PAGE_SIZE - INTERIOR_HEADER_SIZE) / (2 + 4 + 9) + 1;
+        let mut children = self.leaves;
+        while children.len() > 1 {
+            // the children are spread evenly so no page is left with its right child alone
+            let parent_count = children.len().div_ceil(MAX_CHILDREN);
+            let mut parents = Vec::new();
+            let mut rest = children.as_slice();
+            for i in 0..parent_count {
+                let (siblings, others) = rest.split_at(children.len() / parent_count + usize::from(i < children.len() % parent_count));
+                rest = others;
+                let (&(right_child, largest_rowid), cell_children) = siblings.split_last().unwrap();
+                let cells: Vec<Vec<u8>> = cell_children.iter().map(|&(page, rowid)| {
+                    let mut cell = page.to_be_bytes().to_vec();
+                    write_varint(&mut cell, rowid);
+                    cell
+                }).collect();
+                parents.push((pages.write_page(&btree_page(TABLE_INTERIOR_PAGE, &cells, Some(right_child), false))?, largest_rowid));
+            }
+            children = parents;
+        }
+        Ok(children[0].0)
+    }
+}
+
+// the size of the payload, the rowid and the payload itself, what doesn't fit in the page going to overflow pages
+fn leaf_cell(pages: &mut PageWriter<impl Write + Seek>, rowid: u64, payload: &[u8]) -> io::Result<Vec<u8>> {
+    let mut cell = Vec::new();
+    write_varint(&mut cell, payload.len() as u64);
+    write_varint(&mut cell, rowid);
+    let max_local = PAGE_SIZE - 35;
+    if payload.len() <= max_local {
+        cell.extend_from_slice(payload);
+        return Ok(cell);
+    }
+    let min_local = (PAGE_SIZE - 12) * 32 / 255 - 23;
+    let local = match min_local + (payload.len() - min_local) % (PAGE_SIZE - 4) {
+        local if local <= max_local => local,
+        _ => min_local,
+    };
+    let first_overflow_page = pages.write_overflow(&payload[local..])?;
+    cell.extend_from_slice(&payload[..local]);
+    cell.extend_from_slice(&first_overflow_page.to_be_bytes());
+    Ok(cell)
+}
+
+fn database_header(page_count: u32) -> [u8; DATABASE_HEADER_SIZE] {
+    let mut header = [0; DATABASE_HEADER_SIZE];
+    header[..16].copy_from_slice(b"SQLite format 3\0");
+    header[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+    // legacy journal, no reserved bytes, and the payload fractions every file uses
+    header[18..24].copy_from_slice(&[1, 1, 0, 64, 32, 32]);
+    // file change counter
+    header[24..28].copy_from_slice(&1u32.to_be_bytes());
+    header[28..32].copy_from_slice(&page_count.to_be_bytes());
+    // schema cookie and schema format
+    header[40..44].copy_from_slice(&1u32.to_be_bytes());
+    header[44..48].copy_from_slice(&4u32.to_be_bytes());
+    // UTF-8
+    header[56..60].copy_from_slice(&1u32.to_be_bytes());
+    // the page count is valid for this change counter
+    header[92..96].copy_from_slice(&1u32.to_be_bytes());
+    header[96..100].copy_from_slice(&SQLITE_VERSION_NUMBER.to_be_bytes());
+    header
+}
+
+// the capture, its trace, its frames and its functions as tables to query with SQL, and to gather with the ones of other captures
+pub fn write_analysis_database(output: impl Write + Seek, database: &AnalysisDatabase) -> io::Result<()> {
+    let AnalysisDatabase { path, profiling, symbols, trace_events, frames, function_stats } = database;
+    let us = |cycles: u64| Value::Real(cycle_to_us(cycles, profiling.mclk));
+    let mut pages = PageWriter::new(output)?;
+    let mut roots = Vec::new();
+
+    let mut table = TableWriter::new();
+    let capture_info = &profiling.capture_info;
+    table.insert(&mut pages, &[
+        text(*path),
+        optional_text(capture_info.rom_name.as_deref()),
+        capture_info.rom_checksum.map_or(Value::Null, |checksum| Value::Integer(checksum as i64)),
+        optional_text(capture_info.build_id.as_deref().map(format_build_id)),
+        optional_text(capture_info.emulator.as_deref()),
+        capture_info.captured_at.map_or(Value::Null, |time| Value::Integer(time as i64)),
+        Value::Real(profiling.mclk),
+        Value::Integer(profiling.m68k_divider as i64),
+        optional_text(profiling.symbol_file_hash.map(|hash| format!("{:016x}", hash))),
+        text(env!("CARGO_PKG_VERSION")),
+    ])?;
+    roots.push(("capture", CAPTURE_TABLE, table.finish(&mut pages)?));
+
+    // the names of the processes and threads are metadata events of the trace
+    let metadata_name = |trace_event: &TraceEvent, name: &str| if trace_event.ph == 'M' && trace_event.name == name {
+        trace_event.args.as_ref().and_then(|args| args.name.clone())
+    } else {
+        None
+    };
+    let process_names: HashMap<u32, String> = trace_events.iter()
+        .filter_map(|trace_event| metadata_name(trace_event, "process_name").map(|name| (trace_event.pid, name)))
+        .collect();
+    let mut table = TableWriter::new();
+    let mut threads = HashSet::new();
+    for trace_event in trace_events.iter() {
+        if let Some(thread_name) = metadata_name(trace_event, "thread_name") {
+            if threads.insert((trace_event.pid, trace_event.tid)) {
+                table.insert(&mut pages, &[
+                    Value::Integer(trace_event.pid as i64),
+                    Value::Integer(trace_event.tid as i64),
+                    optional_text(process_names.get(&trace_event.pid).cloned()),
+                    text(thread_name),
+                ])?;
+            }
+        }
+    }
+    roots.push(("threads", THREADS_TABLE, table.finish(&mut pages)?));
+
+    let mut table = TableWriter::new();
+    for trace_event in trace_events.iter().filter(|trace_event| trace_event.ph != 'M') {
+        let args = trace_event.args.as_ref().map(|args| serde_json::to_string(args).expect("Error serializing event args"));
+        table.insert(&mut pages, &[
+            Value::Real(trace_event.ts),
+            if trace_event.ph == 'X' { Value::Real(trace_event.dur) } else { Value::Null },
+            text(trace_event.name.as_ref()),
+            optional_text(trace_event.cat.as_deref()),
+            text(trace_event.ph.to_string()),
+            Value::Integer(trace_event.pid as i64),
+            Value::Integer(trace_event.tid as i64),
+            optional_text(args),
+        ])?;
+    }
+    roots.push(("events", EVENTS_TABLE, table.finish(&mut pages)?));
+
+    let mut table = TableWriter::new();
+    for frame in frames.iter() {
+        table.insert(&mut pages, &[
+            Value::Integer(frame.index as i64),
+            us(frame.start_cycle),
+            us(frame.cycles()),
+            us(frame.busy_cycles),
+            us(frame.interrupt_cycles),
+            us(frame.dma_halt_cycles),
+            Value::Integer(frame.hints as i64),
+            Value::Integer(frame.vints as i64),
+            Value::Integer(frame.missed_vblanks as i64),
+            Value::Integer(frame.is_lag() as i64),
+        ])?;
+    }
+    roots.push(("frames", FRAMES_TABLE, table.finish(&mut pages)?));
+
+    let mut table = TableWriter::new();
+    for frame in frames.iter() {
+        // by address, so the same capture always gives the same file
+        let mut self_cycles: Vec<(u32, u64)> = frame.self_cycles.iter().map(|(&address, &cycles)| (address, cycles)).collect();
+        self_cycles.sort_unstable();
+        for (address, cycles) in self_cycles {
+            table.insert(&mut pages, &[Value::Integer(frame.index as i64), Value::Integer(address as i64), text(symbols.name(address)), us(cycles)])?;
+        }
+    }
+    roots.push(("frame_functions", FRAME_FUNCTIONS_TABLE, table.finish(&mut pages)?));
+
+    let mut table = TableWriter::new();
+    for function_stats in function_stats.iter() {
+        table.insert(&mut pages, &[
+            Value::Integer(function_stats.address as i64),
+            text(symbols.name(function_stats.address)),
+            Value::Integer(function_stats.calls as i64),
+            us(function_stats.total_cycles),
+            us(function_stats.self_cycles),
+        ])?;
+    }
+    roots.push(("functions", FUNCTIONS_TABLE, table.finish(&mut pages)?));
+
+    // the schema table lists the tables and their root pages, it fits in the first page
+    let mut cells = Vec::new();
+    for (rowid, &(name, sql, root)) in roots.iter().enumerate() {
+        let record = record(&[text("table"), text(name), text(name), Value::Integer(root as i64), text(sql)]);
+        let mut cell = Vec::new();
+        write_varint(&mut cell, record.len() as u64);
+        write_varint(&mut cell, rowid as u64 + 1);
+        cell.extend_from_slice(&record);
+        cells.push(cell);
+    }
+    let mut first_page = btree_page(TABLE_LEAF_PAGE, &cells, None, true);
+    first_page[..DATABASE_HEADER_SIZE].copy_from_slice(&database_header(pages.page_count));
+    pages.finish(&first_page)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::TryInto, io::Cursor};
+
+    use super::*;
+    use crate::{builder::MdpBuilder, profiling::TraceEventArgs};
+
+    fn read_varint(bytes: &[u8]) -> (u64, usize) {
+        let mut value = 0;
+        for (i, &byte) in bytes.iter().take(8).enumerate() {
+            value = (value << 7) | (byte & 0x7f) as u64;
+            if byte & 0x80 == 0 {
+                return (value, i + 1);
+            }
+        }
+        ((value << 8) | bytes[8] as u64, 9)
+    }
+
+    fn decode_record(payload: &[u8]) -> Vec<Value<'static>> {
+        let (header_len, mut position) = read_varint(payload);
+        let mut body = header_len as usize;
+        let mut values = Vec::new();
+        while position < header_len as usize {
+            let (serial_type, len) = read_varint(&payload[position..]);
+            position += len;
+            let (value, size) = match serial_type {
+                0 => (Value::Null, 0),
+                8 => (Value::Integer(0), 0),
+                9 => (Value::Integer(1), 0),
+                1..=6 => {
+                    let size = [1, 2, 3, 4, 6, 8][serial_type as usize - 1];
+                    // big endian two's complement, sign extended
+                    let bytes = &payload[body..body + size];
+                    let value = bytes.iter().fold(if bytes[0] & 0x80 != 0 { -1 } else { 0 }, |value, &byte| (value << 8) | byte as i64);
+                    (Value::Integer(value), size)
+                },
+                7 => (Value::Real(f64::from_be_bytes(payload[body..body + 8].try_into().unwrap())), 8),
+                _ => {
+                    assert!(serial_type >= 13 && serial_type % 2 == 1, "serial type {}", serial_type);
+                    let size = (serial_type as usize - 13) / 2;
+                    (text(String::from_utf8(payload[body..body + size].to_vec()).unwrap()), size)
+                },
+            };
+            body += size;
+            values.push(value);
+        }
+        assert_eq!(position, header_len as usize);
+        assert_eq!(body, payload.len());
+        values
+    }
+
+    fn page(database: &[u8], number: u32) -> &[u8] {
+        &database[(number as usize - 1) * PAGE_SIZE..][..PAGE_SIZE]
+    }
+
+    fn be_u16(bytes: &[u8]) -> usize {
+        u16::from_be_bytes([bytes[0], bytes[1]]) as usize
+    }
+
+    fn be_u32(bytes: &[u8]) -> u32 {
+        u32::from_be_bytes(bytes[..4].try_into().unwrap())
+    }
+
+    // the payload of a leaf cell starting with its local part, the rest following the chain of overflow pages
+    fn read_payload(database: &[u8], cell: &[u8], len: usize) -> Vec<u8> {
+        // the sizes given by the file format for a table leaf, with no reserved bytes
+        let max_local = PAGE_SIZE - 35;
+        if len <= max_local {
+            return cell[..len].to_vec();
+        }
+        let min_local = (PAGE_SIZE - 12) * 32 / 255 - 23;
+        let local = match min_local + (len - min_local) % (PAGE_SIZE - 4) {
+            local if local <= max_local => local,
+            _ => min_local,
+        };
+        let mut payload = cell[..local].to_vec();
+        let mut next = be_u32(&cell[local..]);
+        while next != 0 {
+            let overflow = page(database, next);
+            let size = (len - payload.len()).min(PAGE_SIZE - 4);
+            payload.extend_from_slice(&overflow[4..4 + size]);
+            next = be_u32(overflow);
+        }
+        assert_eq!(payload.len(), len);
+        payload
+    }
+
+    // appends the rows of the table b-tree under this page, returns how many levels of interior pages it has
+    fn read_table(database: &[u8], number: u32, rows: &mut Vec<(u64, Vec<Value<'static>>)>) -> usize {
+        let page = page(database, number);
+        let header = if number == 1 { DATABASE_HEADER_SIZE } else { 0 };
+        let cell_count = be_u16(&page[header + 3..]);
+        match page[header] {
+            TABLE_INTERIOR_PAGE => {
+                let mut levels = Vec::new();
+                for i in 0..cell_count {
+                    let cell = be_u16(&page[header + INTERIOR_HEADER_SIZE + 2 * i..]);
+                    levels.push(read_table(database, be_u32(&page[cell..]), rows));
+                    // the key of a child is the largest rowid under it
+                    assert_eq!(rows.last().unwrap().0, read_varint(&page[cell + 4..]).0);
+                }
+                levels.push(read_table(database, be_u32(&page[header + 8..]), rows));
+                assert!(levels.iter().all(|&level| level == levels[0]), "unbalanced b-tree");
+                levels[0] + 1
+            },
+            TABLE_LEAF_PAGE => {
+                for i in 0..cell_count {
+                    let cell = &page[be_u16(&page[header + LEAF_HEADER_SIZE + 2 * i..])..];
+                    let (payload_len, payload_len_size) = read_varint(cell);
+                    let (rowid, rowid_size) = read_varint(&cell[payload_len_size..]);
+                    let payload = read_payload(database, &cell[payload_len_size + rowid_size..], payload_len as usize);
+                    rows.push((rowid, decode_record(&payload)));
+                }
+                0
+            },
+            page_type => panic!("page {} of type {:#04x}", number, page_type),
+        }
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 0x7f, 0x80, 0x3fff, 0x4000, (1 << 56) - 1, 1 << 56, u64::MAX] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value);
+            assert_eq!(read_varint(&bytes), (value, bytes.len()), "{:#x}", value);
+            assert_eq!(varint_len(value), bytes.len());
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let values: Vec<Value> = [i64::MIN, -0x8000_0000_0001, -0x8000_0001, -0x80_0001, -0x8001, -0x81, -1, 0, 1, 2, 0x7f, 0x80, 0x7fff_ffff_ffff, 0x8000_0000_0000, i64::MAX]
+            .iter()
+            .map(|&value| Value::Integer(value))
+            .chain([Value::Null, Value::Real(-0.5), text(""), text("x".repeat(200))])
+            .collect();
+        assert_eq!(decode_record(&record(&values)), values);
+    }
+
+    #[test]
+    fn analysis_database_round_trips() {
+        // enough events for two levels of interior pages above the leaves, and args of every size of local part,
+        // the longest spanning several overflow pages
+        let long_args: Vec<String> = (0..15).map(|i| "x".repeat(4000 + 300 * i)).chain(std::iter::once("args ".repeat(3000))).collect();
+        let mut trace_events: Vec<TraceEvent> = (0..20000).map(|i| TraceEvent {
+            name: format!("{:0>100}", i).into(),
+            ph: 'X',
+            ts: i as f64,
+            dur: 1.0,
+            ..Default::default()
+        }).collect();
+        for (i, args) in long_args.iter().enumerate() {
+            trace_events[1000 + i].args = Some(TraceEventArgs { name: Some(args.clone()), ..Default::default() });
+        }
+        let profiling = MdpBuilder::default().build();
+        let symbols = Symbols::default();
+        let database = AnalysisDatabase { path: "capture.mdp", profiling: &profiling, symbols: &symbols, trace_events: &trace_events, frames: &[], function_stats: &[] };
+        let mut output = Cursor::new(Vec::new());
+        write_analysis_database(&mut output, &database).unwrap();
+        let database = output.into_inner();
+
+        assert_eq!(&database[..16], b"SQLite format 3\0");
+        assert_eq!(database.len(), be_u32(&database[28..]) as usize * PAGE_SIZE);
+        let mut schema = Vec::new();
+        assert_eq!(read_table(&database, 1, &mut schema), 0);
+        let roots: HashMap<String, u32> = schema.iter().map(|(_rowid, values)| match values.as_slice() {
+            [_type, Value::Text(name), _table, Value::Integer(root), Value::Text(sql)] => {
+                assert!(sql.starts_with(&format!("CREATE TABLE {}(", name)));
+                (name.to_string(), *root as u32)
+            },
+            values => panic!("schema row {:?}", values),
+        }).collect();
+        assert_eq!(roots.len(), 6);
+
+        let mut events = Vec::new();
+        assert_eq!(read_table(&database, roots["events"], &mut events), 2);
+        assert_eq!(events.len(), trace_events.len());
+        for (i, (rowid, values)) in events.iter().enumerate() {
+            assert_eq!(*rowid, i as u64 + 1);
+            assert_eq!(values[0], Value::Real(i as f64));
+            assert_eq!(values[2], text(format!("{:0>100}", i)));
+        }
+        for (i, args) in long_args.iter().enumerate() {
+            assert_eq!(events[1000 + i].1[7], text(format!("{{\"name\":\"{}\"}}", args)));
+        }
+        assert_eq!(events[1000 + long_args.len()].1[7], Value::Null);
+
+        let mut functions = Vec::new();
+        assert_eq!(read_table(&database, roots["functions"], &mut functions), 0);
+        assert!(functions.is_empty());
+    }
+}
@@ -0,0 +1,163 @@
+use std::{collections::HashMap, io::Write};
+
+use crate::{profiling::*, symbols::Symbols};
+
+// how deep the stack went below the stack pointer a function was entered with, its callees included
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionStackUsage {
+    pub function: u32,
+    pub calls: u64,
+    pub max_bytes: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StackUsage {
+    // the stack pointer the usage is measured from
+    pub top: u32,
+    // the lowest stack pointer of the capture, when it was reached, and the functions running then, outermost first,
+    // those of an interrupt handler after the ones of the main code it interrupted
+    pub deepest: u32,
+    pub deepest_cycle: u64,
+    pub deepest_stack: Vec<(u32, bool)>,
+    // deepest first
+    pub functions: Vec<FunctionStackUsage>,
+}
+
+impl StackUsage {
+    pub fn max_bytes(&self) -> u32 {
+        self.top.saturating_sub(self.deepest)
+    }
+}
+
+// a function on the stack, with the lowest stack pointer seen since it was entered
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    function: u32,
+    lowest_stack_pointer: u32,
+}
+
+// the lowest stack pointer seen by the frame goes to its caller as it returns
+fn pop(stack: &mut CallStack<Frame>, usages: &mut HashMap<u32, (u64, u32)>) {
+    let (frame, enter_stack_pointer) = stack.pop().unwrap();
+    let usage = usages.entry(frame.function).or_insert((0, 0));
+    usage.0 += 1;
+    usage.1 = usage.1.max(enter_stack_pointer.saturating_sub(frame.lowest_stack_pointer));
+    if let Some((caller, _stack_pointer)) = stack.last_mut() {
+        caller.lowest_stack_pointer = caller.lowest_stack_pointer.min(frame.lowest_stack_pointer);
+    }
+}
+
+// the stack usage of the 68000, as seen by the stack pointers of the packets: what the code pushes between two packets
+// isn't seen, so these are lower bounds. The interrupt handlers run on the stack of the code they interrupt, they're part
+// of the deepest point of the capture, but not of the usage of the functions they interrupted. top defaults to the highest
+// stack pointer of the capture
+pub fn compute_stack_usage(input: &ParsedProfilingFile, top: Option<u32>) -> StackUsage {
+    let mut usages: HashMap<u32, (u64, u32)> = HashMap::new();
+    let mut main_stack: CallStack<Frame> = CallStack::new(input.exit_matching);
+    let mut interrupt_stack: CallStack<Frame> = CallStack::new(input.exit_matching);
+    let mut in_interrupt = false;
+    let mut highest = 0;
+    let mut deepest = StackUsage { deepest: u32::MAX, ..Default::default() };
+    // a stack pointer of 0 is one the emulator didn't record
+    for packet in input.packets.iter().filter(|packet| packet.stack_pointer != 0) {
+        let stack_pointer = packet.stack_pointer;
+        highest = highest.max(stack_pointer);
+        let stack = if in_interrupt { &mut interrupt_stack } else { &mut main_stack };
+        if let Some(change) = packet.call_stack_change() {
+            if let Some(exit_stack_pointer) = change.exit {
+                for _ in 0..stack.returning(exit_stack_pointer) {
+                    pop(stack, &mut usages);
+                }
+            }
+            if let Some((target_subroutine, enter_stack_pointer)) = change.enter {
+                stack.enter(Frame { function: target_subroutine, lowest_stack_pointer: enter_stack_pointer }, enter_stack_pointer);
+            }
+        }
+        match packet.inner {
+            ProfilingPacketInner::InterruptEnter { target_interrupt } => {
+                in_interrupt = true;
+                interrupt_stack.clear();
+                interrupt_stack.enter(Frame { function: target_interrupt, lowest_stack_pointer: stack_pointer }, stack_pointer);
+            },
+            ProfilingPacketInner::InterruptExit => {
+                while !interrupt_stack.is_empty() {
+                    pop(&mut interrupt_stack, &mut usages);
+                }
+                in_interrupt = false;
+            },
+            _ => {},
+        }
+        let stack = if in_interrupt { &mut interrupt_stack } else { &mut main_stack };
+        if let Some((frame, _enter_stack_pointer)) = stack.last_mut() {
+            frame.lowest_stack_pointer = frame.lowest_stack_pointer.min(stack_pointer);
+        }
+        if stack_pointer < deepest.deepest {
+            deepest.deepest = stack_pointer;
+            deepest.deepest_cycle = packet.cycle;
+            deepest.deepest_stack = main_stack.iter().map(|(frame, _stack_pointer)| (frame.function, false))
+                .chain(interrupt_stack.iter().map(|(frame, _stack_pointer)| (frame.function, true)))
+                .collect();
+        }
+    }
+    // the functions still running at the end of the capture
+    while !interrupt_stack.is_empty() {
+        pop(&mut interrupt_stack, &mut usages);
+    }
+    while !main_stack.is_empty() {
+        pop(&mut main_stack, &mut usages);
+    }
+    if deepest.deepest == u32::MAX {
+        return StackUsage::default();
+    }
+    let mut functions: Vec<FunctionStackUsage> = usages.into_iter().map(|(function, (calls, max_bytes))| FunctionStackUsage { function, calls, max_bytes }).collect();
+    functions.sort_by_key(|usage| (std::cmp::Reverse(usage.max_bytes), usage.function));
+    StackUsage {
+        top: top.unwrap_or(highest),
+        functions,
+        ..deepest
+    }
+}
+
+// a counter of the stack pointer at each packet it changed at, or of the bytes of stack used below top
+pub fn stack_counter_events<'a>(input: &ParsedProfilingFile, top: Option<u32>) -> Vec<TraceEvent<'a>> {
+    let name = if top.is_some() { "Stack used (bytes)" } else { "Stack pointer" };
+    let mut last_stack_pointer = None;
+    let mut trace_events = Vec::new();
+    for packet in input.packets.iter().filter(|packet| packet.stack_pointer != 0) {
+        if last_stack_pointer.replace(packet.stack_pointer) == Some(packet.stack_pointer) {
+            continue;
+        }
+        trace_events.push(TraceEvent {
+            name: name.into(),
+            ph: 'C',
+            ts: cycle_to_us(packet.cycle, input.mclk),
+            dur: 0.0,
+            pid: 0,
+            tid: 0,
+            args: Some(TraceEventArgs {
+                value: Some(top.map_or(packet.stack_pointer, |top| top.saturating_sub(packet.stack_pointer)) as u64),
+                ..Default::default()
+            }),
+            cat: Some(CATEGORY_SUBROUTINE.into()),
+            ..Default::default()
+        });
+    }
+    trace_events
+}
+
+// the deepest point of the capture with the functions running then, and the count functions using the most stack
+pub fn write_stack_usage(mut output: impl Write, usage: &StackUsage, symbols: &Symbols, count: usize, mclk: f64) -> std::io::Result<()> {
+    writeln!(output, "Deepest stack pointer: {:#08x} at {:.3} ms, {} bytes below {:#08x}", usage.deepest, cycle_to_us(usage.deepest_cycle, mclk) / 1000.0, usage.max_bytes(), usage.top)?;
+    for (depth, (function, interrupt)) in usage.deepest_stack.iter().rev().enumerate() {
+        writeln!(output, "  #{:<2} {}{}", depth, symbols.name(*function), if *interrupt { " (interrupt)" } else { "" })?;
+    }
+    if usage.functions.is_empty() {
+        return Ok(());
+    }
+    writeln!(output, "Functions by deepest stack use below their entry, callees included:")?;
+    let name_width = usage.functions.iter().take(count).map(|function| symbols.name(function.function).len()).max().unwrap_or(0);
+    for function in usage.functions.iter().take(count) {
+        writeln!(output, "  {:<name_width$} {:5} bytes, {} calls", symbols.name(function.function), function.max_bytes, function.calls, name_width = name_width)?;
+    }
+    Ok(())
+}
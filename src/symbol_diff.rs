@@ -0,0 +1,64 @@
+use std::{collections::BTreeMap, io::{BufWriter, Write}};
+
+use crate::{profiling::*, symbols::*};
+
+// how many times each subroutine, interrupt handler and manual breakpoint address was reached in the capture
+fn reached_addresses(input: &ParsedProfilingFile) -> BTreeMap<u32, u64> {
+    let mut reached = BTreeMap::new();
+    for packet in &input.packets {
+        let address = match packet.inner {
+            ProfilingPacketInner::SubroutineEnter { target_subroutine, .. }
+            | ProfilingPacketInner::TailCall { target_subroutine }
+            | ProfilingPacketInner::TrapEnter { handler: target_subroutine, .. } => target_subroutine,
+            ProfilingPacketInner::InterruptEnter { target_interrupt } => target_interrupt,
+            ProfilingPacketInner::ManualBreakpoint { pc } => pc,
+            _ => continue,
+        };
+        *reached.entry(address).or_insert(0) += 1;
+    }
+    reached
+}
+
+// compares how the addresses reached in the capture are named by two symbol files,
+// to track the symbolication progress of a disassembly between two builds of its symbols
+pub fn write_symbol_diff(output: &mut impl Write, input: &ParsedProfilingFile, old_symbols: &Symbols, new_symbols: &Symbols) {
+    let mut buf_writer = BufWriter::new(output);
+    let reached = reached_addresses(input);
+    let mut renamed = Vec::new();
+    let mut newly_resolved = Vec::new();
+    let mut no_longer_resolved = Vec::new();
+    let mut unresolved = Vec::new();
+    for (&address, &count) in &reached {
+        match (old_symbols.label(address), new_symbols.label(address)) {
+            (Some(old_label), Some(new_label)) if old_label != new_label => renamed.push((address, count, old_label, new_label)),
+            (Some(_), Some(_)) => {},
+            (None, Some(new_label)) => newly_resolved.push((address, count, new_label)),
+            (Some(old_label), None) => no_longer_resolved.push((address, count, old_label)),
+            (None, None) => unresolved.push((address, count)),
+        }
+    }
+    // the unresolved addresses reached the most are the most worth naming
+    unresolved.sort_by(|(address_a, count_a), (address_b, count_b)| count_b.cmp(count_a).then(address_a.cmp(address_b)));
+    let percent = |resolved: usize| if reached.is_empty() { 0.0 } else { resolved as f64 * 100.0 / reached.len() as f64 };
+    let old_resolved = reached.keys().filter(|&&address| old_symbols.label(address).is_some()).count();
+    let new_resolved = reached.keys().filter(|&&address| new_symbols.label(address).is_some()).count();
+    writeln!(buf_writer, "Addresses reached in the capture: {}", reached.len()).unwrap();
+    writeln!(buf_writer, "Resolved with the old symbols: {} ({:.1}%)", old_resolved, percent(old_resolved)).unwrap();
+    writeln!(buf_writer, "Resolved with the new symbols: {} ({:.1}%)", new_resolved, percent(new_resolved)).unwrap();
+    writeln!(buf_writer, "Renamed: {}", renamed.len()).unwrap();
+    for (address, count, old_label, new_label) in renamed {
+        writeln!(buf_writer, "  {:#08x} {} -> {} (reached {} times)", address, old_label, new_label, count).unwrap();
+    }
+    writeln!(buf_writer, "Newly resolved: {}", newly_resolved.len()).unwrap();
+    for (address, count, new_label) in newly_resolved {
+        writeln!(buf_writer, "  {:#08x} -> {} (reached {} times)", address, new_label, count).unwrap();
+    }
+    writeln!(buf_writer, "No longer resolved: {}", no_longer_resolved.len()).unwrap();
+    for (address, count, old_label) in no_longer_resolved {
+        writeln!(buf_writer, "  {:#08x} {} -> {} (reached {} times)", address, old_label, new_symbols.name(address), count).unwrap();
+    }
+    writeln!(buf_writer, "Still unresolved: {}", unresolved.len()).unwrap();
+    for (address, count) in unresolved {
+        writeln!(buf_writer, "  {:#08x} {} (reached {} times)", address, new_symbols.name(address), count).unwrap();
+    }
+}
@@ -1,24 +1,617 @@
-use std::{collections::{BTreeMap, HashMap}, convert::TryInto};
+use std::{borrow::Cow, collections::{BTreeMap, HashMap, HashSet}, convert::TryInto, sync::OnceLock};
+
+#[cfg(feature = "symbol-patterns")]
+use regex::Regex;
+#[cfg(feature = "elf")]
+use addr2line::gimli;
+#[cfg(feature = "elf")]
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol, SymbolKind};
+
+// past this distance from the nearest label, the address is very unlikely to belong to the same function
+const MAX_LABEL_OFFSET: u32 = 0x10000;
+
+// trace viewer colors given to the regions that don't choose one
+const REGION_COLORS: &[&str] = &["rail_response", "rail_animation", "rail_idle", "rail_load", "startup", "olive", "yellow", "generic_work"];
+
+// a named address range (engine, sound driver, ROM bank...), the functions it contains are colored and tagged with its name
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub start: u32,
+    // excluded from the region
+    pub end: u32,
+    pub name: String,
+    pub color: String,
+}
+
+// code copied from ROM to RAM and run from there, its RAM addresses are named after the ROM code
+#[derive(Debug, Clone, Copy)]
+struct Relocation {
+    rom_start: u32,
+    // excluded from the relocation
+    rom_end: u32,
+    ram_start: u32,
+}
+
+// which label to show when an address has several of them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LabelPolicy {
+    // the last one defined
+    #[default]
+    Last,
+    // the last one that doesn't look like a local label
+    Global,
+    Shortest,
+}
+
+impl LabelPolicy {
+    pub fn parse(policy: &str) -> Result<LabelPolicy, String> {
+        match policy {
+            "last" => Ok(LabelPolicy::Last),
+            "global" => Ok(LabelPolicy::Global),
+            "shortest" => Ok(LabelPolicy::Shortest),
+            _ => Err(format!("Invalid label policy: {}, expected last, global or shortest", policy)),
+        }
+    }
+}
+
+// how the addresses without a label of their own are named
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AddressNaming {
+    // the raw address
+    Hex,
+    // the nearest label before it with an offset, or the raw address
+    #[default]
+    Nearest,
+    // the nearest label before it with an offset, or the raw address after the region (see --regions) or memory area it's in
+    Region,
+}
+
+impl AddressNaming {
+    pub fn parse(naming: &str) -> Result<AddressNaming, String> {
+        match naming {
+            "hex" => Ok(AddressNaming::Hex),
+            "nearest" => Ok(AddressNaming::Nearest),
+            "region" => Ok(AddressNaming::Region),
+            _ => Err(format!("Invalid address naming: {}, expected hex, nearest or region", naming)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum HexStyle {
+    // 0xff0000, like the rest of the output
+    #[default]
+    Prefix,
+    // $FF0000, like asm68k and vasm
+    Dollar,
+    // FF0000h, like AS
+    Suffix,
+}
+
+// how the raw addresses are written, with a style and a minimum number of digits
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HexFormat {
+    style: HexStyle,
+    digits: usize,
+}
+
+impl HexFormat {
+    // "0x", "$" or "h", followed by the minimum number of digits if the address should be padded with zeros, such as "$6"
+    pub fn parse(format: &str) -> Result<HexFormat, String> {
+        let error = || format!("Invalid hex format: {}, expected 0x, $ or h, optionally followed by a number of digits", format);
+        let style_end = format.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+        // the 0 of 0x isn't a number of digits
+        let style_end = if format.starts_with("0x") { style_end.max(2) } else { style_end };
+        let (style, digits) = format.split_at(style_end);
+        let style = match style {
+            "0x" => HexStyle::Prefix,
+            "$" => HexStyle::Dollar,
+            "h" => HexStyle::Suffix,
+            _ => return Err(error()),
+        };
+        let digits = if digits.is_empty() { 0 } else { digits.parse().ok().filter(|&digits| digits <= 8).ok_or_else(error)? };
+        Ok(HexFormat { style, digits })
+    }
+
+    pub fn format(self, value: u32) -> String {
+        let digits = self.digits;
+        match self.style {
+            HexStyle::Prefix => format!("0x{:0digits$x}", value, digits = digits),
+            HexStyle::Dollar => format!("${:0digits$X}", value, digits = digits),
+            HexStyle::Suffix => format!("{:0digits$X}h", value, digits = digits),
+        }
+    }
+
+    // offsets from a label are never padded
+    fn format_offset(self, offset: u32) -> String {
+        HexFormat { digits: 0, ..self }.format(offset)
+    }
+}
+
+// the area of the 68000 memory map an address is in
+pub fn memory_area(address: u32) -> &'static str {
+    match address & 0xffffff {
+        0x000000..=0x3fffff => "ROM",
+        0x400000..=0x7fffff => "Expansion",
+        0x800000..=0x9fffff => "32X",
+        0xa00000..=0xa0ffff => "Z80 area",
+        0xa10000..=0xa1ffff => "I/O",
+        0xc00000..=0xdfffff => "VDP",
+        0xe00000..=0xffffff => "Work RAM",
+        _ => "Unmapped",
+    }
+}
 
 #[derive(Debug, Default)]
 pub struct Symbols {
-    pub address_to_label: HashMap<u32, Vec<String>>,
-    pub label_to_address: BTreeMap<String, u32>,
+    // FNV-1a hash of the symbol file, so traces can tell which build the symbols come from
+    pub hash: u64,
+    // the checksum of the ROM header and the build id of the build the symbols come from, when the symbol file tells,
+    // see read_build_labels
+    pub rom_checksum: Option<u16>,
+    pub build_id: Option<Vec<u8>>,
+    // raw (address, label) pairs in file order, the lookup maps are only built when something asks for them
+    labels: Vec<(u32, String)>,
+    // asm68k local labels, they only get combined with their parent global label on first use
+    local_labels: Vec<(u32, String)>,
+    address_to_label: OnceLock<HashMap<u32, Vec<String>>>,
+    label_to_address: OnceLock<BTreeMap<String, u32>>,
+    // sorted addresses that have a label, to find the nearest label before an address
+    sorted_addresses: OnceLock<Vec<u32>>,
+    // "file:line" of the labels, only available with ELF files that have DWARF debug info
+    source_locations: HashMap<u32, String>,
+    // (source file, lowest address of the code of the line) by line, from the DWARF line info of ELF files
+    source_lines: HashMap<u32, Vec<(String, u32)>>,
+    // size in bytes of the functions starting at these addresses, only available with nm -S, vlink maps and ELF files
+    sizes: HashMap<u32, u32>,
+    label_policy: LabelPolicy,
+    address_naming: AddressNaming,
+    hex_format: HexFormat,
+    // names forced by an alias file, they take precedence over any label
+    aliases: HashMap<u32, String>,
+    // sorted by start address, they don't overlap
+    regions: Vec<Region>,
+    relocations: Vec<Relocation>,
+}
+
+impl Symbols {
+    fn new(labels: Vec<(u32, String)>, local_labels: Vec<(u32, String)>) -> Self {
+        Symbols {
+            labels,
+            local_labels,
+            ..Default::default()
+        }
+    }
+
+    // replaces mangled C++ and Rust names by their readable form, must be called before any lookup
+    pub fn demangle(&mut self) {
+        for (_address, label) in self.labels.iter_mut().chain(self.local_labels.iter_mut()) {
+            if let Some(demangled) = demangle(label) {
+                *label = demangled;
+            }
+        }
+    }
+
+    // adds the symbols of another file, giving priority to the ones already there:
+    // labels of the other file are dropped if their address or their name is already labeled
+    pub fn merge(&mut self, other: Symbols) {
+        let mut labels: Vec<_> = self.all_labels().collect();
+        let labeled_addresses: HashSet<u32> = labels.iter().map(|(address, _label)| *address).collect();
+        let label_names: HashSet<String> = labels.iter().map(|(_address, label)| label.clone()).collect();
+        labels.extend(other.all_labels().filter(|(address, label)| !labeled_addresses.contains(address) && !label_names.contains(label)));
+        let mut source_locations = std::mem::take(&mut self.source_locations);
+        for (address, source_location) in other.source_locations {
+            source_locations.entry(address).or_insert(source_location);
+        }
+        let mut sizes = std::mem::take(&mut self.sizes);
+        for (address, size) in other.sizes {
+            sizes.entry(address).or_insert(size);
+        }
+        let mut source_lines = std::mem::take(&mut self.source_lines);
+        for (line, files) in other.source_lines {
+            let known_files = source_lines.entry(line).or_default();
+            for (file, address) in files {
+                if !known_files.iter().any(|(known_file, _address)| *known_file == file) {
+                    known_files.push((file, address));
+                }
+            }
+        }
+        *self = Symbols {
+            hash: self.hash,
+            rom_checksum: self.rom_checksum.or(other.rom_checksum),
+            build_id: self.build_id.take().or(other.build_id),
+            source_locations,
+            source_lines,
+            sizes,
+            ..Symbols::new(labels, Vec::new())
+        };
+    }
+
+    pub fn set_label_policy(&mut self, label_policy: LabelPolicy) {
+        self.label_policy = label_policy;
+    }
+
+    pub fn set_address_naming(&mut self, address_naming: AddressNaming, hex_format: HexFormat) {
+        self.address_naming = address_naming;
+        self.hex_format = hex_format;
+    }
+
+    // alias file lines are "LABEL OR HEX ADDRESS,NAME", the name is shown for that address whatever its labels are, the label can be
+    // a glob or regex pattern naming every label it matches, the functions given the same name are merged by the reports
+    pub fn read_aliases(&mut self, input: &[u8]) -> Result<(), String> {
+        let input = String::from_utf8_lossy(input);
+        for line in input.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (target, name) = line.split_once(',').ok_or_else(|| format!("Invalid alias: {}, expected LABEL,NAME", line))?;
+            let target = target.trim();
+            let addresses = match self.pattern_addresses(target)? {
+                Some(addresses) => addresses,
+                None => vec![self.resolve(target).ok_or_else(|| format!("Alias target {} not found in the symbol file", target))?],
+            };
+            for address in addresses {
+                self.aliases.insert(address, name.trim().to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // the functions given the same name by the aliases, each mapped to the lowest of their addresses, which stands for all of them
+    pub fn merged_functions(&self) -> HashMap<u32, u32> {
+        let mut lowest: HashMap<&str, u32> = HashMap::new();
+        for (&address, name) in &self.aliases {
+            let lowest = lowest.entry(name).or_insert(address);
+            *lowest = (*lowest).min(address);
+        }
+        self.aliases.iter()
+            .map(|(&address, name)| (address, lowest[name.as_str()]))
+            .filter(|(address, lowest)| address != lowest)
+            .collect()
+    }
+
+    // every labeled address is shown as func_001, func_002... in address order, labels can still be used to refer to them,
+    // source locations are dropped, returns (anonymized name, name) pairs
+    pub fn anonymize(&mut self) -> Vec<(String, String)> {
+        let mut addresses: Vec<u32> = self.address_to_label().keys().chain(self.aliases.keys()).copied().collect::<HashSet<_>>().into_iter().collect();
+        addresses.sort_unstable();
+        let mut mapping = Vec::new();
+        let mut aliases = HashMap::new();
+        for (index, address) in addresses.into_iter().enumerate() {
+            let anonymized_name = format!("func_{:03}", index + 1);
+            mapping.push((anonymized_name.clone(), self.label(address).unwrap().to_string()));
+            aliases.insert(address, anonymized_name);
+        }
+        self.aliases = aliases;
+        self.source_locations.clear();
+        mapping
+    }
+
+    // region file lines are "START,END,NAME" or "START,END,NAME,COLOR", START and END are labels or hex addresses, END is excluded
+    pub fn read_regions(&mut self, input: &[u8]) -> Result<(), String> {
+        let input = String::from_utf8_lossy(input);
+        let mut regions = Vec::new();
+        for line in input.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let (start, end, name, color) = match fields[..] {
+                [start, end, name] => (start, end, name, None),
+                [start, end, name, color] => (start, end, name, Some(color)),
+                _ => return Err(format!("Invalid region: {}, expected START,END,NAME or START,END,NAME,COLOR", line)),
+            };
+            let start_address = self.resolve(start).ok_or_else(|| format!("Region start {} not found in the symbol file", start))?;
+            let end_address = self.resolve(end).ok_or_else(|| format!("Region end {} not found in the symbol file", end))?;
+            if end_address <= start_address {
+                return Err(format!("Region {} ends before it starts", name));
+            }
+            let color = color.unwrap_or(REGION_COLORS[regions.len() % REGION_COLORS.len()]);
+            regions.push(Region {
+                start: start_address,
+                end: end_address,
+                name: name.to_string(),
+                color: color.to_string(),
+            });
+        }
+        regions.sort_by_key(|region| region.start);
+        if let Some(overlap) = regions.windows(2).find(|pair| pair[1].start < pair[0].end) {
+            return Err(format!("Regions {} and {} overlap", overlap[0].name, overlap[1].name));
+        }
+        self.regions = regions;
+        Ok(())
+    }
+
+    // relocation file lines are "ROM START,ROM END,RAM START": the code between ROM START and ROM END (excluded), labels
+    // or hex addresses, is copied to RAM START and run from there, the RAM copy is named after the ROM code
+    pub fn read_relocations(&mut self, input: &[u8]) -> Result<(), String> {
+        let input = String::from_utf8_lossy(input);
+        let mut relocations = Vec::new();
+        for line in input.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (rom_start, rom_end, ram_start) = match line.split(',').map(str::trim).collect::<Vec<_>>()[..] {
+                [rom_start, rom_end, ram_start] => (rom_start, rom_end, ram_start),
+                _ => return Err(format!("Invalid relocation: {}, expected ROM START,ROM END,RAM START", line)),
+            };
+            let rom_start_address = self.resolve(rom_start).ok_or_else(|| format!("Relocation start {} not found in the symbol file", rom_start))?;
+            let rom_end_address = self.resolve(rom_end).ok_or_else(|| format!("Relocation end {} not found in the symbol file", rom_end))?;
+            let ram_start_address = self.resolve(ram_start).ok_or_else(|| format!("Relocation destination {} not found in the symbol file", ram_start))?;
+            if rom_end_address <= rom_start_address {
+                return Err(format!("Relocation {} ends before it starts", line));
+            }
+            relocations.push(Relocation {
+                rom_start: rom_start_address,
+                rom_end: rom_end_address,
+                ram_start: ram_start_address,
+            });
+        }
+        self.relocations = relocations;
+        Ok(())
+    }
+
+    // the ROM address of the code copied to this RAM address, or the address itself if it isn't part of a relocation
+    fn unrelocated(&self, address: u32) -> u32 {
+        self.relocations.iter()
+            .find(|relocation| address >= relocation.ram_start && address - relocation.ram_start < relocation.rom_end - relocation.rom_start)
+            .map_or(address, |relocation| relocation.rom_start + (address - relocation.ram_start))
+    }
+
+    pub fn region(&self, address: u32) -> Option<&Region> {
+        let address = self.unrelocated(address);
+        let index = self.regions.partition_point(|region| region.start <= address);
+        self.regions.get(index.checked_sub(1)?).filter(|region| address < region.end)
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len() + self.local_labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn address_to_label(&self) -> &HashMap<u32, Vec<String>> {
+        self.address_to_label.get_or_init(|| {
+            let mut address_to_label: HashMap<u32, Vec<String>> = HashMap::new();
+            for (address, label) in self.all_labels() {
+                address_to_label.entry(address).or_default().push(label);
+            }
+            address_to_label
+        })
+    }
+
+    pub fn label_to_address(&self) -> &BTreeMap<String, u32> {
+        self.label_to_address.get_or_init(|| {
+            self.all_labels().map(|(address, label)| (label, address)).collect()
+        })
+    }
+
+    pub fn label(&self, address: u32) -> Option<&str> {
+        let address = self.unrelocated(address);
+        if let Some(alias) = self.aliases.get(&address) {
+            return Some(alias);
+        }
+        let labels = self.address_to_label().get(&address)?;
+        let label = match self.label_policy {
+            LabelPolicy::Last => labels.last(),
+            LabelPolicy::Global => labels.iter().rev().find(|label| !is_local_label(label)).or_else(|| labels.last()),
+            // among the shortest ones, the last one
+            LabelPolicy::Shortest => labels.iter().rev().min_by_key(|label| label.len()),
+        };
+        label.map(String::as_str)
+    }
+
+    // the label at this address, or depending on the address naming, the nearest label before it with an offset ("SomeFunc+0x1a"),
+    // and the raw address, after its region ("Work RAM 0xff0120") with the region naming
+    pub fn name(&self, address: u32) -> Cow<'_, str> {
+        let address = self.unrelocated(address);
+        if let Some(label) = self.label(address) {
+            return Cow::Borrowed(label);
+        }
+        if self.address_naming != AddressNaming::Hex {
+            if let Some((label_address, label)) = self.nearest_label(address) {
+                return Cow::Owned(format!("{}+{}", label, self.hex_format.format_offset(address - label_address)));
+            }
+        }
+        if self.address_naming == AddressNaming::Region {
+            let region = self.region(address).map_or_else(|| memory_area(address), |region| region.name.as_str());
+            return Cow::Owned(format!("{} {}", region, self.hex_format.format(address)));
+        }
+        Cow::Owned(self.hex_format.format(address))
+    }
+
+    // the nearest label before this address, if the address is inside its function when its size is known,
+    // otherwise if it's close enough to plausibly be the same function
+    pub fn nearest_label(&self, address: u32) -> Option<(u32, &str)> {
+        let address = self.unrelocated(address);
+        let sorted_addresses = self.sorted_addresses.get_or_init(|| {
+            let mut sorted_addresses: Vec<_> = self.address_to_label().keys().chain(self.aliases.keys().filter(|address| !self.address_to_label().contains_key(address))).copied().collect();
+            sorted_addresses.sort_unstable();
+            sorted_addresses
+        });
+        let index = sorted_addresses.partition_point(|&label_address| label_address <= address);
+        let label_address = *sorted_addresses.get(index.checked_sub(1)?)?;
+        let max_offset = self.sizes.get(&label_address).copied().filter(|&size| size != 0).unwrap_or(MAX_LABEL_OFFSET);
+        if address - label_address >= max_offset {
+            return None;
+        }
+        self.label(label_address).map(|label| (label_address, label))
+    }
+
+    pub fn source_location(&self, address: u32) -> Option<&str> {
+        self.source_locations.get(&self.unrelocated(address)).map(String::as_str)
+    }
+
+    // the address of the first instruction of a line of a source file, file being the path as written by the compiler or its
+    // last components ("main.c" or "src/main.c"), only available with ELF files that have DWARF debug info
+    pub fn source_line_address(&self, file: &str, line: u32) -> Result<u32, String> {
+        if self.source_lines.is_empty() {
+            return Err(format!("{}:{} needs an ELF symbol file with DWARF debug info", file, line));
+        }
+        let matching: Vec<&(String, u32)> = self.source_lines.get(&line).into_iter().flatten()
+            .filter(|(path, _address)| is_path_suffix(path, file))
+            .collect();
+        match matching.as_slice() {
+            [] => Err(format!("No code for {}:{} in the DWARF debug info", file, line)),
+            [(_path, address)] => Ok(*address),
+            _ => Err(format!("{}:{} is ambiguous, it could be {}", file, line, matching.iter().map(|(path, _address)| path.as_str()).collect::<Vec<_>>().join(" or "))),
+        }
+    }
+
+    // looks up a label, then a name given by the aliases (the lowest address having it), falling back to parsing it as an hex address
+    pub fn resolve(&self, label: &str) -> Option<u32> {
+        self.label_to_address().get(label).copied()
+            .or_else(|| self.aliases.iter().filter(|(_address, name)| *name == label).map(|(&address, _name)| address).min())
+            .or_else(|| u32::from_str_radix(label, 16).ok())
+    }
+
+    // the addresses of the labels matched by a glob or regex pattern (see symbol_pattern), None if input isn't a pattern
+    #[cfg(feature = "symbol-patterns")]
+    pub fn pattern_addresses(&self, input: &str) -> Result<Option<Vec<u32>>, String> {
+        let pattern = match symbol_pattern(input)? {
+            Some(pattern) => pattern,
+            None => return Ok(None),
+        };
+        let addresses: Vec<u32> = self.label_to_address().iter().filter(|(symbol, _)| pattern.is_match(symbol)).map(|(_, &address)| address).collect();
+        if addresses.is_empty() {
+            return Err(format!("{} doesn't match any symbol of the symbol file", input));
+        }
+        Ok(Some(addresses))
+    }
+
+    #[cfg(not(feature = "symbol-patterns"))]
+    pub fn pattern_addresses(&self, input: &str) -> Result<Option<Vec<u32>>, String> {
+        symbol_pattern(input)?;
+        Ok(None)
+    }
+
+    fn all_labels(&self) -> impl Iterator<Item = (u32, String)> + '_ {
+        let globals: BTreeMap<u32, &str> = if self.local_labels.is_empty() {
+            BTreeMap::new()
+        } else {
+            self.labels.iter().map(|(address, label)| (*address, label.as_str())).collect()
+        };
+        let locals = self.local_labels.iter().map(move |(address, local_label)| {
+            let (_parent_addr, parent_label) = globals.range(..*address).next_back().unwrap_or_else(|| panic!("Got local label {} without a parent", local_label));
+            let mut combined_label = parent_label.to_string();
+            combined_label.push_str(local_label);
+            (*address, combined_label)
+        });
+        self.labels.iter().cloned().chain(locals)
+    }
+}
+
+// "/regex/" or a glob pattern with * and ?, matched against whole symbol names
+#[cfg(feature = "symbol-patterns")]
+fn symbol_pattern(input: &str) -> Result<Option<Regex>, String> {
+    let pattern = if input.len() >= 2 && input.starts_with('/') && input.ends_with('/') {
+        format!("^(?:{})$", &input[1..input.len() - 1])
+    } else if input.contains(['*', '?']) {
+        let mut pattern = String::from("^");
+        for c in input.chars() {
+            match c {
+                '*' => pattern.push_str(".*"),
+                '?' => pattern.push('.'),
+                c => pattern.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+            }
+        }
+        pattern.push('$');
+        pattern
+    } else {
+        return Ok(None);
+    };
+    Regex::new(&pattern).map(Some).map_err(|err| format!("Invalid pattern {}: {}", input, err))
+}
+
+#[cfg(not(feature = "symbol-patterns"))]
+fn symbol_pattern(input: &str) -> Result<Option<()>, String> {
+    if input.contains(['*', '?']) || (input.len() >= 2 && input.starts_with('/') && input.ends_with('/')) {
+        return Err(format!("{} looks like a symbol pattern, but md-profiler was built without the symbol-patterns feature", input));
+    }
+    Ok(None)
+}
+
+// local labels of the various assemblers (asm68k's "Parent@loop" or "Parent.loop", AS's "$$loop", vasm's "loop$")
+// and compiler generated variants such as "func.part.0"
+fn is_local_label(label: &str) -> bool {
+    label.contains(['.', '@', '$'])
+}
+
+#[cfg(feature = "demangle")]
+fn demangle(label: &str) -> Option<String> {
+    if let Ok(demangled) = rustc_demangle::try_demangle(label) {
+        // the alternate format leaves out the hash suffix
+        return Some(format!("{:#}", demangled));
+    }
+    if label.starts_with("_Z") {
+        return cpp_demangle::Symbol::new(label).ok()?.demangle().ok();
+    }
+    None
+}
+
+#[cfg(not(feature = "demangle"))]
+fn demangle(_label: &str) -> Option<String> {
+    None
+}
+
+// 64-bit FNV-1a, the emulator computes the same hash when it records the symbol file hash in the mdp header
+pub fn symbol_file_hash(input: &[u8]) -> u64 {
+    input.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
 }
 
 pub fn read_symbols(input: &[u8]) -> Symbols {
-    if input[..3] == b"MND"[..] {
+    let mut symbols = if input.starts_with(b"\x7fELF") {
+        read_elf_symbols(input)
+    } else if input[..3] == b"MND"[..] {
         read_asm68k_symbols(input)
     } else if input[..12] == b"Segment CODE"[..] {
         read_as_symbols(input)
+    } else if input.starts_with(b"Sections:") || contains(input, b"Symbols by value:") {
+        read_vasm_symbols(input)
+    } else if contains(input, b"Linker script and memory map") {
+        read_ld_map_symbols(input)
+    } else if contains(input, b"Section mapping") && contains(input, b"Symbols of ") {
+        read_vlink_map_symbols(input)
     } else {
         read_nm_symbols(input)
+    };
+    symbols.hash = symbol_file_hash(input);
+    symbols.read_build_labels();
+    symbols
+}
+
+// labels telling which build the symbols come from, for the symbol files that have no other way to tell:
+// the value of mdp_rom_checksum is the checksum of the ROM header, and the one of mdp_build_id a build number,
+// they're values rather than addresses so they don't name anything
+const ROM_CHECKSUM_LABEL: &str = "mdp_rom_checksum";
+const BUILD_ID_LABEL: &str = "mdp_build_id";
+
+impl Symbols {
+    fn read_build_labels(&mut self) {
+        for (address, label) in &self.labels {
+            if label == ROM_CHECKSUM_LABEL && self.rom_checksum.is_none() {
+                self.rom_checksum = Some(*address as u16).filter(|&checksum| checksum != 0);
+            } else if label == BUILD_ID_LABEL && self.build_id.is_none() {
+                // big endian, as the game would store it in its ROM
+                self.build_id = Some(address.to_be_bytes().to_vec());
+            }
+        }
+        self.labels.retain(|(_address, label)| label != ROM_CHECKSUM_LABEL && label != BUILD_ID_LABEL);
     }
 }
 
+fn contains(input: &[u8], needle: &[u8]) -> bool {
+    input.windows(needle.len()).any(|window| window == needle)
+}
+
 fn read_asm68k_symbols(input: &[u8]) -> Symbols {
-    let mut address_to_label: BTreeMap<u32, Vec<String>> = BTreeMap::new();
-    let mut label_to_address: BTreeMap<String, u32> = BTreeMap::new();
+    let mut labels = Vec::new();
+    let mut local_labels = Vec::new();
     let mut i = 8; // skip header
     while i < input.len() {
         let address = u32::from_le_bytes(input[i..i+4].try_into().unwrap());
@@ -27,31 +620,20 @@ fn read_asm68k_symbols(input: &[u8]) -> Symbols {
         i += 1;
         let label_len = input[i] as usize;
         i += 1;
-        let label = match label_type {
-            2 => String::from_utf8_lossy(&input[i..i+label_len]).to_string(), // global label
-            6 => { // local label
-                let local_label = String::from_utf8_lossy(&input[i..i+label_len]);
-                // local labels are located after the global ones in the symbol file so we should already have seen all the parents by now
-                let (_parent_addr, parent_label) = address_to_label.range(..address).next_back().unwrap_or_else(|| panic!("Got local label {} without a parent", local_label));
-                let mut combined_label = parent_label.last().unwrap().clone();
-                combined_label.push_str(&local_label);
-                combined_label
-            }
-            x => panic!("Unknown label type: {} for {}", x, String::from_utf8_lossy(&input[i..i+label_len])),
+        let label = String::from_utf8_lossy(&input[i..i+label_len]).to_string();
+        match label_type {
+            2 => labels.push((address, label)), // global label
+            // local labels are located after the global ones in the symbol file, the parent is resolved lazily
+            6 => local_labels.push((address, label)),
+            x => panic!("Unknown label type: {} for {}", x, label),
         };
         i += label_len;
-        address_to_label.entry(address).or_default().push(label.clone());
-        label_to_address.insert(label, address);
-    }
-    Symbols {
-        address_to_label: address_to_label.into_iter().collect(),
-        label_to_address
     }
+    Symbols::new(labels, local_labels)
 }
 
 fn read_as_symbols(input: &[u8]) -> Symbols {
-    let mut address_to_symbols: HashMap<u32, Vec<String>> = HashMap::new();
-    let mut symbol_to_address: BTreeMap<String, u32> = BTreeMap::new();
+    let mut labels = Vec::new();
     let input = String::from_utf8_lossy(input);
     let (_, input_symbols) = input.split_once("Symbols in Segment").expect("Error parsing as symbols");
     for line in input_symbols.lines().skip(1) {
@@ -66,34 +648,285 @@ fn read_as_symbols(input: &[u8]) -> Symbols {
         }
         let symbol_addr = elm_iter.next().expect("Error parsing as symbols");
         if let Ok(address) = u64::from_str_radix(symbol_addr, 16) {
-            let address = address as u32;
-            address_to_symbols.entry(address).or_default().push(symbol_name.to_string());
-            symbol_to_address.insert(symbol_name.to_string(), address);
+            labels.push((address as u32, symbol_name.to_string()));
         }
-        
-    }
-    Symbols {
-        address_to_label: address_to_symbols,
-        label_to_address: symbol_to_address
+
     }
+    Symbols::new(labels, Vec::new())
 }
 
+// "ADDRESS TYPE NAME" lines, or "ADDRESS SIZE TYPE NAME" with nm -S
 fn read_nm_symbols(input: &[u8]) -> Symbols {
-    let mut address_to_symbols: HashMap<u32, Vec<String>> = HashMap::new();
-    let mut symbol_to_address: BTreeMap<String, u32> = BTreeMap::new();
+    let mut labels = Vec::new();
+    let mut sizes = HashMap::new();
     let input = String::from_utf8_lossy(input);
     for line in input.split('\n') {
         let elms: Vec<_> = line.split_ascii_whitespace().collect();
-        if elms.len() == 3 {
-            if let Ok(address) = u32::from_str_radix(elms[0], 16) {
-                let label = elms[2];
-                address_to_symbols.entry(address).or_default().push(label.to_string());
-                symbol_to_address.insert(label.to_string(), address);
+        let (address, size, label) = match elms[..] {
+            [address, _symbol_type, label] => (address, None, label),
+            [address, size, _symbol_type, label] => (address, Some(size), label),
+            _ => continue,
+        };
+        if let Ok(address) = u32::from_str_radix(address, 16) {
+            labels.push((address, label.to_string()));
+            if let Some(size) = size.and_then(|size| u32::from_str_radix(size, 16).ok()) {
+                sizes.insert(address, size);
+            }
+        }
+    }
+    let mut symbols = Symbols::new(labels, Vec::new());
+    symbols.sizes = sizes;
+    symbols
+}
+
+// GNU ld map file (-Map), symbols are listed under their input section as "0x<address> <name>" lines,
+// only the symbols located inside code output sections are kept so data symbols don't shadow function names
+fn read_ld_map_symbols(input: &[u8]) -> Symbols {
+    let mut labels = Vec::new();
+    let mut code_sections = Vec::new();
+    let mut has_sections = false;
+    let input = String::from_utf8_lossy(input);
+    let (_, memory_map) = input.split_once("Linker script and memory map").expect("Error parsing ld map symbols");
+    let mut lines = memory_map.lines().peekable();
+    while let Some(line) = lines.next() {
+        let mut elms: Vec<_> = line.split_ascii_whitespace().collect();
+        // output section, "<name> 0x<address> 0x<size>", long names have their address and size on the next line
+        if line.starts_with('.') {
+            if elms.len() == 1 {
+                if let Some(next_line) = lines.peek() {
+                    if next_line.starts_with(' ') && next_line.split_ascii_whitespace().count() == 2 {
+                        elms.extend(lines.next().unwrap().split_ascii_whitespace());
+                    }
+                }
+            }
+            let base = elms.get(1).and_then(|base| base.strip_prefix("0x")).and_then(|base| u64::from_str_radix(base, 16).ok());
+            let size = elms.get(2).and_then(|size| size.strip_prefix("0x")).and_then(|size| u64::from_str_radix(size, 16).ok());
+            if let (Some(base), Some(size)) = (base, size) {
+                has_sections = true;
+                if is_code_section(elms[0]) {
+                    code_sections.push(base..base + size);
+                }
+            }
+            continue;
+        }
+        // input section lines have 3 or more elements (address, size, object file) and assignments contain '='
+        if elms.len() != 2 || !line.starts_with(' ') {
+            continue;
+        }
+        if let Some(address) = elms[0].strip_prefix("0x") {
+            let label = elms[1];
+            if label.starts_with("0x") || label.contains(['=', '(', ')'].as_ref()) {
+                continue;
+            }
+            if let Ok(address) = u64::from_str_radix(address, 16) {
+                labels.push((address, label.to_string()));
+            }
+        }
+    }
+    let labels = labels.into_iter()
+        .filter(|(address, _label)| !has_sections || code_sections.iter().any(|section| section.contains(address)))
+        .map(|(address, label)| (address as u32, label))
+        .collect();
+    Symbols::new(labels, Vec::new())
+}
+
+fn is_code_section(name: &str) -> bool {
+    [".text", ".init", ".fini"].iter().any(|code_section| name == *code_section || name.starts_with(&format!("{}.", code_section)))
+}
+
+// vlink map file (-M), symbols are listed in "Symbols of <section>:" blocks as "<name>: <type>, value 0x<value>, addr 0x<address>, size <size>"
+fn read_vlink_map_symbols(input: &[u8]) -> Symbols {
+    let mut labels = Vec::new();
+    let mut sizes = HashMap::new();
+    let input = String::from_utf8_lossy(input);
+    let mut in_symbols = false;
+    for line in input.lines() {
+        if line.starts_with("Symbols of ") {
+            in_symbols = true;
+            continue;
+        }
+        if line.trim().is_empty() || !line.starts_with(' ') {
+            in_symbols = false;
+            continue;
+        }
+        if !in_symbols {
+            continue;
+        }
+        let (label, attributes) = match line.trim().split_once(':') {
+            Some(split) => split,
+            None => continue,
+        };
+        let mut value = None;
+        let mut addr = None;
+        let mut size = None;
+        for attribute in attributes.split(',') {
+            let mut attribute_elms = attribute.split_ascii_whitespace();
+            match (attribute_elms.next(), attribute_elms.next()) {
+                (Some("value"), Some(number)) => value = number.strip_prefix("0x").and_then(|number| u64::from_str_radix(number, 16).ok()),
+                (Some("addr"), Some(number)) => addr = number.strip_prefix("0x").and_then(|number| u64::from_str_radix(number, 16).ok()),
+                // in decimal, unlike the addresses
+                (Some("size"), Some(number)) => size = number.parse::<u32>().ok(),
+                _ => {},
+            }
+        }
+        if let Some(address) = addr.or(value) {
+            labels.push((address as u32, label.to_string()));
+            if let Some(size) = size {
+                sizes.insert(address as u32, size);
+            }
+        }
+    }
+    let mut symbols = Symbols::new(labels, Vec::new());
+    symbols.sizes = sizes;
+    symbols
+}
+
+// vasm listing file (-L), the symbol table at the end has "Symbols by name:" lines ("<name> <section>:<address>")
+// and "Symbols by value:" lines ("<address> <name>"), the later is used when present
+fn read_vasm_symbols(input: &[u8]) -> Symbols {
+    let mut labels_by_name = Vec::new();
+    let mut labels_by_value = Vec::new();
+    let input = String::from_utf8_lossy(input);
+    let mut current_table = None;
+    for line in input.lines() {
+        if line.starts_with("Symbols by name:") || line.starts_with("Symbols by value:") {
+            current_table = Some(line.starts_with("Symbols by value:"));
+            continue;
+        }
+        let elms: Vec<_> = line.split_ascii_whitespace().collect();
+        if elms.len() != 2 {
+            continue;
+        }
+        match current_table {
+            Some(false) => {
+                // equates are listed with their value instead of a section:address pair, and external symbols as "E"
+                if let Some((_section, address)) = elms[1].split_once(':') {
+                    if let Ok(address) = u32::from_str_radix(address, 16) {
+                        labels_by_name.push((address, elms[0].to_string()));
+                    }
+                }
+            },
+            Some(true) => {
+                if let Ok(address) = u32::from_str_radix(elms[0], 16) {
+                    labels_by_value.push((address, elms[1].to_string()));
+                }
+            },
+            None => {},
+        }
+    }
+    if labels_by_value.is_empty() {
+        Symbols::new(labels_by_name, Vec::new())
+    } else {
+        Symbols::new(labels_by_value, Vec::new())
+    }
+}
+
+// ELF file (the linked binary itself), the code symbols come from the symbol table and their source location from the DWARF line info if present
+#[cfg(feature = "elf")]
+fn read_elf_symbols(input: &[u8]) -> Symbols {
+    let elf = object::File::parse(input).expect("Error parsing ELF file");
+    let mut labels = Vec::new();
+    let mut sizes = HashMap::new();
+    for symbol in elf.symbols() {
+        if symbol.kind() != SymbolKind::Text || symbol.is_undefined() {
+            continue;
+        }
+        if let Ok(label) = symbol.name() {
+            if !label.is_empty() {
+                labels.push((symbol.address() as u32, label.to_string()));
+                if symbol.size() != 0 {
+                    sizes.insert(symbol.address() as u32, symbol.size() as u32);
+                }
             }
         }
     }
-    Symbols {
-        address_to_label: address_to_symbols,
-        label_to_address: symbol_to_address
+    let source_locations = match read_dwarf_source_locations(&elf, labels.iter().map(|(address, _label)| *address)) {
+        Ok(source_locations) => source_locations,
+        Err(err) => {
+            eprintln!("Warning: couldn't read the DWARF debug info of the ELF file: {}", err);
+            HashMap::new()
+        }
+    };
+    // an error reading the debug info was already reported with the source locations
+    let source_lines = read_dwarf_source_lines(&elf).unwrap_or_default();
+    let mut symbols = Symbols::new(labels, Vec::new());
+    symbols.source_locations = source_locations;
+    symbols.source_lines = source_lines;
+    symbols.sizes = sizes;
+    symbols.rom_checksum = read_elf_rom_checksum(&elf);
+    symbols.build_id = elf.build_id().ok().flatten().map(<[u8]>::to_vec);
+    symbols
+}
+
+// the checksum word of the ROM header, SGDK links it in with rom_head.c, None when it's left zeroed for a tool to fix up after linking
+#[cfg(feature = "elf")]
+fn read_elf_rom_checksum(elf: &object::File) -> Option<u16> {
+    const ROM_CHECKSUM_ADDRESS: u64 = 0x18e;
+    let segment = elf.segments().find(|segment| (segment.address()..segment.address() + segment.size()).contains(&ROM_CHECKSUM_ADDRESS))?;
+    let offset = (ROM_CHECKSUM_ADDRESS - segment.address()) as usize;
+    let bytes = segment.data().ok()?.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes(bytes.try_into().unwrap())).filter(|&checksum| checksum != 0)
+}
+
+#[cfg(not(feature = "elf"))]
+fn read_elf_symbols(_input: &[u8]) -> Symbols {
+    eprintln!("Warning: md-profiler was built without the elf feature, ELF symbol files can't be read");
+    Symbols::default()
+}
+
+#[cfg(feature = "elf")]
+fn dwarf_sections<'a>(elf: &object::File<'a>) -> Result<gimli::DwarfSections<Cow<'a, [u8]>>, gimli::Error> {
+    let load_section = |id: gimli::SectionId| -> Result<Cow<[u8]>, gimli::Error> {
+        Ok(elf.section_by_name(id.name()).and_then(|section| section.uncompressed_data().ok()).unwrap_or(Cow::Borrowed(&[])))
+    };
+    gimli::DwarfSections::load(load_section)
+}
+
+#[cfg(feature = "elf")]
+fn read_dwarf_source_locations(elf: &object::File, addresses: impl Iterator<Item = u32>) -> Result<HashMap<u32, String>, gimli::Error> {
+    let endian = if elf.is_little_endian() { gimli::RunTimeEndian::Little } else { gimli::RunTimeEndian::Big };
+    let dwarf_sections = dwarf_sections(elf)?;
+    let dwarf = dwarf_sections.borrow(|section| gimli::EndianSlice::new(section, endian));
+    let context = addr2line::Context::from_dwarf(dwarf)?;
+    let mut source_locations = HashMap::new();
+    for address in addresses {
+        if let Some(location) = context.find_location(address as u64)? {
+            if let (Some(file), Some(line)) = (location.file, location.line) {
+                source_locations.insert(address, format!("{}:{}", file, line));
+            }
+        }
     }
+    Ok(source_locations)
+}
+
+// the lowest address of the code of each line of each source file
+#[cfg(feature = "elf")]
+fn read_dwarf_source_lines(elf: &object::File) -> Result<HashMap<u32, Vec<(String, u32)>>, gimli::Error> {
+    let endian = if elf.is_little_endian() { gimli::RunTimeEndian::Little } else { gimli::RunTimeEndian::Big };
+    let dwarf_sections = dwarf_sections(elf)?;
+    let dwarf = dwarf_sections.borrow(|section| gimli::EndianSlice::new(section, endian));
+    let context = addr2line::Context::from_dwarf(dwarf)?;
+    let mut lowest_addresses: HashMap<(u32, &str), u32> = HashMap::new();
+    for (address, _size, location) in context.find_location_range(0, u64::MAX)? {
+        if let (Some(file), Some(line)) = (location.file, location.line) {
+            let lowest_address = lowest_addresses.entry((line, file)).or_insert(address as u32);
+            *lowest_address = (*lowest_address).min(address as u32);
+        }
+    }
+    let mut source_lines: HashMap<u32, Vec<(String, u32)>> = HashMap::new();
+    for ((line, file), address) in lowest_addresses {
+        source_lines.entry(line).or_default().push((file.to_string(), address));
+    }
+    Ok(source_lines)
+}
+
+// the components of a path from the last one, with / or \ separators
+fn path_components(path: &str) -> Vec<&str> {
+    path.split(['/', '\\']).filter(|component| !component.is_empty() && *component != ".").rev().collect()
+}
+
+// whether path ends with the components of suffix
+fn is_path_suffix(path: &str, suffix: &str) -> bool {
+    let suffix = path_components(suffix);
+    !suffix.is_empty() && path_components(path).starts_with(&suffix)
 }
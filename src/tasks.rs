@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use crate::symbols::Symbols;
+
+// a stack of one of the cooperative tasks (coroutines) of the game, in RAM
+#[derive(Debug, Clone)]
+pub struct TaskStack {
+    pub name: String,
+    // the stack pointer of the task stays above start and at most at end, its initial value as the stack grows down
+    pub start: u32,
+    pub end: u32,
+    // thread the code running on this stack is put on
+    pub tid: u32,
+}
+
+// with a stack per task, the stack pointer jumps from one stack to another on task switches,
+// the code running on each stack is put on the thread of its task and its calls are matched with the returns of the same stack
+#[derive(Debug, Clone, Default)]
+pub struct TaskStacks {
+    stacks: Vec<TaskStack>,
+}
+
+impl TaskStacks {
+    // one START,END,NAME line per task, START and END being labels or hex addresses
+    pub fn read(input: &[u8], symbols: &Symbols) -> Result<TaskStacks, String> {
+        let input = String::from_utf8_lossy(input);
+        let mut stacks = Vec::new();
+        for line in input.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let (start, end, name) = match fields[..] {
+                [start, end, name] => (start, end, name),
+                _ => return Err(format!("Invalid task stack: {}, expected START,END,NAME", line)),
+            };
+            let start_address = symbols.resolve(start).ok_or_else(|| format!("Task stack start {} not found in the symbol file", start))?;
+            let end_address = symbols.resolve(end).ok_or_else(|| format!("Task stack end {} not found in the symbol file", end))?;
+            if end_address <= start_address {
+                return Err(format!("Task stack {} ends before it starts", name));
+            }
+            stacks.push(TaskStack {
+                name: name.to_string(),
+                start: start_address,
+                end: end_address,
+                tid: 0,
+            });
+        }
+        let mut sorted: Vec<&TaskStack> = stacks.iter().collect();
+        sorted.sort_by_key(|stack| stack.start);
+        if let Some(overlap) = sorted.windows(2).find(|pair| pair[1].start < pair[0].end) {
+            return Err(format!("Task stacks {} and {} overlap", overlap[0].name, overlap[1].name));
+        }
+        Ok(TaskStacks { stacks })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stacks.is_empty()
+    }
+
+    // each task gets a thread named after it, after the custom threads of the interval file
+    pub fn assign_threads(&mut self, custom_threads: &mut HashMap<String, u32>) {
+        for stack in &mut self.stacks {
+            let new_tid = custom_threads.values().max().map_or(crate::profiling::FIRST_CUSTOM_TID, |tid| tid + 1);
+            stack.tid = *custom_threads.entry(stack.name.clone()).or_insert(new_tid);
+        }
+    }
+
+    // which task this stack pointer belongs to, None for the stack of the main code
+    pub fn task(&self, stack_pointer: u32) -> Option<usize> {
+        self.stacks.iter().position(|stack| stack.start < stack_pointer && stack_pointer <= stack.end)
+    }
+
+    // thread of the code running with this stack pointer outside of interrupts
+    pub fn tid(&self, stack_pointer: u32) -> u32 {
+        self.task(stack_pointer).map_or(0, |task| self.stacks[task].tid)
+    }
+}
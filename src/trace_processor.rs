@@ -0,0 +1,59 @@
+use std::{env, fs, path::{Path, PathBuf}, process::Command};
+
+// (title, query) run on the json trace, durations are in nanoseconds in trace_processor's tables,
+// the slices of the idle functions are left out of the top slices
+pub const QUERIES: &[(&str, &str)] = &[
+    ("Frames (from one VInt to the next)", "
+        SELECT COUNT(*) AS frames, ROUND(AVG(frame_dur) / 1000.0, 1) AS avg_us, ROUND(MIN(frame_dur) / 1000.0, 1) AS min_us, ROUND(MAX(frame_dur) / 1000.0, 1) AS max_us
+        FROM (SELECT LEAD(ts) OVER (ORDER BY ts) - ts AS frame_dur FROM slice WHERE name = 'VInt')
+        WHERE frame_dur IS NOT NULL
+    "),
+    ("Top slices by total time", "
+        SELECT name, COUNT(*) AS calls, ROUND(SUM(dur) / 1000.0, 1) AS total_us, ROUND(AVG(dur) / 1000.0, 1) AS avg_us, ROUND(MAX(dur) / 1000.0, 1) AS max_us
+        FROM slice WHERE dur > 0 AND IFNULL(category, '') != 'idle'
+        GROUP BY name ORDER BY SUM(dur) DESC LIMIT 20
+    "),
+    ("Top slices by self time", "
+        SELECT name, ROUND(SUM(self_dur) / 1000.0, 1) AS self_us
+        FROM (
+            SELECT parent.name AS name, parent.dur - IFNULL(SUM(child.dur), 0) AS self_dur
+            FROM slice AS parent LEFT JOIN slice AS child ON child.parent_id = parent.id
+            WHERE parent.dur > 0 AND IFNULL(parent.category, '') != 'idle'
+            GROUP BY parent.id
+        )
+        GROUP BY name ORDER BY SUM(self_dur) DESC LIMIT 20
+    "),
+];
+
+// the given path, or trace_processor_shell from the PATH
+pub fn locate_trace_processor(path: Option<&str>) -> Result<PathBuf, String> {
+    if let Some(path) = path {
+        return Ok(PathBuf::from(path));
+    }
+    let executable = if cfg!(windows) { "trace_processor_shell.exe" } else { "trace_processor_shell" };
+    env::var_os("PATH")
+        .and_then(|paths| env::split_paths(&paths).map(|dir| dir.join(executable)).find(|path| path.is_file()))
+        .ok_or_else(|| format!("{} not found in the PATH, download it from https://perfetto.dev and pass its path to --trace-processor", executable))
+}
+
+// runs each canned query on the json trace and prints the results
+pub fn run_queries(trace_processor: &Path, trace: &str) -> Result<(), String> {
+    let query_file = env::temp_dir().join(format!("md-profiler-query-{}.sql", std::process::id()));
+    for (title, query) in QUERIES {
+        fs::write(&query_file, query).map_err(|err| format!("Couldn't write {}: {}", query_file.display(), err))?;
+        let output = Command::new(trace_processor)
+            .arg("--query-file")
+            .arg(&query_file)
+            .arg(trace)
+            .output()
+            .map_err(|err| format!("Couldn't run {}: {}", trace_processor.display(), err))?;
+        if !output.status.success() {
+            let _ = fs::remove_file(&query_file);
+            return Err(format!("{} failed: {}", trace_processor.display(), String::from_utf8_lossy(&output.stderr)));
+        }
+        println!("{}:", title);
+        println!("{}", String::from_utf8_lossy(&output.stdout).trim_end());
+    }
+    let _ = fs::remove_file(&query_file);
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use std::io::Write;
+
+use crate::profiling::*;
+
+// anomalies of each kind listed in full, the rest are only counted
+const MAX_LISTED_ANOMALIES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AnomalyKind {
+    NonMonotonicCycles,
+    UnmatchedSubroutineExit,
+    StackPointer,
+    UnmatchedInterruptExit,
+    NestedInterrupt,
+    UnclosedSubroutine,
+    UnclosedInterrupt,
+}
+
+impl AnomalyKind {
+    pub fn description(self) -> &'static str {
+        match self {
+            AnomalyKind::NonMonotonicCycles => "Cycles going backwards",
+            AnomalyKind::UnmatchedSubroutineExit => "Subroutine exits with no matching enter",
+            AnomalyKind::StackPointer => "Stack pointer inconsistencies",
+            AnomalyKind::UnmatchedInterruptExit => "Interrupt exits outside of interrupts",
+            AnomalyKind::NestedInterrupt => "Interrupts entered inside interrupts",
+            AnomalyKind::UnclosedSubroutine => "Subroutines still running at the end of the capture",
+            AnomalyKind::UnclosedInterrupt => "Interrupts still running at the end of the capture",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Anomaly {
+    pub kind: AnomalyKind,
+    pub cycle: u64,
+    pub details: String,
+}
+
+// checks that the packets of a capture are consistent with each other, the converter assumes they are
+pub fn validate_capture(input: &ParsedProfilingFile) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+    let mut anomaly = |kind, cycle, details: String| anomalies.push(Anomaly { kind, cycle, details });
+    let mut last_cycle = 0;
+    let mut in_interrupt = false;
+    // (subroutine, cycle) of the subroutines running in the main code and in the interrupt
+    let mut main_stack: CallStack<(u32, u64)> = CallStack::new(input.exit_matching);
+    let mut interrupt_stack: CallStack<(u32, u64)> = CallStack::new(input.exit_matching);
+    let mut interrupt_enter: Option<(u32, u64)> = None;
+    for packet in &input.packets {
+        if packet.cycle < last_cycle {
+            anomaly(AnomalyKind::NonMonotonicCycles, packet.cycle, format!("{:?} packet {} cycles before the previous packet", packet.inner, last_cycle - packet.cycle));
+        }
+        last_cycle = last_cycle.max(packet.cycle);
+        let stack = if in_interrupt { &mut interrupt_stack } else { &mut main_stack };
+        if let Some(change) = packet.call_stack_change() {
+            if let Some(exit_stack_pointer) = change.exit {
+                let returning = stack.returning(exit_stack_pointer);
+                match returning {
+                    0 if stack.is_empty() => anomaly(AnomalyKind::UnmatchedSubroutineExit, packet.cycle, format!("{:?} with a stack pointer of {:#08x} while no subroutine is running", packet.inner, packet.stack_pointer)),
+                    0 => anomaly(AnomalyKind::StackPointer, packet.cycle, format!("{:?} with a stack pointer of {:#08x}, below the one of the running subroutine {:#08x} ({:#08x})", packet.inner, packet.stack_pointer, stack.last().unwrap().0.0, stack.last().unwrap().1)),
+                    1 => {},
+                    _ => anomaly(AnomalyKind::StackPointer, packet.cycle, format!("{:?} with a stack pointer of {:#08x} returns from {} subroutines at once, the innermost being {:#08x}", packet.inner, packet.stack_pointer, returning, stack.last().unwrap().0.0)),
+                }
+                stack.exit(exit_stack_pointer).for_each(drop);
+            }
+            if let Some((target_subroutine, stack_pointer)) = change.enter {
+                // the return address of the caller is on the stack, the target of a tail call takes the place of the subroutine that jumps
+                match stack.last() {
+                    Some(&((caller, _cycle), caller_stack_pointer)) if change.exit.is_none() && stack_pointer + 4 > caller_stack_pointer => {
+                        anomaly(AnomalyKind::StackPointer, packet.cycle, format!("{:#08x} called from {:#08x} with a stack pointer of {:#08x}, above the one of its caller ({:#08x})", target_subroutine, caller, packet.stack_pointer, caller_stack_pointer));
+                    },
+                    _ => {},
+                }
+                stack.enter((target_subroutine, packet.cycle), stack_pointer);
+            }
+        }
+        match packet.inner {
+            ProfilingPacketInner::InterruptEnter { target_interrupt } => {
+                if let Some((interrupt, cycle)) = interrupt_enter {
+                    anomaly(AnomalyKind::NestedInterrupt, packet.cycle, format!("{:#08x} entered while {:#08x} (entered at cycle {}) is still running", target_interrupt, interrupt, cycle));
+                }
+                in_interrupt = true;
+                interrupt_enter = Some((target_interrupt, packet.cycle));
+                interrupt_stack.clear();
+            },
+            ProfilingPacketInner::InterruptExit => {
+                if !in_interrupt {
+                    anomaly(AnomalyKind::UnmatchedInterruptExit, packet.cycle, "Interrupt exit outside of any interrupt".to_string());
+                } else if let Some(&((subroutine, _cycle), _stack_pointer)) = interrupt_stack.last() {
+                    anomaly(AnomalyKind::StackPointer, packet.cycle, format!("Interrupt exit while {:#08x} is still running in the interrupt", subroutine));
+                }
+                in_interrupt = false;
+                interrupt_enter = None;
+                interrupt_stack.clear();
+            },
+            _ => {},
+        }
+    }
+    if let Some((interrupt, cycle)) = interrupt_enter {
+        anomaly(AnomalyKind::UnclosedInterrupt, cycle, format!("{:#08x}", interrupt));
+    }
+    for &((subroutine, cycle), stack_pointer) in main_stack.iter() {
+        anomaly(AnomalyKind::UnclosedSubroutine, cycle, format!("{:#08x} entered with a stack pointer of {:#08x}", subroutine, stack_pointer));
+    }
+    anomalies.sort_by_key(|anomaly| anomaly.kind);
+    anomalies
+}
+
+// lists the anomalies grouped by kind, with the number of enters and exits of the capture
+pub fn write_validation_report(mut output: impl Write, input: &ParsedProfilingFile, anomalies: &[Anomaly]) -> std::io::Result<()> {
+    let count = |predicate: fn(&ProfilingPacketInner) -> bool| input.packets.iter().filter(|packet| predicate(&packet.inner)).count();
+    writeln!(output, "Subroutine enters: {}, exits: {}, tail calls: {}",
+        count(|inner| matches!(inner, ProfilingPacketInner::SubroutineEnter { .. })),
+        count(|inner| matches!(inner, ProfilingPacketInner::SubroutineExit)),
+        count(|inner| matches!(inner, ProfilingPacketInner::TailCall { .. })),
+    )?;
+    writeln!(output, "Interrupt enters: {}, exits: {}",
+        count(|inner| matches!(inner, ProfilingPacketInner::InterruptEnter { .. })),
+        count(|inner| matches!(inner, ProfilingPacketInner::InterruptExit)),
+    )?;
+    let trap_enters = count(|inner| matches!(inner, ProfilingPacketInner::TrapEnter { .. }));
+    let trap_exits = count(|inner| matches!(inner, ProfilingPacketInner::TrapExit));
+    if trap_enters != 0 || trap_exits != 0 {
+        writeln!(output, "TRAP enters: {}, exits: {}", trap_enters, trap_exits)?;
+    }
+    let exceptions = count(|inner| matches!(inner, ProfilingPacketInner::Exception { .. }));
+    if exceptions != 0 {
+        writeln!(output, "CPU exceptions: {}", exceptions)?;
+    }
+    for kind_anomalies in anomalies.chunk_by(|a, b| a.kind == b.kind) {
+        writeln!(output, "{}: {}", kind_anomalies[0].kind.description(), kind_anomalies.len())?;
+        for anomaly in kind_anomalies.iter().take(MAX_LISTED_ANOMALIES) {
+            writeln!(output, "  cycle {} ({:.3} ms): {}", anomaly.cycle, cycle_to_us(anomaly.cycle, input.mclk) / 1000.0, anomaly.details)?;
+        }
+        if kind_anomalies.len() > MAX_LISTED_ANOMALIES {
+            writeln!(output, "  ... and {} more", kind_anomalies.len() - MAX_LISTED_ANOMALIES)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::MdpBuilder;
+
+    fn kinds(anomalies: &[Anomaly]) -> Vec<AnomalyKind> {
+        anomalies.iter().map(|anomaly| anomaly.kind).collect()
+    }
+
+    #[test]
+    fn balanced_capture_has_no_anomaly() {
+        let mut builder = MdpBuilder::default();
+        builder.call(0x200).wait(100).call(0x300).wait(100).interrupt(0x400).call(0x500).wait(50);
+        builder.ret().unwrap().interrupt_return().unwrap().ret().unwrap().ret().unwrap();
+        assert!(validate_capture(&builder.build()).is_empty());
+    }
+
+    #[test]
+    fn unmatched_exit() {
+        let mut builder = MdpBuilder::default();
+        builder.call(0x200).wait(100);
+        builder.ret().unwrap();
+        let mut capture = builder.build();
+        let last = capture.packets.last().unwrap();
+        let exit = ProfilingPacket { cycle: last.cycle + 700, stack_pointer: last.stack_pointer, inner: ProfilingPacketInner::SubroutineExit };
+        capture.packets.push(exit);
+        assert_eq!(kinds(&validate_capture(&capture)), [AnomalyKind::UnmatchedSubroutineExit]);
+    }
+
+    #[test]
+    fn tail_call_returns_to_the_caller() {
+        // the target of the JMP calls a subroutine of its own, whose RTS must only return from that subroutine
+        let mut builder = MdpBuilder::default();
+        builder.call(0x200).wait(100);
+        builder.tail_call(0x300).unwrap().wait(100).call(0x400).wait(100);
+        builder.ret().unwrap().wait(100).ret().unwrap();
+        assert!(validate_capture(&builder.build()).is_empty());
+    }
+
+    #[test]
+    fn unclosed_subroutine_and_interrupt() {
+        let mut builder = MdpBuilder::default();
+        builder.call(0x200).wait(100).interrupt(0x400).wait(100);
+        assert_eq!(kinds(&validate_capture(&builder.build())), [AnomalyKind::UnclosedSubroutine, AnomalyKind::UnclosedInterrupt]);
+    }
+
+    #[test]
+    fn caller_stack_pointer_below_callee() {
+        let mut builder = MdpBuilder::default();
+        builder.call(0x200).wait(100).call(0x300);
+        let mut capture = builder.build();
+        // as if the stack pointer went up between the two calls
+        capture.packets[1].stack_pointer += 8;
+        assert!(kinds(&validate_capture(&capture)).contains(&AnomalyKind::StackPointer));
+    }
+}
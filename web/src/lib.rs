@@ -0,0 +1,47 @@
+// converts a capture dropped on the web page, everything happens in memory with the default options of md-profiler
+use md_profiler::{intervals::read_intervals, perfetto::write_perfetto_trace, profiling::*, schema::{TraceMetadata, SCHEMA_VERSION}, symbols::{read_symbols, Symbols}, tasks::TaskStacks};
+use wasm_bindgen::prelude::*;
+
+// a Perfetto protobuf trace, or a json one, from the bytes of a capture and of its optional symbol and interval files
+#[wasm_bindgen]
+pub fn convert(capture: &[u8], symbol_file: Option<Vec<u8>>, interval_file: Option<Vec<u8>>, perfetto: bool) -> Result<Vec<u8>, JsValue> {
+    convert_capture(capture, symbol_file.as_deref(), interval_file.as_deref(), perfetto).map_err(|err| JsValue::from_str(&err))
+}
+
+fn convert_capture(capture: &[u8], symbol_file: Option<&[u8]>, interval_file: Option<&[u8]>, perfetto: bool) -> Result<Vec<u8>, String> {
+    let mut profiling = read_profiling_file(capture)?;
+    profiling.check_clocks()?;
+    profiling.resolve_clock_domains();
+    profiling.canonicalize_addresses(&AddressMap::default());
+    profiling.resolve_banked_addresses();
+    let symbols = match symbol_file {
+        Some(symbol_file) => {
+            let mut symbols = read_symbols(symbol_file);
+            symbols.demangle();
+            symbols
+        },
+        None => Symbols::default(),
+    };
+    let (mut intervals, custom_threads) = match interval_file {
+        Some(interval_file) => read_intervals(interval_file, &symbols, "interval file")?,
+        None => Default::default(),
+    };
+    intervals.set_exit_matching(profiling.exit_matching);
+    let mut trace_events = generate_trace_events(&profiling, &symbols, &mut intervals, custom_threads, &MarkerStyles::default(), false, &TaskStacks::default());
+    intervals.configure_threads(&mut trace_events);
+    let mut output = Vec::new();
+    if perfetto {
+        write_perfetto_trace(&mut output, &trace_events, Compression::None, false);
+    } else {
+        let metadata = TraceMetadata {
+            schema_version: SCHEMA_VERSION,
+            symbol_file_hash: Some(symbols.hash).filter(|&hash| hash != 0).map(|hash| format!("{:016x}", hash)),
+            converter_version: Some(format!("{} (web)", env!("CARGO_PKG_VERSION"))),
+            command_line: Vec::new(),
+            symbol_file_hashes: Vec::new(),
+            captures: Vec::new(),
+        };
+        write_profiling_json(&mut output, &trace_events, &metadata, Compression::None, JsonFormat::Object, false);
+    }
+    Ok(output)
+}